@@ -0,0 +1,443 @@
+//!Per-request access logging.
+//!
+//!An [`AccessLog`][access_log] wraps the request handler, or a part of it,
+//!and records an [`Entry`][entry] for each request that passes through it.
+//!The entries are passed to a [`Sink`][sink], which decides what to do with
+//!them. The default sink formats them in the Common Log Format and writes
+//!them to the `log` crate, with the `rustful::access_log` target:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter};
+//!use rustful::access_log::{AccessLog, LogSink, JsonFormat};
+//!
+//!fn say_hello(_context: Context, response: Response) {
+//!    response.send("Hello!");
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("hello").then().on_get(say_hello);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(AccessLog::with_sink(router, LogSink::new(JsonFormat)))
+//!}.run();
+//!```
+//!
+//!The entry is recorded when the response is done, which means that it
+//!includes the final status code and the number of body bytes, even if the
+//!handler returns the request to an outer handler. The trace ID is
+//!included as well, if a `TraceFilter` has added a `TraceContext` before the
+//!request reaches the `AccessLog`, such as when it's a global context filter.
+//!
+//![access_log]: struct.AccessLog.html
+//![entry]: struct.Entry.html
+//![sink]: trait.Sink.html
+
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use time::{self, Tm};
+
+use {HttpVersion, Method, StatusCode};
+use header::{Referer, UserAgent};
+use context::hypermedia::Link;
+use server::Requirements;
+use handler::{HandleRequest, Environment};
+use response::{SentBytes, SentStatus};
+use trace::{TraceContext, TraceId};
+use utils::push_json_string;
+
+///Information about a handled request.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    ///The address of the client, as reported by any trusted proxies. See
+    ///`Context::client_address`.
+    pub address: IpAddr,
+
+    ///When the request was received.
+    pub time: Tm,
+
+    ///The request method.
+    pub method: Method,
+
+    ///The requested path, without the query string.
+    pub path: String,
+
+    ///The HTTP version of the request.
+    pub http_version: HttpVersion,
+
+    ///The status code of the response, if it was sent.
+    pub status: Option<StatusCode>,
+
+    ///The number of response body bytes that were sent.
+    pub bytes: usize,
+
+    ///The time it took to handle the request and send the response.
+    pub duration: Duration,
+
+    ///The `Referer` header, if any.
+    pub referer: Option<String>,
+
+    ///The `User-Agent` header, if any.
+    pub user_agent: Option<String>,
+
+    ///The trace ID from the request's `TraceContext`, if it's traced.
+    pub trace_id: Option<TraceId>,
+}
+
+///Something that takes care of the access log entries.
+pub trait Sink: Send + Sync + 'static {
+    ///Record an entry.
+    fn record(&self, entry: &Entry);
+}
+
+impl<F: Fn(&Entry) + Send + Sync + 'static> Sink for F {
+    fn record(&self, entry: &Entry) {
+        self(entry)
+    }
+}
+
+///Turns an access log entry into a line of text.
+pub trait Format: Send + Sync + 'static {
+    ///Format an entry, without a trailing line break.
+    fn format(&self, entry: &Entry) -> String;
+}
+
+///The Common Log Format, as used by many web servers.
+///
+///```text
+///127.0.0.1 - - [16/Oct/2026:13:55:36 +0000] "GET /hello HTTP/1.1" 200 6
+///```
+///
+///Quotes, backslashes and control characters in the method and path are
+///escaped as `\"`, `\\` and `\xhh`, like in Apache, so they can't break the
+///line apart. The trace ID is added to the end of the line, if the request
+///is traced:
+///
+///```text
+///127.0.0.1 - - [16/Oct/2026:13:55:36 +0000] "GET /hello HTTP/1.1" 200 6 4bf92f3577b34da6a3ce929d0e0e4736
+///```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommonFormat;
+
+impl Format for CommonFormat {
+    fn format(&self, entry: &Entry) -> String {
+        let time = entry.time.to_utc().strftime("%d/%b/%Y:%H:%M:%S +0000").map(|time| time.to_string()).unwrap_or_default();
+        let status = entry.status.map_or("-".into(), |status| status.to_u16().to_string());
+        let bytes = if entry.bytes == 0 { "-".into() } else { entry.bytes.to_string() };
+
+        let mut line = format!("{} - - [{}] \"", entry.address, time);
+        push_escaped(&mut line, &entry.method.to_string());
+        line.push(' ');
+        push_escaped(&mut line, &entry.path);
+        let _ = write!(line, " {}\" {} {}", entry.http_version, status, bytes);
+
+        if let Some(trace_id) = entry.trace_id {
+            let _ = write!(line, " {}", trace_id);
+        }
+
+        line
+    }
+}
+
+//Escape a request field the same way as Apache.
+fn push_escaped(line: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            c if c.is_control() => for byte in c.to_string().bytes() {
+                let _ = write!(line, "\\x{:02x}", byte);
+            },
+            c => line.push(c)
+        }
+    }
+}
+
+///One JSON object per entry.
+///
+///```text
+///{"address":"127.0.0.1","time":"2026-10-16T13:55:36Z","method":"GET","path":"/hello","version":"HTTP/1.1","status":200,"bytes":6,"duration_ms":0.412,"referer":null,"user_agent":"curl/7.64.0","trace_id":null}
+///```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonFormat;
+
+impl Format for JsonFormat {
+    fn format(&self, entry: &Entry) -> String {
+        let mut line = String::from("{");
+
+        line.push_str("\"address\":");
+        push_json_string(&mut line, &entry.address.to_string());
+        line.push_str(",\"time\":");
+        push_json_string(&mut line, &entry.time.to_utc().rfc3339().to_string());
+        line.push_str(",\"method\":");
+        push_json_string(&mut line, &entry.method.to_string());
+        line.push_str(",\"path\":");
+        push_json_string(&mut line, &entry.path);
+        line.push_str(",\"version\":");
+        push_json_string(&mut line, &entry.http_version.to_string());
+
+        match entry.status {
+            Some(status) => { let _ = write!(line, ",\"status\":{}", status.to_u16()); },
+            None => line.push_str(",\"status\":null")
+        }
+
+        let millis = entry.duration.as_secs() as f64 * 1000.0 + f64::from(entry.duration.subsec_nanos()) / 1_000_000.0;
+        let _ = write!(line, ",\"bytes\":{},\"duration_ms\":{:.3}", entry.bytes, millis);
+
+        for &(name, ref value) in &[("referer", &entry.referer), ("user_agent", &entry.user_agent)] {
+            let _ = write!(line, ",\"{}\":", name);
+            match **value {
+                Some(ref value) => push_json_string(&mut line, value),
+                None => line.push_str("null")
+            }
+        }
+
+        line.push_str(",\"trace_id\":");
+        match entry.trace_id {
+            Some(trace_id) => push_json_string(&mut line, &trace_id.to_string()),
+            None => line.push_str("null")
+        }
+
+        line.push('}');
+        line
+    }
+}
+
+///Writes formatted entries to the `log` crate, at the `info` level, using
+///the `rustful::access_log` target.
+#[derive(Clone, Debug, Default)]
+pub struct LogSink<F> {
+    format: F,
+}
+
+impl<F: Format> LogSink<F> {
+    ///Write entries in the format `format`.
+    pub fn new(format: F) -> LogSink<F> {
+        LogSink {
+            format: format,
+        }
+    }
+}
+
+impl<F: Format> Sink for LogSink<F> {
+    fn record(&self, entry: &Entry) {
+        info!(target: "rustful::access_log", "{}", self.format.format(entry));
+    }
+}
+
+///Writes formatted entries, one per line, to something that implements
+///`Write`, such as a file. Write errors are logged and otherwise ignored.
+pub struct WriterSink<W, F> {
+    writer: Mutex<W>,
+    format: F,
+}
+
+impl<W: Write + Send + 'static, F: Format> WriterSink<W, F> {
+    ///Write entries to `writer`, in the format `format`.
+    pub fn new(writer: W, format: F) -> WriterSink<W, F> {
+        WriterSink {
+            writer: Mutex::new(writer),
+            format: format,
+        }
+    }
+}
+
+impl<W: Write + Send + 'static, F: Format> Sink for WriterSink<W, F> {
+    fn record(&self, entry: &Entry) {
+        let mut line = self.format.format(entry);
+        line.push('\n');
+
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Err(e) = writer.write_all(line.as_bytes()) {
+                error!("failed to write to the access log: {}", e);
+            }
+        }
+    }
+}
+
+///Records an access log entry for every request that passes through the
+///inner handler.
+pub struct AccessLog<H> {
+    handler: H,
+    sink: Arc<Sink>,
+}
+
+impl<H: HandleRequest> AccessLog<H> {
+    ///Log the requests in the Common Log Format, using the `log` crate.
+    pub fn new(handler: H) -> AccessLog<H> {
+        AccessLog::with_sink(handler, LogSink::new(CommonFormat))
+    }
+
+    ///Pass the entries to a custom sink.
+    pub fn with_sink<S: Sink>(handler: H, sink: S) -> AccessLog<H> {
+        AccessLog {
+            handler: handler,
+            sink: Arc::new(sink),
+        }
+    }
+
+    ///Get a reference to the inner handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+
+impl<H: HandleRequest> HandleRequest for AccessLog<H> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let pending = {
+            let context = &environment.context;
+            PendingEntry {
+                entry: Some(Entry {
+                    address: context.client_address,
+                    time: time::now_utc(),
+                    method: context.method.clone(),
+                    path: context.uri_path.to_string(),
+                    http_version: context.http_version,
+                    status: None,
+                    bytes: 0,
+                    duration: Duration::from_secs(0),
+                    referer: context.headers.get::<Referer>().map(|referer| referer.0.clone()),
+                    user_agent: context.headers.get::<UserAgent>().map(|agent| agent.0.clone()),
+                    trace_id: context.extensions.get::<TraceContext>().map(|trace| trace.trace_id),
+                }),
+                start: Instant::now(),
+                sent_status: environment.response.sent_status(),
+                sent_bytes: environment.response.sent_bytes(),
+                sink: self.sink.clone(),
+            }
+        };
+
+        //Dropped together with the rest of the response
+        environment.response.filter_storage_mut().insert(pending);
+
+        self.handler.handle_request(environment)
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handler.hyperlinks(base)
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.handler.requirements(requirements);
+    }
+}
+
+struct PendingEntry {
+    entry: Option<Entry>,
+    start: Instant,
+    sent_status: SentStatus,
+    sent_bytes: SentBytes,
+    sink: Arc<Sink>,
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.status = self.sent_status.get();
+            entry.bytes = self.sent_bytes.get();
+            entry.duration = self.start.elapsed();
+            self.sink.record(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use std::sync::{Arc, Mutex};
+
+    use {Context, Response, Method, HttpVersion, StatusCode};
+    use header::Headers;
+    use server::Global;
+    use handler::{HandleRequest, Environment};
+    use trace::{TraceContext, TraceId};
+    use super::{AccessLog, Entry, Format, CommonFormat, JsonFormat};
+
+    fn entry() -> Entry {
+        Entry {
+            address: "127.0.0.1".parse().unwrap(),
+            time: ::time::at_utc(::time::Timespec::new(0, 0)),
+            method: Method::Get,
+            path: "/say \"hi\"".into(),
+            http_version: HttpVersion::Http11,
+            status: Some(StatusCode::Ok),
+            bytes: 6,
+            duration: Duration::from_millis(2),
+            referer: None,
+            user_agent: Some("test".into()),
+            trace_id: None,
+        }
+    }
+
+    #[test]
+    fn common_format() {
+        assert_eq!(
+            CommonFormat.format(&entry()),
+            "127.0.0.1 - - [01/Jan/1970:00:00:00 +0000] \"GET /say \\\"hi\\\" HTTP/1.1\" 200 6"
+        );
+
+        let entry = Entry {
+            method: Method::Extension("A\"B".into()),
+            path: "/x\n1.2.3.4 - - \\\u{7f}".into(),
+            ..entry()
+        };
+        assert_eq!(
+            CommonFormat.format(&entry),
+            "127.0.0.1 - - [01/Jan/1970:00:00:00 +0000] \"A\\\"B /x\\x0a1.2.3.4 - - \\\\\\x7f HTTP/1.1\" 200 6"
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        assert_eq!(
+            JsonFormat.format(&entry()),
+            "{\"address\":\"127.0.0.1\",\"time\":\"1970-01-01T00:00:00Z\",\"method\":\"GET\",\"path\":\"/say \\\"hi\\\"\",\"version\":\"HTTP/1.1\",\"status\":200,\"bytes\":6,\"duration_ms\":2.000,\"referer\":null,\"user_agent\":\"test\",\"trace_id\":null}"
+        );
+    }
+
+    #[test]
+    fn trace_id() {
+        let entry = Entry {
+            trace_id: TraceId::parse("4bf92f3577b34da6a3ce929d0e0e4736"),
+            ..entry()
+        };
+
+        assert_eq!(
+            CommonFormat.format(&entry),
+            "127.0.0.1 - - [01/Jan/1970:00:00:00 +0000] \"GET /say \\\"hi\\\" HTTP/1.1\" 200 6 4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert!(JsonFormat.format(&entry).ends_with(",\"trace_id\":\"4bf92f3577b34da6a3ce929d0e0e4736\"}"));
+    }
+
+    #[test]
+    fn record_response() {
+        let entries = Arc::new(Mutex::new(vec![]));
+        let log_entries = entries.clone();
+        let log = AccessLog::with_sink(
+            |_context: Context, mut response: Response| {
+                response.set_status(StatusCode::Created);
+                response.send("created");
+            },
+            move |entry: &Entry| log_entries.lock().unwrap().push((entry.address, entry.status, entry.bytes, entry.trace_id))
+        );
+
+        let global = Global::default();
+        let trace = TraceContext::new();
+        let mut context = Context::mock(Method::Post, "/things", Headers::new(), &global);
+        context.extensions.insert(trace.clone());
+        context.client_address = "192.0.2.60".parse().unwrap();
+
+        let result = log.handle_request(Environment {
+            context: context,
+            response: Response::mock(&global),
+            route_state: "/things".into(),
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(*entries.lock().unwrap(), vec![("192.0.2.60".parse().unwrap(), Some(StatusCode::Created), 7, Some(trace.trace_id))]);
+    }
+}
@@ -0,0 +1,197 @@
+//!Serving-side support for ACME certificates, as from Let's Encrypt.
+//!
+//!The ACME client itself is left to other crates, but this module has the
+//!parts that the server needs, with the `acme` feature. A
+//![`Challenges`][challenges] store is shared with the client, which adds
+//!the HTTP-01 challenge tokens to it, and it's also the handler that
+//!answers the challenges. A [`CertificateStore`][certificate_store] is
+//!where the client puts renewed certificates, and
+//![`Certificates`][certificates] lets the server pick them up for new
+//!connections:
+//!
+//!```no_run
+//!# use rustful::{Server, Context, Response, Handler, DefaultRouter, HttpResult};
+//!# use rustful::server::Listeners;
+//!# use rustful::net::{Reloadable, SslServer, HttpStream};
+//!use rustful::acme::{Challenges, Certificates};
+//!# #[derive(Clone)]
+//!# struct MyTls;
+//!# impl SslServer for MyTls {
+//!#     type Stream = HttpStream;
+//!#     fn wrap_server(&self, stream: HttpStream) -> HttpResult<HttpStream> { Ok(stream) }
+//!# }
+//!# fn load_certificate() -> MyTls { MyTls }
+//!
+//!fn say_hello(_context: Context, response: Response) {
+//!    response.send("Hello!");
+//!}
+//!
+//!# fn start() -> HttpResult<Listeners<DefaultRouter<Box<Handler>>>> {
+//!let challenges = Challenges::new();
+//!let tls = Reloadable::new(load_certificate());
+//!
+//!let mut router = DefaultRouter::<Box<Handler>>::new();
+//!router.build().many(|mut node| {
+//!    node.path(".well-known/acme-challenge/:token").then().on_get(Box::new(challenges.clone()) as Box<Handler>);
+//!    node.path("hello").then().on_get(Box::new(say_hello as fn(Context, Response)));
+//!});
+//!
+//!let mut listeners = Server::new(router).listeners()?;
+//!listeners
+//!    .http(80.into())?
+//!    .https(443.into(), Certificates::new(tls.clone()))?;
+//!
+//!//Give challenges and tls to the ACME client...
+//!# Ok(listeners)
+//!# }
+//!```
+//!
+//![challenges]: struct.Challenges.html
+//![certificate_store]: trait.CertificateStore.html
+//![certificates]: struct.Certificates.html
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use StatusCode;
+use HttpResult;
+use header::ContentType;
+use mime::{Mime, TopLevel, SubLevel};
+use context::Context;
+use handler::Handler;
+use response::Response;
+use net::{SslServer, HttpStream, Reloadable};
+
+///A shared store for HTTP-01 challenge tokens, and a handler that answers
+///the challenges.
+///
+///The token is taken from the route variable `token`, or from the last
+///path segment if there's no such variable. Unknown tokens are `404 Not
+///Found`. Every copy shares the same tokens.
+#[derive(Clone, Default)]
+pub struct Challenges {
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Challenges {
+    ///Create an empty store.
+    pub fn new() -> Challenges {
+        Challenges::default()
+    }
+
+    ///Answer requests for `token` with `key_authorization`.
+    pub fn insert<T: Into<String>, K: Into<String>>(&self, token: T, key_authorization: K) {
+        self.tokens.write().unwrap_or_else(|e| e.into_inner()).insert(token.into(), key_authorization.into());
+    }
+
+    ///Stop answering requests for `token`, when the challenge is done.
+    pub fn remove(&self, token: &str) -> Option<String> {
+        self.tokens.write().unwrap_or_else(|e| e.into_inner()).remove(token)
+    }
+
+    ///Get the key authorization for `token`.
+    pub fn get(&self, token: &str) -> Option<String> {
+        self.tokens.read().unwrap_or_else(|e| e.into_inner()).get(token).cloned()
+    }
+}
+
+impl Handler for Challenges {
+    fn handle(&self, context: Context, mut response: Response) {
+        let token = match context.variables.get("token") {
+            Some(token) => token.into_owned(),
+            None => context.uri_path.as_utf8_path_lossy()
+                .and_then(|path| path.rsplit('/').next().map(ToOwned::to_owned))
+                .unwrap_or_default()
+        };
+
+        match self.get(&token) {
+            Some(key_authorization) => {
+                response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![])));
+                response.send(key_authorization);
+            },
+            None => response.set_status(StatusCode::NotFound)
+        }
+    }
+}
+
+///Where renewed certificates are kept, as TLS backends.
+pub trait CertificateStore: Send + Sync + 'static {
+    ///The TLS backend, with a loaded certificate.
+    type Ssl: SslServer;
+
+    ///Get the backend with the most recent certificate.
+    fn current(&self) -> Self::Ssl;
+}
+
+impl<S: SslServer + Clone + Send + Sync + 'static> CertificateStore for Reloadable<S> {
+    type Ssl = S;
+
+    fn current(&self) -> S {
+        Reloadable::current(self)
+    }
+}
+
+///An `SslServer` that uses the most recent certificate from a
+///`CertificateStore` for each new connection.
+pub struct Certificates<C> {
+    store: Arc<C>,
+}
+
+impl<C: CertificateStore> Certificates<C> {
+    ///Read the certificates from `store`.
+    pub fn new(store: C) -> Certificates<C> {
+        Certificates {
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl<C> Clone for Certificates<C> {
+    fn clone(&self) -> Certificates<C> {
+        Certificates {
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<C: CertificateStore> SslServer for Certificates<C> {
+    type Stream = <C::Ssl as SslServer>::Stream;
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<Self::Stream> {
+        self.store.current().wrap_server(stream)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::method::Method;
+
+    use StatusCode;
+    use header::Headers;
+    use context::Context;
+    use response::Response;
+    use server::Global;
+    use handler::Handler;
+    use super::Challenges;
+
+    #[test]
+    fn answer_challenges() {
+        let challenges = Challenges::new();
+        challenges.clone().insert("abc", "abc.key");
+
+        let global = Global::default();
+        let context = Context::mock(Method::Get, "/.well-known/acme-challenge/abc", Headers::new(), &global);
+        let response = Response::mock(&global);
+        let status = response.sent_status();
+        challenges.handle(context, response);
+        assert_eq!(status.get(), Some(StatusCode::Ok));
+
+        assert_eq!(challenges.remove("abc"), Some("abc.key".into()));
+
+        let context = Context::mock(Method::Get, "/.well-known/acme-challenge/abc", Headers::new(), &global);
+        let response = Response::mock(&global);
+        let status = response.sent_status();
+        challenges.handle(context, response);
+        assert_eq!(status.get(), Some(StatusCode::NotFound));
+    }
+}
@@ -0,0 +1,327 @@
+//!Content addressed blob serving.
+//!
+//![`Blobs`][blobs] is a handler that serves blobs by their SHA-256 digest,
+//!as in `/blobs/:sha256`, from a [`BlobStore`][blob_store]. The content of a
+//!blob can't change without changing its address, so the responses are
+//!marked as immutable and the digest is used as the entity tag. Conditional
+//!requests with `If-None-Match` and single range requests are supported.
+//!
+//!```no_run
+//!use rustful::{Server, Handler, DefaultRouter};
+//!use rustful::blob::{Blobs, Directory};
+//!
+//!let blobs = Blobs::new(Directory::new("path/to/blobs"));
+//!
+//!let mut router = DefaultRouter::<Box<Handler>>::new();
+//!router.build().path("blobs/:sha256").then().on_get(Box::new(blobs) as Box<Handler>);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!The digest of a whole blob is verified while it's sent. The last part of
+//!the body is held back if it doesn't match, so the client will get a
+//!response that is cut short instead of corrupted data. Partial responses
+//!can't be verified without reading the whole blob, so they are sent as
+//!they are.
+//!
+//![blobs]: struct.Blobs.html
+//![blob_store]: trait.BlobStore.html
+
+use std::fs::File;
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use StatusCode;
+use header::{
+    ETag, EntityTag, IfNoneMatch, IfRange, Range, ByteRangeSpec, ContentRange,
+    ContentRangeSpec, AcceptRanges, RangeUnit, CacheControl, CacheDirective, ContentType
+};
+use mime::{Mime, TopLevel, SubLevel};
+use sha2::{Sha256, Digest};
+use context::Context;
+use handler::Handler;
+use response::{Response, NotModified};
+use utils::to_hex;
+
+///Storage for blobs, addressed by their SHA-256 digest.
+pub trait BlobStore: Send + Sync + 'static {
+    ///A reader for the content of a blob.
+    type Blob: Read + Seek;
+
+    ///Open the blob with the lowercase, hexadecimal digest `hash`. A missing
+    ///blob is `Ok(None)`.
+    fn open(&self, hash: &str) -> io::Result<Option<Self::Blob>>;
+}
+
+///Blobs that are stored as files in a directory, named by their
+///lowercase, hexadecimal digest.
+#[derive(Clone, Debug)]
+pub struct Directory {
+    path: PathBuf,
+}
+
+impl Directory {
+    ///Use the blobs in `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Directory {
+        Directory {
+            path: path.into(),
+        }
+    }
+}
+
+impl BlobStore for Directory {
+    type Blob = File;
+
+    fn open(&self, hash: &str) -> io::Result<Option<File>> {
+        match File::open(self.path.join(hash)) {
+            Ok(file) => Ok(Some(file)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+///A handler that serves blobs from a `BlobStore`.
+///
+///The digest is taken from the route variable `sha256`, by default, and
+///anything that isn't a SHA-256 digest is `404 Not Found`.
+pub struct Blobs<S> {
+    store: S,
+    variable: String,
+    content_type: Mime,
+}
+
+impl<S: BlobStore> Blobs<S> {
+    ///Serve blobs from `store`.
+    pub fn new(store: S) -> Blobs<S> {
+        Blobs {
+            store: store,
+            variable: "sha256".into(),
+            content_type: Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]),
+        }
+    }
+
+    ///Take the digest from the route variable `name`.
+    pub fn variable<N: Into<String>>(mut self, name: N) -> Blobs<S> {
+        self.variable = name.into();
+        self
+    }
+
+    ///Set the `Content-Type` of the blobs. Default is
+    ///`application/octet-stream`.
+    pub fn content_type(mut self, content_type: Mime) -> Blobs<S> {
+        self.content_type = content_type;
+        self
+    }
+}
+
+impl<S: BlobStore> Handler for Blobs<S> {
+    fn handle(&self, context: Context, mut response: Response) {
+        let hash = match context.variables.get(&*self.variable) {
+            Some(ref hash) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => hash.to_lowercase(),
+            _ => {
+                response.set_status(StatusCode::NotFound);
+                return;
+            }
+        };
+
+        let tag = EntityTag::strong(hash.clone());
+        response.headers_mut().set(ETag(tag.clone()));
+        response.headers_mut().set(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(31536000),
+            CacheDirective::Extension("immutable".into(), None)
+        ]));
+
+        let not_modified = match context.headers.get::<IfNoneMatch>() {
+            Some(&IfNoneMatch::Any) => true,
+            Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|other| other.weak_eq(&tag)),
+            None => false
+        };
+
+        if not_modified {
+            return response.send(NotModified);
+        }
+
+        let mut blob = match self.store.open(&hash) {
+            Ok(Some(blob)) => blob,
+            Ok(None) => {
+                response.set_status(StatusCode::NotFound);
+                return;
+            },
+            Err(e) => {
+                error!("failed to open blob {}: {}", hash, e);
+                response.set_status(StatusCode::InternalServerError);
+                return;
+            }
+        };
+
+        let length = match blob.seek(SeekFrom::End(0)) {
+            Ok(length) => length,
+            Err(e) => {
+                error!("failed to read blob {}: {}", hash, e);
+                response.set_status(StatusCode::InternalServerError);
+                return;
+            }
+        };
+
+        let use_range = match context.headers.get::<IfRange>() {
+            Some(&IfRange::EntityTag(ref other)) => other.strong_eq(&tag),
+            Some(&IfRange::Date(_)) => false,
+            None => true
+        };
+
+        let range = match context.headers.get::<Range>() {
+            Some(&Range::Bytes(ref specs)) if use_range && specs.len() == 1 => Some(byte_range(&specs[0], length)),
+            _ => None
+        };
+
+        response.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+        response.headers_mut().set(ContentType(self.content_type.clone()));
+
+        let (start, end) = match range {
+            Some(Some(range)) => range,
+            Some(None) => {
+                response.set_status(StatusCode::RangeNotSatisfiable);
+                response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(length)
+                }));
+                return;
+            },
+            None => (0, length.saturating_sub(1))
+        };
+
+        let partial = range.is_some();
+        let content_length = if length == 0 { 0 } else { end - start + 1 };
+
+        if partial {
+            response.set_status(StatusCode::PartialContent);
+            response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((start, end)),
+                instance_length: Some(length)
+            }));
+        }
+
+        let omit_body = response.omits_body();
+        let mut writer = unsafe { response.into_raw(content_length) };
+
+        if omit_body {
+            return;
+        }
+
+        let result = blob.seek(SeekFrom::Start(start)).and_then(|_| if partial {
+            io::copy(&mut blob.take(content_length), &mut writer).map(|_| ())
+        } else {
+            send_verified(blob, &hash, &mut writer)
+        });
+
+        if let Err(e) = result {
+            error!("failed to send blob {}: {}", hash, e);
+        }
+    }
+}
+
+//Find the first and last byte in a range, or `None` if it's not
+//satisfiable.
+fn byte_range(spec: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    if length == 0 {
+        return None;
+    }
+
+    match *spec {
+        ByteRangeSpec::FromTo(start, end) if start <= end && start < length => Some((start, ::std::cmp::min(end, length - 1))),
+        ByteRangeSpec::AllFrom(start) if start < length => Some((start, length - 1)),
+        ByteRangeSpec::Last(count) if count > 0 => Some((length.saturating_sub(count), length - 1)),
+        _ => None
+    }
+}
+
+//Copy the blob to the writer, but hold back the last chunk until the
+//digest has been checked.
+fn send_verified<R: Read, W: Write>(mut blob: R, hash: &str, writer: &mut W) -> io::Result<()> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 8192];
+    let mut pending = Vec::with_capacity(buffer.len());
+
+    loop {
+        let length = match blob.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => length,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+
+        writer.write_all(&pending)?;
+        pending.clear();
+        hasher.update(&buffer[..length]);
+        pending.extend_from_slice(&buffer[..length]);
+    }
+
+    if to_hex(&hasher.finalize()) == hash {
+        writer.write_all(&pending)
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "the blob doesn't match its digest"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sha2::{Sha256, Digest};
+    use {Context, Method, StatusCode};
+    use header::{Headers, ByteRangeSpec, ContentLength, ETag, EntityTag, IfNoneMatch};
+    use server::Global;
+    use testing::Recorder;
+    use utils::to_hex;
+    use super::{Blobs, Directory, byte_range, send_verified};
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(byte_range(&ByteRangeSpec::FromTo(0, 9), 100), Some((0, 9)));
+        assert_eq!(byte_range(&ByteRangeSpec::FromTo(90, 200), 100), Some((90, 99)));
+        assert_eq!(byte_range(&ByteRangeSpec::FromTo(100, 200), 100), None);
+        assert_eq!(byte_range(&ByteRangeSpec::AllFrom(10), 100), Some((10, 99)));
+        assert_eq!(byte_range(&ByteRangeSpec::Last(10), 100), Some((90, 99)));
+        assert_eq!(byte_range(&ByteRangeSpec::Last(200), 100), Some((0, 99)));
+        assert_eq!(byte_range(&ByteRangeSpec::Last(0), 100), None);
+        assert_eq!(byte_range(&ByteRangeSpec::AllFrom(0), 0), None);
+    }
+
+    #[test]
+    fn verified_blobs() {
+        let data: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+        let hash = to_hex(&Sha256::digest(&data));
+
+        let mut output = vec![];
+        assert!(send_verified(&data[..], &hash, &mut output).is_ok());
+        assert_eq!(output, data);
+
+        let mut output = vec![];
+        assert!(send_verified(&data[1..], &hash, &mut output).is_err());
+        assert!(output.len() < data.len() - 1);
+    }
+
+    #[test]
+    fn not_modified() {
+        let hash = to_hex(&Sha256::digest(b"blob"));
+        let tag = EntityTag::strong(hash.clone());
+        let blobs = Blobs::new(Directory::new("missing"));
+
+        let mut headers = Headers::new();
+        headers.set(IfNoneMatch::Items(vec![tag.clone()]));
+
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", headers, &global);
+        context.variables.insert("sha256", hash);
+
+        let recorded = Recorder::new().run(&blobs, context);
+        assert_eq!(recorded.status, StatusCode::NotModified);
+        assert_eq!(recorded.headers.get(), Some(&ETag(tag)));
+        assert_eq!(recorded.headers.get::<ContentLength>(), None);
+        assert!(recorded.headers.get_raw("transfer-encoding").is_none());
+        assert!(recorded.body.is_empty());
+    }
+}
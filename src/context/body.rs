@@ -1,20 +1,46 @@
 //!Anything related to reading the request body.
 
 #[cfg(feature = "multipart")]
-use multipart::server::{HttpRequest, Multipart};
+use multipart::server::{HttpRequest, Multipart, MultipartField};
 
-use std::io::{self, Read};
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "json")]
+use serde_json;
 
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use std::{error, fmt, mem};
+#[cfg(feature = "multipart")]
+use std::fs::{self, OpenOptions};
+#[cfg(feature = "multipart")]
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
 use hyper::buffer::BufReader;
 use hyper::http::h1::HttpReader;
 use hyper::net::NetworkStream;
 
 use context::Parameters;
-use header::Headers;
+use header::{Headers, ContentType};
+#[cfg(feature = "multipart")]
+use utils::random_id;
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+
+///The default size limit for `read_form`, in bytes.
+pub const FORM_SIZE_LIMIT: u64 = 1024 * 1024;
+
+///The default limit for the number of pairs in `read_form`.
+pub const FORM_PAIR_LIMIT: usize = 1000;
+
+///The default size limit for `read_json`, in bytes.
+#[cfg(feature = "json")]
+pub const JSON_SIZE_LIMIT: u64 = 1024 * 1024;
 
 ///A reader for a request body.
 pub struct BodyReader<'a, 'b: 'a> {
     reader: MaybeMock<HttpReader<&'a mut BufReader<&'b mut NetworkStream>>>,
+    content_type: Option<Mime>,
 
     #[cfg(feature = "multipart")]
     multipart_boundary: Option<String>
@@ -25,9 +51,6 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     #[cfg(feature = "multipart")]
     ///Internal and may change without warning.
     pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers) -> BodyReader<'a, 'b> {
-        use header::ContentType;
-        use mime::{Mime, TopLevel, SubLevel, Attr, Value};
-
         let boundary = match headers.get() {
             Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, ref attrs))) => {
                 attrs.iter()
@@ -43,6 +66,7 @@ impl<'a, 'b> BodyReader<'a, 'b> {
 
         BodyReader {
             reader: MaybeMock::Actual(reader),
+            content_type: headers.get::<ContentType>().map(|t| t.0.clone()),
             multipart_boundary: boundary
         }
     }
@@ -50,18 +74,16 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     #[doc(hidden)]
     #[cfg(not(feature = "multipart"))]
     ///Internal and may change without warning.
-    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, _headers: &Headers) -> BodyReader<'a, 'b> {
+    pub fn from_reader(reader: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>, headers: &Headers) -> BodyReader<'a, 'b> {
         BodyReader {
-            reader: MaybeMock::Actual(reader)
+            reader: MaybeMock::Actual(reader),
+            content_type: headers.get::<ContentType>().map(|t| t.0.clone()),
         }
     }
 
     ///Create a non-functional body reader for testing purposes.
     #[cfg(feature = "multipart")]
     pub fn mock(headers: &'b Headers) -> BodyReader<'static, 'static> {
-        use header::ContentType;
-        use mime::{Mime, TopLevel, SubLevel, Attr, Value};
-
         let boundary = match headers.get() {
             Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, ref attrs))) => {
                 attrs.iter()
@@ -77,20 +99,36 @@ impl<'a, 'b> BodyReader<'a, 'b> {
 
         BodyReader {
             reader: MaybeMock::Mock,
+            content_type: headers.get::<ContentType>().map(|t| t.0.clone()),
             multipart_boundary: boundary,
         }
     }
 
     ///Create a non-functional body reader for testing purposes.
     #[cfg(not(feature = "multipart"))]
-    pub fn mock(_headers: &'b Headers) -> BodyReader<'static, 'static> {
+    pub fn mock(headers: &'b Headers) -> BodyReader<'static, 'static> {
         BodyReader {
-            reader: MaybeMock::Mock
+            reader: MaybeMock::Mock,
+            content_type: headers.get::<ContentType>().map(|t| t.0.clone()),
         }
     }
 }
 
 impl<'a, 'b> BodyReader<'a, 'b> {
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn decode_gzip(&mut self, limit: u64) {
+        self.reader = match mem::replace(&mut self.reader, MaybeMock::Mock) {
+            MaybeMock::Actual(reader) => MaybeMock::Gzip(GzipReader::new(reader, limit)),
+            reader => reader
+        };
+    }
+
+    ///The media type of the request body, if specified.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.content_type.as_ref()
+    }
+
     ///Try to create a `multipart/form-data` reader from the request body.
     ///
     ///```
@@ -155,6 +193,49 @@ impl<'a, 'b> BodyReader<'a, 'b> {
         }
     }
 
+    ///Start reading a `multipart/form-data` request body, part by part.
+    ///Each part has a name, an optional file name and content type, and it
+    ///can be read as a stream of bytes.
+    ///
+    ///```
+    ///use std::io::Read;
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode::BadRequest;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let mut parts = match context.body.read_multipart() {
+    ///        Some(parts) => parts,
+    ///        None => return response.set_status(BadRequest)
+    ///    };
+    ///
+    ///    let mut result = String::new();
+    ///    while let Ok(Some(mut part)) = parts.next_part() {
+    ///        if let Some(filename) = part.filename() {
+    ///            result.push_str(&format!("{}: a file called '{}'\n", part.name(), filename));
+    ///            continue;
+    ///        }
+    ///
+    ///        let mut text = String::new();
+    ///        if part.read_to_string(&mut text).is_ok() {
+    ///            result.push_str(&format!("{}: '{}'\n", part.name(), text));
+    ///        }
+    ///    }
+    ///
+    ///    response.send(result);
+    ///}
+    ///```
+    ///
+    ///Uploaded files can also be stored directly in a directory, using
+    ///[`MultipartParts::save_to`][save_to].
+    ///
+    ///[save_to]: struct.MultipartParts.html#method.save_to
+    #[cfg(feature = "multipart")]
+    pub fn read_multipart<'r>(&'r mut self) -> Option<MultipartParts<'r, 'a, 'b>> {
+        self.as_multipart().map(|multipart| MultipartParts {
+            multipart: multipart
+        })
+    }
+
     ///Read and parse the request body as a query string. The body will be
     ///decoded as UTF-8 and plain '+' characters will be replaced with spaces.
     ///
@@ -177,12 +258,235 @@ impl<'a, 'b> BodyReader<'a, 'b> {
     #[inline]
     pub fn read_query_body(&mut self) -> io::Result<Parameters> {
         let mut buf = Vec::new();
-        try!(self.read_to_end(&mut buf));
+        self.read_to_end(&mut buf)?;
         Ok(::utils::parse_parameters(&buf))
     }
 
+    ///Read and parse an `application/x-www-form-urlencoded` request body,
+    ///with a size limit of `FORM_SIZE_LIMIT` bytes and at most
+    ///`FORM_PAIR_LIMIT` pairs.
+    ///
+    ///The `charset` parameter of the media type is respected, and the
+    ///values are converted from ISO-8859-1 (Latin-1) or US-ASCII to UTF-8.
+    ///UTF-8 is assumed if it's missing. Any other charset is an error. Later
+    ///pairs replace earlier pairs with the same name, just like in
+    ///`context.query`.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///use rustful::context::body::FormError;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.body.read_form() {
+    ///        Ok(form) => response.send(format!("hello, {}", form.get("name").unwrap_or_default())),
+    ///        Err(FormError::ContentType(_)) | Err(FormError::Charset(_)) => response.set_status(StatusCode::UnsupportedMediaType),
+    ///        Err(FormError::TooLarge) | Err(FormError::TooManyPairs) => response.set_status(StatusCode::PayloadTooLarge),
+    ///        Err(FormError::Io(_)) => response.set_status(StatusCode::BadRequest),
+    ///    }
+    ///}
+    ///```
+    pub fn read_form(&mut self) -> Result<Parameters, FormError> {
+        self.read_form_limited(FORM_SIZE_LIMIT, FORM_PAIR_LIMIT)
+    }
+
+    ///Read and parse an `application/x-www-form-urlencoded` request body,
+    ///with a custom size limit in bytes and a custom limit for the number
+    ///of pairs. See `read_form` for more details.
+    pub fn read_form_limited(&mut self, max_size: u64, max_pairs: usize) -> Result<Parameters, FormError> {
+        let latin1 = match self.content_type {
+            Some(Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, ref attrs)) => is_latin1(attrs)?,
+            ref other => return Err(FormError::ContentType(other.clone()))
+        };
+
+        let mut buf = Vec::new();
+        match self.by_ref().take(max_size + 1).read_to_end(&mut buf) {
+            Ok(_) => {},
+            Err(ref e) if is_too_large(e) => return Err(FormError::TooLarge),
+            Err(e) => return Err(e.into())
+        }
+
+        if buf.len() as u64 > max_size {
+            return Err(FormError::TooLarge);
+        }
+
+        parse_form(&buf, latin1, max_pairs)
+    }
+
+    ///Read the whole request body once, while writing it to each of the
+    ///`sinks`, in order. This makes it possible to, for example, store,
+    ///hash and measure an upload at the same time, without buffering it.
+    ///The total number of bytes is returned, and the first error from
+    ///either the body or a sink will abort the process.
+    ///
+    ///```
+    ///use std::fs::File;
+    ///use std::io::{self, Write};
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///
+    /////A simple checksum, as a stand-in for a real hash function
+    ///struct Checksum(u32);
+    ///
+    ///impl Write for Checksum {
+    ///    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///        for &byte in buf {
+    ///            self.0 = self.0.wrapping_mul(31).wrapping_add(byte as u32);
+    ///        }
+    ///        Ok(buf.len())
+    ///    }
+    ///
+    ///    fn flush(&mut self) -> io::Result<()> {
+    ///        Ok(())
+    ///    }
+    ///}
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let mut file = match File::create("upload.bin") {
+    ///        Ok(file) => file,
+    ///        Err(_) => return response.set_status(StatusCode::InternalServerError)
+    ///    };
+    ///    let mut checksum = Checksum(0);
+    ///
+    ///    match context.body.mirror_to(&mut [&mut file, &mut checksum]) {
+    ///        Ok(size) => response.send(format!("stored {} bytes with checksum {:x}", size, checksum.0)),
+    ///        Err(_) => response.set_status(StatusCode::InternalServerError)
+    ///    }
+    ///}
+    ///```
+    pub fn mirror_to(&mut self, sinks: &mut [&mut Write]) -> io::Result<u64> {
+        let mut buf = [0; 8 * 1024];
+        let mut total = 0;
+
+        loop {
+            let length = match self.read(&mut buf) {
+                Ok(0) => break,
+                Ok(length) => length,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+
+            for sink in sinks.iter_mut() {
+                sink.write_all(&buf[..length])?;
+            }
+
+            total += length as u64;
+        }
+
+        for sink in sinks.iter_mut() {
+            sink.flush()?;
+        }
+
+        Ok(total)
     }
 
+    ///Read the body in chunks of at most `max_size` bytes. Each chunk is
+    ///yielded as soon as some data has arrived, instead of waiting for a
+    ///full buffer, so the body can be processed while it's uploaded.
+    ///
+    ///A deadline can be set with `Chunks::timeout` or `Chunks::deadline`,
+    ///to not wait forever for slow clients. The connection's read timeout
+    ///is then shortened to the remaining time before each read, and it
+    ///stays like that for the rest of the request.
+    ///
+    ///```
+    ///use std::time::Duration;
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    let mut size = 0;
+    ///
+    ///    for chunk in context.body.chunks(16 * 1024).timeout(Duration::from_secs(10)) {
+    ///        match chunk {
+    ///            Ok(chunk) => size += chunk.len(),
+    ///            Err(_) => return response.set_status(StatusCode::RequestTimeout)
+    ///        }
+    ///    }
+    ///
+    ///    response.send(format!("received {} bytes", size));
+    ///}
+    ///```
+    ///
+    ///# Panics
+    ///
+    ///Panics if `max_size` is `0`.
+    pub fn chunks<'r>(&'r mut self, max_size: usize) -> Chunks<'r, 'a, 'b> {
+        assert!(max_size > 0, "the chunk size has to be greater than 0");
+
+        Chunks {
+            reader: self,
+            max_size: max_size,
+            deadline: None,
+            done: false,
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self.reader {
+            MaybeMock::Actual(ref reader) => reader.get_ref().get_ref().set_read_timeout(timeout),
+            MaybeMock::Gzip(ref reader) => reader.decoder.get_ref().get_ref().get_ref().set_read_timeout(timeout),
+            MaybeMock::Mock => Ok(())
+        }
+    }
+
+    ///Read and deserialize the request body as JSON, with a size limit of
+    ///`JSON_SIZE_LIMIT` bytes. The media type has to be `application/json`,
+    ///or end with `+json`.
+    ///
+    ///```
+    ///# extern crate rustful;
+    ///#[macro_use] extern crate serde_derive;
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///use rustful::context::body::JsonError;
+    ///
+    ///#[derive(Deserialize)]
+    ///struct Order {
+    ///    item: String,
+    ///    amount: u32,
+    ///}
+    ///
+    ///fn my_handler(mut context: Context, mut response: Response) {
+    ///    match context.body.read_json::<Order>() {
+    ///        Ok(order) => response.send(format!("ordered {} x {}", order.amount, order.item)),
+    ///        Err(JsonError::ContentType(_)) => response.set_status(StatusCode::UnsupportedMediaType),
+    ///        Err(JsonError::TooLarge) => response.set_status(StatusCode::PayloadTooLarge),
+    ///        Err(_) => response.set_status(StatusCode::BadRequest),
+    ///    }
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "json")]
+    pub fn read_json<T: DeserializeOwned>(&mut self) -> Result<T, JsonError> {
+        self.read_json_limited(JSON_SIZE_LIMIT)
+    }
+
+    ///Read and deserialize the request body as JSON, with a custom size
+    ///limit in bytes. See `read_json` for more details.
+    #[cfg(feature = "json")]
+    pub fn read_json_limited<T: DeserializeOwned>(&mut self, limit: u64) -> Result<T, JsonError> {
+        match self.content_type {
+            Some(Mime(TopLevel::Application, SubLevel::Json, _)) => {},
+            Some(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _)) if sub.ends_with("+json") => {},
+            ref other => return Err(JsonError::ContentType(other.clone()))
+        }
+
+        let mut buf = Vec::new();
+        match self.by_ref().take(limit + 1).read_to_end(&mut buf) {
+            Ok(_) => {},
+            Err(ref e) if is_too_large(e) => return Err(JsonError::TooLarge),
+            Err(e) => return Err(e.into())
+        }
+
+        if buf.len() as u64 > limit {
+            return Err(JsonError::TooLarge);
+        }
+
+        serde_json::from_slice(&buf).map_err(JsonError::Parse)
+    }
+}
+
 impl<'a, 'b> Read for BodyReader<'a, 'b> {
     ///Read the request body.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -190,6 +494,78 @@ impl<'a, 'b> Read for BodyReader<'a, 'b> {
     }
 }
 
+///An iterator over chunks of a request body. See `BodyReader::chunks`.
+pub struct Chunks<'r, 'a: 'r, 'b: 'a> {
+    reader: &'r mut BodyReader<'a, 'b>,
+    max_size: usize,
+    deadline: Option<Instant>,
+    done: bool,
+}
+
+impl<'r, 'a, 'b> Chunks<'r, 'a, 'b> {
+    ///Stop waiting for more data at `deadline`. Missing it results in a
+    ///`TimedOut` error, which ends the iteration.
+    pub fn deadline(mut self, deadline: Instant) -> Chunks<'r, 'a, 'b> {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    ///Stop waiting for more data after `timeout`, counted from now. See
+    ///`deadline` for more details.
+    pub fn timeout(self, timeout: Duration) -> Chunks<'r, 'a, 'b> {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the body deadline was reached"));
+            }
+
+            self.reader.set_read_timeout(Some(deadline - now))?;
+        }
+
+        let mut chunk = vec![0; self.max_size];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(None),
+                Ok(length) => {
+                    chunk.truncate(length);
+                    return Ok(Some(chunk));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && self.deadline.is_some() => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "the body deadline was reached"));
+                },
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+impl<'r, 'a, 'b> Iterator for Chunks<'r, 'a, 'b> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_chunk() {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 ///A specialized request representation for the multipart interface.
 #[cfg(feature = "multipart")]
 pub struct MultipartRequest<'r, 'a: 'r, 'b: 'a> {
@@ -218,17 +594,723 @@ impl<'r, 'a, 'b> Read for MultipartRequest<'r, 'a, 'b> {
     }
 }
 
+///A `multipart/form-data` request body, that is read one part at the time.
+#[cfg(feature = "multipart")]
+pub struct MultipartParts<'r, 'a: 'r, 'b: 'a> {
+    multipart: Multipart<MultipartRequest<'r, 'a, 'b>>
+}
+
+#[cfg(feature = "multipart")]
+impl<'r, 'a, 'b> MultipartParts<'r, 'a, 'b> {
+    ///Read the next part, if there are any left. Any unread data from the
+    ///previous part is skipped.
+    pub fn next_part<'m>(&'m mut self) -> io::Result<Option<Part<'m, 'r, 'a, 'b>>> {
+        self.multipart.read_entry().map(|field| field.map(|field| Part {
+            field: field
+        }))
+    }
+
+    ///Read every remaining part, while storing text fields in memory and
+    ///uploaded files in `dir`. The files are given random names, so the
+    ///file names from the client are never used for anything other than
+    ///information.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///use rustful::context::body::{UploadLimits, UploadError};
+    ///
+    ///fn upload(mut context: Context, mut response: Response) {
+    ///    let limits = UploadLimits {
+    ///        max_file_size: 10 * 1024 * 1024,
+    ///        ..UploadLimits::default()
+    ///    };
+    ///
+    ///    let result = match context.body.read_multipart() {
+    ///        Some(parts) => parts.save_to("uploads", &limits),
+    ///        None => return response.set_status(StatusCode::BadRequest)
+    ///    };
+    ///
+    ///    match result {
+    ///        Ok(form) => response.send(format!("received {} files", form.files.len())),
+    ///        Err(UploadError::Io(_)) => response.set_status(StatusCode::InternalServerError),
+    ///        Err(_) => response.set_status(StatusCode::PayloadTooLarge)
+    ///    }
+    ///}
+    ///```
+    ///
+    ///Every file that was stored before an error occurred will be removed.
+    pub fn save_to<P: AsRef<Path>>(mut self, dir: P, limits: &UploadLimits) -> Result<SavedForm, UploadError> {
+        let mut form = SavedForm {
+            fields: Parameters::new(),
+            files: vec![]
+        };
+
+        let result = self.save_parts(dir.as_ref(), limits, &mut form);
+
+        if result.is_err() {
+            for file in &form.files {
+                let _ = fs::remove_file(&file.path);
+            }
+        }
+
+        result.map(|_| form)
+    }
+
+    fn save_parts(&mut self, dir: &Path, limits: &UploadLimits, form: &mut SavedForm) -> Result<(), UploadError> {
+        while let Some(mut part) = self.next_part()? {
+            let name = part.name().to_owned();
+
+            if part.filename().is_some() {
+                if form.files.len() >= limits.max_files {
+                    return Err(UploadError::TooManyFiles);
+                }
+
+                let path = dir.join(format!("upload-{}", random_id()));
+                let mut file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+                form.files.push(SavedFile {
+                    name: name.clone(),
+                    filename: part.filename().map(ToOwned::to_owned),
+                    content_type: part.content_type(),
+                    path: path,
+                    size: 0
+                });
+
+                match copy_limited(&mut part, &mut file, limits.max_file_size)? {
+                    Some(size) => form.files.last_mut().expect("the file was just added").size = size,
+                    None => return Err(UploadError::FileTooLarge(name))
+                }
+            } else {
+                let mut value = vec![];
+                if copy_limited(&mut part, &mut value, limits.max_field_size)?.is_none() {
+                    return Err(UploadError::FieldTooLarge(name));
+                }
+                form.fields.insert(name, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///A part of a `multipart/form-data` request body. The content of the part
+///is read using the `Read` trait.
+#[cfg(feature = "multipart")]
+pub struct Part<'m, 'r: 'm, 'a: 'r, 'b: 'a> {
+    field: MultipartField<&'m mut Multipart<MultipartRequest<'r, 'a, 'b>>>
+}
+
+#[cfg(feature = "multipart")]
+impl<'m, 'r, 'a, 'b> Part<'m, 'r, 'a, 'b> {
+    ///The name of the form field.
+    pub fn name(&self) -> &str {
+        &self.field.headers.name
+    }
+
+    ///The file name, if the part is an uploaded file. This comes from the
+    ///client and should not be used as a path without sanitizing it.
+    pub fn filename(&self) -> Option<&str> {
+        self.field.headers.filename.as_ref().map(|name| &**name)
+    }
+
+    ///The media type of the part, if specified. It should be assumed to be
+    ///`text/plain` if not.
+    pub fn content_type(&self) -> Option<Mime> {
+        self.field.headers.content_type.as_ref().and_then(|mime| mime.to_string().parse().ok())
+    }
+}
+
+#[cfg(feature = "multipart")]
+impl<'m, 'r, 'a, 'b> Read for Part<'m, 'r, 'a, 'b> {
+    ///Read the content of the part.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.field.data.read(buf)
+    }
+}
+
+///Limits for `MultipartParts::save_to`.
+#[cfg(feature = "multipart")]
+#[derive(Clone, Debug)]
+pub struct UploadLimits {
+    ///The maximum size of each file, in bytes. Default is 16 MiB.
+    pub max_file_size: u64,
+
+    ///The maximum number of files. Default is 16.
+    pub max_files: usize,
+
+    ///The maximum size of each text field, in bytes. Default is 64 KiB.
+    pub max_field_size: u64
+}
+
+#[cfg(feature = "multipart")]
+impl Default for UploadLimits {
+    fn default() -> UploadLimits {
+        UploadLimits {
+            max_file_size: 16 * 1024 * 1024,
+            max_files: 16,
+            max_field_size: 64 * 1024
+        }
+    }
+}
+
+///The content of a `multipart/form-data` request body, from
+///`MultipartParts::save_to`.
+#[cfg(feature = "multipart")]
+#[derive(Clone, Debug)]
+pub struct SavedForm {
+    ///The text fields.
+    pub fields: Parameters,
+
+    ///The stored files.
+    pub files: Vec<SavedFile>
+}
+
+///An uploaded file that has been stored by `MultipartParts::save_to`.
+#[cfg(feature = "multipart")]
+#[derive(Clone, Debug)]
+pub struct SavedFile {
+    ///The name of the form field.
+    pub name: String,
+
+    ///The file name from the client, if any.
+    pub filename: Option<String>,
+
+    ///The media type from the client, if any.
+    pub content_type: Option<Mime>,
+
+    ///Where the file was stored.
+    pub path: PathBuf,
+
+    ///The size of the file, in bytes.
+    pub size: u64
+}
+
+///An error that may occur while saving a `multipart/form-data` request
+///body.
+#[cfg(feature = "multipart")]
+#[derive(Debug)]
+pub enum UploadError {
+    ///The body couldn't be read, or a file couldn't be written.
+    Io(io::Error),
+
+    ///The named file is larger than the size limit.
+    FileTooLarge(String),
+
+    ///The named text field is larger than the size limit.
+    FieldTooLarge(String),
+
+    ///There are more files than allowed.
+    TooManyFiles
+}
+
+#[cfg(feature = "multipart")]
+impl From<io::Error> for UploadError {
+    fn from(err: io::Error) -> UploadError {
+        UploadError::Io(err)
+    }
+}
+
+#[cfg(feature = "multipart")]
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UploadError::Io(ref e) => write!(f, "failed to save the upload: {}", e),
+            UploadError::FileTooLarge(ref name) => write!(f, "the file in {} is too large", name),
+            UploadError::FieldTooLarge(ref name) => write!(f, "the field {} is too large", name),
+            UploadError::TooManyFiles => f.write_str("too many files")
+        }
+    }
+}
+
+#[cfg(feature = "multipart")]
+impl error::Error for UploadError {
+    fn description(&self) -> &str {
+        match *self {
+            UploadError::Io(_) => "failed to save the upload",
+            UploadError::FileTooLarge(_) => "file too large",
+            UploadError::FieldTooLarge(_) => "field too large",
+            UploadError::TooManyFiles => "too many files"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            UploadError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "multipart")]
+fn copy_limited<R: Read, W: Write>(reader: &mut R, writer: &mut W, limit: u64) -> io::Result<Option<u64>> {
+    let size = io::copy(&mut reader.take(limit + 1), writer)?;
+    if size > limit {
+        Ok(None)
+    } else {
+        Ok(Some(size))
+    }
+}
+
+//Check if the charset is Latin-1, or an error if it's not supported
+fn is_latin1(attrs: &[(Attr, Value)]) -> Result<bool, FormError> {
+    let charset = match attrs.iter().find(|&&(ref attr, _)| attr == &Attr::Charset) {
+        Some(&(_, Value::Ext(ref charset))) => charset.to_ascii_lowercase(),
+        _ => return Ok(false)
+    };
+
+    match &*charset {
+        "utf-8" | "utf8" => Ok(false),
+        "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" | "us-ascii" | "ascii" => Ok(true),
+        _ => Err(FormError::Charset(charset))
+    }
+}
+
+fn parse_form(buf: &[u8], latin1: bool, max_pairs: usize) -> Result<Parameters, FormError> {
+    if buf.split(|&byte| byte == b'&').filter(|pair| !pair.is_empty()).count() > max_pairs {
+        return Err(FormError::TooManyPairs);
+    }
+
+    let parameters = ::utils::parse_parameters(buf);
+
+    if latin1 {
+        //Each byte is the code point of the same character
+        let to_utf8 = |text: ::context::MaybeUtf8Owned| text.as_bytes().iter().map(|&byte| byte as char).collect::<String>();
+        Ok(parameters.into_iter().map(|(name, value)| (to_utf8(name), to_utf8(value))).collect())
+    } else {
+        Ok(parameters)
+    }
+}
+
+///An error that may occur while reading a form body.
+#[derive(Debug)]
+pub enum FormError {
+    ///The request body is not marked as a form. Contains the actual media
+    ///type, if any.
+    ContentType(Option<Mime>),
+
+    ///The charset of the form is not supported. Contains the charset.
+    Charset(String),
+
+    ///The request body is larger than the size limit.
+    TooLarge,
+
+    ///The form has more pairs than allowed.
+    TooManyPairs,
+
+    ///The body could not be read.
+    Io(io::Error),
+}
+
+impl From<io::Error> for FormError {
+    fn from(err: io::Error) -> FormError {
+        FormError::Io(err)
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormError::ContentType(Some(ref mime)) => write!(f, "expected a form body, but got {}", mime),
+            FormError::ContentType(None) => write!(f, "expected a form body, but the media type is missing"),
+            FormError::Charset(ref charset) => write!(f, "unsupported form charset: {}", charset),
+            FormError::TooLarge => write!(f, "the form body is too large"),
+            FormError::TooManyPairs => write!(f, "the form has too many pairs"),
+            FormError::Io(ref e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl error::Error for FormError {
+    fn description(&self) -> &str {
+        match *self {
+            FormError::ContentType(_) => "unexpected media type",
+            FormError::Charset(_) => "unsupported charset",
+            FormError::TooLarge => "body too large",
+            FormError::TooManyPairs => "too many pairs",
+            FormError::Io(_) => "io error",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            FormError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+///An error that may occur while reading a JSON body.
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub enum JsonError {
+    ///The request body is not marked as JSON. Contains the actual media
+    ///type, if any.
+    ContentType(Option<Mime>),
+
+    ///The request body is larger than the size limit.
+    TooLarge,
+
+    ///The body could not be read.
+    Io(io::Error),
+
+    ///The body could not be parsed or deserialized.
+    Parse(serde_json::Error),
+}
+
+#[cfg(feature = "json")]
+impl From<io::Error> for JsonError {
+    fn from(err: io::Error) -> JsonError {
+        JsonError::Io(err)
+    }
+}
+
+#[cfg(feature = "json")]
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::ContentType(Some(ref mime)) => write!(f, "expected a JSON body, but got {}", mime),
+            JsonError::ContentType(None) => write!(f, "expected a JSON body, but the media type is missing"),
+            JsonError::TooLarge => write!(f, "the JSON body is too large"),
+            JsonError::Io(ref e) => write!(f, "io error: {}", e),
+            JsonError::Parse(ref e) => write!(f, "invalid JSON: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl error::Error for JsonError {
+    fn description(&self) -> &str {
+        match *self {
+            JsonError::ContentType(_) => "unexpected media type",
+            JsonError::TooLarge => "body too large",
+            JsonError::Io(_) => "io error",
+            JsonError::Parse(_) => "invalid JSON",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            JsonError::Io(ref e) => Some(e),
+            JsonError::Parse(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
 enum MaybeMock<R: Read> {
     Actual(R),
+    Gzip(GzipReader<R>),
     Mock
 }
 
 impl<R: Read> Read for MaybeMock<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if let &mut MaybeMock::Actual(ref mut reader) = self {
-            reader.read(buf)
-        } else {
-            Ok(0)
+        match *self {
+            MaybeMock::Actual(ref mut reader) => reader.read(buf),
+            MaybeMock::Gzip(ref mut reader) => reader.read(buf),
+            MaybeMock::Mock => Ok(0)
+        }
+    }
+}
+
+///The decompressed request body is larger than `Server::gzip_body_limit`.
+///
+///It's the inner error of the `io::Error` from reading the body, and
+///`read_form` and `read_json` report it as their `TooLarge` errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecompressedTooLarge;
+
+impl fmt::Display for DecompressedTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the decompressed body is too large")
+    }
+}
+
+impl error::Error for DecompressedTooLarge {
+    fn description(&self) -> &str {
+        "decompressed body too large"
+    }
+}
+
+fn is_too_large(error: &io::Error) -> bool {
+    error.get_ref().map_or(false, |error| error.is::<DecompressedTooLarge>())
+}
+
+//Decompresses a gzip stream while it's read, and fails as soon as it
+//grows beyond the limit.
+struct GzipReader<R: Read> {
+    decoder: GzDecoder<R>,
+    remaining: u64,
+}
+
+impl<R: Read> GzipReader<R> {
+    fn new(reader: R, limit: u64) -> GzipReader<R> {
+        GzipReader {
+            decoder: GzDecoder::new(reader),
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        //One extra byte is enough to know if the limit is exceeded.
+        let max_length = ::std::cmp::min(buf.len() as u64, self.remaining.saturating_add(1)) as usize;
+        let length = self.decoder.read(&mut buf[..max_length])?;
+
+        if length as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, DecompressedTooLarge));
+        }
+
+        self.remaining -= length as u64;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use mime::{Attr, Value};
+    use super::{parse_form, is_latin1, is_too_large, FormError, GzipReader};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn gunzip(data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let mut decoded = vec![];
+        GzipReader::new(data, limit).read_to_end(&mut decoded).map(|_| decoded)
+    }
+
+    //Send `body` to `handler` and return the response body.
+    fn post<H: ::Handler>(content_type: Option<&str>, body: &[u8], handler: H) -> String {
+        use Server;
+        use header::{Headers, ContentType};
+        use testing::TestClient;
+
+        let mut headers = Headers::new();
+        if let Some(content_type) = content_type {
+            headers.set(ContentType(content_type.parse().unwrap()));
+        }
+
+        let response = TestClient::new(Server::new(handler)).post("/", headers, body).unwrap();
+        response.body_utf8().unwrap().to_owned()
+    }
+
+    #[test]
+    fn mirror_body() {
+        use {Context, Response};
+
+        fn mirror(mut context: Context, response: Response) {
+            let mut first = vec![];
+            let mut second = vec![];
+            let total = context.body.mirror_to(&mut [&mut first, &mut second]).unwrap();
+            assert_eq!(first, second);
+            response.send(format!("{} {}", total, String::from_utf8(first).unwrap()));
         }
+
+        let body: String = (0..20000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        assert_eq!(post(None, body.as_bytes(), mirror), format!("20000 {}", body));
+        assert_eq!(post(None, b"", mirror), "0 ");
+    }
+
+    #[test]
+    fn body_chunks() {
+        use {Context, Response};
+
+        fn chunks(mut context: Context, response: Response) {
+            let chunks: Vec<_> = context.body.chunks(4).map(|chunk| String::from_utf8(chunk.unwrap()).unwrap()).collect();
+            response.send(chunks.join(" "));
+        }
+
+        assert_eq!(post(None, b"abcdefgh", chunks), "abcd efgh");
+        assert_eq!(post(None, b"abcdefghij", chunks), "abcd efgh ij");
+        assert_eq!(post(None, b"abc", chunks), "abc");
+        assert_eq!(post(None, b"", chunks), "");
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_chunks() {
+        use {Context, Response};
+
+        fn chunks(mut context: Context, _response: Response) {
+            context.body.chunks(0);
+        }
+
+        post(None, b"abc", chunks);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn read_json() {
+        use std::collections::HashMap;
+        use {Context, Response};
+        use context::body::JsonError;
+
+        fn read(mut context: Context, response: Response) {
+            response.send(match context.body.read_json_limited::<HashMap<String, u32>>(9) {
+                Ok(map) => format!("a = {}", map["a"]),
+                Err(JsonError::ContentType(Some(mime))) => format!("content type {}", mime),
+                Err(JsonError::ContentType(None)) => "no content type".into(),
+                Err(JsonError::TooLarge) => "too large".into(),
+                Err(JsonError::Parse(_)) => "parse error".into(),
+                Err(JsonError::Io(_)) => "io error".into(),
+            });
+        }
+
+        assert_eq!(post(Some("application/json"), b"{\"a\": 10}", read), "a = 10");
+        assert_eq!(post(Some("application/vnd.api+json"), b"{\"a\": 1}", read), "a = 1");
+        assert_eq!(post(Some("text/plain"), b"{\"a\": 1}", read), "content type text/plain");
+        assert_eq!(post(Some("application/jsonx"), b"{\"a\": 1}", read), "content type application/jsonx");
+        assert_eq!(post(None, b"{\"a\": 1}", read), "no content type");
+        assert_eq!(post(Some("application/json"), b"{\"a\": 100}", read), "too large");
+        assert_eq!(post(Some("application/json"), b"{\"a\": -1}", read), "parse error");
+        assert_eq!(post(Some("application/json"), b"{\"a\": 1", read), "parse error");
+    }
+
+    #[test]
+    fn gzip_limits() {
+        let data = vec![b'a'; 100_000];
+        let compressed = gzip(&data);
+
+        assert_eq!(gunzip(&compressed, 100_000).unwrap(), data);
+        assert!(is_too_large(&gunzip(&compressed, 99_999).unwrap_err()));
+
+        //The limit is hit in the middle of the first block
+        let mut decoded = vec![];
+        let error = GzipReader::new(&compressed[..], 1000).read_to_end(&mut decoded).unwrap_err();
+        assert!(is_too_large(&error));
+        assert!(decoded.len() <= 1000);
+    }
+
+    #[test]
+    fn invalid_gzip() {
+        let compressed = gzip(b"hello world");
+        let length = compressed.len();
+
+        assert!(gunzip(b"hello world", 100).is_err());
+        assert!(gunzip(&compressed[..length - 4], 100).is_err());
+
+        let mut bad_crc = compressed.clone();
+        bad_crc[length - 8] ^= 1;
+        assert!(gunzip(&bad_crc, 100).is_err());
+
+        let mut bad_size = compressed.clone();
+        bad_size[length - 4] ^= 1;
+        assert!(gunzip(&bad_size, 100).is_err());
+
+        //A block with the reserved block type
+        let mut bad_block = compressed.clone();
+        bad_block[10] |= 0b110;
+        let error = gunzip(&bad_block, 100).unwrap_err();
+        assert!(!is_too_large(&error));
+    }
+
+    #[test]
+    fn form_charsets() {
+        let form = parse_form(b"name=%E5sa&name=%C5sa&x", true, 3).unwrap();
+        assert_eq!(form.get("name"), Some("Åsa".into()));
+        assert_eq!(form.get("x"), Some("".into()));
+
+        let form = parse_form(b"name=%C3%85sa", false, 3).unwrap();
+        assert_eq!(form.get("name"), Some("Åsa".into()));
+
+        match parse_form(b"a=1&b=2&&c=3", false, 2) {
+            Err(FormError::TooManyPairs) => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+
+        assert_eq!(is_latin1(&[]).ok(), Some(false));
+        assert_eq!(is_latin1(&[(Attr::Charset, Value::Utf8)]).ok(), Some(false));
+        assert_eq!(is_latin1(&[(Attr::Charset, Value::Ext("ISO-8859-1".into()))]).ok(), Some(true));
+        assert!(is_latin1(&[(Attr::Charset, Value::Ext("utf-16".into()))]).is_err());
+    }
+
+    #[cfg(feature = "multipart")]
+    fn save_multipart(parts: &[(&str, Option<&str>, &str)], limits: ::context::body::UploadLimits) -> (String, Vec<::std::path::PathBuf>) {
+        use std::env;
+        use std::fs;
+        use {Server, Context, Response};
+        use header::{Headers, ContentType};
+        use testing::TestClient;
+
+        let dir = env::temp_dir().join(format!("rustful-upload-{}", ::utils::random_u64()));
+        fs::create_dir(&dir).unwrap();
+
+        let mut body = String::new();
+        for &(name, filename, content) in parts {
+            body.push_str("--BOUNDARY\r\nContent-Disposition: form-data; name=\"");
+            body.push_str(name);
+            if let Some(filename) = filename {
+                body.push_str("\"; filename=\"");
+                body.push_str(filename);
+                body.push_str("\"\r\nContent-Type: text/plain");
+            } else {
+                body.push('"');
+            }
+            body.push_str("\r\n\r\n");
+            body.push_str(content);
+            body.push_str("\r\n");
+        }
+        body.push_str("--BOUNDARY--\r\n");
+
+        let upload_dir = dir.clone();
+        let client = TestClient::new(Server::new(move |mut context: Context, response: Response| {
+            let result = context.body.read_multipart().expect("not a multipart body").save_to(&upload_dir, &limits);
+            response.send(match result {
+                Ok(form) => {
+                    let mut saved: Vec<_> = form.fields.into_iter().map(|(name, value)| format!("{}={}", name.as_utf8_lossy(), value.as_utf8_lossy())).collect();
+                    for file in form.files {
+                        let content = fs::read_to_string(&file.path).unwrap();
+                        saved.push(format!("{}:{}={} ({} bytes)", file.name, file.filename.unwrap_or_default(), content, file.size));
+                    }
+                    saved.join(", ")
+                },
+                Err(e) => format!("{:?}", e)
+            });
+        }));
+
+        let mut headers = Headers::new();
+        headers.set(ContentType("multipart/form-data; boundary=BOUNDARY".parse().unwrap()));
+        let response = client.post("/", headers, body.as_bytes()).unwrap();
+
+        let files = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        (response.body_utf8().unwrap().to_owned(), files)
+    }
+
+    #[test]
+    #[cfg(feature = "multipart")]
+    fn save_multipart_parts() {
+        use context::body::UploadLimits;
+
+        let limits = UploadLimits {
+            max_file_size: 5,
+            max_files: 2,
+            max_field_size: 5,
+        };
+
+        let (result, files) = save_multipart(&[("title", None, "hi"), ("file", Some("a.txt"), "hello")], limits.clone());
+        assert_eq!(result, "title=hi, file:a.txt=hello (5 bytes)");
+        assert_eq!(files.len(), 1);
+
+        let (result, files) = save_multipart(&[("first", Some("a.txt"), "abc"), ("second", Some("b.txt"), "abcdef")], limits.clone());
+        assert_eq!(result, "FileTooLarge(\"second\")");
+        assert!(files.is_empty());
+
+        let parts = [("first", Some("a.txt"), "a"), ("second", Some("b.txt"), "b"), ("third", Some("c.txt"), "c")];
+        let (result, files) = save_multipart(&parts, limits.clone());
+        assert_eq!(result, "TooManyFiles");
+        assert!(files.is_empty());
+
+        let (result, files) = save_multipart(&[("file", Some("a.txt"), "abc"), ("title", None, "too long")], limits);
+        assert_eq!(result, "FieldTooLarge(\"title\")");
+        assert!(files.is_empty());
     }
 }
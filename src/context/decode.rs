@@ -0,0 +1,215 @@
+//A serde deserializer for `Parameters`, where each entry is a field.
+
+use std::borrow::Cow;
+use std::collections::hash_map;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, Visitor, MapAccess, SeqAccess, DeserializeSeed, IntoDeserializer};
+
+use context::{Parameters, MaybeUtf8Owned, VariableError};
+
+impl de::Error for VariableError {
+    fn custom<T: Display>(message: T) -> VariableError {
+        VariableError::Invalid(String::new(), message.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> VariableError {
+        VariableError::Missing(field.into())
+    }
+}
+
+//Add the parameter name to errors that don't have it
+fn named(name: &str, error: VariableError) -> VariableError {
+    match error {
+        VariableError::Invalid(ref current, ref message) if current.is_empty() => VariableError::Invalid(name.into(), message.clone()),
+        error => error
+    }
+}
+
+pub struct ParametersDeserializer<'a>(pub &'a Parameters);
+
+impl<'de, 'a> Deserializer<'de> for ParametersDeserializer<'a> {
+    type Error = VariableError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+        visitor.visit_map(Entries {
+            entries: self.0.iter(),
+            current: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+        byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct
+        map struct enum identifier ignored_any
+    }
+}
+
+struct Entries<'a> {
+    entries: hash_map::Iter<'a, MaybeUtf8Owned, MaybeUtf8Owned>,
+    current: Option<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl<'de, 'a> MapAccess<'de> for Entries<'a> {
+    type Error = VariableError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, VariableError> {
+        match self.entries.next() {
+            Some((name, value)) => {
+                let name = name.as_utf8_lossy();
+                let key = seed.deserialize(Value {
+                    name: &name,
+                    value: name.clone(),
+                })?;
+                self.current = Some((name, value.as_utf8_lossy()));
+                Ok(Some(key))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, VariableError> {
+        let (name, value) = self.current.take().ok_or_else(|| de::Error::custom("value without a name"))?;
+        seed.deserialize(Value {
+            name: &name,
+            value: value,
+        }).map_err(|e| named(&name, e))
+    }
+}
+
+//A single value, or a comma separated list of values
+struct Value<'n, 'a> {
+    name: &'n str,
+    value: Cow<'a, str>,
+}
+
+impl<'n, 'a> Value<'n, 'a> {
+    fn parse<T: FromStr>(&self) -> Result<T, VariableError> where T::Err: Display {
+        self.value.parse().map_err(|e: T::Err| VariableError::Invalid(self.name.into(), e.to_string()))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    }
+}
+
+impl<'de, 'n, 'a> Deserializer<'de> for Value<'n, 'a> {
+    type Error = VariableError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+        visitor.visit_str(&self.value)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+        if self.value.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+        let items = if self.value.is_empty() {
+            vec![]
+        } else {
+            self.value.split(',').map(|item| item.to_owned()).collect()
+        };
+
+        visitor.visit_seq(Items {
+            name: self.name,
+            items: items.into_iter(),
+        })
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, VariableError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, VariableError> {
+        visitor.visit_enum(self.value.into_owned().into_deserializer())
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, VariableError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct Items<'n> {
+    name: &'n str,
+    items: ::std::vec::IntoIter<String>,
+}
+
+impl<'de, 'n> SeqAccess<'de> for Items<'n> {
+    type Error = VariableError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, VariableError> {
+        match self.items.next() {
+            Some(item) => seed.deserialize(Value {
+                name: self.name,
+                value: item.into(),
+            }).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use context::{Parameters, VariableError};
+
+    #[test]
+    fn decode_values() {
+        let mut parameters = Parameters::new();
+        parameters.insert("a", "1,2,3");
+        parameters.insert("b", "");
+
+        let lists: HashMap<String, Vec<u32>> = parameters.decode().unwrap();
+        assert_eq!(lists["a"], vec![1, 2, 3]);
+        assert!(lists["b"].is_empty());
+
+        let options: HashMap<String, Option<String>> = parameters.decode().unwrap();
+        assert_eq!(options["a"], Some("1,2,3".into()));
+        assert_eq!(options["b"], None);
+
+        parameters.insert("b", "x");
+        let error = parameters.decode::<HashMap<String, Vec<u32>>>().unwrap_err();
+        assert_eq!(error.name(), "b");
+        match error {
+            VariableError::Invalid(_, _) => {},
+            error => panic!("unexpected error: {}", error)
+        }
+    }
+}
@@ -109,14 +109,20 @@
 //![log]: ../log/index.html
 //![body_reader]: body/struct.BodyReader.html
 
-use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, Ipv4Addr};
 use std::fmt;
 use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use anymap::AnyMap;
 
 use HttpVersion;
 use Method;
-use header::Headers;
-use server::Global;
+use StatusCode;
+use header::{Headers, AcceptEncoding, Encoding};
+use server::{Global, GlobalData};
+use handler::{RouteConfig, RouteTemplate};
+use utils;
 
 use self::body::BodyReader;
 use self::hypermedia::Link;
@@ -128,7 +134,13 @@ mod maybe_utf8;
 pub use self::maybe_utf8::{MaybeUtf8, MaybeUtf8Owned, MaybeUtf8Slice, Buffer};
 
 mod parameters;
-pub use self::parameters::Parameters;
+pub use self::parameters::{Parameters, FromVariable, VariableError};
+
+#[cfg(feature = "decode")]
+mod decode;
+
+mod scope;
+pub use self::scope::{Scope, Task, Cancellation};
 
 ///A container for handler input, like request data and utilities.
 pub struct Context<'a, 'b: 'a, 'l, 'g> {
@@ -141,6 +153,14 @@ pub struct Context<'a, 'b: 'a, 'l, 'g> {
     ///The client address
     pub address: SocketAddr,
 
+    ///The address of the original client. This is the same as `address`,
+    ///unless the request came through one of the `Server.trusted_proxies`.
+    pub client_address: IpAddr,
+
+    ///If the client used HTTPS. This is reported by the proxy, if the
+    ///request came through one of the `Server.trusted_proxies`.
+    pub is_https: bool,
+
     ///The HTTP method.
     pub method: Method,
 
@@ -153,17 +173,40 @@ pub struct Context<'a, 'b: 'a, 'l, 'g> {
     ///Route variables.
     pub variables: Parameters,
 
+    ///Matrix parameters, such as `role=admin` in `/users;role=admin/7`, with
+    ///one set for each path segment. They are only split out of the path if
+    ///`Server.matrix_parameters` is set, and the path will then not contain
+    ///them.
+    pub matrix: Vec<Parameters>,
+
     ///Query variables from the path.
     pub query: Parameters,
 
+    ///The query string, as it was sent, without any decoding. Use
+    ///`parse_query` to decode it in another way than `query`.
+    pub raw_query: Option<String>,
+
     ///The fragment part of the URL (after #), if provided.
     pub fragment: Option<MaybeUtf8Owned>,
 
+    ///Form variables from an `application/x-www-form-urlencoded` request
+    ///body. This is only populated if `Server.form_body_limit` is set, and
+    ///the body will then already have been read.
+    pub post: Parameters,
+
     ///Globally accessible data.
     pub global: &'g Global,
 
     ///A reader for the request body.
     pub body: BodyReader<'a, 'b>,
+
+    ///Request local extensions. Context filters and routers may store
+    ///additional request information here, such as tracing data.
+    pub extensions: AnyMap,
+
+    ///When the response should be sent, at the latest. This is set if the
+    ///server has `Server.deadlines`.
+    pub deadline: Option<Instant>,
 }
 
 impl<'a, 'b, 'l, 'g> Context<'a, 'b, 'l, 'g> {
@@ -175,14 +218,21 @@ impl<'a, 'b, 'l, 'g> Context<'a, 'b, 'l, 'g> {
             headers: headers,
             http_version: HttpVersion::Http11,
             address: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 80)),
+            client_address: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+            is_https: false,
             method: method,
             uri_path: UriPath::Path(path.into().into()),
             hyperlinks: vec![],
             variables: Parameters::new(),
+            matrix: vec![],
             query: Parameters::new(),
+            raw_query: None,
             fragment: None,
+            post: Parameters::new(),
             global: global,
-            body: body
+            body: body,
+            extensions: AnyMap::new(),
+            deadline: None,
         }
     }
 
@@ -196,18 +246,201 @@ impl<'a, 'b, 'l, 'g> Context<'a, 'b, 'l, 'g> {
                 headers: self.headers,
                 http_version: self.http_version,
                 address: self.address,
+                client_address: self.client_address,
+                is_https: self.is_https,
                 method: self.method,
                 uri_path: self.uri_path,
                 hyperlinks: hyperlinks,
                 variables: self.variables,
+                matrix: self.matrix,
                 query: self.query,
+                raw_query: self.raw_query,
                 fragment: self.fragment,
+                post: self.post,
                 global: self.global,
                 body: self.body,
+                extensions: self.extensions,
+                deadline: self.deadline,
             },
             old_links
         )
     }
+
+    ///Get the time that is left until `deadline`, or `None` if there's no
+    ///deadline. It's zero when the deadline has passed.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if let Some(time_left) = context.time_left() {
+    ///        //Use time_left as the timeout when calling the upstream service...
+    ///    }
+    ///
+    ///    response.send("Hello!");
+    ///}
+    ///```
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    ///Parse the cookies from the `Cookie` header. See the
+    ///[`cookie`][cookie] module for more details.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if let Some(theme) = context.cookies().get("theme") {
+    ///        response.send(format!("using the {} theme", theme));
+    ///    } else {
+    ///        response.send("using the default theme");
+    ///    }
+    ///}
+    ///```
+    ///
+    ///[cookie]: ../cookie/index.html
+    pub fn cookies(&self) -> Parameters {
+        ::cookie::parse(&self.headers)
+    }
+
+    ///Pick the most preferred content coding from `available`, based on the
+    ///`Accept-Encoding` header. The codings in `available` should be listed
+    ///in the server's order of preference, which decides between codings
+    ///with the same quality value.
+    ///
+    ///`identity` is acceptable unless it's explicitly rejected, using
+    ///`identity;q=0`, or implicitly rejected, using `*;q=0` without an
+    ///`identity` entry. The error is always `406 Not Acceptable`, which
+    ///can be sent as it is if none of the codings are acceptable.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::header::{ContentEncoding, Encoding};
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    match context.negotiate_encoding(&[Encoding::Gzip, Encoding::Identity]) {
+    ///        Ok(Encoding::Gzip) => {
+    ///            response.headers_mut().set(ContentEncoding(vec![Encoding::Gzip]));
+    ///            //...
+    ///        },
+    ///        Ok(_) => response.send("plain text"),
+    ///        Err(status) => response.set_status(status),
+    ///    }
+    ///}
+    ///```
+    pub fn negotiate_encoding(&self, available: &[Encoding]) -> Result<Encoding, StatusCode> {
+        negotiate_encoding(&self.headers, available).cloned().ok_or(StatusCode::NotAcceptable)
+    }
+
+    ///Parse the raw query string, with or without decoding `+` as space.
+    ///The `query` field is parsed according to `Server.query_plus_as_space`,
+    ///and this is for when a handler needs the other interpretation.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    //Phone numbers may start with a literal +
+    ///    let query = context.parse_query(false);
+    ///    if let Some(number) = query.get("phone") {
+    ///        response.send(format!("calling {}", number));
+    ///    } else {
+    ///        response.send("no phone number provided");
+    ///    }
+    ///}
+    ///```
+    pub fn parse_query(&self, plus_as_space: bool) -> Parameters {
+        match self.raw_query {
+            Some(ref query) => ::utils::parse_query(query.as_bytes(), plus_as_space),
+            None => Parameters::new()
+        }
+    }
+
+    ///Borrow the application's global data of type `T`, or `None` if
+    ///there is no such value. See [`GlobalData`][global_data] for more
+    ///details.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::server::GlobalData;
+    ///
+    ///struct AppData {
+    ///    greeting: String,
+    ///}
+    ///
+    ///impl GlobalData for AppData {}
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    if let Some(data) = context.global_as::<AppData>() {
+    ///        response.send(format!("{}, world!", data.greeting));
+    ///    }
+    ///}
+    ///```
+    ///
+    ///A handler can declare `T` in its `requirements`, to make the server
+    ///refuse to start without it, instead of finding out when the first
+    ///request is handled.
+    ///
+    ///[global_data]: ../server/trait.GlobalData.html
+    pub fn global_as<T: GlobalData>(&self) -> Option<&'g T> {
+        self.global.get()
+    }
+
+    ///Borrow the state of the current worker thread, as created by
+    ///`Server::worker_init`. It's `None` if there is no such state, such as
+    ///when the context has been moved to another thread, or if it's
+    ///already borrowed.
+    pub fn worker_state<T, F: FnOnce(&mut AnyMap) -> T>(&self, f: F) -> Option<T> {
+        utils::with_worker_state(f)
+    }
+
+    ///Run parts of the request in parallel, within a [`Scope`][scope]. Every
+    ///task that is spawned from the scope is joined before this returns, so
+    ///the tasks can borrow from the context, and they are told to stop if
+    ///the client disconnects. See [`Scope`][scope] for an example.
+    ///
+    ///[scope]: struct.Scope.html
+    pub fn scope<'env, T, F>(&self, f: F) -> T where
+        F: for<'scope> FnOnce(&Scope<'scope, 'env>) -> T
+    {
+        scope::run(utils::client_socket(), f)
+    }
+
+    ///Get a route specific configuration value of type `T`, if it has been
+    ///added to the route. See [`RouteConfig`][route_config] for more
+    ///details.
+    ///
+    ///[route_config]: ../handler/struct.RouteConfig.html
+    pub fn config<T: 'static>(&self) -> Option<&T> {
+        self.extensions.get::<RouteConfig<T>>().map(RouteConfig::get)
+    }
+
+    ///Get the route pattern that matched the request, such as
+    ///`/users/:id/posts/*rest`. It's set by [`Variables`][variables], which
+    ///is part of `DefaultRouter`, and it's useful for things like metric
+    ///labels, where the raw path would give too many different values.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let route = context.route_template().unwrap_or("unknown");
+    ///    response.send(format!("handled by {}", route));
+    ///}
+    ///```
+    ///
+    ///[variables]: ../handler/struct.Variables.html
+    pub fn route_template(&self) -> Option<&str> {
+        self.extensions.get::<RouteTemplate>().map(RouteTemplate::as_str)
+    }
 }
 
 ///A URI Path that can be a path or an asterisk (`*`).
@@ -270,3 +503,97 @@ impl fmt::Display for UriPath {
         self.as_utf8_path_lossy().unwrap_or_else(|| "*".into()).fmt(f)
     }
 }
+
+fn negotiate_encoding<'e>(headers: &Headers, available: &'e [Encoding]) -> Option<&'e Encoding> {
+    let accepted = match headers.get::<AcceptEncoding>() {
+        Some(&AcceptEncoding(ref accepted)) => accepted,
+        //Anything goes, but it's safest to not encode the content
+        None => return available.iter().find(|&e| *e == Encoding::Identity).or_else(|| available.first()),
+    };
+
+    let quality = |encoding: &Encoding| {
+        let name = encoding.to_string();
+        let mut wildcard = None;
+
+        for item in accepted {
+            let item_name = item.item.to_string();
+            if item_name.eq_ignore_ascii_case(&name) {
+                return item.quality.0;
+            } else if item_name == "*" {
+                wildcard = Some(item.quality.0);
+            }
+        }
+
+        match (wildcard, encoding) {
+            (Some(quality), _) => quality,
+            (None, &Encoding::Identity) => 1,
+            (None, _) => 0,
+        }
+    };
+
+    let mut best = None;
+    for encoding in available {
+        let quality = quality(encoding);
+        if quality > 0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+            best = Some((encoding, quality));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use Method;
+    use StatusCode;
+    use header::{Headers, Encoding};
+    use server::Global;
+    use super::Context;
+
+    fn negotiate(accept_encoding: Option<&str>, available: &[Encoding]) -> Result<Encoding, StatusCode> {
+        let mut headers = Headers::new();
+        if let Some(value) = accept_encoding {
+            headers.set_raw("Accept-Encoding", vec![value.as_bytes().to_owned()]);
+        }
+
+        let global = Global::default();
+        let context = Context::mock(Method::Get, "/", headers, &global);
+        context.negotiate_encoding(available)
+    }
+
+    #[test]
+    fn prefer_highest_quality() {
+        let available = [Encoding::Gzip, Encoding::Deflate, Encoding::Identity];
+        assert_eq!(negotiate(None, &available), Ok(Encoding::Identity));
+        assert_eq!(negotiate(Some(""), &available), Ok(Encoding::Identity));
+        assert_eq!(negotiate(Some("deflate, gzip"), &available), Ok(Encoding::Gzip));
+        assert_eq!(negotiate(Some("gzip;q=0.5, deflate"), &available), Ok(Encoding::Deflate));
+        assert_eq!(negotiate(Some("*;q=0.5, identity;q=0.1"), &available), Ok(Encoding::Gzip));
+        assert_eq!(negotiate(Some("br"), &available), Ok(Encoding::Identity));
+    }
+
+    #[test]
+    fn reject_identity() {
+        let available = [Encoding::Gzip, Encoding::Identity];
+        assert_eq!(negotiate(Some("identity;q=0"), &available), Err(StatusCode::NotAcceptable));
+        assert_eq!(negotiate(Some("*;q=0"), &available), Err(StatusCode::NotAcceptable));
+        assert_eq!(negotiate(Some("*;q=0, identity"), &available), Ok(Encoding::Identity));
+        assert_eq!(negotiate(Some("gzip;q=0, *"), &available), Ok(Encoding::Identity));
+        assert_eq!(negotiate(Some("gzip, identity;q=0"), &[Encoding::Identity]), Err(StatusCode::NotAcceptable));
+    }
+
+    #[test]
+    fn time_left() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        assert_eq!(context.time_left(), None);
+
+        context.deadline = Some(Instant::now() + Duration::from_secs(60));
+        assert!(context.time_left().map_or(false, |time_left| time_left > Duration::from_secs(30)));
+
+        context.deadline = Some(Instant::now());
+        assert_eq!(context.time_left(), Some(Duration::from_secs(0)));
+    }
+}
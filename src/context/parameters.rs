@@ -5,8 +5,14 @@ use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::hash::Hash;
 use std::borrow::Cow;
+use std::error::Error;
+
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
 
 use context::MaybeUtf8Owned;
+use response::{self, Response, SendResponse};
+use StatusCode;
 
 ///An extended `HashMap` with extra functionality for value parsing.
 ///
@@ -133,6 +139,157 @@ impl Parameters {
     {
         self.parse(key).unwrap_or_else(or_else)
     }
+
+    ///Try to extract an entry as `T`, using `FromVariable`. This is similar
+    ///to `parse`, but the error includes the name of the entry, and it can
+    ///be sent as a `400 Bad Request` response.
+    ///
+    ///```
+    ///# use rustful::{Context, Response};
+    ///fn my_handler(context: Context, response: Response) {
+    ///    response.send(context.variables.extract::<u32, _>("id").map(|id| {
+    ///        format!("showing user {}", id)
+    ///    }));
+    ///}
+    ///```
+    pub fn extract<T, K: ?Sized>(&self, key: &K) -> Result<T, VariableError> where
+        K: Hash + Eq + AsRef<[u8]>,
+        T: FromVariable
+    {
+        let name = || String::from_utf8_lossy(key.as_ref()).into_owned();
+
+        if let Some(val) = self.0.get(key.as_ref()) {
+            T::from_variable(val).map_err(|e| VariableError::Invalid(name(), e.to_string()))
+        } else {
+            Err(VariableError::Missing(name()))
+        }
+    }
+
+    ///Decode the parameters into a type that implements `Deserialize`, with
+    ///each parameter as a field. This requires the `decode` feature, which is
+    ///also included in `json`.
+    ///
+    ///Fields are parsed from their text, and `Option` fields are `None` if
+    ///the parameter is missing or empty. Sequences, such as `Vec`, are read
+    ///from comma separated lists, like `?tags=a,b,c`. Parameters without a
+    ///matching field are ignored. The error can be sent as a `400 Bad
+    ///Request` response, just like from `extract`.
+    ///
+    ///```
+    ///# extern crate rustful;
+    ///#[macro_use] extern crate serde_derive;
+    ///use rustful::{Context, Response};
+    ///
+    ///#[derive(Deserialize)]
+    ///struct Search {
+    ///    q: String,
+    ///    page: Option<u32>,
+    ///    tags: Vec<String>,
+    ///}
+    ///
+    ///fn search(context: Context, response: Response) {
+    ///    response.send(context.query.decode::<Search>().map(|search| {
+    ///        format!("page {} of {}, tagged {:?}", search.page.unwrap_or(1), search.q, search.tags)
+    ///    }));
+    ///}
+    ///# fn main() {}
+    ///```
+    #[cfg(feature = "decode")]
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, VariableError> {
+        T::deserialize(super::decode::ParametersDeserializer(self))
+    }
+}
+
+///Types that can be extracted from route variables and other parameters.
+///
+///It's implemented for every type that implements `FromStr`, but it can
+///also be implemented for types that need access to the raw value.
+///
+///```
+///use rustful::context::{FromVariable, MaybeUtf8Owned};
+///
+///struct Hex(Vec<u8>);
+///
+///impl FromVariable for Hex {
+///    type Err = &'static str;
+///
+///    fn from_variable(value: &MaybeUtf8Owned) -> Result<Hex, &'static str> {
+///        let digits = value.as_utf8().ok_or("not UTF-8")?;
+///        if digits.len() % 2 != 0 {
+///            return Err("odd number of digits");
+///        }
+///
+///        (0..digits.len()).step_by(2)
+///            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| "invalid digit"))
+///            .collect::<Result<_, _>>()
+///            .map(Hex)
+///    }
+///}
+///```
+pub trait FromVariable: Sized {
+    ///The error that is returned if the value can't be extracted.
+    type Err: fmt::Display;
+
+    ///Extract `Self` from a parameter value.
+    fn from_variable(value: &MaybeUtf8Owned) -> Result<Self, Self::Err>;
+}
+
+impl<T: FromStr> FromVariable for T where T::Err: fmt::Display {
+    type Err = T::Err;
+
+    fn from_variable(value: &MaybeUtf8Owned) -> Result<T, T::Err> {
+        value.as_utf8_lossy().parse()
+    }
+}
+
+///An error from `Parameters::extract`.
+///
+///It will respond with `400 Bad Request` and a short description if it's
+///sent as a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VariableError {
+    ///The parameter did not exist.
+    Missing(String),
+
+    ///The parameter could not be extracted. The second field is a
+    ///description of the problem.
+    Invalid(String, String)
+}
+
+impl VariableError {
+    ///The name of the parameter.
+    pub fn name(&self) -> &str {
+        match *self {
+            VariableError::Missing(ref name) | VariableError::Invalid(ref name, _) => name
+        }
+    }
+}
+
+impl fmt::Display for VariableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VariableError::Missing(ref name) => write!(f, "missing parameter '{}'", name),
+            VariableError::Invalid(ref name, ref error) => write!(f, "invalid parameter '{}': {}", name, error)
+        }
+    }
+}
+
+impl Error for VariableError {
+    fn description(&self) -> &str {
+        match *self {
+            VariableError::Missing(_) => "missing parameter",
+            VariableError::Invalid(_, _) => "invalid parameter"
+        }
+    }
+}
+
+impl<'a, 'b> SendResponse<'a, 'b> for VariableError {
+    type Error = response::Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), response::Error> {
+        response.set_status(StatusCode::BadRequest);
+        response.try_send_data(self.to_string())
+    }
 }
 
 impl Deref for Parameters {
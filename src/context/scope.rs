@@ -0,0 +1,278 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, ScopedJoinHandle};
+use std::io;
+
+///A handle for running parts of a request in parallel.
+///
+///A scope is created with [`Context::scope`][context_scope], and every task
+///that is spawned from it is joined before `scope` returns, so the tasks can
+///borrow from the context, or anything else the handler has access to. Tasks
+///that haven't been joined when the scope ends are first told to stop,
+///through their [`Cancellation`][cancellation] token, since their result will
+///never be used.
+///
+///```
+///use rustful::{Context, Response};
+///
+///fn lookup_user(id: &str) -> String {
+///    //...
+///    # format!("user {}", id)
+///}
+///
+///fn lookup_orders(id: &str) -> Vec<u32> {
+///    //...
+///    # vec![id.len() as u32]
+///}
+///
+///fn my_handler(context: Context, response: Response) {
+///    let id = context.variables.get("id").unwrap_or("0".into());
+///
+///    let result = context.scope(|scope| {
+///        let user = scope.spawn(|_| lookup_user(&id));
+///        let orders = scope.spawn(|_| lookup_orders(&id));
+///        (user.join(), orders.join())
+///    });
+///
+///    match result {
+///        (Some(user), Some(orders)) => response.send(format!("{} has {} orders", user, orders.len())),
+///        _ => response.send("something went wrong")
+///    }
+///}
+///```
+///
+///The tasks are also told to stop if the client disconnects, but there are
+///a few limits to when that can be noticed. It's only detected on Unix
+///systems and from the thread that handles the request, and any data that
+///is still waiting to be read, such as an unread request body, has to be
+///read first.
+///
+///[context_scope]: struct.Context.html#method.scope
+///[cancellation]: struct.Cancellation.html
+pub struct Scope<'scope, 'env: 'scope> {
+    scope: &'scope thread::Scope<'scope, 'env>,
+    cancellation: Cancellation,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    ///Run `task` in a separate thread. The result can be collected from the
+    ///returned `Task`.
+    ///
+    ///This will panic if a new thread can't be created. Use `try_spawn` to
+    ///handle that case.
+    pub fn spawn<T, F>(&self, task: F) -> Task<'scope, T> where
+        T: Send + 'scope,
+        F: FnOnce(&Cancellation) -> T + Send + 'scope
+    {
+        self.try_spawn(task).expect("failed to spawn a scoped task")
+    }
+
+    ///Run `task` in a separate thread, or return an error if a new thread
+    ///couldn't be created.
+    pub fn try_spawn<T, F>(&self, task: F) -> io::Result<Task<'scope, T>> where
+        T: Send + 'scope,
+        F: FnOnce(&Cancellation) -> T + Send + 'scope
+    {
+        let cancellation = self.cancellation.clone();
+
+        //A panic is caught here, instead of being passed on when the scope
+        //ends, so it only affects the task's own result.
+        let thread = thread::Builder::new().name("rustful scoped task".into()).spawn_scoped(self.scope, move || {
+            panic::catch_unwind(AssertUnwindSafe(|| task(&cancellation))).ok()
+        })?;
+
+        Ok(Task {
+            thread: thread,
+        })
+    }
+
+    ///Tell every running task to stop. It's up to the tasks to check their
+    ///`Cancellation` token.
+    pub fn cancel(&self) {
+        self.cancellation.0.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    ///Check if the scope has been cancelled, or if the client has
+    ///disconnected.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
+
+impl<'scope, 'env> Drop for Scope<'scope, 'env> {
+    ///Cancels the remaining tasks, before they are joined.
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+///Run `f` with a new scope, and join every task before returning. `client`
+///is the socket of the current connection, if it's known.
+pub fn run<'env, T, F>(client: Option<TcpStream>, f: F) -> T where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>) -> T
+{
+    let cancellation = Cancellation(Arc::new(Shared {
+        cancelled: AtomicBool::new(false),
+        client: client,
+    }));
+
+    thread::scope(|scope| {
+        let scope = Scope {
+            scope: scope,
+            cancellation: cancellation,
+        };
+
+        f(&scope)
+    })
+}
+
+///The result of a task that was spawned from a `Scope`.
+pub struct Task<'scope, T: 'scope> {
+    thread: ScopedJoinHandle<'scope, Option<T>>,
+}
+
+impl<'scope, T> Task<'scope, T> {
+    ///Wait for the task to finish and get its result. `None` is returned if
+    ///the task panicked.
+    pub fn join(self) -> Option<T> {
+        self.thread.join().ok().and_then(|result| result)
+    }
+
+    ///Check if the task has finished.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+struct Shared {
+    cancelled: AtomicBool,
+    client: Option<TcpStream>,
+}
+
+///Tells a scoped task if it should stop early.
+#[derive(Clone)]
+pub struct Cancellation(Arc<Shared>);
+
+impl Cancellation {
+    ///Check if the task should stop, either because the scope was cancelled
+    ///or because the client has disconnected.
+    pub fn is_cancelled(&self) -> bool {
+        if self.0.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        if let Some(ref client) = self.0.client {
+            if is_closed(client) {
+                self.0.cancelled.store(true, Ordering::SeqCst);
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+//Peek at the socket without blocking. The client has closed the connection
+//if it returns 0, and it's broken if it fails for any other reason than
+//having nothing to read.
+#[cfg(unix)]
+fn is_closed(client: &TcpStream) -> bool {
+    use std::os::unix::io::AsRawFd;
+    use libc;
+
+    let mut buffer = [0u8; 1];
+    let result = unsafe {
+        libc::recv(
+            client.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            libc::MSG_PEEK | libc::MSG_DONTWAIT
+        )
+    };
+
+    match result {
+        0 => true,
+        n if n > 0 => false,
+        _ => match io::Error::last_os_error().kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => false,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn is_closed(_client: &TcpStream) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use std::thread;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use context::Context;
+    use header::Headers;
+    use server::Global;
+    use Method;
+    use super::run;
+
+    #[test]
+    fn join_tasks() {
+        let global = Global::default();
+        let context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        let path = &context.uri_path;
+
+        let (a, b) = run(None, |scope| {
+            let a = scope.spawn(|_| path.as_path().is_some());
+            let b = scope.spawn(|_| -> u32 { panic!("task failed") });
+            (a.join(), b.join())
+        });
+
+        assert_eq!(a, Some(true));
+        assert_eq!(b, None);
+    }
+
+    #[test]
+    fn cancel_on_return() {
+        let stopped = AtomicBool::new(false);
+
+        run(None, |scope| {
+            scope.spawn(|cancellation| {
+                while !cancellation.is_cancelled() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                stopped.store(true, Ordering::SeqCst);
+            });
+        });
+
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cancel_on_disconnect() {
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        run(Some(server), |scope| {
+            let task = scope.spawn(|cancellation| {
+                for _ in 0..5000 {
+                    if cancellation.is_cancelled() {
+                        return true;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                false
+            });
+
+            assert!(!scope.is_cancelled());
+            drop(client);
+            assert_eq!(task.join(), Some(true));
+        });
+    }
+}
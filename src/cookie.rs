@@ -0,0 +1,313 @@
+//!Cookie parsing and `Set-Cookie` building.
+//!
+//!Cookies from the request can be accessed through
+//![`Context::cookies`][cookies], which parses the `Cookie` header into
+//![`Parameters`][parameters]. New cookies are added to the response with
+//![`Response::set_cookie`][set_cookie], using the [`Cookie`][cookie]
+//!builder:
+//!
+//!```
+//!use std::time::Duration;
+//!use rustful::{Context, Response};
+//!use rustful::cookie::{Cookie, SameSite};
+//!
+//!fn my_handler(context: Context, mut response: Response) {
+//!    let visits = context.cookies().parse_or("visits", 0u32) + 1;
+//!
+//!    response.set_cookie(
+//!        Cookie::new("visits", visits.to_string())
+//!            .path("/")
+//!            .max_age(Duration::from_secs(60 * 60 * 24))
+//!            .http_only(true)
+//!            .same_site(SameSite::Lax)
+//!    );
+//!
+//!    response.send(format!("visit number {}", visits));
+//!}
+//!```
+//!
+//![cookies]: ../context/struct.Context.html#method.cookies
+//![parameters]: ../context/struct.Parameters.html
+//![set_cookie]: ../response/struct.Response.html#method.set_cookie
+//![cookie]: struct.Cookie.html
+
+use std::fmt;
+use std::time::Duration;
+
+use time::{self, Tm};
+
+use header::{self, Headers};
+use context::Parameters;
+
+///Parse the `Cookie` header into a name-value map.
+///
+///Only the first occurrence of each cookie name is kept, since user agents
+///are expected to send the most specific cookie first. Surrounding double
+///quotes are removed from the values.
+pub fn parse(headers: &Headers) -> Parameters {
+    let mut cookies = Parameters::new();
+
+    if let Some(&header::Cookie(ref pairs)) = headers.get() {
+        for pair in pairs {
+            let mut parts = pair.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = parts.next().unwrap_or("").trim();
+            let value = if value.len() > 1 && value.starts_with('"') && value.ends_with('"') {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+
+            cookies.entry(name).or_insert_with(|| value.into());
+        }
+    }
+
+    cookies
+}
+
+///Add a cookie to a `Set-Cookie` header, without replacing any previously
+///set cookies.
+pub fn append(headers: &mut Headers, cookie: &Cookie) {
+    let cookie = cookie.to_string();
+    if let Some(&mut header::SetCookie(ref mut cookies)) = headers.get_mut() {
+        cookies.push(cookie);
+        return;
+    }
+
+    headers.set(header::SetCookie(vec![cookie]));
+}
+
+///The `SameSite` cookie attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    ///The cookie is only sent with same-site requests.
+    Strict,
+    ///The cookie is also sent when navigating to the site from elsewhere.
+    Lax,
+    ///The cookie is sent with cross-site requests. Browsers require `Secure`
+    ///to be set as well.
+    None
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None"),
+        }
+    }
+}
+
+///A builder for a `Set-Cookie` header value.
+///
+///Characters that are not allowed in a cookie name, value or attribute
+///(such as `;`, whitespace and control characters) are left out when the
+///cookie is formatted, so untrusted input can't be used to inject
+///attributes or additional headers.
+///
+///```
+///use rustful::cookie::{Cookie, SameSite};
+///
+///let cookie = Cookie::new("session", "abc123")
+///    .path("/")
+///    .secure(true)
+///    .http_only(true)
+///    .same_site(SameSite::Strict);
+///
+///assert_eq!(cookie.to_string(), "session=abc123; Path=/; Secure; HttpOnly; SameSite=Strict");
+///```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    expires: Option<Tm>,
+    max_age: Option<Duration>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    ///Create a session cookie without any attributes.
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    ///Create a cookie that tells the client to remove any cookie with the
+    ///same name. The `path` and `domain` has to match the original cookie.
+    pub fn removal<N: Into<String>>(name: N) -> Cookie {
+        Cookie::new(name, "")
+            .expires(time::at_utc(time::Timespec::new(0, 0)))
+            .max_age(Duration::from_secs(0))
+    }
+
+    ///The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    ///Set an absolute expiration time.
+    pub fn expires(mut self, expires: Tm) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    ///Set how long the cookie should live, from when it's received.
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///Set the domain that the cookie should be sent to.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    ///Set the path prefix that the cookie should be sent to.
+    pub fn path<P: Into<String>>(mut self, path: P) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    ///Only send the cookie over secure connections.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    ///Hide the cookie from client side scripts.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    ///Restrict when the cookie is sent with cross-site requests.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_filtered(f, &self.name, is_token_char)?;
+        f.write_str("=")?;
+        write_filtered(f, &self.value, is_cookie_octet)?;
+
+        if let Some(ref expires) = self.expires {
+            write!(f, "; Expires={}", expires.to_utc().rfc822())?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+
+        if let Some(ref domain) = self.domain {
+            f.write_str("; Domain=")?;
+            write_filtered(f, domain, is_attribute_char)?;
+        }
+
+        if let Some(ref path) = self.path {
+            f.write_str("; Path=")?;
+            write_filtered(f, path, is_attribute_char)?;
+        }
+
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_filtered<F: Fn(char) -> bool>(f: &mut fmt::Formatter, text: &str, allowed: F) -> fmt::Result {
+    if text.chars().all(&allowed) {
+        f.write_str(text)
+    } else {
+        for c in text.chars().filter(|&c| allowed(c)) {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    match c {
+        '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '.' | '^' | '_' | '`' | '|' | '~' => true,
+        _ => c.is_ascii_alphanumeric()
+    }
+}
+
+fn is_cookie_octet(c: char) -> bool {
+    match c {
+        '\x21' | '\x23'..='\x2b' | '\x2d'..='\x3a' | '\x3c'..='\x5b' | '\x5d'..='\x7e' => true,
+        _ => false
+    }
+}
+
+fn is_attribute_char(c: char) -> bool {
+    c != ';' && c.is_ascii() && !c.is_ascii_control()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use header::{Headers, Cookie as CookieHeader};
+    use super::{parse, Cookie};
+
+    #[test]
+    fn parse_cookie_header() {
+        let mut headers = Headers::new();
+        headers.set(CookieHeader(vec!["a=1".into(), "b=\"two\"".into(), "a=3".into(), "=x".into()]));
+        let cookies = parse(&headers);
+
+        assert_eq!(cookies.get("a").as_ref().map(|v| &**v), Some("1"));
+        assert_eq!(cookies.get("b").as_ref().map(|v| &**v), Some("two"));
+        assert_eq!(cookies.len(), 2);
+    }
+
+    #[test]
+    fn strip_invalid_characters() {
+        let cookie = Cookie::new("na me", "va;lue\r\nX-Evil: 1").path("/a;b").max_age(Duration::from_secs(10));
+        assert_eq!(cookie.to_string(), "name=valueX-Evil:1; Max-Age=10; Path=/ab");
+    }
+
+    #[test]
+    fn removal_cookie() {
+        assert_eq!(
+            Cookie::removal("session").to_string(),
+            "session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+        );
+    }
+}
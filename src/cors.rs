@@ -0,0 +1,465 @@
+//!Cross-Origin Resource Sharing.
+//!
+//!A [`Cors`][cors] configuration describes which other origins may access
+//!the resources of a handler, and with which methods and headers. It wraps
+//!a router, or any other handler, and takes care of both the preflight
+//!requests and the headers of the actual responses:
+//!
+//!```no_run
+//!use std::time::Duration;
+//!use rustful::{Server, Context, Response, DefaultRouter, Method};
+//!use rustful::cors::Cors;
+//!
+//!fn list_todos(_context: Context, response: Response) {
+//!    response.send("[]");
+//!}
+//!
+//!fn add_todo(_context: Context, response: Response) {
+//!    response.send("added");
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("todos").then().many(|mut node| {
+//!    node.on_get(list_todos);
+//!    node.on_post(add_todo);
+//!});
+//!
+//!let cors = Cors::new()
+//!    .allow_origin("https://example.com")
+//!    .allow_methods(vec![Method::Get, Method::Post])
+//!    .allow_header("Content-Type")
+//!    .max_age(Duration::from_secs(3600));
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(cors.wrap(router))
+//!}.run();
+//!```
+//!
+//!A preflight request is only answered if the wrapped handler knows the
+//!path. That is, if it handles the `OPTIONS` request itself, or if it
+//!rejects it with `405 Method Not Allowed`. This makes it possible to apply
+//!CORS to only a part of a site, by wrapping a separate router and
+//!combining it with the rest, using [`OrElse`][or_else].
+//!
+//![cors]: struct.Cors.html
+//![or_else]: ../handler/struct.OrElse.html
+
+use std::time::Duration;
+
+use {Method, StatusCode};
+use header::Headers;
+use context::hypermedia::Link;
+use server::Requirements;
+use handler::{HandleRequest, Environment};
+
+//The headers that are set by `Cors`, so they can be removed again.
+const RESPONSE_HEADERS: &'static [&'static str] = &[
+    "Access-Control-Allow-Origin",
+    "Access-Control-Allow-Credentials",
+    "Access-Control-Allow-Methods",
+    "Access-Control-Allow-Headers",
+    "Access-Control-Expose-Headers",
+    "Access-Control-Max-Age",
+];
+
+#[derive(Clone, Debug)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+///A CORS configuration.
+///
+///It allows no origins, the methods `GET`, `HEAD` and `POST`, and no
+///additional headers by default.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Option<Vec<String>>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    ///Create a configuration that doesn't allow any origins.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Origins::List(vec![]),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Some(vec![]),
+            expose_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    ///Allow requests from any origin.
+    ///
+    ///It will panic if credentials are allowed, since that would let any
+    ///site make requests with the credentials of the user and read the
+    ///responses. List the trusted origins with `allow_origin` instead.
+    pub fn allow_any_origin(mut self) -> Cors {
+        assert!(!self.credentials, "credentials can't be allowed for any origin");
+        self.origins = Origins::Any;
+        self
+    }
+
+    ///Allow requests from `origin`, such as `"https://example.com"`. This
+    ///has no effect if any origin is allowed.
+    pub fn allow_origin<O: Into<String>>(mut self, origin: O) -> Cors {
+        if let Origins::List(ref mut origins) = self.origins {
+            origins.push(origin.into());
+        }
+        self
+    }
+
+    ///Set which methods are allowed.
+    pub fn allow_methods<I: IntoIterator<Item=Method>>(mut self, methods: I) -> Cors {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    ///Allow a request header, in addition to the ones that are always
+    ///allowed. This has no effect if any header is allowed.
+    pub fn allow_header<H: Into<String>>(mut self, header: H) -> Cors {
+        if let Some(ref mut headers) = self.headers {
+            headers.push(header.into().to_lowercase());
+        }
+        self
+    }
+
+    ///Allow any request header.
+    pub fn allow_any_header(mut self) -> Cors {
+        self.headers = None;
+        self
+    }
+
+    ///Make a response header readable to the client, in addition to the
+    ///ones that are always readable.
+    pub fn expose_header<H: Into<String>>(mut self, header: H) -> Cors {
+        self.expose_headers.push(header.into());
+        self
+    }
+
+    ///Allow requests with credentials, such as cookies.
+    ///
+    ///It will panic if `allow` is `true` and any origin is allowed, since
+    ///that would let any site make requests with the credentials of the user
+    ///and read the responses. List the trusted origins with `allow_origin`
+    ///instead.
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        if let (&Origins::Any, true) = (&self.origins, allow) {
+            panic!("credentials can't be allowed for any origin");
+        }
+        self.credentials = allow;
+        self
+    }
+
+    ///Let the client cache the preflight response for `max_age`.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///Apply the configuration to `handler`.
+    pub fn wrap<H: HandleRequest>(self, handler: H) -> CorsHandler<H> {
+        CorsHandler {
+            handler: handler,
+            cors: self,
+        }
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        match self.origins {
+            Origins::Any => true,
+            Origins::List(ref origins) => origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    fn set_origin(&self, origin: &str, response: &mut Headers) {
+        match self.origins {
+            Origins::Any => response.set_raw("Access-Control-Allow-Origin", vec![b"*".to_vec()]),
+            Origins::List(_) => response.set_raw("Access-Control-Allow-Origin", vec![origin.as_bytes().to_vec()]),
+        }
+
+        if self.credentials {
+            response.set_raw("Access-Control-Allow-Credentials", vec![b"true".to_vec()]);
+        }
+    }
+
+    //The response depends on the origin, whether it's allowed or not, unless
+    //any origin is allowed.
+    fn vary_origin(&self, response: &mut Headers) {
+        if let Origins::List(_) = self.origins {
+            add_vary(response, "Origin");
+        }
+    }
+
+    //Set the headers for an actual request. Returns `false` if the request
+    //isn't a cross-origin request, or if it isn't allowed.
+    fn actual(&self, request: &Headers, response: &mut Headers) -> bool {
+        self.vary_origin(response);
+
+        let origin = match header_value(request, "Origin") {
+            Some(origin) => origin,
+            None => return false
+        };
+
+        if !self.allows_origin(&origin) {
+            return false;
+        }
+
+        self.set_origin(&origin, response);
+
+        if !self.expose_headers.is_empty() {
+            response.set_raw("Access-Control-Expose-Headers", vec![self.expose_headers.join(", ").into_bytes()]);
+        }
+
+        true
+    }
+
+    //Set the headers for a preflight request. Returns `false` if the request
+    //isn't allowed.
+    fn preflight(&self, request: &Headers, response: &mut Headers) -> bool {
+        let (origin, requested_method) = match (header_value(request, "Origin"), header_value(request, "Access-Control-Request-Method")) {
+            (Some(origin), Some(method)) => (origin, method),
+            _ => return false
+        };
+
+        let requested_headers = header_value(request, "Access-Control-Request-Headers").unwrap_or_default();
+        let requested_headers: Vec<_> = requested_headers.split(',')
+            .map(|header| header.trim().to_lowercase())
+            .filter(|header| !header.is_empty())
+            .collect();
+
+        let method_allowed = self.methods.iter().any(|method| method.as_ref() == requested_method);
+        let headers_allowed = match self.headers {
+            Some(ref allowed) => requested_headers.iter().all(|header| allowed.contains(header)),
+            None => true
+        };
+
+        self.vary_origin(response);
+        add_vary(response, "Access-Control-Request-Method");
+        add_vary(response, "Access-Control-Request-Headers");
+
+        if !self.allows_origin(&origin) || !method_allowed || !headers_allowed {
+            return false;
+        }
+
+        self.set_origin(&origin, response);
+
+        let methods: Vec<_> = self.methods.iter().map(|method| method.to_string()).collect();
+        response.set_raw("Access-Control-Allow-Methods", vec![methods.join(", ").into_bytes()]);
+
+        if !requested_headers.is_empty() {
+            response.set_raw("Access-Control-Allow-Headers", vec![requested_headers.join(", ").into_bytes()]);
+        }
+
+        if let Some(max_age) = self.max_age {
+            response.set_raw("Access-Control-Max-Age", vec![max_age.as_secs().to_string().into_bytes()]);
+        }
+
+        true
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+///A handler with a CORS configuration. It's created using `Cors::wrap`.
+pub struct CorsHandler<H> {
+    handler: H,
+    cors: Cors,
+}
+
+impl<H> CorsHandler<H> {
+    ///Get a reference to the inner handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    ///Get a reference to the CORS configuration.
+    pub fn cors(&self) -> &Cors {
+        &self.cors
+    }
+}
+
+impl<H: HandleRequest> HandleRequest for CorsHandler<H> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let allowed_preflight = {
+            let context = &environment.context;
+            let response = environment.response.headers_mut();
+            if is_preflight(&context.method, &context.headers) {
+                self.cors.preflight(&context.headers, response)
+            } else {
+                self.cors.actual(&context.headers, response);
+                false
+            }
+        };
+
+        match self.handler.handle_request(environment) {
+            Ok(()) => Ok(()),
+            Err(mut environment) => {
+                if allowed_preflight {
+                    if environment.response.status() == StatusCode::MethodNotAllowed {
+                        environment.response.set_status(StatusCode::NoContent);
+                        return Ok(());
+                    }
+
+                    //The path is unknown, so let someone else decide.
+                    let headers = environment.response.headers_mut();
+                    for &name in RESPONSE_HEADERS {
+                        headers.remove_raw(name);
+                    }
+                }
+
+                Err(environment)
+            }
+        }
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handler.hyperlinks(base)
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.handler.requirements(requirements);
+    }
+}
+
+fn is_preflight(method: &Method, headers: &Headers) -> bool {
+    *method == Method::Options &&
+        headers.get_raw("Origin").is_some() &&
+        headers.get_raw("Access-Control-Request-Method").is_some()
+}
+
+fn header_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+fn add_vary(headers: &mut Headers, name: &str) {
+    let mut values = header_value(headers, "Vary").unwrap_or_default();
+
+    let present = values.split(',').any(|value| {
+        let value = value.trim();
+        value == "*" || value.eq_ignore_ascii_case(name)
+    });
+
+    if !present {
+        if !values.is_empty() {
+            values.push_str(", ");
+        }
+        values.push_str(name);
+        headers.set_raw("Vary", vec![values.into_bytes()]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Method, Context, Response, StatusCode};
+    use header::Headers;
+    use server::Global;
+    use handler::{HandleRequest, Environment, DefaultRouter};
+    use super::{Cors, header_value};
+
+    fn request(origin: &str, preflight: Option<(&str, &str)>) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Origin", vec![origin.as_bytes().to_vec()]);
+        if let Some((method, request_headers)) = preflight {
+            headers.set_raw("Access-Control-Request-Method", vec![method.as_bytes().to_vec()]);
+            headers.set_raw("Access-Control-Request-Headers", vec![request_headers.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    #[test]
+    fn actual_request() {
+        let cors = Cors::new().allow_origin("https://example.com").expose_header("X-Total");
+        let mut response = Headers::new();
+        response.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+
+        assert!(cors.actual(&request("https://example.com", None), &mut response));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), Some("https://example.com".into()));
+        assert_eq!(header_value(&response, "Access-Control-Expose-Headers"), Some("X-Total".into()));
+        assert_eq!(header_value(&response, "Vary"), Some("Accept-Encoding, Origin".into()));
+
+        let mut response = Headers::new();
+        assert!(!cors.actual(&request("https://example.org", None), &mut response));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header_value(&response, "Vary"), Some("Origin".into()));
+
+        let mut response = Headers::new();
+        assert!(!cors.actual(&Headers::new(), &mut response));
+        assert_eq!(header_value(&response, "Vary"), Some("Origin".into()));
+
+        let mut response = Headers::new();
+        assert!(Cors::new().allow_any_origin().actual(&request("https://example.org", None), &mut response));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), Some("*".into()));
+        assert_eq!(header_value(&response, "Vary"), None);
+    }
+
+    #[test]
+    fn preflight_request() {
+        let cors = Cors::new()
+            .allow_origin("https://example.com")
+            .allow_credentials(true)
+            .allow_methods(vec![Method::Get, Method::Put])
+            .allow_header("Content-Type");
+
+        let mut response = Headers::new();
+        assert!(cors.preflight(&request("https://example.com", Some(("PUT", "content-type"))), &mut response));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), Some("https://example.com".into()));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Credentials"), Some("true".into()));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Methods"), Some("GET, PUT".into()));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Headers"), Some("content-type".into()));
+
+        let mut response = Headers::new();
+        assert!(!cors.preflight(&request("https://example.com", Some(("DELETE", ""))), &mut response));
+        assert!(!cors.preflight(&request("https://example.com", Some(("PUT", "X-Secret"))), &mut response));
+        assert!(!cors.preflight(&request("null", Some(("PUT", ""))), &mut response));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header_value(&response, "Access-Control-Allow-Credentials"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn credentials_for_any_origin() {
+        Cors::new().allow_any_origin().allow_credentials(true);
+    }
+
+    #[test]
+    #[should_panic]
+    fn any_origin_with_credentials() {
+        Cors::new().allow_credentials(true).allow_any_origin();
+    }
+
+    #[test]
+    fn unknown_path() {
+        let mut router = DefaultRouter::<fn(Context, Response)>::new();
+        router.build().path("known").then().on_get(|_: Context, response: Response| response.send("hello"));
+        let handler = Cors::new().allow_any_origin().wrap(router);
+
+        let global = Global::default();
+        let result = handler.handle_request(Environment {
+            context: Context::mock(Method::Options, "/unknown", request("https://example.com", Some(("GET", ""))), &global),
+            response: Response::mock(&global),
+            route_state: "/unknown".into(),
+        });
+
+        match result {
+            Ok(()) => panic!("the preflight request should not be answered"),
+            Err(environment) => {
+                assert_eq!(environment.response.status(), StatusCode::NotFound);
+                assert_eq!(header_value(environment.response.headers(), "Access-Control-Allow-Origin"), None);
+            }
+        }
+    }
+}
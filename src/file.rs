@@ -1,6 +1,12 @@
 //!File related utilities.
 
-use std::path::{Path, Component};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf, Component};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 use mime::{Mime, TopLevel, SubLevel};
 
@@ -86,3 +92,178 @@ pub fn check_path<P: ?Sized + AsRef<Path>>(path: &P) -> Result<(), Component> {
 
     Ok(())
 }
+
+///A cache for file contents, that reloads files when they have changed.
+///
+///Each file is loaded when it's first requested, and optionally processed,
+///such as parsed or minified, before it's stored. The modification time of
+///the file is checked every time it's requested, and it's loaded again if
+///it has changed. It can be shared between threads, so it can be kept in
+///`Global`:
+///
+///```no_run
+///use std::path::Path;
+///use rustful::{Server, Context, Response, StatusCode};
+///use rustful::file::{self, Cache};
+///
+///fn show_file(context: Context, mut response: Response) {
+///    let name = context.variables.get("name").unwrap_or_default();
+///    if file::check_path(&*name).is_err() {
+///        return response.set_status(StatusCode::Forbidden);
+///    }
+///
+///    let cache: &Cache = context.global.get().unwrap();
+///    let path = Path::new("static").join(&*name);
+///    let _ = response.send_cached_file(cache, path)
+///        .or_else(|e| e.send_not_found("the file was not found"));
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    global: Box::new(Cache::new()).into(),
+///    ..Server::new(show_file)
+///}.run();
+///```
+///
+///Stale entries are only noticed when they are requested, so files that
+///are removed stay in memory until `remove_stale` is called, for example
+///from a periodic task. Entries can also be removed directly with
+///`invalidate`, such as when a file watcher reports a change.
+pub struct Cache<T = Vec<u8>> {
+    entries: RwLock<HashMap<PathBuf, Entry<T>>>,
+    process: Box<Fn(&Path, Vec<u8>) -> io::Result<T> + Send + Sync>,
+}
+
+struct Entry<T> {
+    value: Arc<T>,
+    modified: Option<SystemTime>,
+}
+
+impl Cache<Vec<u8>> {
+    ///Create a cache for the raw file contents.
+    pub fn new() -> Cache<Vec<u8>> {
+        Cache::processed(|_, content| Ok(content))
+    }
+}
+
+impl Cache<String> {
+    ///Create a cache for UTF-8 text files, such as templates.
+    pub fn text() -> Cache<String> {
+        Cache::processed(|_, content| String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+impl<T: Send + Sync + 'static> Cache<T> {
+    ///Create a cache that stores the result of `process`, which gets the
+    ///path and content of each file.
+    pub fn processed<F>(process: F) -> Cache<T> where
+        F: Fn(&Path, Vec<u8>) -> io::Result<T> + Send + Sync + 'static
+    {
+        Cache {
+            entries: RwLock::new(HashMap::new()),
+            process: Box::new(process),
+        }
+    }
+
+    ///Get the cached content of the file at `path`, or load it if it's not
+    ///cached or if it has been modified.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> io::Result<Arc<T>> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path)?.modified().ok();
+
+        if let Some(entry) = self.entries.read().unwrap_or_else(|e| e.into_inner()).get(path) {
+            if modified.is_some() && entry.modified == modified {
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let mut content = vec![];
+        File::open(path)?.read_to_end(&mut content)?;
+        let value = Arc::new((self.process)(path, content)?);
+
+        self.entries.write().unwrap_or_else(|e| e.into_inner()).insert(path.to_owned(), Entry {
+            value: value.clone(),
+            modified: modified,
+        });
+
+        Ok(value)
+    }
+
+    ///Remove the file at `path` from the cache. It returns `true` if it
+    ///was cached.
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.entries.write().unwrap_or_else(|e| e.into_inner()).remove(path.as_ref()).is_some()
+    }
+
+    ///Remove every entry whose file has been modified or removed, and
+    ///return how many they were.
+    pub fn remove_stale(&self) -> usize {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let before = entries.len();
+        entries.retain(|path, entry| {
+            let modified = fs::metadata(path).ok().and_then(|metadata| metadata.modified().ok());
+            modified.is_some() && modified == entry.modified
+        });
+        before - entries.len()
+    }
+
+    ///Remove every entry.
+    pub fn clear(&self) {
+        self.entries.write().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    ///The number of cached files.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    ///Check if no files are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Cache<Vec<u8>> {
+    fn default() -> Cache<Vec<u8>> {
+        Cache::new()
+    }
+}
+
+impl<T> fmt::Debug for Cache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("Cache").field("files", &entries.keys().collect::<Vec<_>>()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Cache;
+
+    #[test]
+    fn reload_modified() {
+        let path = env::temp_dir().join(format!("rustful-cache-{}.txt", ::utils::random_u64()));
+        File::create(&path).unwrap().write_all(b"first").unwrap();
+
+        let cache = Cache::text();
+        assert_eq!(*cache.get(&path).unwrap(), "first");
+        assert_eq!(cache.remove_stale(), 0);
+
+        //Some file systems only store the time with a second's precision
+        thread::sleep(Duration::from_millis(1100));
+        File::create(&path).unwrap().write_all(b"second").unwrap();
+        assert_eq!(*cache.get(&path).unwrap(), "second");
+        assert_eq!(cache.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+        assert!(cache.get(&path).is_err());
+        assert_eq!(cache.remove_stale(), 1);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+//!One-shot messages, stored in a signed cookie.
+//!
+//!A flash message is typically set before redirecting after a form
+//!submission, and shown once on the page that the client is redirected to.
+//!The messages are kept in a cookie, which is signed with a secret key, so
+//!they can't be forged by the client, and no session storage is needed:
+//!
+//!```
+//!use rustful::{Context, Response, StatusCode};
+//!use rustful::header::Location;
+//!use rustful::flash::{Flash, Message};
+//!use rustful::template::push_escaped_html;
+//!
+//!fn save_settings(context: Context, mut response: Response) {
+//!    let flash = Flash::new("a long and secret key");
+//!
+//!    //...
+//!    flash.push(&mut response, Message::info("The settings were saved."));
+//!    response.headers_mut().set(Location("/settings".into()));
+//!    response.set_status(StatusCode::SeeOther);
+//!}
+//!
+//!fn show_settings(context: Context, mut response: Response) {
+//!    let flash = Flash::new("a long and secret key");
+//!    let mut page = String::new();
+//!
+//!    for message in flash.take(&context, &mut response) {
+//!        page.push_str(&format!("<p class=\"{}\">", message.level));
+//!        push_escaped_html(&mut page, &message.text);
+//!        page.push_str("</p>");
+//!    }
+//!
+//!    //...
+//!    response.send(page);
+//!}
+//!```
+//!
+//!The messages are not encrypted, so they should not contain any secrets,
+//!and a cookie can only be about 4 kB, so they should be short.
+
+use std::fmt;
+use std::sync::Arc;
+
+use url::percent_encoding::percent_decode;
+
+use header::SetCookie;
+use context::Context;
+use cookie::{Cookie, SameSite};
+use response::Response;
+use utils::{hmac_sha256, constant_time_eq, to_hex};
+
+///The category of a flash message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Level {
+    ///Information, such as a confirmation.
+    Info,
+    ///A warning.
+    Warning,
+    ///An error.
+    Error
+}
+
+impl Level {
+    fn code(self) -> char {
+        match self {
+            Level::Info => 'i',
+            Level::Warning => 'w',
+            Level::Error => 'e',
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Level> {
+        match code {
+            "i" => Some(Level::Info),
+            "w" => Some(Level::Warning),
+            "e" => Some(Level::Error),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Level::Info => f.write_str("info"),
+            Level::Warning => f.write_str("warning"),
+            Level::Error => f.write_str("error"),
+        }
+    }
+}
+
+///A flash message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    ///The category of the message.
+    pub level: Level,
+    ///The message text.
+    pub text: String,
+}
+
+impl Message {
+    ///Create a message with an arbitrary level.
+    pub fn new<T: Into<String>>(level: Level, text: T) -> Message {
+        Message {
+            level: level,
+            text: text.into(),
+        }
+    }
+
+    ///Create an information message.
+    pub fn info<T: Into<String>>(text: T) -> Message {
+        Message::new(Level::Info, text)
+    }
+
+    ///Create a warning message.
+    pub fn warning<T: Into<String>>(text: T) -> Message {
+        Message::new(Level::Warning, text)
+    }
+
+    ///Create an error message.
+    pub fn error<T: Into<String>>(text: T) -> Message {
+        Message::new(Level::Error, text)
+    }
+}
+
+//The messages that are about to be sent in the current response.
+struct Pending(Vec<Message>);
+
+///Reads and writes flash messages.
+#[derive(Clone)]
+pub struct Flash {
+    key: Arc<Vec<u8>>,
+    cookie_name: String,
+    path: String,
+}
+
+impl Flash {
+    ///Create a flash message helper that signs its cookie with `key`. The
+    ///cookie will be called `"flash"` and it will be sent for every path.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> Flash {
+        Flash {
+            key: Arc::new(key.into()),
+            cookie_name: "flash".into(),
+            path: "/".into(),
+        }
+    }
+
+    ///Set the name of the cookie.
+    pub fn cookie_name<N: Into<String>>(mut self, name: N) -> Flash {
+        self.cookie_name = name.into();
+        self
+    }
+
+    ///Set the path of the cookie.
+    pub fn path<P: Into<String>>(mut self, path: P) -> Flash {
+        self.path = path.into();
+        self
+    }
+
+    ///Add a message to the response. It will be available through `take`
+    ///during the next request. Every message that is added during the same
+    ///request is kept.
+    pub fn push(&self, response: &mut Response, message: Message) {
+        let value = {
+            let pending = response.filter_storage_mut().entry::<Pending>().or_insert_with(|| Pending(vec![]));
+            pending.0.push(message);
+            self.encode(&pending.0)
+        };
+
+        let cookie = Cookie::new(self.cookie_name.clone(), value)
+            .path(self.path.clone())
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        self.replace_cookie(response, cookie);
+    }
+
+    ///Get the messages from the request and tell the client to remove
+    ///them. Missing, tampered or otherwise invalid cookies results in an
+    ///empty list.
+    pub fn take(&self, context: &Context, response: &mut Response) -> Vec<Message> {
+        let value = match context.cookies().get(&*self.cookie_name) {
+            Some(value) => value.into_owned(),
+            None => return vec![]
+        };
+
+        if response.filter_storage().get::<Pending>().is_none() {
+            self.replace_cookie(response, Cookie::removal(self.cookie_name.clone()).path(self.path.clone()));
+        }
+
+        self.decode(&value).unwrap_or_default()
+    }
+
+    fn replace_cookie(&self, response: &mut Response, cookie: Cookie) {
+        let prefix = format!("{}=", self.cookie_name);
+        if let Some(&mut SetCookie(ref mut cookies)) = response.headers_mut().get_mut() {
+            cookies.retain(|cookie| !cookie.starts_with(&prefix));
+        }
+
+        response.set_cookie(cookie);
+    }
+
+    fn signature(&self, payload: &str) -> String {
+        let mut message = self.cookie_name.clone().into_bytes();
+        message.push(b'=');
+        message.extend_from_slice(payload.as_bytes());
+        to_hex(&hmac_sha256(&self.key, &message))
+    }
+
+    fn encode(&self, messages: &[Message]) -> String {
+        let payload = messages.iter().map(|message| {
+            let mut part = String::new();
+            part.push(message.level.code());
+            part.push(':');
+            encode_text(&message.text, &mut part);
+            part
+        }).collect::<Vec<_>>().join("&");
+
+        let signature = self.signature(&payload);
+        format!("{}.{}", payload, signature)
+    }
+
+    fn decode(&self, value: &str) -> Option<Vec<Message>> {
+        let split = value.rfind('.')?;
+        let (payload, signature) = (&value[..split], &value[split + 1..]);
+
+        if !constant_time_eq(self.signature(payload).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+
+        if payload.is_empty() {
+            return Some(vec![]);
+        }
+
+        payload.split('&').map(|part| {
+            let mut parts = part.splitn(2, ':');
+            let level = Level::from_code(parts.next()?)?;
+            let text = percent_decode(parts.next()?.as_bytes()).decode_utf8().ok()?;
+            Some(Message::new(level, text.into_owned()))
+        }).collect()
+    }
+}
+
+fn encode_text(text: &str, buffer: &mut String) {
+    for &byte in text.as_bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'~' | b'!' | b'*' | b'\'' | b'(' | b')' => {
+                buffer.push(byte as char)
+            },
+            _ => buffer.push_str(&format!("%{:02X}", byte))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Flash, Message};
+
+    #[test]
+    fn round_trip() {
+        let flash = Flash::new("secret");
+        let messages = vec![
+            Message::info("Saved; all good & well."),
+            Message::error("100% wrong: \"ö\""),
+        ];
+
+        let value = flash.encode(&messages);
+        assert!(!value.contains(|c: char| c == ';' || c == ' ' || c == '"' || c == ','));
+        assert_eq!(flash.decode(&value), Some(messages));
+    }
+
+    #[test]
+    fn reject_tampered() {
+        let flash = Flash::new("secret");
+        let value = flash.encode(&[Message::info("hello")]);
+
+        assert_eq!(flash.decode(&value.replace("hello", "hallo")), None);
+        assert_eq!(Flash::new("other secret").decode(&value), None);
+        assert_eq!(flash.decode("i:hello"), None);
+    }
+}
@@ -0,0 +1,275 @@
+//! A router that tries a sequence of handlers until one accepts the request.
+
+use context::hypermedia::Link;
+use StatusCode;
+use server::Requirements;
+use handler::{HandleRequest, Environment, Build, FromHandler, BuilderContext, ApplyContext, Merge};
+
+/// A router that tries a sequence of handlers until one accepts the request.
+///
+/// Each handler gets the request in turn, until one of them handles it
+/// instead of returning it. This is like nesting `OrElse`, but without the
+/// nesting, which makes it easier to have three or more alternatives. The
+/// route state is restored before each attempt, so every handler gets to
+/// see the whole path.
+///
+/// The status code that was set by a failed attempt is kept by default,
+/// which allows the last handler to act on it, but it can be reset to `200
+/// OK` between the attempts with `reset_status`.
+///
+/// ```
+/// use rustful::{Context, Response, DefaultRouter};
+/// use rustful::handler::Chain;
+///
+/// fn api(_context: Context, response: Response) {
+///     response.send("api");
+/// }
+///
+/// fn page(_context: Context, response: Response) {
+///     response.send("page");
+/// }
+///
+/// fn fallback(_context: Context, response: Response) {
+///     let status = response.status();
+///     response.send(format!("Status: {}", status));
+/// }
+///
+/// let mut chain = Chain::<DefaultRouter<fn(Context, Response)>>::new();
+///
+/// chain.build().many(|mut chain| {
+///     chain.then().path("api").then().on_get(api as fn(Context, Response));
+///     chain.then().path("pages/:page").then().on_get(page);
+///     chain.then().path("*").then().on_get(fallback);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Chain<T> {
+    /// The handlers, in the order they are tried.
+    pub handlers: Vec<T>,
+
+    /// Reset the status code to `200 OK` before trying the next handler.
+    /// Default is `false`.
+    pub reset_status: bool,
+}
+
+impl<T> Chain<T> {
+    /// Create an empty `Chain`.
+    pub fn new() -> Chain<T> {
+        Chain::default()
+    }
+
+    /// Build the router and its children using a chainable API.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::Chain;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello!");
+    /// }
+    ///
+    /// let mut chain = Chain::<fn(Context, Response)>::new();
+    /// chain.build().on(handler as fn(Context, Response));
+    /// ```
+    pub fn build(&mut self) -> Builder<T> {
+        self.get_builder(BuilderContext::new())
+    }
+
+    /// Add a handler to the end of the chain.
+    pub fn push(&mut self, handler: T) {
+        self.handlers.push(handler);
+    }
+}
+
+impl<T: HandleRequest> HandleRequest for Chain<T> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let snapshot = environment.route_state.snapshot();
+
+        for (i, handler) in self.handlers.iter().enumerate() {
+            if i > 0 {
+                environment.route_state.go_to(snapshot);
+                if self.reset_status {
+                    environment.response.set_status(StatusCode::Ok);
+                }
+            }
+
+            environment = match handler.handle_request(environment) {
+                Ok(()) => return Ok(()),
+                Err(environment) => environment
+            };
+        }
+
+        if self.handlers.is_empty() {
+            environment.response.set_status(StatusCode::NotFound);
+        }
+
+        Err(environment)
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handlers.iter().flat_map(|handler| handler.hyperlinks(base.clone())).collect()
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        for handler in &self.handlers {
+            handler.requirements(requirements);
+        }
+    }
+}
+
+impl<T> Default for Chain<T> {
+    fn default() -> Chain<T> {
+        Chain {
+            handlers: vec![],
+            reset_status: false,
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Chain<T> {
+    fn from(handlers: Vec<T>) -> Chain<T> {
+        Chain {
+            handlers: handlers,
+            reset_status: false,
+        }
+    }
+}
+
+impl<'a, T: 'a> Build<'a> for Chain<T> {
+    type Builder = Builder<'a, T>;
+
+    fn get_builder(&'a mut self, context: BuilderContext) -> Builder<'a, T> {
+        Builder {
+            router: self,
+            context: context
+        }
+    }
+}
+
+impl<T: ApplyContext> ApplyContext for Chain<T> {
+    fn apply_context(&mut self, context: BuilderContext) {
+        for handler in &mut self.handlers {
+            handler.apply_context(context.clone());
+        }
+    }
+
+    fn prepend_context(&mut self, context: BuilderContext) {
+        for handler in &mut self.handlers {
+            handler.prepend_context(context.clone());
+        }
+    }
+}
+
+impl<T> Merge for Chain<T> {
+    fn merge(&mut self, other: Chain<T>) {
+        self.handlers.extend(other.handlers);
+        self.reset_status = other.reset_status;
+    }
+}
+
+/// A builder for a `Chain`.
+pub struct Builder<'a, T: 'a> {
+    router: &'a mut Chain<T>,
+    context: BuilderContext
+}
+
+impl<'a, T> Builder<'a, T> {
+    /// Perform more than one operation on this builder.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::Chain;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello!");
+    /// }
+    ///
+    /// let mut chain = Chain::<fn(Context, Response)>::new();
+    ///
+    /// chain.build().many(|mut chain| {
+    ///     chain.reset_status(true);
+    ///     chain.on(handler as fn(Context, Response));
+    /// });
+    /// ```
+    pub fn many<F: FnOnce(&mut Builder<'a, T>)>(&mut self, build: F) -> &mut Builder<'a, T> {
+        build(self);
+        self
+    }
+
+    /// Reset the status code to `200 OK` before trying the next handler.
+    pub fn reset_status(&mut self, reset: bool) -> &mut Builder<'a, T> {
+        self.router.reset_status = reset;
+        self
+    }
+
+    /// Add a handler to the end of the chain.
+    pub fn on<H>(&mut self, handler: H) -> &mut Builder<'a, T> where T: FromHandler<H> {
+        self.router.push(T::from_handler(self.context.clone(), handler));
+        self
+    }
+}
+
+impl<'a: 'b, 'b, T: Default + ApplyContext + Build<'b>> Builder<'a, T> {
+    /// Add an empty handler to the end of the chain and build it.
+    pub fn then(&'b mut self) -> T::Builder {
+        let mut handler = T::default();
+        handler.apply_context(self.context.clone());
+        self.router.push(handler);
+
+        self.router.handlers.last_mut().expect("the chain can't be empty").get_builder(self.context.clone())
+    }
+}
+
+impl<'a, T: ApplyContext> Builder<'a, T> {
+    ///Move handlers from another chain to the end of this one.
+    pub fn merge(&mut self, mut other: Chain<T>) -> &mut Builder<'a, T> {
+        other.apply_context(self.context.clone());
+        self.router.merge(other);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+    use hyper::method::Method;
+
+    use {Context, Response, StatusCode};
+    use header::Headers;
+    use server::Global;
+    use handler::{HandleRequest, Environment, TreeRouter};
+    use super::Chain;
+
+    static CALLS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    fn handler(_context: Context, response: Response) {
+        assert_eq!(response.status(), StatusCode::Ok);
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn try_handlers_in_order() {
+        let mut chain = Chain::<TreeRouter<Option<fn(Context, Response)>>>::new();
+        chain.build().many(|chain| {
+            chain.reset_status(true);
+            chain.then().path("a/b");
+            chain.then().on_path("a/c", handler as fn(Context, Response));
+            chain.then().on_path("a/b", handler as fn(Context, Response));
+        });
+
+        let global = Global::default();
+        for &(path, handled) in &[("a/b", true), ("a/c", true), ("a/d", false)] {
+            let result = chain.handle_request(Environment {
+                context: Context::mock(Method::Get, path, Headers::new(), &global),
+                response: Response::mock(&global),
+                route_state: path.into(),
+            });
+
+            assert_eq!(result.is_ok(), handled);
+        }
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+}
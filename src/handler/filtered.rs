@@ -0,0 +1,621 @@
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
+
+use context::Context;
+use context::hypermedia::Link;
+use filter::{ContextFilter, ContextAction, FilterContext};
+use StatusCode;
+use header::{Headers, ContentType, ContentLength, TransferEncoding};
+use mime::{Mime, TopLevel, SubLevel};
+use server::Requirements;
+use handler::{HandleRequest, Environment, FromHandler, Build, BuilderContext, ApplyContext, Merge, Prepend};
+
+///Context type for storing route specific context filters.
+#[derive(Clone, Default)]
+pub struct RouteFilters(pub Vec<Arc<ContextFilter>>);
+
+impl Prepend for RouteFilters {
+    fn prepend(&mut self, prefix: &RouteFilters) {
+        let mut filters = prefix.0.clone();
+        filters.extend(self.0.drain(..));
+        self.0 = filters;
+    }
+}
+
+///A context filter that makes a configuration value available to the
+///handlers, through `Context::config`.
+///
+///It's usually added to a part of a router, using `with_config` on the
+///`TreeRouter` builder:
+///
+///```
+///use rustful::{Context, Response, DefaultRouter};
+///
+///struct UploadLimits {
+///    max_size: u64,
+///}
+///
+///fn upload(context: Context, response: Response) {
+///    let max_size = context.config::<UploadLimits>().map_or(0, |limits| limits.max_size);
+///    response.send(format!("the maximum size is {} bytes", max_size));
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("uploads").with_config(UploadLimits { max_size: 1024 }).many(|mut node| {
+///    node.then().on_post(upload);
+///});
+///```
+///
+///Configuration values of the same type will shadow each other, so the one
+///that is closest to the handler is used.
+pub struct RouteConfig<T>(Arc<T>);
+
+impl<T> RouteConfig<T> {
+    ///Wrap a configuration value.
+    pub fn new(config: T) -> RouteConfig<T> {
+        RouteConfig(Arc::new(config))
+    }
+
+    ///Get a reference to the configuration value.
+    pub fn get(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Clone for RouteConfig<T> {
+    fn clone(&self) -> RouteConfig<T> {
+        RouteConfig(self.0.clone())
+    }
+}
+
+impl<T: Send + Sync + 'static> ContextFilter for RouteConfig<T> {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        request_context.extensions.insert(self.clone());
+        ContextAction::next()
+    }
+}
+
+///A context filter that limits how many requests can be handled at the same
+///time.
+///
+///The server handles requests on a shared pool of worker threads, so a few
+///slow endpoints may otherwise end up occupying every worker. Adding a
+///`ConcurrencyLimit` to the slow part of a router caps how many workers it
+///can occupy at the same time. Additional requests are rejected right away,
+///so the rest of the workers are left for the rest of the router, as long
+///as the limit is lower than the number of threads:
+///
+///```
+///use rustful::{Context, Response, DefaultRouter};
+///use rustful::handler::ConcurrencyLimit;
+///
+///fn generate_report(_context: Context, response: Response) {
+///    //...
+///    response.send("a very expensive report");
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("reports").with_filter(ConcurrencyLimit::new(2)).many(|mut node| {
+///    node.path(":id").then().on_get(generate_report);
+///});
+///```
+///
+///A request that doesn't get a slot is answered with `503 Service
+///Unavailable`. The slot is held by a guard in `context.extensions`, so
+///it's released when the context is dropped. The limited requests are
+///still handled by the server's own workers, and not by a separate pool.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    slots: Arc<Slots>,
+    wait: Option<Duration>,
+}
+
+struct Slots {
+    max: usize,
+    in_use: Mutex<usize>,
+    released: Condvar,
+}
+
+impl ConcurrencyLimit {
+    ///Allow at most `max` requests at the same time, and reject any
+    ///additional requests immediately.
+    pub fn new(max: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            slots: Arc::new(Slots {
+                max: max,
+                in_use: Mutex::new(0),
+                released: Condvar::new(),
+            }),
+            wait: None,
+        }
+    }
+
+    ///Let additional requests wait for up to `timeout` for a free slot.
+    ///
+    ///A waiting request occupies a worker thread for the whole time, just
+    ///like a request that is being handled, so this brings back the risk of
+    ///filling the thread pool. Keep the timeout short, if it's used at all.
+    pub fn wait(mut self, timeout: Duration) -> ConcurrencyLimit {
+        self.wait = Some(timeout);
+        self
+    }
+
+    ///The number of requests that are currently being handled.
+    pub fn in_use(&self) -> usize {
+        *self.slots.in_use.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn acquire(&self) -> Option<SlotGuard> {
+        let mut in_use = self.slots.in_use.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(timeout) = self.wait {
+            let deadline = Instant::now() + timeout;
+            while *in_use >= self.slots.max {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+
+                in_use = self.slots.released.wait_timeout(in_use, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+            }
+        } else if *in_use >= self.slots.max {
+            return None;
+        }
+
+        *in_use += 1;
+        Some(SlotGuard(self.slots.clone()))
+    }
+}
+
+impl ContextFilter for ConcurrencyLimit {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        match self.acquire() {
+            Some(guard) => {
+                request_context.extensions.insert(guard);
+                ContextAction::next()
+            },
+            None => ContextAction::abort(StatusCode::ServiceUnavailable)
+        }
+    }
+}
+
+struct SlotGuard(Arc<Slots>);
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        //The count is only changed while the lock is held, so it's still
+        //correct if another thread panicked with it.
+        let mut in_use = self.0.in_use.lock().unwrap_or_else(|e| e.into_inner());
+        *in_use -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+///A context filter that rewrites the request headers before they reach the
+///handler.
+///
+///It can be used to give a part of a router a consistent view of the
+///request, regardless of what the client sent:
+///
+///```
+///use rustful::{Context, Response, DefaultRouter};
+///use rustful::handler::RequestHeaders;
+///
+///fn legacy_api(_context: Context, response: Response) {
+///    response.send("{}");
+///}
+///
+///fn public_page(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().many(|mut node| {
+///    node.path("api")
+///        .with_filter(RequestHeaders::new().set("Accept", "application/json"))
+///        .then().on_get(legacy_api);
+///
+///    node.path("public")
+///        .with_filter(RequestHeaders::new().remove("Cookie").set_default("Accept-Language", "en"))
+///        .then().on_get(public_page);
+///});
+///```
+///
+///The rules are applied in the order they were added. The request body is
+///already set up when the filter runs, so changing `Content-Length` or
+///`Transfer-Encoding` will not change how it's read.
+#[derive(Clone, Default)]
+pub struct RequestHeaders {
+    rules: Vec<HeaderRule>,
+}
+
+#[derive(Clone)]
+enum HeaderRule {
+    Set(String, Vec<u8>),
+    SetDefault(String, Vec<u8>),
+    Remove(String),
+}
+
+impl RequestHeaders {
+    ///Create an empty set of rules.
+    pub fn new() -> RequestHeaders {
+        RequestHeaders::default()
+    }
+
+    ///Set a header, replacing any value from the client.
+    pub fn set<N: Into<String>, V: Into<Vec<u8>>>(mut self, name: N, value: V) -> RequestHeaders {
+        self.rules.push(HeaderRule::Set(name.into(), value.into()));
+        self
+    }
+
+    ///Set a header, unless the client has already set it.
+    pub fn set_default<N: Into<String>, V: Into<Vec<u8>>>(mut self, name: N, value: V) -> RequestHeaders {
+        self.rules.push(HeaderRule::SetDefault(name.into(), value.into()));
+        self
+    }
+
+    ///Remove a header.
+    pub fn remove<N: Into<String>>(mut self, name: N) -> RequestHeaders {
+        self.rules.push(HeaderRule::Remove(name.into()));
+        self
+    }
+
+    fn apply(&self, headers: &mut Headers) {
+        for rule in &self.rules {
+            match *rule {
+                HeaderRule::Set(ref name, ref value) => headers.set_raw(name.clone(), vec![value.clone()]),
+                HeaderRule::SetDefault(ref name, ref value) => if headers.get_raw(name).is_none() {
+                    headers.set_raw(name.clone(), vec![value.clone()]);
+                },
+                HeaderRule::Remove(ref name) => headers.remove_raw(name),
+            }
+        }
+    }
+}
+
+impl ContextFilter for RequestHeaders {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        self.apply(&mut request_context.headers);
+        ContextAction::next()
+    }
+}
+
+///A route filter that decides if `+` should be decoded as space in
+///`context.query`, overriding `Server.query_plus_as_space`.
+///
+///```
+///use rustful::{Context, Response, DefaultRouter};
+///use rustful::handler::PlusAsSpace;
+///
+///fn search(context: Context, response: Response) {
+///    //"?q=c++" gives "c++"
+///    let query = context.query.get("q").unwrap_or_default();
+///    response.send(format!("searching for {}", query));
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("search").with_filter(PlusAsSpace(false)).then().on_get(search);
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlusAsSpace(pub bool);
+
+impl ContextFilter for PlusAsSpace {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        request_context.query = request_context.parse_query(self.0);
+        ContextAction::next()
+    }
+}
+
+///A context filter that only lets through requests with one of the
+///accepted content types, and answers the rest with `415 Unsupported Media
+///Type`.
+///
+///It's usually added to a part of a router, using `accepting` on the
+///`TreeRouter` builder:
+///
+///```
+///#[macro_use(content_type)]
+///extern crate rustful;
+///use rustful::{Context, Response, DefaultRouter};
+///
+///fn create_user(context: Context, response: Response) {
+///    //The body is JSON here...
+///    response.send("created");
+///}
+///
+///# fn main() {
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("users").accepting(&[content_type!(Application / Json)]).many(|mut node| {
+///    node.then().on_post(create_user);
+///});
+///# }
+///```
+///
+///The parameters of the content types are ignored, and `*` can be used as a
+///wildcard, as in `content_type!(Text / Star)`. Requests without a body are
+///always let through, while requests with a body, but without a
+///`Content-Type` header, are rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accepting(Vec<Mime>);
+
+impl Accepting {
+    ///Accept the content types in `types`.
+    pub fn new<I: IntoIterator<Item = Mime>>(types: I) -> Accepting {
+        Accepting(types.into_iter().collect())
+    }
+
+    ///Check if a request with `headers` is accepted.
+    pub fn accepts(&self, headers: &Headers) -> bool {
+        match headers.get::<ContentType>() {
+            Some(&ContentType(Mime(ref top, ref sub, _))) => self.0.iter().any(|&Mime(ref accepted_top, ref accepted_sub, _)| {
+                (*accepted_top == TopLevel::Star || accepted_top == top) && (*accepted_sub == SubLevel::Star || accepted_sub == sub)
+            }),
+            None => !has_body(headers)
+        }
+    }
+}
+
+impl ContextFilter for Accepting {
+    fn modify(&self, _context: FilterContext, request_context: &mut Context) -> ContextAction {
+        if self.accepts(&request_context.headers) {
+            ContextAction::next()
+        } else {
+            ContextAction::abort(StatusCode::UnsupportedMediaType)
+        }
+    }
+}
+
+fn has_body(headers: &Headers) -> bool {
+    match headers.get::<ContentLength>() {
+        Some(&ContentLength(length)) => length > 0,
+        None => headers.has::<TransferEncoding>()
+    }
+}
+
+///Runs a stack of context filters before a handler.
+///
+///It makes it possible to apply context filters to only a part of a router,
+///such as an authentication filter for an admin section. The filters are
+///collected from the `BuilderContext`, so they can be added to a subtree of
+///a `TreeRouter` using its builder:
+///
+///```
+///use rustful::{Context, Response, DefaultRouter, StatusCode};
+///use rustful::filter::{ContextFilter, ContextAction, FilterContext};
+///
+///struct RequireToken;
+///
+///impl ContextFilter for RequireToken {
+///    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+///        if context.headers.get_raw("x-token").is_some() {
+///            ContextAction::next()
+///        } else {
+///            ContextAction::abort(StatusCode::Unauthorized)
+///        }
+///    }
+///}
+///
+///fn show_stats(_context: Context, response: Response) {
+///    response.send("very secret stats");
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("admin").with_filter(RequireToken).many(|mut node| {
+///    node.path("stats").then().on_get(show_stats);
+///});
+///```
+///
+///The filters will run after the global context filters and the route
+///variables are available to them. An aborting filter will stop the request
+///from reaching the handler and the status code will be used for the
+///response. Response filters are still global.
+///
+///Filters from the surrounding context are put before the handler's own
+///filters, so a router that is merged into a filtered subtree will keep its
+///filters, but they will run after the ones from the subtree. Merging two
+///handlers for the same route keeps the filters of both, with the existing
+///ones first, and shared filters are only kept once.
+#[derive(Clone)]
+pub struct Filtered<H> {
+    handler: H,
+    filters: Vec<Arc<ContextFilter>>,
+}
+
+impl<T: FromHandler<H>, H> FromHandler<H> for Filtered<T> {
+    fn from_handler(mut context: BuilderContext, handler: H) -> Filtered<T> {
+        Filtered {
+            filters: context.remove::<RouteFilters>().unwrap_or_default().0,
+            handler: T::from_handler(context, handler),
+        }
+    }
+}
+
+impl<H> Filtered<H> {
+    fn prepend_filters(&mut self, context: &mut BuilderContext) {
+        if let Some(prefix) = context.remove::<RouteFilters>() {
+            let mut filters = RouteFilters(::std::mem::replace(&mut self.filters, vec![]));
+            filters.prepend(&prefix);
+            self.filters = filters.0;
+        }
+    }
+}
+
+impl<T: ApplyContext> ApplyContext for Filtered<T> {
+    fn apply_context(&mut self, mut context: BuilderContext) {
+        self.prepend_filters(&mut context);
+        self.handler.apply_context(context);
+    }
+
+    fn prepend_context(&mut self, mut context: BuilderContext) {
+        self.prepend_filters(&mut context);
+        self.handler.prepend_context(context);
+    }
+}
+
+impl<T: Merge> Merge for Filtered<T> {
+    fn merge(&mut self, other: Filtered<T>) {
+        for filter in other.filters {
+            if !self.filters.iter().any(|existing| Arc::ptr_eq(existing, &filter)) {
+                self.filters.push(filter);
+            }
+        }
+        self.handler.merge(other.handler);
+    }
+}
+
+impl<'a, H: Build<'a>> Build<'a> for Filtered<H> {
+    type Builder = H::Builder;
+
+    fn get_builder(&'a mut self, mut context: BuilderContext) -> Self::Builder {
+        context.remove::<RouteFilters>();
+        self.handler.get_builder(context)
+    }
+}
+
+impl<H: HandleRequest> HandleRequest for Filtered<H> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let global = environment.context.global;
+
+        for filter in &self.filters {
+            let filter_context = FilterContext {
+                storage: environment.response.filter_storage_mut(),
+                global: global,
+            };
+
+            if let ContextAction::Abort(status) = filter.modify(filter_context, &mut environment.context) {
+                environment.response.set_status(status);
+                return Ok(());
+            }
+        }
+
+        self.handler.handle_request(environment)
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handler.hyperlinks(base)
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.handler.requirements(requirements);
+    }
+}
+
+impl<H: Default> Default for Filtered<H> {
+    fn default() -> Filtered<H> {
+        Filtered {
+            handler: H::default(),
+            filters: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use {Context, Response};
+    use filter::ContextFilter;
+    use handler::Merge;
+    use header::{Headers, ContentType, ContentLength};
+    use super::{ConcurrencyLimit, RequestHeaders, Accepting, Filtered};
+
+    #[test]
+    fn merge_filters() {
+        fn handler(_context: Context, response: Response) {
+            response.send("");
+        }
+
+        let shared: Arc<ContextFilter> = Arc::new(ConcurrencyLimit::new(1));
+        let first: Arc<ContextFilter> = Arc::new(ConcurrencyLimit::new(1));
+        let second: Arc<ContextFilter> = Arc::new(ConcurrencyLimit::new(1));
+
+        let mut filtered = Filtered {
+            handler: handler as fn(Context, Response),
+            filters: vec![shared.clone(), first.clone()],
+        };
+        filtered.merge(Filtered {
+            handler: handler as fn(Context, Response),
+            filters: vec![shared.clone(), second.clone()],
+        });
+
+        assert_eq!(filtered.filters.len(), 3);
+        assert!(Arc::ptr_eq(&filtered.filters[0], &shared));
+        assert!(Arc::ptr_eq(&filtered.filters[1], &first));
+        assert!(Arc::ptr_eq(&filtered.filters[2], &second));
+    }
+
+    #[test]
+    fn concurrency_limit() {
+        let limit = ConcurrencyLimit::new(2);
+        let first = limit.acquire();
+        let second = limit.acquire();
+
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(limit.acquire().is_none());
+        assert_eq!(limit.in_use(), 2);
+
+        drop(first);
+        assert_eq!(limit.in_use(), 1);
+        assert!(limit.acquire().is_some());
+    }
+
+    #[test]
+    fn concurrency_limit_poisoned() {
+        use std::thread;
+
+        let limit = ConcurrencyLimit::new(1);
+        let guard = limit.acquire();
+        assert!(guard.is_some());
+
+        let slots = limit.slots.clone();
+        let result = thread::spawn(move || {
+            let _in_use = slots.in_use.lock().unwrap();
+            panic!("poison the lock");
+        }).join();
+        assert!(result.is_err());
+        assert!(limit.slots.in_use.is_poisoned());
+
+        drop(guard);
+        assert_eq!(limit.in_use(), 0);
+        assert!(limit.acquire().is_some());
+    }
+
+    #[test]
+    fn rewrite_request_headers() {
+        let rules = RequestHeaders::new()
+            .set("Accept", "application/json")
+            .set_default("Accept-Language", "en")
+            .set_default("User-Agent", "unknown")
+            .remove("Cookie");
+
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", vec![b"text/html".to_vec()]);
+        headers.set_raw("User-Agent", vec![b"test".to_vec()]);
+        headers.set_raw("Cookie", vec![b"a=b".to_vec()]);
+        rules.apply(&mut headers);
+
+        assert_eq!(headers.get_raw("Accept"), Some(&[b"application/json".to_vec()][..]));
+        assert_eq!(headers.get_raw("Accept-Language"), Some(&[b"en".to_vec()][..]));
+        assert_eq!(headers.get_raw("User-Agent"), Some(&[b"test".to_vec()][..]));
+        assert_eq!(headers.get_raw("Cookie"), None);
+    }
+
+    #[test]
+    fn accepted_content_types() {
+        let accepting = Accepting::new(vec![content_type!(Application / Json), content_type!(Text / Star)]);
+        let mut headers = Headers::new();
+        assert!(accepting.accepts(&headers));
+
+        headers.set(ContentLength(2));
+        assert!(!accepting.accepts(&headers));
+
+        headers.set(ContentType(content_type!(Application / Json; Charset = Utf8)));
+        assert!(accepting.accepts(&headers));
+
+        headers.set(ContentType(content_type!(Text / Plain)));
+        assert!(accepting.accepts(&headers));
+
+        headers.set(ContentType(content_type!(Application / WwwFormUrlEncoded)));
+        assert!(!accepting.accepts(&headers));
+    }
+}
@@ -0,0 +1,300 @@
+//! A router that selects a handler from the requested host name.
+
+use context::hypermedia::Link;
+use StatusCode;
+use header::Host;
+use server::Requirements;
+use handler::{HandleRequest, Environment, Build, FromHandler, BuilderContext, ApplyContext, Merge};
+use utils::host_matches;
+
+/// A router that selects a handler from the requested host name.
+///
+/// The host name is taken from the `Host` header, without the port, and is
+/// matched against a set of patterns. A pattern can be an exact host name,
+/// like `example.com`, a wildcard that matches exactly one label, like
+/// `*.example.com`, or `*`, which matches any host. Exact names are tried
+/// first, then wildcards, and `*` is used if nothing else matches. The
+/// comparison is case insensitive.
+///
+/// Requests that don't match any pattern are rejected with `404 Not
+/// Found`.
+///
+/// ```
+/// use rustful::{Context, Response, DefaultRouter};
+/// use rustful::handler::HostRouter;
+///
+/// fn blog(_context: Context, response: Response) {
+///     response.send("The blog");
+/// }
+///
+/// fn tenant(context: Context, response: Response) {
+///     response.send(format!("Welcome to {}", context.variables.get("page").unwrap_or_default()));
+/// }
+///
+/// let mut router = HostRouter::<DefaultRouter<fn(Context, Response)>>::new();
+///
+/// router.build().many(|mut router| {
+///     router.host("blog.example.com").then().on_get(blog as fn(Context, Response));
+///     router.host("*.example.com").path(":page").then().on_get(tenant);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct HostRouter<T> {
+    handlers: Vec<(String, T)>,
+}
+
+impl<T> HostRouter<T> {
+    /// Create an empty `HostRouter`.
+    pub fn new() -> HostRouter<T> {
+        HostRouter::default()
+    }
+
+    /// Build the router and its children using a chainable API.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::HostRouter;
+    ///
+    /// fn example_com(_context: Context, response: Response) {
+    ///     response.send("example.com");
+    /// }
+    ///
+    /// fn example_org(_context: Context, response: Response) {
+    ///     response.send("example.org");
+    /// }
+    ///
+    /// let mut host_router = HostRouter::<fn(Context, Response)>::new();
+    ///
+    /// host_router.build().many(|mut host_router|{
+    ///     host_router.on("example.com", example_com as fn(Context, Response));
+    ///     host_router.on("example.org", example_org);
+    /// });
+    /// ```
+    pub fn build(&mut self) -> Builder<T> {
+        self.get_builder(BuilderContext::new())
+    }
+
+    /// Insert a handler for host names that match `pattern`.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::{HostRouter, TreeRouter};
+    ///
+    /// let route_tree = TreeRouter::<Option<fn(Context, Response)>>::new();
+    /// //Fill route_tree with handlers...
+    ///
+    /// let mut host_router = HostRouter::new();
+    /// host_router.insert("*.example.com", route_tree);
+    /// ```
+    pub fn insert<P: Into<String>>(&mut self, pattern: P, handler: T) {
+        let pattern = pattern.into().to_lowercase();
+        match self.handlers.iter().position(|&(ref existing, _)| *existing == pattern) {
+            Some(index) => self.handlers[index].1 = handler,
+            None => self.handlers.push((pattern, handler)),
+        }
+    }
+
+    fn find(&self, host: Option<&str>) -> Option<&T> {
+        let exact = host.and_then(|host| self.handlers.iter().find(|&&(ref pattern, _)| {
+            !pattern.starts_with('*') && host_matches(pattern, host)
+        }));
+
+        let wildcard = || host.and_then(|host| self.handlers.iter().find(|&&(ref pattern, _)| {
+            pattern.starts_with("*.") && host_matches(pattern, host)
+        }));
+
+        let any = || self.handlers.iter().find(|&&(ref pattern, _)| pattern == "*");
+
+        exact.or_else(wildcard).or_else(any).map(|&(_, ref handler)| handler)
+    }
+}
+
+impl<T: HandleRequest> HandleRequest for HostRouter<T> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let handler = {
+            let host = environment.context.headers.get::<Host>().map(|host| &*host.hostname);
+            self.find(host)
+        };
+
+        if let Some(handler) = handler {
+            handler.handle_request(environment)
+        } else {
+            environment.response.set_status(StatusCode::NotFound);
+            Err(environment)
+        }
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handlers.iter().flat_map(|&(_, ref handler)| {
+            handler.hyperlinks(base.clone())
+        }).collect()
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        for &(_, ref handler) in &self.handlers {
+            handler.requirements(requirements);
+        }
+    }
+}
+
+impl<T> Default for HostRouter<T> {
+    fn default() -> HostRouter<T> {
+        HostRouter {
+            handlers: vec![],
+        }
+    }
+}
+
+impl<'a, T: 'a> Build<'a> for HostRouter<T> {
+    type Builder = Builder<'a, T>;
+
+    fn get_builder(&'a mut self, context: BuilderContext) -> Builder<'a, T> {
+        Builder {
+            router: self,
+            context: context
+        }
+    }
+}
+
+impl<T: ApplyContext> ApplyContext for HostRouter<T> {
+    fn apply_context(&mut self, context: BuilderContext) {
+        for &mut (_, ref mut handler) in &mut self.handlers {
+            handler.apply_context(context.clone());
+        }
+    }
+
+    fn prepend_context(&mut self, context: BuilderContext) {
+        for &mut (_, ref mut handler) in &mut self.handlers {
+            handler.prepend_context(context.clone());
+        }
+    }
+}
+
+impl<T: Merge> Merge for HostRouter<T> {
+    fn merge(&mut self, other: HostRouter<T>) {
+        for (pattern, handler) in other.handlers {
+            match self.handlers.iter().position(|&(ref existing, _)| *existing == pattern) {
+                Some(index) => self.handlers[index].1.merge(handler),
+                None => self.handlers.push((pattern, handler)),
+            }
+        }
+    }
+}
+
+/// A builder for a `HostRouter`.
+pub struct Builder<'a, T: 'a> {
+    router: &'a mut HostRouter<T>,
+    context: BuilderContext
+}
+
+impl<'a, T> Builder<'a, T> {
+    /// Perform more than one operation on this builder.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::HostRouter;
+    ///
+    /// fn example_com(_context: Context, response: Response) {
+    ///     response.send("example.com");
+    /// }
+    ///
+    /// fn anything_else(_context: Context, response: Response) {
+    ///     response.send("some other host");
+    /// }
+    ///
+    /// let mut host_router = HostRouter::<fn(Context, Response)>::new();
+    ///
+    /// host_router.build().many(|mut host_router|{
+    ///     host_router.on("example.com", example_com as fn(Context, Response));
+    ///     host_router.on("*", anything_else);
+    /// });
+    /// ```
+    pub fn many<F: FnOnce(&mut Builder<'a, T>)>(&mut self, build: F) -> &mut Builder<'a, T> {
+        build(self);
+        self
+    }
+
+    /// Insert a handler for host names that match `pattern`.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::HostRouter;
+    ///
+    /// fn subdomain(_context: Context, response: Response) {
+    ///     response.send("a subdomain of example.com");
+    /// }
+    ///
+    /// let mut host_router = HostRouter::<fn(Context, Response)>::new();
+    /// host_router.build().on("*.example.com", subdomain as fn(Context, Response));
+    /// ```
+    pub fn on<P: Into<String>, H>(&mut self, pattern: P, handler: H) where T: FromHandler<H> {
+        self.router.insert(pattern, T::from_handler(self.context.clone(), handler));
+    }
+}
+
+impl<'a: 'b, 'b, T: Default + ApplyContext + Build<'b>> Builder<'a, T> {
+    /// Build a handler and its children, for host names that match
+    /// `pattern`.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::{HostRouter, MethodRouter};
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello from example.com!");
+    /// }
+    ///
+    /// let mut host_router = HostRouter::<MethodRouter<fn(Context, Response)>>::new();
+    ///
+    /// host_router.build()
+    ///     .host("example.com")
+    ///     .on_get(handler as fn(Context, Response));
+    /// ```
+    pub fn host<P: Into<String>>(&'b mut self, pattern: P) -> T::Builder {
+        let pattern = pattern.into().to_lowercase();
+        let index = match self.router.handlers.iter().position(|&(ref existing, _)| *existing == pattern) {
+            Some(index) => index,
+            None => {
+                let mut handler = T::default();
+                handler.apply_context(self.context.clone());
+                self.router.handlers.push((pattern, handler));
+                self.router.handlers.len() - 1
+            }
+        };
+
+        self.router.handlers[index].1.get_builder(self.context.clone())
+    }
+}
+
+impl<'a, T: Merge + ApplyContext> Builder<'a, T> {
+    ///Move handlers from another router into this, overwriting conflicting handlers and properties.
+    pub fn merge(&mut self, mut other: HostRouter<T>) -> &mut Builder<'a, T> {
+        other.apply_context(self.context.clone());
+        self.router.merge(other);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HostRouter;
+
+    #[test]
+    fn find_host() {
+        let mut router = HostRouter::new();
+        router.insert("*", "any");
+        router.insert("*.Example.com", "wildcard");
+        router.insert("blog.example.com", "exact");
+
+        assert_eq!(router.find(Some("blog.example.com")), Some(&"exact"));
+        assert_eq!(router.find(Some("BLOG.example.com")), Some(&"exact"));
+        assert_eq!(router.find(Some("shop.example.com")), Some(&"wildcard"));
+        assert_eq!(router.find(Some("example.com")), Some(&"any"));
+        assert_eq!(router.find(None), Some(&"any"));
+
+        let mut router = HostRouter::new();
+        router.insert("example.com", "exact");
+        assert_eq!(router.find(Some("example.org")), None);
+    }
+}
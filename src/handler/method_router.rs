@@ -3,17 +3,39 @@
 use std::collections::hash_map::{HashMap, Entry};
 
 use {Method, StatusCode};
+use header::Allow;
 use context::hypermedia::Link;
+use server::Requirements;
 use handler::{HandleRequest, Environment, Build, FromHandler, BuilderContext, ApplyContext, Merge};
+use webdav;
 
 /// A router that selects a handler from an HTTP method.
 ///
 /// It's a simple mapping between `Method` and a router `T`, while the
 /// requested path is ignored. It's therefore a good idea to pair a
 /// `MethodRouter` with an exhaustive path router of some sort.
+///
+/// Requests with unknown methods are rejected with `405 Method Not
+/// Allowed`. Automatic `OPTIONS` responses can be enabled, either for a
+/// single router, using `Builder::auto_options`, or for every router that
+/// is built with a particular `BuilderContext`, using
+/// [`AutoOptions`][auto_options]. Enabling it will make the router answer
+/// `OPTIONS` requests that don't have a handler, and add an `Allow`
+/// header, with the registered methods, to both those responses and the
+/// `405` responses.
+///
+/// `HEAD` requests can similarly be answered by the `GET` handler, using
+/// `Builder::auto_head` or [`AutoHead`][auto_head]. The handler will see the
+/// `HEAD` method, but the body of its response is discarded. The status
+/// code and headers, including `Content-Length`, are sent as usual.
+///
+/// [auto_options]: struct.AutoOptions.html
+/// [auto_head]: struct.AutoHead.html
 #[derive(Clone)]
 pub struct MethodRouter<T> {
     handlers: HashMap<Method, T>,
+    auto_options: bool,
+    auto_head: bool,
 }
 
 impl<T> MethodRouter<T> {
@@ -22,7 +44,7 @@ impl<T> MethodRouter<T> {
         MethodRouter::default()
     }
 
-    /// Build the router and its children using a chaninable API.
+    /// Build the router and its children using a chainable API.
     ///
     /// ```
     /// use rustful::{Context, Response};
@@ -60,14 +82,97 @@ impl<T> MethodRouter<T> {
     /// method_router.insert(Method::Get, route_tree);
     /// ```
     pub fn insert(&mut self, method: Method, handler: T) {
-        self.handlers.insert(method, handler);
+        self.handlers.insert(normalize(method), handler);
+    }
+
+    /// Enable or disable automatic `OPTIONS` responses and `Allow` headers.
+    pub fn set_auto_options(&mut self, enabled: bool) {
+        self.auto_options = enabled;
+    }
+
+    /// Enable or disable answering `HEAD` requests with the `GET` handler.
+    pub fn set_auto_head(&mut self, enabled: bool) {
+        self.auto_head = enabled;
+    }
+
+    fn head_handler(&self) -> Option<&T> {
+        if self.auto_head {
+            self.handlers.get(&Method::Get)
+        } else {
+            None
+        }
+    }
+
+    fn allowed_methods(&self) -> Vec<Method> {
+        let mut methods: Vec<_> = self.handlers.keys().cloned().collect();
+        if !self.handlers.contains_key(&Method::Options) {
+            methods.push(Method::Options);
+        }
+        if !self.handlers.contains_key(&Method::Head) && self.head_handler().is_some() {
+            methods.push(Method::Head);
+        }
+        methods.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        methods
     }
 }
 
+/// Context type for enabling automatic `OPTIONS` responses in every
+/// `MethodRouter` that is built with the context.
+///
+/// ```
+/// use rustful::{Context, Response, DefaultRouter};
+/// use rustful::handler::{AutoOptions, Build, BuilderContext};
+///
+/// fn handler(_context: Context, response: Response) {
+///     response.send("Hello world!");
+/// }
+///
+/// let mut context = BuilderContext::new();
+/// context.insert(AutoOptions(true));
+///
+/// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+/// router.get_builder(context).path("hello").then().on_get(handler);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoOptions(pub bool);
+
+/// Context type for answering `HEAD` requests with the `GET` handler in
+/// every `MethodRouter` that is built with the context.
+///
+/// ```
+/// use rustful::{Context, Response, DefaultRouter};
+/// use rustful::handler::{AutoHead, Build, BuilderContext};
+///
+/// fn handler(_context: Context, response: Response) {
+///     response.send("Hello world!");
+/// }
+///
+/// let mut context = BuilderContext::new();
+/// context.insert(AutoHead(true));
+///
+/// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+/// router.get_builder(context).path("hello").then().on_get(handler);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoHead(pub bool);
+
 impl<T: HandleRequest> HandleRequest for MethodRouter<T> {
     fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
         if let Some(handler) = self.handlers.get(&environment.context.method) {
             handler.handle_request(environment)
+        } else if let (&Method::Head, Some(handler)) = (&environment.context.method, self.head_handler()) {
+            environment.response.set_omit_body(true);
+            handler.handle_request(environment)
+        } else if self.auto_options {
+            environment.response.headers_mut().set(Allow(self.allowed_methods()));
+
+            if environment.context.method == Method::Options {
+                environment.response.send("");
+                Ok(())
+            } else {
+                environment.response.set_status(StatusCode::MethodNotAllowed);
+                Err(environment)
+            }
         } else {
             environment.response.set_status(StatusCode::MethodNotAllowed);
             Err(environment)
@@ -81,12 +186,20 @@ impl<T: HandleRequest> HandleRequest for MethodRouter<T> {
             handler.hyperlinks(link)
         }).collect()
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        for handler in self.handlers.values() {
+            handler.requirements(requirements);
+        }
+    }
 }
 
 impl<T> Default for MethodRouter<T> {
     fn default() -> MethodRouter<T> {
         MethodRouter {
             handlers: HashMap::new(),
+            auto_options: false,
+            auto_head: false,
         }
     }
 }
@@ -95,6 +208,13 @@ impl<'a, T: 'a> Build<'a> for MethodRouter<T> {
     type Builder = Builder<'a, T>;
 
     fn get_builder(&'a mut self, context: BuilderContext) -> Self::Builder {
+        if let Some(&AutoOptions(enabled)) = context.get() {
+            self.auto_options = enabled;
+        }
+        if let Some(&AutoHead(enabled)) = context.get() {
+            self.auto_head = enabled;
+        }
+
         Builder {
             router: self,
             context: context
@@ -104,6 +224,13 @@ impl<'a, T: 'a> Build<'a> for MethodRouter<T> {
 
 impl<T: ApplyContext> ApplyContext for MethodRouter<T> {
     fn apply_context(&mut self, context: BuilderContext) {
+        if let Some(&AutoOptions(enabled)) = context.get() {
+            self.auto_options = enabled;
+        }
+        if let Some(&AutoHead(enabled)) = context.get() {
+            self.auto_head = enabled;
+        }
+
         for (_, handler) in &mut self.handlers {
             handler.apply_context(context.clone());
         }
@@ -118,6 +245,8 @@ impl<T: ApplyContext> ApplyContext for MethodRouter<T> {
 
 impl<T: Merge> Merge for MethodRouter<T> {
     fn merge(&mut self, other: MethodRouter<T>) {
+        self.auto_options = self.auto_options || other.auto_options;
+        self.auto_head = self.auto_head || other.auto_head;
         println!("merging {:} methods with {:} methods", self.handlers.len(), other.handlers.len());
         for (method, handler) in other.handlers {
             println!("merging {:}", method);
@@ -162,6 +291,48 @@ impl<'a, T> Builder<'a, T> {
         self
     }
 
+    /// Enable or disable automatic `OPTIONS` responses and `Allow` headers
+    /// for this router.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::MethodRouter;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello world!");
+    /// }
+    ///
+    /// let mut method_router = MethodRouter::<fn(Context, Response)>::new();
+    ///
+    /// //OPTIONS requests will be answered with "Allow: GET, OPTIONS"
+    /// method_router.build().auto_options(true).on_get(handler as fn(Context, Response));
+    /// ```
+    pub fn auto_options(&mut self, enabled: bool) -> &mut Builder<'a, T> {
+        self.router.auto_options = enabled;
+        self
+    }
+
+    /// Enable or disable answering `HEAD` requests with the `GET` handler
+    /// for this router.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::MethodRouter;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello world!");
+    /// }
+    ///
+    /// let mut method_router = MethodRouter::<fn(Context, Response)>::new();
+    ///
+    /// //HEAD requests will get the same headers as GET requests
+    /// method_router.build().auto_head(true).on_get(handler as fn(Context, Response));
+    /// ```
+    pub fn auto_head(&mut self, enabled: bool) -> &mut Builder<'a, T> {
+        self.router.auto_head = enabled;
+        self
+    }
+
     /// Insert a handler for GET requests.
     ///
     /// ```
@@ -215,23 +386,64 @@ impl<'a, T> Builder<'a, T> {
         self.on(Method::Connect, handler);
     }
 
-    /// Insert a handler, similar to `on_get`, but for any HTTP method.
+    /// Insert a handler for TRACE requests. See `on_get` for an example.
+    pub fn on_trace<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(Method::Trace, handler);
+    }
+
+    /// Insert a handler for WebDAV PROPFIND requests. See `on_get` for an example.
+    pub fn on_propfind<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::propfind(), handler);
+    }
+
+    /// Insert a handler for WebDAV PROPPATCH requests. See `on_get` for an example.
+    pub fn on_proppatch<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::proppatch(), handler);
+    }
+
+    /// Insert a handler for WebDAV MKCOL requests. See `on_get` for an example.
+    pub fn on_mkcol<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::mkcol(), handler);
+    }
+
+    /// Insert a handler for WebDAV COPY requests. See `on_get` for an example.
+    pub fn on_copy<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::copy(), handler);
+    }
+
+    /// Insert a handler for WebDAV MOVE requests. See `on_get` for an example.
+    pub fn on_move<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::move_to(), handler);
+    }
+
+    /// Insert a handler for WebDAV LOCK requests. See `on_get` for an example.
+    pub fn on_lock<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::lock(), handler);
+    }
+
+    /// Insert a handler for WebDAV UNLOCK requests. See `on_get` for an example.
+    pub fn on_unlock<H>(&mut self, handler: H) where T: FromHandler<H> {
+        self.on(webdav::unlock(), handler);
+    }
+
+    /// Insert a handler, similar to `on_get`, but for any HTTP method,
+    /// including custom methods. Method names are case sensitive, so
+    /// `Method::Extension("PURGE".into())` will only match `PURGE`.
     ///
     /// ```
-    /// use rustful::{Context, Response};
+    /// use rustful::{Context, Response, Method};
     /// use rustful::handler::MethodRouter;
-    /// # fn choose_a_method() -> rustful::Method { rustful::Method::Get }
     ///
-    /// fn handler(_context: Context, response: Response) {
-    ///     response.send("Hello world!");
+    /// fn purge(_context: Context, response: Response) {
+    ///     response.send("Purged!");
     /// }
     ///
     /// let mut method_router = MethodRouter::<fn(Context, Response)>::new();
     ///
-    /// method_router.build().on(choose_a_method(), handler as fn(Context, Response));
+    /// method_router.build().on(Method::Extension("PURGE".into()), purge as fn(Context, Response));
     /// ```
     pub fn on<H>(&mut self, method: Method, handler: H) where T: FromHandler<H> {
-        self.router.handlers.insert(method, T::from_handler(self.context.clone(), handler));
+        self.router.handlers.insert(normalize(method), T::from_handler(self.context.clone(), handler));
     }
 }
 
@@ -289,6 +501,11 @@ impl<'a: 'b, 'b, T: Default + ApplyContext + Build<'b>> Builder<'a, T> {
         self.method(Method::Connect)
     }
 
+    /// Build a handler and its children, for TRACE requests. See `get` for an example.
+    pub fn trace<H>(&'b mut self) -> T::Builder where T: FromHandler<H> {
+        self.method(Method::Trace)
+    }
+
     /// Build a handler and its children, similar to `get`, but for any HTTP method.
     ///
     /// ```
@@ -307,7 +524,7 @@ impl<'a: 'b, 'b, T: Default + ApplyContext + Build<'b>> Builder<'a, T> {
     ///     .on_path("hello/world", handler as fn(Context, Response));
     /// ```
     pub fn method<H>(&'b mut self, method: Method) -> T::Builder where T: FromHandler<H> {
-        match self.router.handlers.entry(method) {
+        match self.router.handlers.entry(normalize(method)) {
             Entry::Occupied(entry) => entry.into_mut().get_builder(self.context.clone()),
             Entry::Vacant(entry) => {
                 let mut handler = T::default();
@@ -327,3 +544,117 @@ impl<'a, T: Merge + ApplyContext> Builder<'a, T> {
         self
     }
 }
+
+// Turns extension methods with standard names, like `Extension("GET")`,
+// into their standard variants, so they match parsed requests.
+fn normalize(method: Method) -> Method {
+    match method {
+        Method::Extension(name) => name.parse().unwrap_or_else(|_| Method::Extension(name)),
+        method => method
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Context, Response, Method, StatusCode};
+    use header::{Headers, Allow};
+    use server::Global;
+    use handler::{HandleRequest, Environment};
+    use super::MethodRouter;
+
+    fn handler(_context: Context, response: Response) {
+        response.send("Hello world!");
+    }
+
+    #[test]
+    fn auto_options() {
+        let mut router = MethodRouter::<fn(Context, Response)>::new();
+        router.build().auto_options(true).many(|mut node| {
+            node.on_get(handler);
+            node.on_put(handler);
+        });
+
+        let global = Global::default();
+        let options = router.handle_request(Environment {
+            context: Context::mock(Method::Options, "/", Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: "/".into(),
+        });
+        assert!(options.is_ok());
+
+        let post = router.handle_request(Environment {
+            context: Context::mock(Method::Post, "/", Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: "/".into(),
+        });
+
+        match post {
+            Ok(()) => panic!("POST should not be allowed"),
+            Err(environment) => {
+                assert_eq!(environment.response.status(), StatusCode::MethodNotAllowed);
+                assert_eq!(
+                    environment.response.headers().get::<Allow>(),
+                    Some(&Allow(vec![Method::Get, Method::Options, Method::Put]))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn auto_head() {
+        let mut router = MethodRouter::<fn(Context, Response)>::new();
+        router.build().auto_head(true).auto_options(true).on_get(|context: Context, response: Response| {
+            assert_eq!(context.method, Method::Head);
+            assert!(response.omits_body());
+        });
+
+        let global = Global::default();
+        let head = router.handle_request(Environment {
+            context: Context::mock(Method::Head, "/", Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: "/".into(),
+        });
+        assert!(head.is_ok());
+
+        let post = router.handle_request(Environment {
+            context: Context::mock(Method::Post, "/", Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: "/".into(),
+        });
+
+        match post {
+            Ok(()) => panic!("POST should not be allowed"),
+            Err(environment) => assert_eq!(
+                environment.response.headers().get::<Allow>(),
+                Some(&Allow(vec![Method::Get, Method::Head, Method::Options]))
+            )
+        }
+    }
+
+    #[test]
+    fn extension_methods() {
+        let mut router = MethodRouter::<fn(Context, Response)>::new();
+        router.build().many(|mut node| {
+            node.on(Method::Extension("PURGE".into()), handler);
+            node.on(Method::Extension("PATCH".into()), handler);
+            node.on_trace(handler);
+        });
+
+        let global = Global::default();
+        for &(ref method, allowed) in &[
+            (Method::Extension("PURGE".into()), true),
+            (Method::Extension("purge".into()), false),
+            (Method::Patch, true),
+            (Method::Trace, true),
+            (Method::Get, false)
+        ] {
+            let result = router.handle_request(Environment {
+                context: Context::mock(method.clone(), "/", Headers::new(), &global),
+                response: Response::mock(&global),
+                route_state: "/".into(),
+            });
+
+            assert_eq!(result.is_ok(), allowed, "{}", method);
+        }
+    }
+}
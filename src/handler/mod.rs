@@ -47,7 +47,9 @@
 //!useful for capturing parts of the requested path as input to the handler.
 //!The syntax for a variable is simply an indicator character (`:` or `*`)
 //!followed by a label. Variables without labels are also valid, but their
-//!values will be discarded.
+//!values will be discarded. A label can only be used once per route, and the
+//!router builders will panic if it's repeated, either directly or when
+//!merging routers.
 //!
 //!##Variable Segments (:label)
 //!
@@ -87,10 +89,11 @@
 //!
 //!# Router Composition
 //!
-//!The default router is actually a composition of three routers:
-//![`TreeRouter`][tree_router], [`MethodRouter`][method_router] and
-//![`Variables`][variables]. They come together as the type
-//!`DefaultRouter<T>`, so no need to write it all out in most of the cases.
+//!The default router is actually a composition of four routers:
+//![`TreeRouter`][tree_router], [`MethodRouter`][method_router],
+//![`Variables`][variables] and [`Filtered`][filtered]. They come together as
+//!the type `DefaultRouter<T>`, so no need to write it all out in most of the
+//!cases.
 //!
 //!There may, however, be cases where you want something else. What if you
 //!don't care about the HTTP method? Maybe your handler takes care of that
@@ -125,9 +128,11 @@
 //![tree_router]: struct.TreeRouter.html
 //![method_router]: struct.MethodRouter.html
 //![variables]: struct.Variables.html
+//![filtered]: struct.Filtered.html
 //![handle_request]: trait.HandleRequest.html
 //![build]: trait.Build.html
 
+use std::any::Any;
 use std::borrow::Cow;
 use std::sync::Arc;
 use anymap;
@@ -135,28 +140,41 @@ use anymap;
 use context::{Context, MaybeUtf8Owned};
 use context::hypermedia::Link;
 use response::{Response, SendResponse};
+use trace::Spans;
+use server::Requirements;
 use self::routing::RouteState;
 use StatusCode;
 
-pub use self::tree_router::TreeRouter;
-pub use self::method_router::MethodRouter;
+pub use self::tree_router::{TreeRouter, TrailingSlash, MatchOrder};
+pub use self::radix_router::RadixRouter;
+pub use self::method_router::{MethodRouter, AutoOptions, AutoHead};
 pub use self::variables::Variables;
+pub use self::filtered::{Filtered, RouteFilters, RouteConfig, ConcurrencyLimit, RequestHeaders, PlusAsSpace, Accepting};
 pub use self::or_else::OrElse;
+pub use self::chain::Chain;
 pub use self::status_router::StatusRouter;
+pub use self::host_router::HostRouter;
+pub use self::swappable::{Swappable, OnReplace};
 
 pub mod routing;
 
 pub mod tree_router;
+pub mod radix_router;
 pub mod method_router;
 pub mod or_else;
+pub mod chain;
 pub mod status_router;
+pub mod host_router;
+pub mod swappable;
 mod variables;
+mod filtered;
 
-///Alias for `TreeRouter<MethodRouter<Variables<T>>>`.
+///Alias for `TreeRouter<MethodRouter<Variables<Filtered<T>>>>`.
 ///
 ///This is probably the most common composition, which will select handlers
-///based on path and then HTTP method, and collect any variables on the way.
-pub type DefaultRouter<T> = TreeRouter<MethodRouter<Variables<T>>>;
+///based on path and then HTTP method, collect any variables on the way and
+///run any route specific context filters.
+pub type DefaultRouter<T> = TreeRouter<MethodRouter<Variables<Filtered<T>>>>;
 
 ///A trait for request handlers.
 pub trait Handler: Send + Sync + 'static {
@@ -168,6 +186,10 @@ pub trait Handler: Send + Sync + 'static {
     fn description(&self) -> Option<Cow<'static, str>> {
         None
     }
+
+    ///Declare the global data that the handler requires. The server will
+    ///refuse to start if any of it is missing. Default is nothing.
+    fn requirements(&self, _requirements: &mut Requirements) {}
 }
 
 impl<F: Fn(Context, Response) + Send + Sync + 'static> Handler for F {
@@ -180,12 +202,20 @@ impl<T: Handler> Handler for Arc<T> {
     fn handle(&self, context: Context, response: Response) {
         (**self).handle(context, response);
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        (**self).requirements(requirements);
+    }
 }
 
 impl Handler for Box<Handler> {
     fn handle(&self, context: Context, response: Response) {
         (**self).handle(context, response);
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        (**self).requirements(requirements);
+    }
 }
 
 ///A request environment, containing the context, response and route state.
@@ -230,11 +260,25 @@ pub trait HandleRequest: Send + Sync + 'static {
     ///base link. It's up to the handler implementation to decide how deep to
     ///go.
     fn hyperlinks<'a>(&'a self, base_link: Link<'a>) -> Vec<Link<'a>>;
+
+    ///Declare the global data that the handler, and any handlers within it,
+    ///requires. The server will refuse to start if any of it is missing.
+    ///Default is nothing.
+    fn requirements(&self, _requirements: &mut Requirements) {}
 }
 
 impl<H: Handler> HandleRequest for H {
     fn handle_request<'a, 'b, 'l, 'g>(&self, environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let spans = environment.response.filter_storage().get::<Spans>().cloned();
+        if let Some(ref spans) = spans {
+            spans.handler_start();
+        }
+
         self.handle(environment.context, environment.response);
+
+        if let Some(spans) = spans {
+            spans.handler_end();
+        }
         Ok(())
     }
 
@@ -242,6 +286,10 @@ impl<H: Handler> HandleRequest for H {
         base_link.handler = Some(self);
         vec![base_link]
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        Handler::requirements(self, requirements);
+    }
 }
 
 impl<H: HandleRequest> HandleRequest for Option<H> {
@@ -261,6 +309,12 @@ impl<H: HandleRequest> HandleRequest for Option<H> {
             vec![]
         }
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        if let Some(ref handler) = *self {
+            handler.requirements(requirements);
+        }
+    }
 }
 
 ///An adapter for simple content creation handlers.
@@ -371,14 +425,70 @@ impl<T: FromHandler<H>, H> FromHandler<H> for Option<T> {
 }
 
 /// Apply a `BuilderContext` in various ways.
+///
+/// A handler that is moved into a router, such as when another router is
+/// merged into it, is given the context from where it ends up, and the
+/// entries are combined with what the handler already has:
+///
+/// * Entries that implement [`Prepend`][prepend], such as `VariableNames`,
+///   `RouteTemplate` and `RouteFilters`, describe the route that leads to
+///   the handler. The entries from the context are put in front of the
+///   handler's own entries.
+/// * Any other entries, such as `AutoOptions`, are settings, and they
+///   replace the handler's own settings.
+///
+/// Routers should pass the whole context on to each of their children, and
+/// leave it to the handlers to pick up the entries they use. User defined
+/// entries can be added with `extend_context` or `with_context` on the
+/// router builders.
+///
+/// [prepend]: trait.Prepend.html
 pub trait ApplyContext {
-    /// Set properties, based on a given context.
+    /// Set properties, based on a given context, as described above.
     fn apply_context(&mut self, context: BuilderContext);
 
-    /// Prepend existing properties, based on a given context.
+    /// Prepend existing properties, based on a given context. Only the
+    /// `Prepend` entries are used.
     fn prepend_context(&mut self, context: BuilderContext);
 }
 
+/// A `BuilderContext` entry that is collected along a route, rather than
+/// replaced.
+///
+/// ```
+/// use rustful::handler::{Prepend, BuilderContext};
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct Breadcrumbs(Vec<String>);
+///
+/// impl Prepend for Breadcrumbs {
+///     fn prepend(&mut self, prefix: &Breadcrumbs) {
+///         let mut crumbs = prefix.0.clone();
+///         crumbs.extend(self.0.drain(..));
+///         self.0 = crumbs;
+///     }
+/// }
+///
+/// let mut context = BuilderContext::new();
+/// context.insert(Breadcrumbs(vec!["Home".into()]));
+///
+/// let mut crumbs = Breadcrumbs(vec!["Products".into()]);
+/// crumbs.prepend_from(&context);
+/// assert_eq!(crumbs, Breadcrumbs(vec!["Home".into(), "Products".into()]));
+/// ```
+pub trait Prepend: Clone + Any {
+    /// Put `prefix` in front of this entry.
+    fn prepend(&mut self, prefix: &Self);
+
+    /// Put the entry of the same type from `context` in front of this
+    /// entry, if there is one.
+    fn prepend_from(&mut self, context: &BuilderContext) where Self: Sized {
+        if let Some(prefix) = context.get::<Self>() {
+            self.prepend(prefix);
+        }
+    }
+}
+
 impl<T: Handler> ApplyContext for T {
     fn apply_context(&mut self, _context: BuilderContext) {}
     fn prepend_context(&mut self, _context: BuilderContext) {}
@@ -420,3 +530,72 @@ impl<T: Merge> Merge for Option<T> {
 ///Context type for storing path variable names.
 #[derive(Clone, Debug, Default)]
 pub struct VariableNames(pub Vec<MaybeUtf8Owned>);
+
+impl Prepend for VariableNames {
+    fn prepend(&mut self, prefix: &VariableNames) {
+        for name in &prefix.0 {
+            if !name.is_empty() && self.0.contains(name) {
+                panic!("the variable name '{}' would be used more than once in the same route", name.as_utf8_lossy());
+            }
+        }
+
+        let mut variables = prefix.0.clone();
+        variables.extend(self.0.drain(..));
+        self.0 = variables;
+    }
+}
+
+//Adds a variable name to a route while building a path based router.
+//Panics if the name is already taken, except for the empty names of
+//unnamed wildcards.
+fn push_variable(variables: &mut Cow<[MaybeUtf8Owned]>, name: &[u8]) {
+    if !name.is_empty() && variables.iter().any(|existing| existing.as_ref() == name) {
+        panic!("the variable name '{}' is used more than once in the same route", String::from_utf8_lossy(name));
+    }
+
+    variables.to_mut().push(name.to_owned().into());
+}
+
+///Context type for storing the route pattern that leads to a handler, such
+///as `/users/:id/posts/*rest`.
+///
+///It's collected by the path based routers while building, and `Variables`
+///makes it available to the request handlers through
+///`Context::route_template`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteTemplate(pub String);
+
+impl RouteTemplate {
+    ///Borrow the template as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    //Adds a path segment to the template in a `BuilderContext`.
+    fn push_segment(context: &mut Cow<BuilderContext>, segment: &[u8]) {
+        let context = context.to_mut();
+        let mut template = context.remove::<RouteTemplate>().unwrap_or_default();
+        if !template.0.ends_with('/') {
+            template.0.push('/');
+        }
+        template.0.push_str(&String::from_utf8_lossy(segment));
+        context.insert(template);
+    }
+}
+
+impl Prepend for RouteTemplate {
+    fn prepend(&mut self, prefix: &RouteTemplate) {
+        let prefix = prefix.0.trim_end_matches('/');
+        if self.0 == "/" {
+            self.0 = if prefix.is_empty() { "/".into() } else { prefix.into() };
+        } else {
+            self.0.insert_str(0, prefix);
+        }
+    }
+}
+
+impl Default for RouteTemplate {
+    fn default() -> RouteTemplate {
+        RouteTemplate("/".into())
+    }
+}
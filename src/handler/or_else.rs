@@ -1,6 +1,7 @@
 //! A router that selects a secondary handler on error.
 
 use context::hypermedia::Link;
+use server::Requirements;
 use handler::{HandleRequest, Environment, Build, BuilderContext, ApplyContext, Merge};
 
 /// A router that selects a secondary handler on error.
@@ -36,7 +37,7 @@ impl<A, B> OrElse<A, B> {
         }
     }
 
-    /// Build the router and its children using a chaninable API.
+    /// Build the router and its children using a chainable API.
     ///
     /// ```
     /// use rustful::{Context, Response, OrElse};
@@ -98,6 +99,11 @@ impl<A: HandleRequest, B: HandleRequest> HandleRequest for OrElse<A, B> {
         links.extend(self.secondary.hyperlinks(base.clone()));
         links
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.primary.requirements(requirements);
+        self.secondary.requirements(requirements);
+    }
 }
 
 impl<A: Default, B: Default> Default for OrElse<A, B> {
@@ -0,0 +1,700 @@
+//! A radix tree router for large, mostly static, routing tables.
+
+use std::iter::{Iterator, IntoIterator, FromIterator};
+use std::any::Any;
+use std::borrow::Cow;
+use std::sync::Arc;
+use hyper::method::Method;
+
+use context::MaybeUtf8Owned;
+use context::hypermedia::Link;
+use server::Requirements;
+use handler::{HandleRequest, Environment, MethodRouter, Variables, Build, FromHandler, ApplyContext, Merge, BuilderContext, Prepend, VariableNames, RouteTemplate, RouteFilters, RouteConfig, push_variable};
+use handler::routing::{Route, RouteState};
+use filter::ContextFilter;
+use StatusCode;
+
+/// A radix tree router that selects handlers using paths.
+///
+/// It's an alternative to `TreeRouter`, for when there are a lot of routes.
+/// Each static path segment still gets its own node, but the children of a
+/// node are kept in a sorted list instead of a `HashMap`, and sibling
+/// segments that start with the same bytes share a node for their common
+/// prefix. This makes the lookups cheaper when most of the routes are
+/// static, since the path is matched byte by byte, without hashing each
+/// segment.
+///
+/// The route syntax is the same as for `TreeRouter`, including variable
+/// segments (`:label`) and variable sequences (`*label`), and the same
+/// priorities apply: static segments are tried first, then variable
+/// segments and finally variable sequences. Hyperlinks to child routes are
+/// not supported.
+///
+/// ```
+/// use rustful::{Context, Response};
+/// use rustful::handler::{RadixRouter, MethodRouter, Variables};
+///
+/// fn show_user(context: Context, response: Response) {
+///     let id = context.variables.get("id").unwrap_or("?".into());
+///     response.send(format!("user {}", id));
+/// }
+///
+/// fn list_users(_context: Context, response: Response) {
+///     response.send("every user");
+/// }
+///
+/// let mut router = RadixRouter::<MethodRouter<Variables<fn(Context, Response)>>>::new();
+/// router.build().path("users").many(|mut node| {
+///     node.then().on_get(list_users);
+///     node.path(":id").then().on_get(show_user);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct RadixRouter<T> {
+    prefix: Vec<u8>,
+    item: T,
+    endpoint: bool,
+    static_routes: Vec<RadixRouter<T>>,
+    variable_route: Option<Box<RadixRouter<T>>>,
+    wildcard_route: Option<Box<RadixRouter<T>>>,
+}
+
+impl<T: Default> RadixRouter<T> {
+    /// Creates an empty `RadixRouter`.
+    pub fn new() -> RadixRouter<T> {
+        RadixRouter::default()
+    }
+}
+
+impl<T> RadixRouter<T> {
+    /// Creates a `RadixRouter` with only a root handler.
+    pub fn with_handler(handler: T) -> RadixRouter<T> {
+        RadixRouter {
+            prefix: vec![],
+            item: handler,
+            endpoint: true,
+            static_routes: vec![],
+            variable_route: None,
+            wildcard_route: None,
+        }
+    }
+
+    /// Build the router and its children using a chainable API.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::RadixRouter;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello world!");
+    /// }
+    ///
+    /// let mut router = RadixRouter::<Option<fn(Context, Response)>>::new();
+    /// router.build().on_path("hello/world", handler);
+    /// ```
+    pub fn build(&mut self) -> Builder<T> {
+        self.get_builder(BuilderContext::new())
+    }
+
+    // Finds the node that matches the rest of the route.
+    fn find<'s>(&'s self, state: &mut RouteState) -> Option<&'s RadixRouter<T>> {
+        let start = state.snapshot();
+
+        for (node, snapshot) in self.static_matches(state).into_iter().rev() {
+            state.go_to(snapshot);
+            if let Some(found) = node.find_here(state) {
+                return Some(found);
+            }
+        }
+
+        state.go_to(start);
+        self.find_here(state)
+    }
+
+    // Checks if the route ends at this node, or continues with a variable.
+    // Nodes without a handler of their own are skipped, so that variables
+    // in the parent nodes can be tried instead.
+    fn find_here<'s>(&'s self, state: &mut RouteState) -> Option<&'s RadixRouter<T>> {
+        if state.is_empty() {
+            return if self.endpoint { Some(self) } else { None };
+        }
+
+        let snapshot = state.snapshot();
+
+        if let Some(ref next) = self.variable_route {
+            state.keep();
+            if let Some(found) = next.find(state) {
+                return Some(found);
+            }
+            state.go_to(snapshot);
+        }
+
+        if let Some(ref next) = self.wildcard_route {
+            let mut fused = snapshot;
+            loop {
+                state.go_to(fused);
+                if state.is_empty() {
+                    break;
+                }
+
+                state.keep();
+                if let Some(found) = next.find(state) {
+                    return Some(found);
+                }
+
+                state.go_to(fused);
+                state.fuse();
+                fused = state.snapshot();
+            }
+            state.go_to(snapshot);
+        }
+
+        None
+    }
+
+    // Follows the static prefixes as far as possible and returns each node
+    // that ends where a path segment ends, starting with the closest one.
+    fn static_matches<'s>(&'s self, state: &mut RouteState) -> Vec<(&'s RadixRouter<T>, (usize, usize))> {
+        let mut matches = vec![];
+        let mut node = self;
+        let mut matched = node.prefix.len();
+
+        while let Some(segment) = state.get() {
+            for &byte in Some(&b'/').into_iter().chain(segment) {
+                if matched == node.prefix.len() {
+                    node = match node.find_child(byte) {
+                        Some(child) => child,
+                        None => return matches
+                    };
+                    matched = 1;
+                } else if node.prefix[matched] == byte {
+                    matched += 1;
+                } else {
+                    return matches;
+                }
+            }
+
+            state.skip();
+            if matched == node.prefix.len() {
+                matches.push((node, state.snapshot()));
+            }
+        }
+
+        matches
+    }
+
+    fn find_child(&self, first: u8) -> Option<&RadixRouter<T>> {
+        self.static_routes
+            .binary_search_by_key(&first, |node| node.prefix[0])
+            .ok()
+            .map(|index| &self.static_routes[index])
+    }
+}
+
+impl<T: Default> RadixRouter<T> {
+    // Tries to find a router matching the label or inserts a new one if none exists.
+    fn find_or_insert_router<'a, F: FnOnce() -> T>(&'a mut self, label: &[u8], create_handler: F) -> &'a mut RadixRouter<T> {
+        match label.get(0) {
+            Some(&b'*') => {
+                if self.wildcard_route.is_none() {
+                    self.wildcard_route = Some(Box::new(RadixRouter::path_node(create_handler())));
+                }
+                &mut **self.wildcard_route.as_mut().unwrap()
+            },
+            Some(&b':') => {
+                if self.variable_route.is_none() {
+                    self.variable_route = Some(Box::new(RadixRouter::path_node(create_handler())));
+                }
+                &mut **self.variable_route.as_mut().unwrap()
+            },
+            _ => {
+                let mut key = Vec::with_capacity(label.len() + 1);
+                key.push(b'/');
+                key.extend_from_slice(label);
+                self.find_or_insert_static(&key, create_handler)
+            }
+        }
+    }
+
+    fn find_or_insert_static<'a, F: FnOnce() -> T>(&'a mut self, key: &[u8], create_handler: F) -> &'a mut RadixRouter<T> {
+        if key.is_empty() {
+            return self;
+        }
+
+        let index = match self.static_routes.binary_search_by_key(&key[0], |node| node.prefix[0]) {
+            Ok(index) => index,
+            Err(index) => {
+                let mut node = RadixRouter::path_node(create_handler());
+                node.prefix = key.to_owned();
+                self.static_routes.insert(index, node);
+                return &mut self.static_routes[index];
+            }
+        };
+
+        let node = &mut self.static_routes[index];
+        let common = common_prefix(&node.prefix, key);
+
+        if common < node.prefix.len() {
+            node.split(common);
+            if common == key.len() {
+                node.item = create_handler();
+                return node;
+            }
+        }
+
+        node.find_or_insert_static(&key[common..], create_handler)
+    }
+
+    // Moves everything after `at` in the prefix to a new child node.
+    fn split(&mut self, at: usize) {
+        let mut tail = RadixRouter::with_handler(::std::mem::replace(&mut self.item, T::default()));
+        tail.prefix = self.prefix.split_off(at);
+        tail.static_routes = ::std::mem::replace(&mut self.static_routes, vec![]);
+        tail.variable_route = self.variable_route.take();
+        tail.wildcard_route = self.wildcard_route.take();
+        tail.endpoint = self.endpoint;
+        self.endpoint = false;
+        self.static_routes.push(tail);
+    }
+
+    // Creates a node that is only a part of a path, until it gets a handler.
+    fn path_node(item: T) -> RadixRouter<T> {
+        let mut node = RadixRouter::with_handler(item);
+        node.endpoint = false;
+        node
+    }
+}
+
+impl<T: Merge + Default> RadixRouter<T> {
+    // Inserts a node, with a prefix that is relative to this node, and
+    // merges it with any existing node with the same prefix.
+    fn insert_node(&mut self, mut other: RadixRouter<T>) {
+        let index = match self.static_routes.binary_search_by_key(&other.prefix[0], |node| node.prefix[0]) {
+            Ok(index) => index,
+            Err(index) => {
+                self.static_routes.insert(index, other);
+                return;
+            }
+        };
+
+        let node = &mut self.static_routes[index];
+        let common = common_prefix(&node.prefix, &other.prefix);
+
+        if common < node.prefix.len() {
+            node.split(common);
+        }
+
+        if common == other.prefix.len() {
+            node.merge(other);
+        } else {
+            other.prefix.drain(..common);
+            node.insert_node(other);
+        }
+    }
+}
+
+impl<T: FromHandler<H> + ApplyContext, D: AsRef<[u8]>, H> FromIterator<(Method, D, H)> for RadixRouter<MethodRouter<Variables<T>>> {
+    /// Create a `RadixRouter` from a collection of routes.
+    ///
+    /// ```
+    /// extern crate rustful;
+    /// use rustful::Method::Get;
+    /// use rustful::handler::{RadixRouter, MethodRouter, Variables};
+    /// # use rustful::{Handler, Context, Response};
+    ///
+    /// # struct ExampleHandler;
+    /// # impl Handler for ExampleHandler {
+    /// #     fn handle(&self, _: Context, _: Response){}
+    /// # }
+    /// # fn main() {
+    /// # let about_us = ExampleHandler;
+    /// # let show_user = ExampleHandler;
+    /// # let list_users = ExampleHandler;
+    /// let routes = vec![
+    ///     (Get, "/about", about_us),
+    ///     (Get, "/users", list_users),
+    ///     (Get, "/users/:id", show_user),
+    /// ];
+    ///
+    /// let router: RadixRouter<MethodRouter<Variables<ExampleHandler>>> = routes.into_iter().collect();
+    /// # }
+    /// ```
+    fn from_iter<I: IntoIterator<Item=(Method, D, H)>>(iterator: I) -> RadixRouter<MethodRouter<Variables<T>>> {
+        let mut root = RadixRouter::<MethodRouter<Variables<T>>>::new();
+
+        for (method, route, item) in iterator {
+            root.build().path(route).then().on(method, item);
+        }
+
+        root
+    }
+}
+
+impl<T: HandleRequest> HandleRequest for RadixRouter<T> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        let found = self.find(&mut environment.route_state);
+
+        if let Some(node) = found {
+            let (new_environment, old_hyperlinks) = environment.replace_hyperlinks(vec![]);
+            node.item.handle_request(new_environment).map_err(|returned_environment| {
+                returned_environment.replace_hyperlinks(old_hyperlinks).0
+            })
+        } else {
+            environment.response.set_status(StatusCode::NotFound);
+            Err(environment)
+        }
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.item.hyperlinks(base)
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.item.requirements(requirements);
+
+        for next in &self.static_routes {
+            next.requirements(requirements);
+        }
+
+        if let Some(ref next) = self.variable_route {
+            next.requirements(requirements);
+        }
+
+        if let Some(ref next) = self.wildcard_route {
+            next.requirements(requirements);
+        }
+    }
+}
+
+impl<T: Default> Default for RadixRouter<T> {
+    fn default() -> RadixRouter<T> {
+        RadixRouter::path_node(T::default())
+    }
+}
+
+impl<'a, T: 'a> Build<'a> for RadixRouter<T> {
+    type Builder = Builder<'a, T>;
+
+    fn get_builder(&'a mut self, mut context: BuilderContext) -> Builder<'a, T> {
+        if !context.contains::<RouteTemplate>() {
+            context.insert(RouteTemplate::default());
+        }
+
+        Builder {
+            node: self,
+            variables: Cow::Owned(context.remove::<VariableNames>().unwrap_or_default().0),
+            context: Cow::Owned(context)
+        }
+    }
+}
+
+impl<T: FromHandler<H>, H> FromHandler<H> for RadixRouter<T> {
+    fn from_handler(context: BuilderContext, handler: H) -> RadixRouter<T> {
+        RadixRouter::with_handler(T::from_handler(context, handler))
+    }
+}
+
+impl<T: ApplyContext> ApplyContext for RadixRouter<T> {
+    fn apply_context(&mut self, context: BuilderContext) {
+        self.item.apply_context(context.clone());
+
+        for node in &mut self.static_routes {
+            node.apply_context(context.clone());
+        }
+
+        if let Some(ref mut node) = self.variable_route {
+            node.apply_context(context.clone())
+        }
+
+        if let Some(ref mut node) = self.wildcard_route {
+            node.apply_context(context)
+        }
+    }
+
+    fn prepend_context(&mut self, context: BuilderContext) {
+        self.item.prepend_context(context.clone());
+
+        for node in &mut self.static_routes {
+            node.prepend_context(context.clone());
+        }
+
+        if let Some(ref mut node) = self.variable_route {
+            node.prepend_context(context.clone())
+        }
+
+        if let Some(ref mut node) = self.wildcard_route {
+            node.prepend_context(context)
+        }
+    }
+}
+
+impl<T: Merge + Default> Merge for RadixRouter<T> {
+    fn merge(&mut self, other: RadixRouter<T>) {
+        self.item.merge(other.item);
+        self.endpoint |= other.endpoint;
+
+        for other_node in other.static_routes {
+            self.insert_node(other_node);
+        }
+
+        if let Some(ref mut this_node) = self.variable_route {
+            if let Some(other_node) = other.variable_route {
+                this_node.merge(*other_node);
+            }
+        } else {
+            self.variable_route = other.variable_route;
+        }
+
+        if let Some(ref mut this_node) = self.wildcard_route {
+            if let Some(other_node) = other.wildcard_route {
+                this_node.merge(*other_node);
+            }
+        } else {
+            self.wildcard_route = other.wildcard_route;
+        }
+    }
+}
+
+/// A builder for a `RadixRouter`.
+pub struct Builder<'a, T: 'a> {
+    node: &'a mut RadixRouter<T>,
+    variables: Cow<'a, [MaybeUtf8Owned]>,
+    context: Cow<'a, BuilderContext>
+}
+
+impl<'a, T: Default + ApplyContext> Builder<'a, T> {
+    /// Add a path to the router and keep building the resulting node.
+    pub fn path<'b, S: AsRef<[u8]>>(&'b mut self, path: S) -> Builder<'b, T> {
+        let mut variables = self.variables.clone();
+        let mut context = self.context.clone();
+
+        let node = path.as_ref().segments().fold(&mut *self.node, |node, segment| {
+            if segment[0] == b':' || segment[0] == b'*' {
+                push_variable(&mut variables, &segment[1..]);
+            }
+            RouteTemplate::push_segment(&mut context, segment);
+
+            node.find_or_insert_router(segment, || {
+                let mut new_context = context.clone().into_owned();
+                new_context.insert(VariableNames(variables.clone().into_owned()));
+                let mut handler = T::default();
+                handler.apply_context(new_context);
+                handler
+            })
+        });
+
+        Builder {
+            node: node,
+            variables: variables,
+            context: context
+        }
+    }
+
+    /// Add a handler at the end of a path and keep building the resulting node.
+    pub fn on_path<'b, S: AsRef<[u8]>, H>(&'b mut self, path: S, handler: H) -> Builder<'b, T> where T: FromHandler<H> {
+        let mut builder = self.path(path);
+        builder.handler(handler);
+        builder
+    }
+}
+
+impl<'a, T> Builder<'a, T> {
+    /// Perform more than one operation on this builder.
+    pub fn many<F: FnOnce(&mut Builder<'a, T>)>(&mut self, build: F) -> &mut Builder<'a, T> {
+        build(self);
+        self
+    }
+
+    /// Set or replace the handler at the current node.
+    pub fn handler<'b, H>(&'b mut self, handler: H) -> Builder<'b, T> where T: FromHandler<H> {
+        let mut new_context = self.context.clone().into_owned();
+        new_context.insert(VariableNames(self.variables.clone().into_owned()));
+        self.node.item = T::from_handler(new_context, handler);
+        self.node.endpoint = true;
+
+        Builder {
+            node: self.node,
+            variables: self.variables.clone(),
+            context: self.context.clone()
+        }
+    }
+
+    /// Add a context filter to every handler that is added through this
+    /// builder, or any builder that is derived from it. See
+    /// `tree_router::Builder::with_filter` for more details.
+    pub fn with_filter<F: ContextFilter + 'static>(&mut self, filter: F) -> &mut Builder<'a, T> {
+        self.extend_context(RouteFilters(vec![Arc::new(filter)]))
+    }
+
+    /// Make a configuration value available to every handler that is added
+    /// through this builder, or any builder that is derived from it. It can
+    /// be accessed using `Context::config`.
+    pub fn with_config<C: Send + Sync + 'static>(&mut self, config: C) -> &mut Builder<'a, T> {
+        self.with_filter(RouteConfig::new(config))
+    }
+
+    /// Add an entry to the `BuilderContext` of every handler that is added
+    /// through this builder, or any builder that is derived from it,
+    /// replacing any entry of the same type. See
+    /// `tree_router::Builder::with_context` for more details.
+    pub fn with_context<C: Clone + Any>(&mut self, entry: C) -> &mut Builder<'a, T> {
+        self.context.to_mut().insert(entry);
+        self
+    }
+
+    /// Add an entry to the `BuilderContext` of every handler that is added
+    /// through this builder, or any builder that is derived from it, after
+    /// any entry of the same type. See `tree_router::Builder::extend_context`
+    /// for more details.
+    pub fn extend_context<C: Prepend>(&mut self, mut entry: C) -> &mut Builder<'a, T> {
+        let context = self.context.to_mut();
+        entry.prepend_from(context);
+        context.insert(entry);
+        self
+    }
+}
+
+impl<'a: 'b, 'b, T: Build<'b>> Builder<'a, T> {
+    /// Build the handler at the current node.
+    pub fn then(&'b mut self) -> T::Builder {
+        let mut new_context = self.context.clone().into_owned();
+        new_context.insert(VariableNames(self.variables.clone().into_owned()));
+        self.node.endpoint = true;
+        self.node.item.get_builder(new_context)
+    }
+}
+
+impl<'a, T: Merge + ApplyContext + Default> Builder<'a, T> {
+    ///Move handlers from another router into this, overwriting conflicting handlers and properties.
+    pub fn merge(&mut self, mut other: RadixRouter<T>) -> &mut Builder<'a, T> {
+        let mut new_context = self.context.clone().into_owned();
+        new_context.insert(VariableNames(self.variables.clone().into_owned()));
+        other.apply_context(new_context);
+
+        self.node.merge(other);
+
+        self
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|&(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::RadixRouter;
+    use context::Context;
+    use response::Response;
+    use handler::{Handler, HandleRequest, Environment, MethodRouter, Variables};
+    use header::Headers;
+    use server::Global;
+    use Method::Get;
+
+    type TestRouter = RadixRouter<MethodRouter<Variables<TestHandler>>>;
+
+    struct TestHandler {
+        name: &'static str,
+        visited: Arc<Mutex<Option<(&'static str, Vec<(String, String)>)>>>,
+    }
+
+    impl Handler for TestHandler {
+        fn handle(&self, context: Context, _: Response) {
+            let mut variables: Vec<_> = context.variables.into_iter()
+                .map(|(key, value)| (key.as_utf8_lossy().into_owned(), value.as_utf8_lossy().into_owned()))
+                .collect();
+            variables.sort();
+            *self.visited.lock().unwrap() = Some((self.name, variables));
+        }
+    }
+
+    fn add(router: &mut TestRouter, visited: &Arc<Mutex<Option<(&'static str, Vec<(String, String)>)>>>, path: &'static str) {
+        router.build().path(path).then().on_get(TestHandler {
+            name: path,
+            visited: visited.clone(),
+        });
+    }
+
+    fn check(router: &TestRouter, visited: &Arc<Mutex<Option<(&'static str, Vec<(String, String)>)>>>, path: &str) -> Option<(&'static str, Vec<(String, String)>)> {
+        let global = Global::default();
+        let result = router.handle_request(Environment {
+            context: Context::mock(Get, path, Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: path.into(),
+        });
+
+        let visited = visited.lock().unwrap().take();
+        assert_eq!(result.is_ok(), visited.is_some());
+        visited
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()
+    }
+
+    #[test]
+    fn static_routes() {
+        let visited = Arc::new(Mutex::new(None));
+        let mut router = TestRouter::new();
+        for path in &["/", "users/list", "users", "user", "users/new", "products/list"] {
+            add(&mut router, &visited, *path);
+        }
+
+        for path in &["/", "users/list", "users", "user", "users/new", "products/list"] {
+            assert_eq!(check(&router, &visited, path), Some((*path, vec![])));
+        }
+
+        assert_eq!(check(&router, &visited, "use"), None);
+        assert_eq!(check(&router, &visited, "users/li"), None);
+        assert_eq!(check(&router, &visited, "products"), None);
+        assert_eq!(check(&router, &visited, "users/list/more"), None);
+    }
+
+    #[test]
+    fn variable_routes() {
+        let visited = Arc::new(Mutex::new(None));
+        let mut router = TestRouter::new();
+        add(&mut router, &visited, "users/:id");
+        add(&mut router, &visited, "users/new");
+        add(&mut router, &visited, "users/:id/posts/*path");
+        add(&mut router, &visited, "files/*path/raw");
+
+        assert_eq!(check(&router, &visited, "users/new"), Some(("users/new", vec![])));
+        assert_eq!(check(&router, &visited, "users/7"), Some(("users/:id", vars(&[("id", "7")]))));
+        assert_eq!(check(&router, &visited, "users/new/posts/a/b"), Some(("users/:id/posts/*path", vars(&[("id", "new"), ("path", "a/b")]))));
+        assert_eq!(check(&router, &visited, "files/a/b/raw"), Some(("files/*path/raw", vars(&[("path", "a/b")]))));
+        assert_eq!(check(&router, &visited, "files/raw"), None);
+    }
+
+    #[test]
+    fn skip_split_prefixes() {
+        let visited = Arc::new(Mutex::new(None));
+        let mut router = TestRouter::new();
+        add(&mut router, &visited, "users");
+        add(&mut router, &visited, "useful");
+        add(&mut router, &visited, ":name");
+
+        assert_eq!(check(&router, &visited, "users"), Some(("users", vec![])));
+        assert_eq!(check(&router, &visited, "useful"), Some(("useful", vec![])));
+        assert_eq!(check(&router, &visited, "use"), Some((":name", vars(&[("name", "use")]))));
+        assert_eq!(check(&router, &visited, "user"), Some((":name", vars(&[("name", "user")]))));
+    }
+
+    #[test]
+    fn merge_routers() {
+        let visited = Arc::new(Mutex::new(None));
+        let mut router = TestRouter::new();
+        add(&mut router, &visited, "users/list");
+
+        let mut other = TestRouter::new();
+        add(&mut other, &visited, "users");
+        add(&mut other, &visited, "useful");
+        router.build().merge(other);
+
+        assert_eq!(check(&router, &visited, "users/list"), Some(("users/list", vec![])));
+        assert_eq!(check(&router, &visited, "users"), Some(("users", vec![])));
+        assert_eq!(check(&router, &visited, "useful"), Some(("useful", vec![])));
+    }
+}
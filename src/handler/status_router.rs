@@ -4,6 +4,7 @@ use std::collections::hash_map::{HashMap, Entry};
 
 use context::hypermedia::Link;
 use StatusCode;
+use server::Requirements;
 use handler::{HandleRequest, Environment, Build, ApplyContext, Merge, FromHandler, BuilderContext};
 
 /// A router that selects an item from an HTTP status code.
@@ -20,7 +21,7 @@ impl<T> StatusRouter<T> {
         StatusRouter::default()
     }
 
-    /// Build the router and its children using a chaninable API.
+    /// Build the router and its children using a chainable API.
     ///
     /// ```
     /// use rustful::{Context, Response, StatusCode, StatusRouter};
@@ -74,6 +75,12 @@ impl<T: HandleRequest> HandleRequest for StatusRouter<T> {
             item.hyperlinks(base.clone())
         }).collect()
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        for handler in self.handlers.values() {
+            handler.requirements(requirements);
+        }
+    }
 }
 
 impl<T> Default for StatusRouter<T> {
@@ -0,0 +1,249 @@
+//! A handler that can be replaced while the server is running.
+
+use std::sync::{Arc, RwLock, Mutex};
+
+use context::hypermedia::Link;
+use server::{Global, Requirements, MissingGlobals};
+use handler::{HandleRequest, Environment};
+
+/// A handler that can be replaced while the server is running.
+///
+/// Every copy of a `Swappable` shares the same handler, so a copy can be
+/// kept after starting the server, or given to an admin handler, and used
+/// to replace the whole handler tree. Requests that are already being
+/// handled keep using the old handler, and connections are not affected.
+///
+/// A new handler is checked against the server's global data, just like
+/// when the server is started, so it has to be replaced from somewhere
+/// that can borrow the `Global`, such as from another handler:
+///
+/// ```no_run
+/// use rustful::{Server, Context, Response, DefaultRouter};
+/// use rustful::handler::Swappable;
+///
+/// fn hello(_context: Context, response: Response) {
+///     response.send("Hello!");
+/// }
+///
+/// fn goodbye(_context: Context, response: Response) {
+///     response.send("Goodbye!");
+/// }
+///
+/// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+/// router.build().path("hello").then().on_get(hello);
+///
+/// let handlers = Swappable::new(router);
+///
+/// let _server = Server {
+///     host: 8080.into(),
+///     ..Server::new(handlers.clone())
+/// }.run();
+///
+/// //Later, when the routes have changed:
+/// fn reload(handlers: &Swappable<DefaultRouter<fn(Context, Response)>>, context: Context, response: Response) {
+///     let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///     router.build().many(|mut node| {
+///         node.path("hello").then().on_get(hello);
+///         node.path("goodbye").then().on_get(goodbye);
+///     });
+///
+///     match handlers.replace(router, context.global) {
+///         Ok(_) => response.send("the routes were replaced"),
+///         Err(e) => response.send(format!("the routes were not replaced: {}", e)),
+///     }
+/// }
+/// ```
+///
+/// Anything that is derived from the handler, such as cached responses or
+/// hyperlinks, can be kept up to date by adding an [`OnReplace`][on_replace]
+/// listener, that is called each time the handler is replaced.
+///
+/// The current handler can't be borrowed for longer than a request, so a
+/// `Swappable` doesn't list any hyperlinks.
+///
+/// [on_replace]: trait.OnReplace.html
+pub struct Swappable<H> {
+    current: Arc<RwLock<Arc<H>>>,
+    listeners: Arc<Mutex<Vec<Box<OnReplace<H>>>>>,
+}
+
+impl<H: HandleRequest> Swappable<H> {
+    /// Start with `handler` as the current handler.
+    pub fn new(handler: H) -> Swappable<H> {
+        Swappable {
+            current: Arc::new(RwLock::new(Arc::new(handler))),
+            listeners: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Call `listener` each time the handler is replaced, from any copy of
+    /// this `Swappable`.
+    pub fn on_replace<L: OnReplace<H>>(&self, listener: L) {
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(listener));
+    }
+
+    /// Use `handler` for every new request from now on, and get the old
+    /// handler back. The current handler is kept if `global` is missing
+    /// any of the data that `handler` requires.
+    ///
+    /// The `OnReplace` listeners are called before this returns, with the
+    /// new handler already in use.
+    pub fn replace(&self, handler: H, global: &Global) -> Result<Arc<H>, MissingGlobals> {
+        let mut requirements = Requirements::new();
+        handler.requirements(&mut requirements);
+        requirements.check(global)?;
+
+        //Hold on to the listeners until they are called, so they see the
+        //replacements in the same order as the requests
+        let listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+
+        let new = Arc::new(handler);
+        let old = {
+            let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+            ::std::mem::replace(&mut *current, new.clone())
+        };
+
+        for listener in listeners.iter() {
+            listener.replaced(&old, &new);
+        }
+
+        Ok(old)
+    }
+
+    /// Get the current handler.
+    pub fn current(&self) -> Arc<H> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl<H> Clone for Swappable<H> {
+    fn clone(&self) -> Swappable<H> {
+        Swappable {
+            current: self.current.clone(),
+            listeners: self.listeners.clone(),
+        }
+    }
+}
+
+impl<H: HandleRequest> HandleRequest for Swappable<H> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        //Don't block replacements while the request is handled
+        self.current().handle_request(environment)
+    }
+
+    fn hyperlinks<'a>(&'a self, _base: Link<'a>) -> Vec<Link<'a>> {
+        vec![]
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.current().requirements(requirements);
+    }
+}
+
+/// Gets notified when the handler of a `Swappable` is replaced.
+///
+/// It's called while the replacement is finishing, so it should not replace
+/// the handler or add listeners to the same `Swappable`.
+pub trait OnReplace<H>: Send + Sync + 'static {
+    /// `old` has been replaced by `new`.
+    fn replaced(&self, old: &H, new: &H);
+}
+
+impl<H, F: Fn(&H, &H) + Send + Sync + 'static> OnReplace<H> for F {
+    fn replaced(&self, old: &H, new: &H) {
+        self(old, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use hyper::method::Method;
+
+    use {Context, Response, StatusCode};
+    use header::Headers;
+    use server::{Global, Requirements};
+    use handler::{Handler, HandleRequest, Environment, TreeRouter};
+    use super::Swappable;
+
+    fn handler(_context: Context, _response: Response) {}
+
+    struct NeedsNumber;
+
+    impl Handler for NeedsNumber {
+        fn handle(&self, _context: Context, _response: Response) {}
+
+        fn requirements(&self, requirements: &mut Requirements) {
+            requirements.require::<u32>();
+        }
+    }
+
+    #[test]
+    fn replace_handler() {
+        let mut router = TreeRouter::<Option<fn(Context, Response)>>::new();
+        router.build().on_path("a", handler as fn(Context, Response));
+        let handlers = Swappable::new(router);
+        let copy = handlers.clone();
+
+        let global = Global::default();
+        let handled = |path: &str| handlers.handle_request(Environment {
+            context: Context::mock(Method::Get, path, Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: path.into(),
+        }).is_ok();
+
+        assert!(handled("a"));
+        assert!(!handled("b"));
+
+        let mut router = TreeRouter::<Option<fn(Context, Response)>>::new();
+        router.build().on_path("b", handler as fn(Context, Response));
+        let old = copy.replace(router, &global).unwrap();
+
+        assert!(!handled("a"));
+        assert!(handled("b"));
+        assert_eq!(old.handle_request(Environment {
+            context: Context::mock(Method::Get, "b", Headers::new(), &global),
+            response: Response::mock(&global),
+            route_state: "b".into(),
+        }).err().map(|environment| environment.response.status()), Some(StatusCode::NotFound));
+    }
+
+    #[test]
+    fn reject_missing_globals() {
+        let handlers = Swappable::new(TreeRouter::<Option<Box<Handler>>>::new());
+        let original = handlers.current();
+
+        let mut router = TreeRouter::<Option<Box<Handler>>>::new();
+        router.build().on_path("a", Box::new(NeedsNumber) as Box<Handler>);
+        let missing = handlers.replace(router, &Global::default()).err().map(|e| e.0);
+        assert_eq!(missing, Some(vec!["u32"]));
+        assert!(Arc::ptr_eq(&original, &handlers.current()));
+
+        let mut router = TreeRouter::<Option<Box<Handler>>>::new();
+        router.build().on_path("a", Box::new(NeedsNumber) as Box<Handler>);
+        assert!(handlers.replace(router, &Box::new(5u32).into()).is_ok());
+    }
+
+    struct Version(u8);
+
+    impl Handler for Version {
+        fn handle(&self, _context: Context, _response: Response) {}
+    }
+
+    #[test]
+    fn notify_on_replace() {
+        let handlers = Swappable::new(Version(1));
+        let replaced = Arc::new(Mutex::new(vec![]));
+
+        let listener_replaced = replaced.clone();
+        handlers.clone().on_replace(move |old: &Version, new: &Version| {
+            listener_replaced.lock().unwrap().push((old.0, new.0));
+        });
+
+        let global = Global::default();
+        handlers.replace(Version(2), &global).unwrap();
+        handlers.clone().replace(Version(3), &global).unwrap();
+
+        assert_eq!(*replaced.lock().unwrap(), vec![(1, 2), (2, 3)]);
+    }
+}
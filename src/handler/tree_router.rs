@@ -3,24 +3,81 @@
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::iter::{Iterator, IntoIterator, FromIterator};
+use std::any::Any;
 use std::borrow::Cow;
+use std::str::from_utf8;
+use std::sync::Arc;
 use hyper::method::Method;
+use hyper::header::Location;
+use url::percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 
-use context::{MaybeUtf8Owned, MaybeUtf8Slice};
+use context::{MaybeUtf8Owned, MaybeUtf8Slice, UriPath};
 use context::hypermedia::{Link, LinkSegment, SegmentType};
-use handler::{HandleRequest, Environment, MethodRouter, Variables, Build, FromHandler, ApplyContext, Merge, BuilderContext, VariableNames};
+use server::Requirements;
+use handler::{HandleRequest, Environment, MethodRouter, Variables, Build, FromHandler, ApplyContext, Merge, BuilderContext, Prepend, VariableNames, RouteTemplate, RouteFilters, RouteConfig, Accepting, push_variable};
+use mime::Mime;
+use filter::ContextFilter;
 use handler::routing::Route;
 use StatusCode;
 
 use self::Branch::{Static, Variable, Wildcard};
 
-#[derive(PartialEq)]
+#[derive(Clone)]
+struct RouteName {
+    name: String,
+    variables: Vec<MaybeUtf8Owned>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
 enum Branch {
     Static,
     Variable,
     Wildcard
 }
 
+/// How a `TreeRouter` treats a trailing slash in a path.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrailingSlash {
+    /// Ignore trailing slashes, so `/foo` and `/foo/` are the same route.
+    /// This is the default.
+    Ignore,
+
+    /// Treat `/foo` and `/foo/` as different routes.
+    Strict,
+
+    /// Treat `/foo` and `/foo/` as different routes, but redirect with `301
+    /// Moved Permanently` to the other one if only it exists.
+    Redirect
+}
+
+impl Default for TrailingSlash {
+    fn default() -> TrailingSlash {
+        TrailingSlash::Ignore
+    }
+}
+
+/// The order in which a `TreeRouter` tries overlapping routes.
+///
+/// A path segment may match a static segment, a variable segment and a
+/// variable sequence at the same time, as in `users/me`, `users/:id` and
+/// `users/*path`. The first one that leads to a complete match wins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchOrder {
+    /// Try the most specific route first, meaning static segments before
+    /// variable segments, and variable segments before variable sequences.
+    /// This is the default.
+    Specificity,
+
+    /// Try the route that was added first, first.
+    Registration
+}
+
+impl Default for MatchOrder {
+    fn default() -> MatchOrder {
+        MatchOrder::Specificity
+    }
+}
+
 /// A tree shaped router that selects handlers using paths.
 ///
 /// Each tree node stores an other router of type `T`, which has to implement
@@ -40,9 +97,19 @@ pub struct TreeRouter<T> {
     static_routes: HashMap<MaybeUtf8Owned, TreeRouter<T>>,
     variable_route: Option<Box<TreeRouter<T>>>,
     wildcard_route: Option<Box<TreeRouter<T>>>,
+    constraint: Option<Arc<Fn(&str) -> bool + Send + Sync>>,
+    name: Option<RouteName>,
+    endpoint: bool,
+    order: usize,
+    next_order: usize,
     /// Should the router search for hyperlinks? Setting this to `true` may
     /// slow down endpoint search, but enables hyperlinks.
-    pub find_hyperlinks: bool
+    pub find_hyperlinks: bool,
+    /// How trailing slashes are treated. This has to be set before any
+    /// routes are added, since it changes how they are stored.
+    pub trailing_slash: TrailingSlash,
+    /// The order in which overlapping routes are tried.
+    pub match_order: MatchOrder
 }
 
 impl<T: Default> TreeRouter<T> {
@@ -71,11 +138,18 @@ impl<T> TreeRouter<T> {
             static_routes: HashMap::new(),
             variable_route: None,
             wildcard_route: None,
-            find_hyperlinks: false
+            constraint: None,
+            name: None,
+            endpoint: true,
+            order: 0,
+            next_order: 0,
+            find_hyperlinks: false,
+            trailing_slash: TrailingSlash::Ignore,
+            match_order: MatchOrder::Specificity
         }
     }
 
-    /// Build the router and its children using a chaninable API.
+    /// Build the router and its children using a chainable API.
     ///
     /// ```
     /// use rustful::{Context, Response};
@@ -92,22 +166,224 @@ impl<T> TreeRouter<T> {
         self.get_builder(BuilderContext::new())
     }
 
+    /// Generate a path to a named route, with the values in `variables`
+    /// inserted in place of the route variables. The values are percent
+    /// encoded, but `/` is kept as it is in values for variable sequences.
+    ///
+    /// `None` is returned if there is no route with the name, or if a
+    /// variable value is missing.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::TreeRouter;
+    ///
+    /// fn show_user(_context: Context, response: Response) {
+    ///     response.send("a user");
+    /// }
+    ///
+    /// let mut router = TreeRouter::<Option<fn(Context, Response)>>::new();
+    /// router.build().path("users/:id").name("user_detail").handler(show_user);
+    ///
+    /// assert_eq!(router.url_for("user_detail", &[("id", "7")]), Some("/users/7".into()));
+    /// assert_eq!(router.url_for("user_detail", &[("name", "alice")]), None);
+    /// ```
+    pub fn url_for<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, name: &str, variables: &[(K, V)]) -> Option<String> {
+        let mut segments = vec![];
+        let route_name = match self.find_named(name, &mut segments) {
+            Some(route_name) => route_name,
+            None => return None
+        };
+
+        let variable_count = segments.iter().filter(|segment| segment.ty != SegmentType::Static).count();
+        if route_name.variables.len() < variable_count {
+            return None;
+        }
+        let mut names = route_name.variables[route_name.variables.len() - variable_count..].iter();
+
+        let mut path = String::new();
+        for segment in segments {
+            path.push('/');
+
+            if segment.ty == SegmentType::Static {
+                path.extend(percent_encode(segment.label.as_ref(), PATH_SEGMENT_ENCODE_SET));
+                continue;
+            }
+
+            let name = names.next().expect("too few variable names");
+            let value = match variables.iter().find(|&&(ref key, _)| key.as_ref() == name.as_ref()) {
+                Some(&(_, ref value)) => value.as_ref(),
+                None => return None
+            };
+
+            if segment.ty == SegmentType::VariableSequence {
+                for (i, part) in value.split(|&b| b == b'/').enumerate() {
+                    if i > 0 {
+                        path.push('/');
+                    }
+                    path.extend(percent_encode(part, PATH_SEGMENT_ENCODE_SET));
+                }
+            } else {
+                path.extend(percent_encode(value, PATH_SEGMENT_ENCODE_SET));
+            }
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        Some(path)
+    }
+
+    /// List every route in the router, with the whole path from the root and
+    /// the methods that are handled. Variable segments have empty labels,
+    /// just like in hyperlinks.
+    ///
+    /// ```
+    /// use rustful::{Context, Response, DefaultRouter};
+    /// use rustful::Method::Get;
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello world!");
+    /// }
+    ///
+    /// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+    /// router.build().many(|node| {
+    ///     node.then().on_get(handler);
+    ///     node.path("users/:id").then().on_get(handler);
+    /// });
+    ///
+    /// let routes = router.routes();
+    /// assert_eq!(routes.len(), 2);
+    /// assert!(routes.iter().all(|route| route.method == Some(Get)));
+    /// ```
+    pub fn routes<'a>(&'a self) -> Vec<Link<'a>> where T: HandleRequest {
+        let mut routes = vec![];
+        self.collect_routes(&mut vec![], &mut routes);
+        routes.sort();
+        routes
+    }
+
+    // Collects the routes from this node and its children.
+    fn collect_routes<'a>(&'a self, segments: &mut Vec<LinkSegment<'a>>, routes: &mut Vec<Link<'a>>) where T: HandleRequest {
+        if self.endpoint {
+            routes.extend(self.item.hyperlinks(Link {
+                method: None,
+                path: segments.clone(),
+                handler: None,
+            }));
+        }
+
+        let static_routes = self.static_routes.iter().map(|(label, node)| (label.as_slice(), SegmentType::Static, node));
+        let variable_route = self.variable_route.iter().map(|node| (MaybeUtf8Slice::new(), SegmentType::VariableSegment, &**node));
+        let wildcard_route = self.wildcard_route.iter().map(|node| (MaybeUtf8Slice::new(), SegmentType::VariableSequence, &**node));
+
+        for (label, ty, node) in static_routes.chain(variable_route).chain(wildcard_route) {
+            segments.push(LinkSegment {
+                label: label,
+                ty: ty
+            });
+            node.collect_routes(segments, routes);
+            segments.pop();
+        }
+    }
+
+    // Finds a named node, while collecting the path to it.
+    fn find_named<'a>(&'a self, name: &str, segments: &mut Vec<LinkSegment<'a>>) -> Option<&'a RouteName> {
+        if let Some(ref route_name) = self.name {
+            if route_name.name == name {
+                return Some(route_name);
+            }
+        }
+
+        let static_routes = self.static_routes.iter().map(|(label, node)| (label.as_slice(), SegmentType::Static, node));
+        let variable_route = self.variable_route.iter().map(|node| (MaybeUtf8Slice::new(), SegmentType::VariableSegment, &**node));
+        let wildcard_route = self.wildcard_route.iter().map(|node| (MaybeUtf8Slice::new(), SegmentType::VariableSequence, &**node));
+
+        for (label, ty, node) in static_routes.chain(variable_route).chain(wildcard_route) {
+            segments.push(LinkSegment {
+                label: label,
+                ty: ty
+            });
+
+            if let Some(route_name) = node.find_named(name, segments) {
+                return Some(route_name);
+            }
+
+            segments.pop();
+        }
+
+        None
+    }
+
+    // Checks if a variable segment is accepted by this node's constraint.
+    fn accepts(&self, segment: &[u8]) -> bool {
+        match self.constraint {
+            Some(ref constraint) => from_utf8(segment).map(|s| constraint(s)).unwrap_or(false),
+            None => true
+        }
+    }
+
+    // Finds the node that serves the end of a path, with or without a
+    // trailing slash.
+    fn endpoint_node(&self, trailing_slash: bool) -> Option<&TreeRouter<T>> {
+        if trailing_slash {
+            self.static_routes.get(&b""[..])
+        } else {
+            Some(self)
+        }
+    }
+
+    // Pushes the branches of this node to the search stack, so that the
+    // preferred one is popped first.
+    fn push_branches<'a>(&'a self, stack: &mut Vec<(&'a TreeRouter<T>, Branch, (usize, usize))>, snapshot: (usize, usize), segment: Option<&[u8]>, match_order: MatchOrder) {
+        let mut branches = [Wildcard, Variable, Static];
+
+        if match_order == MatchOrder::Registration {
+            let static_order = segment.and_then(|segment| self.static_routes.get(segment)).map(|node| node.order);
+            let variable_order = self.variable_route.as_ref().map(|node| node.order);
+            let wildcard_order = self.wildcard_route.as_ref().map(|node| node.order);
+
+            branches.sort_by_key(|branch| {
+                let order = match *branch {
+                    Static => static_order,
+                    Variable => variable_order,
+                    Wildcard => wildcard_order
+                };
+                ::std::cmp::Reverse(order.unwrap_or(::std::usize::MAX))
+            });
+        }
+
+        for &branch in &branches {
+            stack.push((self, branch, snapshot));
+        }
+    }
+
     // Tries to find a router matching the key or inserts a new one if none exists.
     fn find_or_insert_router<'a, F: FnOnce() -> T>(&'a mut self, key: &[u8], create_handler: F) -> &'a mut TreeRouter<T> {
+        let order = self.next_order;
+        let next_order = &mut self.next_order;
+        let new_node = || {
+            *next_order += 1;
+            let mut node = TreeRouter::with_handler(create_handler());
+            node.endpoint = false;
+            node.order = order;
+            node
+        };
+
         if let Some(&b'*') = key.get(0) {
             if self.wildcard_route.is_none() {
-                self.wildcard_route = Some(Box::new(TreeRouter::with_handler(create_handler())));
+                self.wildcard_route = Some(Box::new(new_node()));
             }
             &mut **self.wildcard_route.as_mut().unwrap()
         } else if let Some(&b':') = key.get(0) {
             if self.variable_route.is_none() {
-                self.variable_route = Some(Box::new(TreeRouter::with_handler(create_handler())));
+                self.variable_route = Some(Box::new(new_node()));
             }
             &mut **self.variable_route.as_mut().unwrap()
         } else {
             match self.static_routes.entry(key.to_owned().into()) {
                 Occupied(entry) => entry.into_mut(),
-                Vacant(entry) => entry.insert(TreeRouter::with_handler(create_handler()))
+                Vacant(entry) => entry.insert(new_node())
             }
         }
     }
@@ -160,17 +436,40 @@ impl<T: FromHandler<H> + ApplyContext, D: AsRef<[u8]>, H> FromIterator<(Method,
 impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
     fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
         let now = environment.route_state.snapshot();
-        let mut stack = vec![(self, Wildcard, now), (self, Variable, now), (self, Static, now)];
+        let mut stack = vec![];
+        self.push_branches(&mut stack, now, environment.route_state.get(), self.match_order);
 
         let mut hyperlinks = vec![];
         let mut matches = vec![];
 
+        let trailing_slash = environment.context.uri_path.as_path().map_or(false, |path| {
+            path.len() > 1 && path.ends_with(b"/")
+        });
+        let mut redirect = false;
+
         while let Some((current, branch, snapshot)) = stack.pop() {
             environment.route_state.go_to(snapshot);
             if environment.route_state.is_empty() {
+                let endpoint = if self.trailing_slash == TrailingSlash::Ignore {
+                    current
+                } else {
+                    let endpoint = current.endpoint_node(trailing_slash)
+                        .filter(|node| node.endpoint || self.trailing_slash == TrailingSlash::Strict);
+
+                    match endpoint {
+                        Some(endpoint) => endpoint,
+                        None => {
+                            if self.trailing_slash == TrailingSlash::Redirect {
+                                redirect |= current.endpoint_node(!trailing_slash).map_or(false, |node| node.endpoint);
+                            }
+                            continue;
+                        }
+                    }
+                };
+
                 if !self.find_hyperlinks {
                     let (new_environment, old_hyperlinks) = environment.replace_hyperlinks(vec![]);
-                    if let Err(returned_environment) = current.item.handle_request(new_environment) {
+                    if let Err(returned_environment) = endpoint.item.handle_request(new_environment) {
                         environment = returned_environment.replace_hyperlinks(old_hyperlinks).0;
                         return Err(environment);
                     } else {
@@ -178,7 +477,7 @@ impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
                     }
                 }
 
-                matches.push((&current.item, environment.route_state.clone()));
+                matches.push((&endpoint.item, environment.route_state.clone()));
 
                 if branch == Static {
                     let base_link = Link {
@@ -232,18 +531,14 @@ impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
                         current.static_routes.get(segment).map(|next| {
                             environment.route_state.skip();
                             let snapshot = environment.route_state.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
+                            next.push_branches(&mut stack, snapshot, environment.route_state.get(), self.match_order);
                         });
                     },
                     Variable => {
-                        current.variable_route.as_ref().map(|next| {
+                        current.variable_route.as_ref().filter(|next| next.accepts(segment)).map(|next| {
                             environment.route_state.keep();
                             let snapshot = environment.route_state.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
+                            next.push_branches(&mut stack, snapshot, environment.route_state.get(), self.match_order);
                         });
                     },
                     Wildcard => {
@@ -255,9 +550,7 @@ impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
 
                             environment.route_state.keep();
                             let snapshot = environment.route_state.snapshot();
-                            stack.push((next, Wildcard, snapshot));
-                            stack.push((next, Variable, snapshot));
-                            stack.push((next, Static, snapshot));
+                            next.push_branches(&mut stack, snapshot, environment.route_state.get(), self.match_order);
                         });
                     }
                 }
@@ -265,6 +558,14 @@ impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
         }
 
         if matches.is_empty() {
+            if redirect {
+                if let Some(location) = toggle_trailing_slash(&environment.context.uri_path, environment.context.raw_query.as_ref()) {
+                    environment.response.set_status(StatusCode::MovedPermanently);
+                    environment.response.headers_mut().set(Location(location));
+                    return Ok(());
+                }
+            }
+
             environment.response.set_status(StatusCode::NotFound);
         } else if self.find_hyperlinks {
             hyperlinks.sort();
@@ -319,11 +620,29 @@ impl<T: HandleRequest> HandleRequest for TreeRouter<T> {
 
         links
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.item.requirements(requirements);
+
+        for next in self.static_routes.values() {
+            next.requirements(requirements);
+        }
+
+        if let Some(ref next) = self.variable_route {
+            next.requirements(requirements);
+        }
+
+        if let Some(ref next) = self.wildcard_route {
+            next.requirements(requirements);
+        }
+    }
 }
 
 impl<T: Default> Default for TreeRouter<T> {
     fn default() -> TreeRouter<T> {
-        TreeRouter::with_handler(T::default())
+        let mut router = TreeRouter::with_handler(T::default());
+        router.endpoint = false;
+        router
     }
 }
 
@@ -331,6 +650,14 @@ impl<'a, T: 'a> Build<'a> for TreeRouter<T> {
     type Builder = Builder<'a, T>;
 
     fn get_builder(&'a mut self, mut context: BuilderContext) -> Builder<'a, T> {
+        if !context.contains::<RouteTemplate>() {
+            context.insert(RouteTemplate::default());
+        }
+
+        if !context.contains::<TrailingSlash>() {
+            context.insert(self.trailing_slash);
+        }
+
         Builder {
             node: self,
             variables: Cow::Owned(context.remove::<VariableNames>().unwrap_or_default().0),
@@ -346,17 +673,14 @@ impl<T: FromHandler<H>, H> FromHandler<H> for TreeRouter<T> {
 }
 
 impl<T: ApplyContext> ApplyContext for TreeRouter<T> {
-    fn apply_context(&mut self, mut context: BuilderContext) {
-        if let Some(VariableNames(variables)) = context.remove() {
-            let mut variable_context = BuilderContext::new();
-            variable_context.insert(VariableNames(variables.clone()));
-
-            self.item.prepend_context(variable_context);
-            self.item.apply_context(context.clone());
-
-            context.insert(VariableNames(variables));
+    fn apply_context(&mut self, context: BuilderContext) {
+        if let (Some(&VariableNames(ref variables)), Some(route_name)) = (context.get(), self.name.as_mut()) {
+            let mut prefixed = variables.clone();
+            prefixed.extend(route_name.variables.drain(..));
+            route_name.variables = prefixed;
         }
 
+        self.item.apply_context(context.clone());
 
         for (_, mut node) in &mut self.static_routes {
             node.apply_context(context.clone());
@@ -391,11 +715,26 @@ impl<T: ApplyContext> ApplyContext for TreeRouter<T> {
 impl<T: Merge> Merge for TreeRouter<T> {
     fn merge(&mut self, other: TreeRouter<T>) {
         self.item.merge(other.item);
+        self.endpoint |= other.endpoint;
+
+        if other.constraint.is_some() {
+            self.constraint = other.constraint;
+        }
+
+        if other.name.is_some() {
+            self.name = other.name;
+        }
 
-        for (key, other_node) in other.static_routes {
+        let offset = self.next_order;
+        self.next_order += other.next_order;
+
+        for (key, mut other_node) in other.static_routes {
             println!("merging {:}", key.as_utf8_lossy());
             match self.static_routes.entry(key) {
-                Vacant(entry) => { entry.insert(other_node); },
+                Vacant(entry) => {
+                    other_node.order += offset;
+                    entry.insert(other_node);
+                },
                 Occupied(mut entry) => entry.get_mut().merge(other_node)
             }
         }
@@ -405,7 +744,10 @@ impl<T: Merge> Merge for TreeRouter<T> {
                 this_node.merge(*other_node);
             }
         } else {
-            self.variable_route = other.variable_route;
+            self.variable_route = other.variable_route.map(|mut node| {
+                node.order += offset;
+                node
+            });
         }
 
         if let Some(ref mut this_node) = self.wildcard_route {
@@ -413,7 +755,10 @@ impl<T: Merge> Merge for TreeRouter<T> {
                 this_node.merge(*other_node);
             }
         } else {
-            self.wildcard_route = other.wildcard_route;
+            self.wildcard_route = other.wildcard_route.map(|mut node| {
+                node.order += offset;
+                node
+            });
         }
     }
 }
@@ -448,12 +793,13 @@ impl<'a, T: Default + ApplyContext> Builder<'a, T> {
     /// ```
     pub fn path<'b, S: AsRef<[u8]>>(&'b mut self, path: S) -> Builder<'b, T> {
         let mut variables = self.variables.clone();
-        let context = self.context.clone();
+        let mut context = self.context.clone();
 
         let node = path.as_ref().segments().fold(&mut *self.node, |node, segment| {
             if segment[0] == b':' || segment[0] == b'*' {
-                variables.to_mut().push(segment[1..].to_owned().into());
+                push_variable(&mut variables, &segment[1..]);
             }
+            RouteTemplate::push_segment(&mut context, segment);
 
             node.find_or_insert_router(segment, || {
                 let mut new_context = context.clone().into_owned();
@@ -463,11 +809,18 @@ impl<'a, T: Default + ApplyContext> Builder<'a, T> {
                 handler
             })
         });
+        let node = with_trailing_slash(node, path.as_ref(), &context, || {
+            let mut new_context = context.clone().into_owned();
+            new_context.insert(VariableNames(variables.clone().into_owned()));
+            let mut handler = T::default();
+            handler.apply_context(new_context);
+            handler
+        });
 
         Builder {
             node: node,
             variables: variables,
-            context: self.context.clone()
+            context: context
         }
     }
 
@@ -497,12 +850,13 @@ impl<'a, T: Default + ApplyContext> Builder<'a, T> {
     /// ```
     pub fn on_path<'b, S: AsRef<[u8]>, H>(&'b mut self, path: S, handler: H) -> Builder<'b, T> where T: FromHandler<H> {
         let mut variables = self.variables.clone();
-        let context = self.context.clone();
+        let mut context = self.context.clone();
 
         let node = path.as_ref().segments().fold(&mut *self.node, |node, segment| {
             if segment[0] == b':' || segment[0] == b'*' {
-                variables.to_mut().push(segment[1..].to_owned().into());
+                push_variable(&mut variables, &segment[1..]);
             }
+            RouteTemplate::push_segment(&mut context, segment);
 
             node.find_or_insert_router(segment, || {
                 let mut new_context = context.clone().into_owned();
@@ -512,10 +866,18 @@ impl<'a, T: Default + ApplyContext> Builder<'a, T> {
                 handler
             })
         });
+        let node = with_trailing_slash(node, path.as_ref(), &context, || {
+            let mut new_context = context.clone().into_owned();
+            new_context.insert(VariableNames(variables.clone().into_owned()));
+            let mut handler = T::default();
+            handler.apply_context(new_context);
+            handler
+        });
 
         let mut new_context = context.clone().into_owned();
         new_context.insert(VariableNames(variables.clone().into_owned()));
         node.item = T::from_handler(new_context, handler);
+        node.endpoint = true;
 
         Builder {
             node: node,
@@ -523,6 +885,43 @@ impl<'a, T: Default + ApplyContext> Builder<'a, T> {
             context: context
         }
     }
+
+    /// Add a variable segment with a constraint, and keep building the
+    /// resulting node. The segment will only match if it's valid UTF-8 and
+    /// `constraint` accepts it, so other routes can be tried instead.
+    ///
+    /// There can only be one variable segment per node, so this will
+    /// replace any previous constraint for that segment, even if the
+    /// variable name is different.
+    ///
+    /// ```
+    /// use rustful::{Context, Response};
+    /// use rustful::handler::TreeRouter;
+    ///
+    /// fn show_user(_context: Context, response: Response) {
+    ///     response.send("user by id");
+    /// }
+    ///
+    /// fn show_user_by_name(_context: Context, response: Response) {
+    ///     response.send("user by name");
+    /// }
+    ///
+    /// let mut router = TreeRouter::<Option<fn(Context, Response)>>::new();
+    /// router.build().path("users").many(|mut node| {
+    ///     node.path_var("id", |id| id.parse::<u32>().is_ok()).handler(show_user);
+    ///     node.path("*name").handler(show_user_by_name);
+    /// });
+    /// ```
+    pub fn path_var<'b, S: AsRef<[u8]>, F>(&'b mut self, name: S, constraint: F) -> Builder<'b, T> where
+        F: Fn(&str) -> bool + Send + Sync + 'static
+    {
+        let mut label = vec![b':'];
+        label.extend_from_slice(name.as_ref());
+
+        let builder = self.path(label);
+        builder.node.constraint = Some(Arc::new(constraint));
+        builder
+    }
 }
 
 impl<'a, T> Builder<'a, T> {
@@ -577,7 +976,7 @@ impl<'a, T> Builder<'a, T> {
     /// ```
     pub fn on_route<'b, S: AsRef<[u8]>, H>(&'b mut self, label: S, handler: H) -> Builder<'b, T> where T: FromHandler<H> {
         let mut variables = self.variables.clone();
-        let context = self.context.clone();
+        let mut context = self.context.clone();
 
         let mut label_iter = label.as_ref().segments();
         let label = if let Some(label) = label_iter.next() {
@@ -595,19 +994,21 @@ impl<'a, T> Builder<'a, T> {
         }
 
         if label[0] == b':' || label[0] == b'*' {
-            variables.to_mut().push(label[1..].to_owned().into());
+            push_variable(&mut variables, &label[1..]);
         }
+        RouteTemplate::push_segment(&mut context, label);
 
         let node = self.node.find_or_insert_router(label, || {
             let mut new_context = context.clone().into_owned();
             new_context.insert(VariableNames(variables.clone().into_owned()));
             T::from_handler(new_context, handler)
         });
+        node.endpoint = true;
 
         Builder {
             node: node,
             variables: variables,
-            context: self.context.clone()
+            context: context
         }
     }
 
@@ -629,26 +1030,32 @@ impl<'a, T> Builder<'a, T> {
     /// ```
     pub fn get_path<'b, S: AsRef<[u8]>>(&'b mut self, path: S) -> Option<Builder<'b, T>> {
         let mut variables = self.variables.clone();
+        let mut context = self.context.clone();
 
         let node = path.as_ref().segments().fold(Some(&mut *self.node), |maybe_node, segment| {
+            RouteTemplate::push_segment(&mut context, segment);
             maybe_node.and_then(|node| {
                 if let Some(&b'*') = segment.get(0) {
-                    variables.to_mut().push(segment[1..].to_owned().into());
+                    push_variable(&mut variables, &segment[1..]);
                     node.wildcard_route.as_mut().map(|node| &mut **node)
                 } else if let Some(&b':') = segment.get(0) {
-                    variables.to_mut().push(segment[1..].to_owned().into());
+                    push_variable(&mut variables, &segment[1..]);
                     node.variable_route.as_mut().map(|node| &mut **node)
                 } else {
                     node.static_routes.get_mut(segment)
                 }
             })
         });
+        let node = if ends_with_slash(path.as_ref(), &context) {
+            node.and_then(|node| node.static_routes.get_mut(&b""[..]))
+        } else {
+            node
+        };
 
-        let new_context = self.context.clone();
         node.map(|node| Builder {
             node: node,
             variables: variables,
-            context: new_context
+            context: context
         })
     }
 
@@ -657,6 +1064,7 @@ impl<'a, T> Builder<'a, T> {
         let mut new_context = self.context.clone().into_owned();
         new_context.insert(VariableNames(self.variables.clone().into_owned()));
         self.node.item = T::from_handler(new_context, handler);
+        self.node.endpoint = true;
 
         Builder {
             node: self.node,
@@ -683,10 +1091,129 @@ impl<'a: 'b, 'b, T: Build<'b>> Builder<'a, T> {
     pub fn then(&'b mut self) -> T::Builder {
         let mut new_context = self.context.clone().into_owned();
         new_context.insert(VariableNames(self.variables.clone().into_owned()));
+        self.node.endpoint = true;
         self.node.item.get_builder(new_context)
     }
 }
 
+impl<'a, T> Builder<'a, T> {
+    /// Give the current node a name, to be able to generate paths to it
+    /// using `TreeRouter::url_for`. Any previous name of the node is
+    /// replaced.
+    ///
+    /// ```
+    /// use rustful::{Context, Response, DefaultRouter};
+    ///
+    /// fn show_user(_context: Context, response: Response) {
+    ///     response.send("a user");
+    /// }
+    ///
+    /// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+    /// router.build().path("users/:id").name("user_detail").then().on_get(show_user);
+    /// ```
+    pub fn name<S: Into<String>>(&mut self, name: S) -> &mut Builder<'a, T> {
+        self.node.name = Some(RouteName {
+            name: name.into(),
+            variables: self.variables.clone().into_owned(),
+        });
+        self
+    }
+
+    /// Add a context filter to every handler that is added through this
+    /// builder, or any builder that is derived from it. Handlers that are
+    /// already in the router are not affected. This requires a handler type
+    /// that picks up `RouteFilters`, such as `Filtered`.
+    ///
+    /// ```
+    /// use rustful::{Context, Response, DefaultRouter};
+    /// use rustful::filter::{ContextFilter, ContextAction, FilterContext};
+    ///
+    /// struct LogAdmin;
+    ///
+    /// impl ContextFilter for LogAdmin {
+    ///     fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+    ///         println!("admin access from {}", context.address);
+    ///         ContextAction::next()
+    ///     }
+    /// }
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello admin!");
+    /// }
+    ///
+    /// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+    /// router.build().path("admin").with_filter(LogAdmin).many(|mut node| {
+    ///     node.then().on_get(handler);
+    /// });
+    /// ```
+    pub fn with_filter<F: ContextFilter + 'static>(&mut self, filter: F) -> &mut Builder<'a, T> {
+        self.extend_context(RouteFilters(vec![Arc::new(filter)]))
+    }
+
+    /// Make a configuration value available to every handler that is added
+    /// through this builder, or any builder that is derived from it. It's
+    /// added as a `RouteConfig` filter, so the same rules as for
+    /// `with_filter` applies. The value can be accessed using
+    /// `Context::config`.
+    pub fn with_config<C: Send + Sync + 'static>(&mut self, config: C) -> &mut Builder<'a, T> {
+        self.with_filter(RouteConfig::new(config))
+    }
+
+    /// Only let through requests with one of the content types in `types`,
+    /// for every handler that is added through this builder, or any builder
+    /// that is derived from it. Other requests are answered with `415
+    /// Unsupported Media Type`. It's added as an `Accepting` filter, so the
+    /// same rules as for `with_filter` applies.
+    pub fn accepting(&mut self, types: &[Mime]) -> &mut Builder<'a, T> {
+        self.with_filter(Accepting::new(types.iter().cloned()))
+    }
+
+    /// Add an entry to the `BuilderContext` of every handler that is added
+    /// through this builder, or any builder that is derived from it,
+    /// replacing any entry of the same type. It's up to the handlers to pick
+    /// it up.
+    pub fn with_context<C: Clone + Any>(&mut self, entry: C) -> &mut Builder<'a, T> {
+        self.context.to_mut().insert(entry);
+        self
+    }
+
+    /// Add an entry to the `BuilderContext` of every handler that is added
+    /// through this builder, or any builder that is derived from it, after
+    /// any entry of the same type. The entries are also put in front of the
+    /// entries in routers that are merged into this one.
+    ///
+    /// ```
+    /// use rustful::{Context, Response, DefaultRouter};
+    /// use rustful::handler::Prepend;
+    ///
+    /// #[derive(Clone)]
+    /// struct Breadcrumbs(Vec<String>);
+    ///
+    /// impl Prepend for Breadcrumbs {
+    ///     fn prepend(&mut self, prefix: &Breadcrumbs) {
+    ///         let mut crumbs = prefix.0.clone();
+    ///         crumbs.extend(self.0.drain(..));
+    ///         self.0 = crumbs;
+    ///     }
+    /// }
+    ///
+    /// fn handler(_context: Context, response: Response) {
+    ///     response.send("Hello world!");
+    /// }
+    ///
+    /// let mut router = DefaultRouter::<fn(Context, Response)>::new();
+    /// router.build().path("products").extend_context(Breadcrumbs(vec!["Products".into()])).many(|mut node| {
+    ///     node.then().on_get(handler);
+    /// });
+    /// ```
+    pub fn extend_context<C: Prepend>(&mut self, mut entry: C) -> &mut Builder<'a, T> {
+        let context = self.context.to_mut();
+        entry.prepend_from(context);
+        context.insert(entry);
+        self
+    }
+}
+
 impl<'a, T: Merge + ApplyContext> Builder<'a, T> {
     ///Move handlers from another router into this, overwriting conflicting handlers and properties.
     pub fn merge(&mut self, mut other: TreeRouter<T>) -> &mut Builder<'a, T> {
@@ -700,6 +1227,52 @@ impl<'a, T: Merge + ApplyContext> Builder<'a, T> {
     }
 }
 
+// Checks if a path that is added to a router should get its own trailing
+// slash node.
+fn ends_with_slash(path: &[u8], context: &BuilderContext) -> bool {
+    let policy = context.get::<TrailingSlash>().cloned().unwrap_or_default();
+    policy != TrailingSlash::Ignore && path.ends_with(b"/") && path.segments().next().is_some()
+}
+
+// Continues to the trailing slash node of `node`, if `path` has a trailing
+// slash that should be kept.
+fn with_trailing_slash<'a, T, F: FnOnce() -> T>(node: &'a mut TreeRouter<T>, path: &[u8], context: &BuilderContext, create_handler: F) -> &'a mut TreeRouter<T> {
+    if ends_with_slash(path, context) {
+        node.find_or_insert_router(b"", create_handler)
+    } else {
+        node
+    }
+}
+
+// Adds or removes the trailing slash of the requested path, for redirects.
+fn toggle_trailing_slash(path: &UriPath, query: Option<&String>) -> Option<String> {
+    let path = match path.as_path() {
+        Some(path) => path,
+        None => return None
+    };
+
+    let mut location = String::with_capacity(path.len() + 1);
+    for segment in path.segments() {
+        location.push('/');
+        location.extend(percent_encode(segment, PATH_SEGMENT_ENCODE_SET));
+    }
+
+    if !path.ends_with(b"/") {
+        location.push('/');
+    }
+
+    if location.is_empty() {
+        location.push('/');
+    }
+
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    Some(location)
+}
+
 
 #[cfg(test)]
 mod test {
@@ -858,6 +1431,7 @@ mod test {
     struct HandlerState {
         visited: bool,
         variables: Parameters,
+        template: Option<String>,
     }
 
     impl HandlerState {
@@ -865,6 +1439,7 @@ mod test {
             HandlerState {
                 visited: false,
                 variables: Parameters::new(),
+                template: None,
             }
         }
 
@@ -896,11 +1471,13 @@ mod test {
                     panic!("missing link: {:?} (path: {})", link, context.uri_path);
                 }
             }
+            let template = context.route_template().map(Into::into);
             for link in context.hyperlinks {
                 panic!("unexpected hyperlink: {:?} (path: {})", link, context.uri_path);
             }
 
             let mut state = self.state.lock().unwrap();
+            state.template = template;
             state.variables = context.variables;
             state.visited = true;
 
@@ -1145,6 +1722,338 @@ mod test {
         check!(router1(Get, "path") => None);
     }
 
+    #[test]
+    fn variable_constraints() {
+        let mut router = TestRouter::new();
+
+        let by_id = Arc::new(Mutex::new(HandlerState::new()));
+        router.build().path("users").path_var("id", |id| id.parse::<u32>().is_ok()).then().on(Get, TestHandler {
+            state: by_id.clone(),
+            links: vec![],
+        });
+        let by_name = route!(router(Get, "users/*name"));
+
+        check!(router(Get, "users/42") => Some(&by_id), {"id" => "42"});
+        check!(router(Get, "users/alice") => Some(&by_name), {"name" => "alice"});
+        check!(router(Get, "users/42/x") => Some(&by_name), {"name" => "42/x"});
+    }
+
+    #[test]
+    fn named_routes() {
+        let mut router = TestRouter::new();
+        route!(router(Get, "files/*path"));
+        router.build().path("files/*path").name("file");
+
+        let mut other = TestRouter::new();
+        route!(other(Get, "posts/:post"));
+        other.build().path("posts/:post").name("post");
+        router.build().path("users/:user").merge(other);
+
+        assert_eq!(router.url_for("file", &[("path", "a b/c")]), Some("/files/a%20b/c".into()));
+        assert_eq!(router.url_for("post", &[("user", "alice"), ("post", "1")]), Some("/users/alice/posts/1".into()));
+        assert_eq!(router.url_for("post", &[("post", "1")]), None);
+        assert_eq!(router.url_for("nothing", &[("post", "1")]), None);
+    }
+
+    #[test]
+    fn list_routes() {
+        let mut router = TestRouter::new();
+        route!(router(Get, ""));
+        route!(router(Post, "users"));
+        route!(router(Get, "users/:id"));
+        route!(router(Get, "files/*path"));
+
+        let routes: Vec<_> = router.routes().into_iter().map(|route| {
+            let path: Vec<_> = route.path.into_iter().map(|segment| (segment.label.as_utf8_lossy().into_owned(), segment.ty)).collect();
+            (route.method, path)
+        }).collect();
+
+        assert_eq!(routes, vec![
+            (Some(Get), vec![]),
+            (Some(Get), vec![("files".to_owned(), SegmentType::Static), ("".to_owned(), SegmentType::VariableSequence)]),
+            (Some(Get), vec![("users".to_owned(), SegmentType::Static), ("".to_owned(), SegmentType::VariableSegment)]),
+            (Some(Post), vec![("users".to_owned(), SegmentType::Static)]),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the variable name 'id' is used more than once")]
+    fn duplicate_variable_names() {
+        let mut router = TestRouter::new();
+        route!(router(Get, "a/:id/b/:id"));
+    }
+
+    #[test]
+    #[should_panic(expected = "the variable name 'id' would be used more than once")]
+    fn duplicate_merged_variable_names() {
+        let mut router = TestRouter::new();
+        let mut other = TestRouter::new();
+        route!(other(Get, "b/:id"));
+        router.build().path("a/:id").merge(other);
+    }
+
+    #[test]
+    fn match_order() {
+        use super::MatchOrder;
+
+        let mut router = TestRouter::new();
+        let by_path = route!(router(Get, "users/*path"));
+        let by_id = route!(router(Get, "users/:id"));
+        let me = route!(router(Get, "users/me"));
+
+        check!(router(Get, "users/me") => Some(&me));
+        check!(router(Get, "users/42") => Some(&by_id), {"id" => "42"});
+
+        router.match_order = MatchOrder::Registration;
+        check!(router(Get, "users/me") => Some(&by_path), {"path" => "me"});
+        check!(router(Get, "users/42") => Some(&by_path), {"path" => "42"});
+
+        let mut router = TestRouter::new();
+        router.match_order = MatchOrder::Registration;
+        let me = route!(router(Get, "users/me"));
+        let by_id = route!(router(Get, "users/:id"));
+        let mut other = TestRouter::new();
+        let by_path = route!(other(Get, "*path"));
+        router.build().path("users").merge(other);
+
+        check!(router(Get, "users/me") => Some(&me));
+        check!(router(Get, "users/42") => Some(&by_id), {"id" => "42"});
+        check!(router(Get, "users/42/x") => Some(&by_path), {"path" => "42/x"});
+    }
+
+    #[test]
+    fn strict_trailing_slash() {
+        use super::TrailingSlash;
+
+        let mut router = TestRouter::new();
+        router.trailing_slash = TrailingSlash::Strict;
+
+        let test1 = route!(router(Get, "path/to/test1"));
+        let test2 = route!(router(Get, "path/to/test2/"));
+        let test3 = route!(router(Get, "path/:a/"));
+
+        check!(router(Get, "/path/to/test1") => Some(&test1));
+        check!(router(Get, "/path/to/test1/") => None);
+        check!(router(Get, "/path/to/test2/") => Some(&test2));
+        check!(router(Get, "/path/to/test2") => None);
+        check!(router(Get, "/path/x/") => Some(&test3), {"a" => "x"});
+        check!(router(Get, "/path/x") => None);
+    }
+
+    #[test]
+    fn redirect_trailing_slash() {
+        use handler::{Environment, HandleRequest};
+        use header::Headers;
+        use server::Global;
+        use context::UriPath;
+        use StatusCode;
+        use super::{TrailingSlash, toggle_trailing_slash};
+
+        let mut router = TestRouter::new();
+        router.trailing_slash = TrailingSlash::Redirect;
+
+        let test1 = route!(router(Get, "path/to/test1"));
+        let test2 = route!(router(Get, "path/to/test2/"));
+        route!(router(Get, "path/to/test3/nested"));
+
+        check!(router(Get, "/path/to/test1") => Some(&test1));
+        check!(router(Get, "/path/to/test2/") => Some(&test2));
+
+        let global = Global::default();
+        for &(path, redirected) in &[("/path/to/test1/", true), ("/path/to/test2", true), ("/path/to/test3/", false), ("/path/to/test3", false)] {
+            let result = router.handle_request(Environment {
+                context: Context::mock(Get, path, Headers::new(), &global),
+                response: Response::mock(&global),
+                route_state: path.into(),
+            });
+
+            match result {
+                Ok(()) => assert!(redirected, "unexpected redirect from {}", path),
+                Err(environment) => {
+                    assert!(!redirected, "expected a redirect from {}", path);
+                    assert_eq!(environment.response.status(), StatusCode::NotFound);
+                }
+            }
+        }
+
+        let query = Some("a=1".to_owned());
+        assert_eq!(toggle_trailing_slash(&UriPath::Path("/a b/c/".into()), query.as_ref()), Some("/a%20b/c?a=1".into()));
+        assert_eq!(toggle_trailing_slash(&UriPath::Path("/a b/c".into()), None), Some("/a%20b/c/".into()));
+        assert_eq!(toggle_trailing_slash(&UriPath::Asterisk, None), None);
+    }
+
+    #[test]
+    fn route_filters() {
+        use handler::{Environment, HandleRequest, Filtered};
+        use filter::{ContextFilter, ContextAction, FilterContext};
+        use header::Headers;
+        use server::Global;
+        use StatusCode;
+
+        struct Deny;
+
+        impl ContextFilter for Deny {
+            fn modify(&self, _: FilterContext, _: &mut Context) -> ContextAction {
+                ContextAction::abort(StatusCode::Forbidden)
+            }
+        }
+
+        let open = Arc::new(Mutex::new(HandlerState::new()));
+        let closed = Arc::new(Mutex::new(HandlerState::new()));
+
+        let mut router = TreeRouter::<MethodRouter<Variables<Filtered<TestHandler>>>>::new();
+        router.build().path("open").then().on_get(TestHandler { state: open.clone(), links: vec![] });
+        let merged = Arc::new(Mutex::new(HandlerState::new()));
+        let mut other = TreeRouter::<MethodRouter<Variables<Filtered<TestHandler>>>>::new();
+        other.build().path("merged").then().on_get(TestHandler { state: merged.clone(), links: vec![] });
+
+        router.build().path("closed").with_filter(Deny).many(|node| {
+            node.then().on_get(TestHandler { state: closed.clone(), links: vec![] });
+            node.merge(other);
+        });
+
+        let global = Global::default();
+        for path in &["open", "closed", "closed/merged"] {
+            let result = router.handle_request(Environment {
+                context: Context::mock(Get, *path, Headers::new(), &global),
+                response: Response::mock(&global),
+                route_state: (*path).into(),
+            });
+            assert!(result.is_ok());
+        }
+
+        assert!(open.lock().unwrap().visited);
+        assert!(!closed.lock().unwrap().visited);
+        assert!(!merged.lock().unwrap().visited);
+    }
+
+    #[test]
+    fn route_templates() {
+        use handler::{Environment, HandleRequest};
+        use header::Headers;
+        use server::Global;
+
+        let mut router = TestRouter::new();
+        let root = route!(router(Get, ""));
+        let post = route!(router(Get, "users/:id/posts/*rest"));
+        let merged = Arc::new(Mutex::new(HandlerState::new()));
+        let merged_root = Arc::new(Mutex::new(HandlerState::new()));
+
+        let mut other = TestRouter::new();
+        other.build().many(|node| {
+            node.then().on_get(TestHandler { state: merged_root.clone(), links: vec![] });
+            node.path(":name").then().on_get(TestHandler { state: merged.clone(), links: vec![] });
+        });
+        router.build().path("groups/:group").merge(other);
+
+        let global = Global::default();
+        for &(path, ref state, template) in &[
+            ("/", &root, "/"),
+            ("users/1/posts/a/b", &post, "/users/:id/posts/*rest"),
+            ("groups/a", &merged_root, "/groups/:group"),
+            ("groups/a/b", &merged, "/groups/:group/:name"),
+        ] {
+            let result = router.handle_request(Environment {
+                context: Context::mock(Get, path, Headers::new(), &global),
+                response: Response::mock(&global),
+                route_state: path.into(),
+            });
+            assert!(result.is_ok());
+            assert_eq!(state.lock().unwrap().template, Some(template.into()));
+        }
+    }
+
+    #[test]
+    fn custom_context_entries() {
+        use handler::{FromHandler, ApplyContext, Merge, BuilderContext, Prepend};
+
+        #[derive(Clone)]
+        struct Tags(Vec<&'static str>);
+
+        impl Prepend for Tags {
+            fn prepend(&mut self, prefix: &Tags) {
+                let mut tags = prefix.0.clone();
+                tags.extend(self.0.drain(..));
+                self.0 = tags;
+            }
+        }
+
+        #[derive(Clone)]
+        struct Mode(&'static str);
+
+        #[derive(Default)]
+        struct Tagged {
+            tags: Option<Tags>,
+            mode: Option<&'static str>,
+        }
+
+        impl FromHandler<()> for Tagged {
+            fn from_handler(context: BuilderContext, _handler: ()) -> Tagged {
+                Tagged {
+                    tags: context.get().cloned(),
+                    mode: context.get().map(|&Mode(mode)| mode),
+                }
+            }
+        }
+
+        impl ApplyContext for Tagged {
+            fn apply_context(&mut self, context: BuilderContext) {
+                if let Some(&Mode(mode)) = context.get() {
+                    self.mode = Some(mode);
+                }
+                self.prepend_context(context);
+            }
+
+            fn prepend_context(&mut self, context: BuilderContext) {
+                if let Some(ref mut tags) = self.tags {
+                    tags.prepend_from(&context);
+                }
+            }
+        }
+
+        impl Merge for Tagged {
+            fn merge(&mut self, other: Tagged) {
+                *self = other;
+            }
+        }
+
+        let mut other = TreeRouter::<Tagged>::new();
+        other.build().path("b").extend_context(Tags(vec!["b"])).with_context(Mode("inner")).handler(());
+
+        let mut router = TreeRouter::<Tagged>::new();
+        router.build().extend_context(Tags(vec!["root"])).path("a").extend_context(Tags(vec!["a"])).with_context(Mode("outer")).merge(other);
+
+        let mut builder = router.build();
+        let item = &builder.get_path("a/b").expect("the merged route is missing").node.item;
+        assert_eq!(item.tags.as_ref().map(|tags| &*tags.0), Some(&["root", "a", "b"][..]));
+        assert_eq!(item.mode, Some("outer"));
+    }
+
+    #[test]
+    fn collect_requirements() {
+        use handler::{HandleRequest, DefaultRouter};
+        use server::{Global, Requirements};
+
+        struct NeedsNumber;
+
+        impl Handler for NeedsNumber {
+            fn handle(&self, _context: Context, _response: Response) {}
+
+            fn requirements(&self, requirements: &mut Requirements) {
+                requirements.require::<u32>();
+            }
+        }
+
+        let mut router = DefaultRouter::<Box<Handler>>::new();
+        router.build().path("a/:b/c").then().on_get(Box::new(NeedsNumber) as Box<Handler>);
+        router.build().path("d").then().on_post(Box::new(|_: Context, _: Response| {}) as Box<Handler>);
+
+        let mut requirements = Requirements::new();
+        router.requirements(&mut requirements);
+        assert_eq!(requirements.check(&Global::default()).unwrap_err().0, vec!["u32"]);
+        assert!(requirements.check(&Box::new(5u32).into()).is_ok());
+    }
+
    //  #[bench]
    //  #[cfg(feature = "benchmark")]
    //  fn search_speed(b: &mut Bencher) {
@@ -1,6 +1,10 @@
+use url::percent_encoding::percent_decode;
+
 use context::MaybeUtf8Owned;
 use context::hypermedia::Link;
-use handler::{HandleRequest, Environment, FromHandler, Build, BuilderContext, ApplyContext, Merge, VariableNames};
+use trace::Spans;
+use server::{PathDecoding, Requirements};
+use handler::{HandleRequest, Environment, FromHandler, Build, BuilderContext, ApplyContext, Merge, Prepend, VariableNames, RouteTemplate};
 
 ///Assigns names to route variables.
 ///
@@ -9,36 +13,56 @@ use handler::{HandleRequest, Environment, FromHandler, Build, BuilderContext, Ap
 ///since it has be at the end of a router chain to know what variables to use.
 ///It won't do any other routing work, so make sure to pair it with, at least,
 ///a path based router.
+///
+///The route template, such as `/users/:id`, is also picked up from the path
+///based router and made available through `Context::route_template`, and
+///to response filters as a `RouteTemplate` in the filter storage.
+///
+///The variable values are percent-decoded here if the server uses
+///`PathDecoding::KeepSlashes`, since the path was only partially decoded
+///before routing.
 #[derive(Clone)]
 pub struct Variables<H> {
     handler: H,
     variables: Vec<MaybeUtf8Owned>,
+    template: Option<RouteTemplate>,
 }
 
 impl<T: FromHandler<H>, H> FromHandler<H> for Variables<T> {
     fn from_handler(mut context: BuilderContext, handler: H) -> Variables<T> {
         Variables {
             variables: context.remove::<VariableNames>().unwrap_or_default().0,
+            template: context.get::<RouteTemplate>().cloned(),
             handler: T::from_handler(context, handler),
         }
     }
 }
 
-impl<T: ApplyContext> ApplyContext for Variables<T> {
-    fn apply_context(&mut self, mut context: BuilderContext) {
-        if let Some(VariableNames(variables)) = context.remove() {
-            self.variables = variables;
+impl<H> Variables<H> {
+    fn prepend_route(&mut self, context: &mut BuilderContext) {
+        if let Some(prefix) = context.remove::<VariableNames>() {
+            let mut variables = VariableNames(::std::mem::replace(&mut self.variables, vec![]));
+            variables.prepend(&prefix);
+            self.variables = variables.0;
         }
 
+        if let Some(prefix) = context.get::<RouteTemplate>() {
+            match self.template {
+                Some(ref mut template) => template.prepend(prefix),
+                None => self.template = Some(prefix.clone()),
+            }
+        }
+    }
+}
+
+impl<T: ApplyContext> ApplyContext for Variables<T> {
+    fn apply_context(&mut self, mut context: BuilderContext) {
+        self.prepend_route(&mut context);
         self.handler.apply_context(context);
     }
 
     fn prepend_context(&mut self, mut context: BuilderContext) {
-        if let Some(VariableNames(mut variables)) = context.remove() {
-            ::std::mem::swap(&mut self.variables, &mut variables);
-            self.variables.extend(variables);
-        }
-
+        self.prepend_route(&mut context);
         self.handler.prepend_context(context);
     }
 }
@@ -46,6 +70,7 @@ impl<T: ApplyContext> ApplyContext for Variables<T> {
 impl<T: Merge> Merge for Variables<T> {
     fn merge(&mut self, other: Variables<T>) {
         self.variables = other.variables;
+        self.template = other.template;
         self.handler.merge(other.handler);
     }
 }
@@ -61,13 +86,31 @@ impl<'a, H: Build<'a>> Build<'a> for Variables<H> {
 
 impl<H: HandleRequest> HandleRequest for Variables<H> {
     fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
-        environment.context.variables = environment.route_state.variables(&self.variables).into();
+        let mut variables = environment.route_state.variables(&self.variables);
+        if let Some(&PathDecoding::KeepSlashes) = environment.context.extensions.get::<PathDecoding>() {
+            for value in variables.values_mut() {
+                let decoded: Vec<u8> = percent_decode(value.as_bytes()).collect();
+                *value = decoded.into();
+            }
+        }
+        environment.context.variables = variables.into();
+        if let Some(ref template) = self.template {
+            environment.context.extensions.insert(template.clone());
+            environment.response.filter_storage_mut().insert(template.clone());
+            if let Some(spans) = environment.response.filter_storage().get::<Spans>() {
+                spans.routed(template.as_str());
+            }
+        }
         self.handler.handle_request(environment)
     }
 
     fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
         self.handler.hyperlinks(base)
     }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.handler.requirements(requirements);
+    }
 }
 
 impl<H: Default> Default for Variables<H> {
@@ -75,6 +118,7 @@ impl<H: Default> Default for Variables<H> {
         Variables {
             handler: H::default(),
             variables: vec![],
+            template: None,
         }
     }
 }
@@ -70,12 +70,24 @@ extern crate test;
 #[cfg(feature = "multipart")]
 extern crate multipart;
 
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+
 extern crate url;
 extern crate time;
 extern crate hyper;
 extern crate anymap;
 extern crate phf;
 extern crate num_cpus;
+extern crate getrandom;
+extern crate flate2;
+extern crate sha2;
+extern crate hmac;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate log;
 
@@ -92,6 +104,7 @@ pub use self::context::Context;
 pub use self::response::Response;
 pub use self::response::Error;
 pub use self::response::SendResponse;
+pub use self::response::StatusError;
 pub use self::handler::Handler;
 pub use self::handler::DefaultRouter;
 pub use self::handler::ContentFactory;
@@ -110,4 +123,26 @@ pub mod context;
 pub mod response;
 pub mod filter;
 pub mod file;
+pub mod blob;
 pub mod net;
+pub mod cookie;
+pub mod session;
+pub mod flash;
+pub mod cors;
+pub mod template;
+pub mod transform;
+pub mod rewrite;
+pub mod sse;
+pub mod trace;
+pub mod access_log;
+pub mod timing;
+pub mod sampling;
+pub mod metrics;
+pub mod testing;
+pub mod webdav;
+pub mod tus;
+pub mod validate;
+#[cfg(feature = "minify")]
+pub mod minify;
+#[cfg(feature = "acme")]
+pub mod acme;
@@ -0,0 +1,289 @@
+//!Request metrics, in the Prometheus text format.
+//!
+//!A [`Metrics`][metrics] collector counts the requests by route, method and
+//!status code, measures how long they take and keeps track of how many are
+//!in flight. It has to be added as both a context filter and a response
+//!filter, and it's also a handler that shows the collected metrics:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, Handler, DefaultRouter};
+//!use rustful::metrics::Metrics;
+//!
+//!fn say_hello(_context: Context, response: Response) {
+//!    response.send("Hello!");
+//!}
+//!
+//!let metrics = Metrics::new();
+//!
+//!let mut router = DefaultRouter::<Box<Handler>>::new();
+//!router.build().many(|mut node| {
+//!    node.path("hello/:name").then().on_get(Box::new(say_hello as fn(Context, Response)) as Box<Handler>);
+//!    node.path("metrics").then().on_get(Box::new(metrics.clone()));
+//!});
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    context_filters: vec![Box::new(metrics.clone())],
+//!    response_filters: vec![Box::new(metrics)],
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!The routes are labeled with their templates, such as `/hello/:name`,
+//!which are picked up by the `Variables` handler in the router. Requests
+//!that didn't reach a route are labeled as `unknown`, and non-standard
+//!methods are labeled as `other`, to keep the number of series bounded.
+//!The time is measured from when the context filter runs until the
+//!response is done, so it should be the first context filter.
+//!
+//![metrics]: struct.Metrics.html
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use {Method, StatusCode};
+use header::{Headers, ContentType};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+use handler::{Handler, RouteTemplate};
+use response::{Data, Response};
+
+///Collects request metrics and shows them in the Prometheus text format.
+///
+///Clones share the same metrics, so the same collector can be used as
+///filters and as a handler.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+}
+
+impl Metrics {
+    ///Create a collector with the default latency buckets, from 5
+    ///milliseconds to 10 seconds.
+    pub fn new() -> Metrics {
+        Metrics::with_buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0])
+    }
+
+    ///Create a collector with custom latency buckets, in seconds.
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Metrics {
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+        buckets.dedup();
+
+        Metrics {
+            registry: Arc::new(Registry {
+                buckets: buckets,
+                in_flight: AtomicUsize::new(0),
+                series: Mutex::new(Series::default()),
+            }),
+        }
+    }
+
+    ///Get the number of requests that are currently being handled.
+    pub fn in_flight(&self) -> usize {
+        self.registry.in_flight.load(Ordering::SeqCst)
+    }
+
+    ///Render the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let registry = &self.registry;
+        let series = match registry.series.lock() {
+            Ok(series) => series,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        let mut output = String::new();
+
+        output.push_str("# HELP rustful_requests_total The number of handled requests.\n");
+        output.push_str("# TYPE rustful_requests_total counter\n");
+        let mut requests: Vec<_> = series.requests.iter().collect();
+        requests.sort();
+        for (&(ref route, ref method, status), count) in requests {
+            let _ = writeln!(output, "rustful_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}", escape(route), escape(method), status, count);
+        }
+
+        output.push_str("# HELP rustful_request_duration_seconds The time it took to handle the requests.\n");
+        output.push_str("# TYPE rustful_request_duration_seconds histogram\n");
+        let mut latencies: Vec<_> = series.latencies.iter().collect();
+        latencies.sort_by(|a, b| a.0.cmp(b.0));
+        for (&(ref route, ref method), histogram) in latencies {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), escape(method));
+            let mut cumulative = 0;
+            for (bound, count) in registry.buckets.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(output, "rustful_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative);
+            }
+            let _ = writeln!(output, "rustful_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(output, "rustful_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(output, "rustful_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+
+        output.push_str("# HELP rustful_requests_in_flight The number of requests that are being handled.\n");
+        output.push_str("# TYPE rustful_requests_in_flight gauge\n");
+        let _ = writeln!(output, "rustful_requests_in_flight {}", self.in_flight());
+
+        output
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl ContextFilter for Metrics {
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        self.registry.in_flight.fetch_add(1, Ordering::SeqCst);
+        ctx.storage.insert(Pending {
+            registry: self.registry.clone(),
+            method: context.method.clone(),
+            start: Instant::now(),
+            route: None,
+            status: None,
+        });
+
+        ContextAction::next()
+    }
+}
+
+impl ResponseFilter for Metrics {
+    fn begin(&self, ctx: FilterContext, status: StatusCode, _headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let route = ctx.storage.get::<RouteTemplate>().map(|template| template.as_str().to_owned());
+        if let Some(pending) = ctx.storage.get_mut::<Pending>() {
+            pending.route = route;
+            pending.status = Some(status);
+        }
+
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _ctx: FilterContext) -> ResponseAction {
+        ResponseAction::next(None::<Data>)
+    }
+}
+
+impl Handler for Metrics {
+    fn handle(&self, _context: Context, mut response: Response) {
+        response.headers_mut().set(ContentType(Mime(
+            TopLevel::Text,
+            SubLevel::Plain,
+            vec![(Attr::Ext("version".into()), Value::Ext("0.0.4".into()))]
+        )));
+        response.send(self.render());
+    }
+}
+
+struct Registry {
+    buckets: Vec<f64>,
+    in_flight: AtomicUsize,
+    series: Mutex<Series>,
+}
+
+#[derive(Default)]
+struct Series {
+    requests: HashMap<(String, String, u16), u64>,
+    latencies: HashMap<(String, String), Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+//Stored in the filter storage and recorded when the response is dropped.
+struct Pending {
+    registry: Arc<Registry>,
+    method: Method,
+    start: Instant,
+    route: Option<String>,
+    status: Option<StatusCode>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        let route = self.route.take().unwrap_or_else(|| "unknown".into());
+        //Clients can make up any number of methods.
+        let method = match self.method {
+            Method::Extension(_) => "other".into(),
+            ref method => method.to_string()
+        };
+        let status = self.status.map_or(0, |status| status.to_u16());
+
+        let registry = &self.registry;
+        registry.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let mut series = match registry.series.lock() {
+            Ok(series) => series,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        *series.requests.entry((route.clone(), method.clone(), status)).or_insert(0) += 1;
+
+        let histogram = series.latencies.entry((route, method)).or_insert_with(Histogram::default);
+        if histogram.buckets.is_empty() {
+            histogram.buckets = vec![0; registry.buckets.len()];
+        }
+        if let Some(index) = registry.buckets.iter().position(|&bound| seconds <= bound) {
+            histogram.buckets[index] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use {Method, StatusCode};
+    use super::{Metrics, Pending};
+
+    #[test]
+    fn render_metrics() {
+        let metrics = Metrics::with_buckets(vec![1.0, 0.5]);
+
+        let requests = [
+            (Some("/users/:id"), Method::Get, StatusCode::Ok),
+            (Some("/users/:id"), Method::Get, StatusCode::Ok),
+            (None, Method::Get, StatusCode::NotFound),
+            (None, Method::Extension("A".into()), StatusCode::NotFound),
+            (None, Method::Extension("B".into()), StatusCode::NotFound),
+        ];
+
+        for &(route, ref method, status) in &requests {
+            metrics.registry.in_flight.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+            Pending {
+                registry: metrics.registry.clone(),
+                method: method.clone(),
+                start: Instant::now(),
+                route: route.map(Into::into),
+                status: Some(status),
+            };
+        }
+
+        let output = metrics.render();
+        assert!(output.contains("rustful_requests_total{route=\"/users/:id\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(output.contains("rustful_requests_total{route=\"unknown\",method=\"GET\",status=\"404\"} 1\n"));
+        assert!(output.contains("rustful_request_duration_seconds_bucket{route=\"/users/:id\",method=\"GET\",le=\"0.5\"} 2\n"));
+        assert!(output.contains("rustful_request_duration_seconds_bucket{route=\"/users/:id\",method=\"GET\",le=\"1\"} 2\n"));
+        assert!(output.contains("rustful_request_duration_seconds_bucket{route=\"/users/:id\",method=\"GET\",le=\"+Inf\"} 2\n"));
+        assert!(output.contains("rustful_request_duration_seconds_count{route=\"unknown\",method=\"GET\"} 1\n"));
+        assert!(output.contains("rustful_requests_total{route=\"unknown\",method=\"other\",status=\"404\"} 2\n"));
+        assert!(output.contains("rustful_requests_in_flight 0\n"));
+    }
+}
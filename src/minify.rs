@@ -0,0 +1,745 @@
+//!Response body minification.
+//!
+//!The [`Minify`][minify] response filter removes comments and redundant
+//!whitespace from HTML, CSS and JavaScript responses, as they are written.
+//!It's enabled with the `minify` feature, and added to the server like any
+//!other response filter:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response};
+//!use rustful::minify::Minify;
+//!
+//!fn my_handler(_context: Context, response: Response) {
+//!    response.send("<p>\n    Hello,   <!-- greeting -->   world!\n</p>");
+//!}
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    response_filters: vec![Box::new(Minify::default())],
+//!    ..Server::new(my_handler)
+//!}.run();
+//!```
+//!
+//!The minifiers are conservative. They work on one byte at a time, so they
+//!don't need the whole body at once and they work just as well with chunked
+//!responses, but they will not rename anything or rewrite any expressions.
+//!Whitespace in HTML is collapsed, rather than removed, and the content of
+//!`pre`, `textarea`, `script` and `style` elements is left untouched.
+//!JavaScript is left untouched from the first slash that could be either a
+//!division or the start of a regular expression.
+//!
+//!Responses with a `Content-Encoding` are not minified, and neither are
+//!responses that are smaller than `Minify::min_size`. A handler can opt out
+//!of minification by adding [`NoMinify`][no_minify] to the filter storage.
+//!
+//![minify]: struct.Minify.html
+//![no_minify]: struct.NoMinify.html
+
+use StatusCode;
+use header::{Headers, ContentType, ContentLength, ContentEncoding};
+use mime::{Mime, TopLevel, SubLevel};
+
+use filter::{FilterContext, ResponseFilter, ResponseAction};
+use response::Data;
+
+///A response filter that minifies HTML, CSS and JavaScript.
+#[derive(Clone, Debug)]
+pub struct Minify {
+    ///Minify `text/html` responses. Default is `true`.
+    pub html: bool,
+
+    ///Minify `text/css` responses. Default is `true`.
+    pub css: bool,
+
+    ///Minify `application/javascript` and `text/javascript` responses.
+    ///Default is `true`.
+    pub javascript: bool,
+
+    ///The smallest body, in bytes, that will be minified. Bodies are
+    ///buffered until they reach this size, and smaller bodies are sent as
+    ///they are. Default is 256 bytes.
+    pub min_size: usize,
+}
+
+impl Default for Minify {
+    fn default() -> Minify {
+        Minify {
+            html: true,
+            css: true,
+            javascript: true,
+            min_size: 256,
+        }
+    }
+}
+
+impl Minify {
+    fn minifier(&self, content_type: &Mime) -> Option<Minifier> {
+        match *content_type {
+            Mime(TopLevel::Text, SubLevel::Html, _) if self.html => Some(Minifier::Html(Html::new())),
+            Mime(TopLevel::Text, SubLevel::Css, _) if self.css => Some(Minifier::Css(Css::new())),
+            Mime(TopLevel::Application, SubLevel::Javascript, _) |
+            Mime(TopLevel::Text, SubLevel::Javascript, _) if self.javascript => Some(Minifier::JavaScript(JavaScript::new())),
+            _ => None
+        }
+    }
+}
+
+impl ResponseFilter for Minify {
+    fn begin(&self, ctx: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        ctx.storage.remove::<State>();
+
+        if ctx.storage.contains::<NoMinify>() || headers.has::<ContentEncoding>() {
+            return (status, ResponseAction::next(None::<Data>));
+        }
+
+        let minifier = headers.get::<ContentType>().and_then(|&ContentType(ref mime)| self.minifier(mime));
+
+        if let Some(minifier) = minifier {
+            headers.remove::<ContentLength>();
+            ctx.storage.insert(State {
+                minifier: minifier,
+                buffer: Some(vec![]),
+            });
+        }
+
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        let (state, content) = match (ctx.storage.get_mut::<State>(), content) {
+            (Some(state), Some(content)) => (state, content),
+            (_, content) => return ResponseAction::next(content),
+        };
+
+        let mut output = vec![];
+
+        if let Some(mut buffer) = state.buffer.take() {
+            buffer.extend_from_slice(content.as_bytes());
+            if buffer.len() < self.min_size {
+                state.buffer = Some(buffer);
+                return ResponseAction::next(None::<Data>);
+            }
+
+            state.minifier.feed(&buffer, &mut output);
+        } else {
+            state.minifier.feed(content.as_bytes(), &mut output);
+        }
+
+        ResponseAction::next(Some(output))
+    }
+
+    fn end(&self, ctx: FilterContext) -> ResponseAction {
+        let mut state = match ctx.storage.remove::<State>() {
+            Some(state) => state,
+            None => return ResponseAction::next(None::<Data>)
+        };
+
+        //The body was too small to be minified.
+        if let Some(buffer) = state.buffer.take() {
+            return ResponseAction::next(Some(buffer));
+        }
+
+        let mut output = vec![];
+        state.minifier.finish(&mut output);
+        ResponseAction::next(Some(output))
+    }
+}
+
+///Add this to the filter storage to prevent the current response from being
+///minified.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::minify::NoMinify;
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    response.filter_storage_mut().insert(NoMinify);
+///    response.send("<pre>  keep  this  as  it  is  </pre>");
+///}
+///```
+#[derive(Clone, Copy, Debug)]
+pub struct NoMinify;
+
+struct State {
+    minifier: Minifier,
+    //Holds the body until it reaches the minimum size.
+    buffer: Option<Vec<u8>>,
+}
+
+enum Minifier {
+    Html(Html),
+    Css(Css),
+    JavaScript(JavaScript),
+}
+
+impl Minifier {
+    fn feed(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        match *self {
+            Minifier::Html(ref mut html) => for &byte in input {
+                html.next(byte, output);
+            },
+            Minifier::Css(ref mut css) => for &byte in input {
+                css.next(byte, output);
+            },
+            Minifier::JavaScript(ref mut javascript) => for &byte in input {
+                javascript.next(byte, output);
+            },
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        match *self {
+            Minifier::Html(ref mut html) => html.finish(output),
+            Minifier::Css(ref mut css) => css.finish(output),
+            Minifier::JavaScript(ref mut javascript) => javascript.finish(output),
+        }
+    }
+}
+
+fn is_space(byte: u8) -> bool {
+    match byte {
+        b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x0b' => true,
+        _ => false
+    }
+}
+
+//Elements where whitespace and comments have to be preserved.
+const RAW_ELEMENTS: &'static [&'static [u8]] = &[b"pre", b"textarea", b"script", b"style"];
+const MAX_TAG_NAME: usize = 16;
+
+enum HtmlState {
+    Text,
+    TagStart,
+    Tag(Option<u8>),
+    CommentStart,
+    Comment {
+        keep: bool,
+        dashes: usize,
+    },
+    Raw {
+        end_tag: Vec<u8>,
+        matched: usize,
+    },
+}
+
+struct Html {
+    state: HtmlState,
+    tag_name: Vec<u8>,
+    raw_element: Option<Vec<u8>>,
+    in_space: bool,
+}
+
+impl Html {
+    fn new() -> Html {
+        Html {
+            state: HtmlState::Text,
+            tag_name: vec![],
+            raw_element: None,
+            in_space: false,
+        }
+    }
+
+    fn next(&mut self, byte: u8, output: &mut Vec<u8>) {
+        let mut reprocess = false;
+
+        match self.state {
+            HtmlState::Text => if byte == b'<' {
+                self.tag_name.clear();
+                self.state = HtmlState::TagStart;
+            } else if is_space(byte) {
+                if !self.in_space {
+                    output.push(b' ');
+                    self.in_space = true;
+                }
+            } else {
+                output.push(byte);
+                self.in_space = false;
+            },
+            HtmlState::TagStart => {
+                let continues_name = match byte {
+                    b'-' if self.tag_name == b"!-" => {
+                        self.state = HtmlState::CommentStart;
+                        return;
+                    },
+                    b'/' => self.tag_name.is_empty(),
+                    b'!' | b'-' | b':' | b'_' => true,
+                    byte => byte.is_ascii_alphanumeric(),
+                };
+
+                if continues_name && self.tag_name.len() < MAX_TAG_NAME {
+                    self.tag_name.push(byte);
+                } else if self.tag_name.is_empty() {
+                    //Not a tag, but a lone `<`.
+                    output.push(b'<');
+                    self.in_space = false;
+                    self.state = HtmlState::Text;
+                    reprocess = true;
+                } else {
+                    output.push(b'<');
+                    output.extend_from_slice(&self.tag_name);
+
+                    let name = self.tag_name.to_ascii_lowercase();
+                    self.raw_element = if RAW_ELEMENTS.contains(&&*name) {
+                        Some(name)
+                    } else {
+                        None
+                    };
+
+                    self.in_space = false;
+                    self.state = HtmlState::Tag(None);
+                    reprocess = true;
+                }
+            },
+            HtmlState::Tag(None) => if byte == b'>' {
+                output.push(byte);
+                self.in_space = false;
+                self.state = match self.raw_element.take() {
+                    Some(name) => {
+                        let mut end_tag = b"</".to_vec();
+                        end_tag.extend_from_slice(&name);
+                        HtmlState::Raw {
+                            end_tag: end_tag,
+                            matched: 0,
+                        }
+                    },
+                    None => HtmlState::Text
+                };
+            } else if is_space(byte) {
+                if !self.in_space {
+                    output.push(b' ');
+                    self.in_space = true;
+                }
+            } else {
+                if byte == b'"' || byte == b'\'' {
+                    self.state = HtmlState::Tag(Some(byte));
+                }
+                output.push(byte);
+                self.in_space = false;
+            },
+            HtmlState::Tag(Some(quote)) => {
+                if byte == quote {
+                    self.state = HtmlState::Tag(None);
+                }
+                output.push(byte);
+            },
+            HtmlState::CommentStart => {
+                //Conditional comments are kept.
+                let keep = byte == b'[';
+                if keep {
+                    output.extend_from_slice(b"<!--");
+                    self.in_space = false;
+                }
+
+                self.state = HtmlState::Comment {
+                    keep: keep,
+                    dashes: 0,
+                };
+                reprocess = true;
+            },
+            HtmlState::Comment { keep, ref mut dashes } => {
+                if keep {
+                    output.push(byte);
+                }
+
+                match byte {
+                    b'-' => *dashes += 1,
+                    b'>' if *dashes >= 2 => self.state = HtmlState::Text,
+                    _ => *dashes = 0,
+                }
+            },
+            HtmlState::Raw { ref end_tag, ref mut matched } => {
+                output.push(byte);
+
+                if byte.to_ascii_lowercase() == end_tag[*matched] {
+                    *matched += 1;
+                } else {
+                    *matched = if byte == b'<' { 1 } else { 0 };
+                }
+
+                if *matched == end_tag.len() {
+                    self.state = HtmlState::Tag(None);
+                }
+            },
+        }
+
+        if reprocess {
+            self.next(byte, output);
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        match self.state {
+            HtmlState::TagStart => {
+                output.push(b'<');
+                output.extend_from_slice(&self.tag_name);
+            },
+            HtmlState::CommentStart => output.extend_from_slice(b"<!--"),
+            _ => {}
+        }
+
+        self.state = HtmlState::Text;
+    }
+}
+
+enum CssState {
+    Normal,
+    Slash,
+    Comment,
+    CommentStar,
+    String {
+        quote: u8,
+        escaped: bool,
+    },
+}
+
+struct Css {
+    state: CssState,
+    last: u8,
+    pending_space: bool,
+}
+
+impl Css {
+    fn new() -> Css {
+        Css {
+            state: CssState::Normal,
+            last: 0,
+            pending_space: false,
+        }
+    }
+
+    fn next(&mut self, byte: u8, output: &mut Vec<u8>) {
+        match self.state {
+            CssState::Normal => match byte {
+                b'/' => self.state = CssState::Slash,
+                byte if is_space(byte) => self.pending_space = true,
+                b'"' | b'\'' => {
+                    self.emit(byte, output);
+                    self.state = CssState::String {
+                        quote: byte,
+                        escaped: false,
+                    };
+                },
+                byte => self.emit(byte, output),
+            },
+            CssState::Slash => if byte == b'*' {
+                self.state = CssState::Comment;
+            } else {
+                self.emit(b'/', output);
+                self.state = CssState::Normal;
+                self.next(byte, output);
+            },
+            CssState::Comment => if byte == b'*' {
+                self.state = CssState::CommentStar;
+            },
+            CssState::CommentStar => match byte {
+                b'/' => {
+                    self.pending_space = true;
+                    self.state = CssState::Normal;
+                },
+                b'*' => {},
+                _ => self.state = CssState::Comment,
+            },
+            CssState::String { quote, ref mut escaped } => {
+                output.push(byte);
+
+                if *escaped {
+                    *escaped = false;
+                } else if byte == b'\\' {
+                    *escaped = true;
+                } else if byte == quote {
+                    self.last = byte;
+                    self.state = CssState::Normal;
+                }
+            },
+        }
+    }
+
+    fn emit(&mut self, byte: u8, output: &mut Vec<u8>) {
+        if self.pending_space {
+            //Spaces before `:` and `(` are significant in selectors and
+            //media queries, so they are only removed after them.
+            let removable_after = b"{};,:(>".contains(&self.last);
+            let removable_before = b"{};,)>".contains(&byte);
+            if self.last != 0 && !removable_after && !removable_before {
+                output.push(b' ');
+            }
+
+            self.pending_space = false;
+        }
+
+        output.push(byte);
+        self.last = byte;
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        if let CssState::Slash = self.state {
+            output.push(b'/');
+        }
+
+        self.state = CssState::Normal;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Space {
+    None,
+    Space,
+    Newline,
+}
+
+enum JsState {
+    Normal,
+    Slash,
+    LineComment,
+    BlockComment {
+        star: bool,
+    },
+    String {
+        quote: u8,
+        escaped: bool,
+    },
+    Regex {
+        escaped: bool,
+        in_class: bool,
+    },
+    Verbatim,
+}
+
+//Keywords that may be followed by a regular expression.
+const REGEX_KEYWORDS: &'static [&'static [u8]] = &[
+    b"return", b"typeof", b"instanceof", b"in", b"of", b"new", b"delete",
+    b"void", b"throw", b"case", b"do", b"else", b"yield", b"await",
+];
+const MAX_WORD: usize = 16;
+
+struct JavaScript {
+    state: JsState,
+    last: u8,
+    last_word: Vec<u8>,
+    pending: Space,
+}
+
+impl JavaScript {
+    fn new() -> JavaScript {
+        JavaScript {
+            state: JsState::Normal,
+            last: 0,
+            last_word: vec![],
+            pending: Space::None,
+        }
+    }
+
+    fn next(&mut self, byte: u8, output: &mut Vec<u8>) {
+        match self.state {
+            JsState::Normal => match byte {
+                b'\n' | b'\r' => self.pending = Space::Newline,
+                byte if is_space(byte) => self.pending = ::std::cmp::max(self.pending, Space::Space),
+                b'/' => self.state = JsState::Slash,
+                b'"' | b'\'' | b'`' => {
+                    self.emit(byte, output);
+                    self.state = JsState::String {
+                        quote: byte,
+                        escaped: false,
+                    };
+                },
+                byte => self.emit(byte, output),
+            },
+            JsState::Slash => match byte {
+                b'/' => self.state = JsState::LineComment,
+                b'*' => self.state = JsState::BlockComment { star: false },
+                byte => {
+                    //A slash after a value is a division, and a slash after
+                    //an operator starts a regular expression. It can be
+                    //either after `)`, `}`, `++`, `--` or another slash, so
+                    //the rest is left as it is, rather than guessing wrong.
+                    let is_division = is_word_byte(self.last) && !REGEX_KEYWORDS.contains(&&*self.last_word)
+                        || b"]\"'`".contains(&self.last);
+                    let is_ambiguous = b")}+-/".contains(&self.last);
+
+                    self.emit(b'/', output);
+                    if is_ambiguous {
+                        self.state = JsState::Verbatim;
+                    } else if is_division {
+                        self.state = JsState::Normal;
+                    } else {
+                        self.state = JsState::Regex {
+                            escaped: false,
+                            in_class: false,
+                        };
+                    }
+                    self.next(byte, output);
+                }
+            },
+            JsState::LineComment => if byte == b'\n' || byte == b'\r' {
+                self.pending = Space::Newline;
+                self.state = JsState::Normal;
+            },
+            JsState::BlockComment { ref mut star } => match byte {
+                b'/' if *star => {
+                    self.pending = ::std::cmp::max(self.pending, Space::Space);
+                    self.state = JsState::Normal;
+                },
+                b'\n' | b'\r' => {
+                    self.pending = Space::Newline;
+                    *star = false;
+                },
+                byte => *star = byte == b'*',
+            },
+            JsState::String { quote, ref mut escaped } => {
+                output.push(byte);
+
+                if *escaped {
+                    *escaped = false;
+                } else if byte == b'\\' {
+                    *escaped = true;
+                } else if byte == quote {
+                    self.last = byte;
+                    self.state = JsState::Normal;
+                }
+            },
+            JsState::Regex { ref mut escaped, ref mut in_class } => {
+                output.push(byte);
+
+                if *escaped {
+                    *escaped = false;
+                } else {
+                    match byte {
+                        b'\\' => *escaped = true,
+                        b'[' => *in_class = true,
+                        b']' => *in_class = false,
+                        b'/' if !*in_class => {
+                            self.last = byte;
+                            self.state = JsState::Normal;
+                        },
+                        //Regular expressions can't span multiple lines, so
+                        //this was probably not one.
+                        b'\n' | b'\r' => {
+                            self.last = b'\n';
+                            self.state = JsState::Normal;
+                        },
+                        _ => {}
+                    }
+                }
+            },
+            JsState::Verbatim => output.push(byte),
+        }
+    }
+
+    fn emit(&mut self, byte: u8, output: &mut Vec<u8>) {
+        match self.pending {
+            Space::None => {},
+            _ if self.last == 0 => {},
+            Space::Space => {
+                let words = is_word_byte(self.last) && is_word_byte(byte);
+                let operators = self.last == byte && (byte == b'+' || byte == b'-');
+                if words || operators {
+                    output.push(b' ');
+                }
+            },
+            Space::Newline => {
+                //Keep line breaks where automatic semicolon insertion may
+                //depend on them.
+                let removable = b"{[(,;\n".contains(&self.last) || b"}]),;".contains(&byte);
+                if !removable {
+                    output.push(b'\n');
+                }
+            },
+        }
+        self.pending = Space::None;
+
+        if is_word_byte(byte) {
+            if !is_word_byte(self.last) {
+                self.last_word.clear();
+            }
+
+            if self.last_word.len() < MAX_WORD {
+                self.last_word.push(byte);
+            }
+        }
+
+        output.push(byte);
+        self.last = byte;
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        if let JsState::Slash = self.state {
+            output.push(b'/');
+        }
+
+        self.state = JsState::Normal;
+    }
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte == b'\\' || byte >= 0x80
+}
+
+#[cfg(test)]
+mod test {
+    use super::Minifier;
+    use super::{Html, Css, JavaScript};
+
+    fn minify<F: Fn() -> Minifier>(new: F, input: &str) -> String {
+        let mut whole = vec![];
+        let mut minifier = new();
+        minifier.feed(input.as_bytes(), &mut whole);
+        minifier.finish(&mut whole);
+
+        //The result should not depend on how the input is split.
+        let mut split = vec![];
+        let mut minifier = new();
+        for byte in input.as_bytes().chunks(1) {
+            minifier.feed(byte, &mut split);
+        }
+        minifier.finish(&mut split);
+
+        assert_eq!(whole, split);
+        String::from_utf8(whole).unwrap()
+    }
+
+    #[test]
+    fn minify_html() {
+        let html = || Minifier::Html(Html::new());
+
+        assert_eq!(
+            minify(&html, "<p  class=\"a  b\">\n    Hello,   <!-- greeting -->   <b>world</b>!\n</p>"),
+            "<p class=\"a  b\"> Hello, <b>world</b>! </p>"
+        );
+        assert_eq!(
+            minify(&html, "<PRE>  a\n  b  </pre>  <!--[if IE]> x <![endif]--> <!---->"),
+            "<PRE>  a\n  b  </pre> <!--[if IE]> x <![endif]--> "
+        );
+        assert_eq!(
+            minify(&html, "<script>\n  if (a < b) { }  // </scrip\n</script >  <"),
+            "<script>\n  if (a < b) { }  // </scrip\n</script > <"
+        );
+        assert_eq!(minify(&html, "1 < 2  and  3 > 2"), "1 < 2 and 3 > 2");
+    }
+
+    #[test]
+    fn minify_css() {
+        let css = || Minifier::Css(Css::new());
+
+        assert_eq!(
+            minify(&css, "/* header */\na :hover ,\nb > c {\n  color : red;\n  content: \"  /* x */  \";\n}\n@media screen and (max-width: 10px) { }"),
+            "a :hover,b>c{color :red;content:\"  /* x */  \";}@media screen and (max-width:10px){}"
+        );
+    }
+
+    #[test]
+    fn minify_javascript() {
+        let javascript = || Minifier::JavaScript(JavaScript::new());
+
+        assert_eq!(
+            minify(&javascript, "// comment\nvar a = b / c, d = /\\/\\/ x/g;\nreturn a + +b\n  + 'it\\'s  /* not */ a comment' /* but this is */;\n"),
+            "var a=b/c,d=/\\/\\/ x/g;return a+ +b\n+'it\\'s  /* not */ a comment';"
+        );
+        assert_eq!(minify(&javascript, "x = y\n/regex/.test(z)\nreturn /a  b/"), "x=y\n/regex/.test(z)\nreturn/a  b/");
+        assert_eq!(minify(&javascript, "x = 'a' / 2 ;  y = [1] / 2"), "x='a'/2;y=[1]/2");
+        assert_eq!(
+            minify(&javascript, "if (a) /b  c/.test(d);\n  e = f ;"),
+            "if(a)/b  c/.test(d);\n  e = f ;"
+        );
+        assert_eq!(minify(&javascript, "x = (a) / b  /  c ;"), "x=(a)/ b  /  c ;");
+    }
+}
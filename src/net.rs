@@ -1,3 +1,492 @@
 //! Network related types and traits, reexported from Hyper.
+//!
+//! # TLS
+//!
+//! Rustful doesn't depend on any particular TLS library. `Server::run_https`
+//! accepts anything that implements [`SslServer`][ssl_server], which is
+//! responsible for wrapping each accepted TCP connection in an encrypted
+//! stream. Crates like `hyper-native-tls`, `hyper-openssl` and
+//! `hyper-rustls` (for Hyper 0.10) provide implementations for their
+//! backends, and other backends can be plugged in by implementing it:
+//!
+//! ```no_run
+//! use std::io::{self, Read, Write};
+//! use std::net::SocketAddr;
+//! use std::time::Duration;
+//! use rustful::{Server, Context, Response};
+//! use rustful::net::{SslServer, HttpStream, NetworkStream};
+//!
+//! //A stand-in for the configuration of a real TLS library.
+//! #[derive(Clone)]
+//! struct MyTls;
+//!
+//! //The encrypted stream. This one just passes the data through.
+//! #[derive(Clone)]
+//! struct MyTlsStream(HttpStream);
+//!
+//! impl SslServer for MyTls {
+//!     type Stream = MyTlsStream;
+//!
+//!     fn wrap_server(&self, stream: HttpStream) -> rustful::HttpResult<MyTlsStream> {
+//!         //The TLS handshake would be performed here.
+//!         Ok(MyTlsStream(stream))
+//!     }
+//! }
+//!
+//! impl Read for MyTlsStream {
+//!     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+//!         self.0.read(buf)
+//!     }
+//! }
+//!
+//! impl Write for MyTlsStream {
+//!     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+//!         self.0.write(buf)
+//!     }
+//!
+//!     fn flush(&mut self) -> io::Result<()> {
+//!         self.0.flush()
+//!     }
+//! }
+//!
+//! impl NetworkStream for MyTlsStream {
+//!     fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+//!         self.0.peer_addr()
+//!     }
+//!
+//!     fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+//!         self.0.set_read_timeout(duration)
+//!     }
+//!
+//!     fn set_write_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+//!         self.0.set_write_timeout(duration)
+//!     }
+//! }
+//!
+//! fn handler(_context: Context, response: Response) {
+//!     response.send("Hello, securely!");
+//! }
+//!
+//! let server_result = Server {
+//!     host: 8443.into(),
+//!     ..Server::new(handler)
+//! }.run_https(MyTls);
+//! ```
+//!
+//! The stream has to be `Clone`, since Hyper shares it between the request
+//! and the response, so TLS libraries usually put their session behind an
+//! `Arc<Mutex<...>>`.
+//!
+//! The certificate can be replaced while the server is running, without
+//! dropping any connections, by wrapping the backend in
+//! [`Reloadable`][reloadable], and different certificates can be served
+//! for different host names, using [`Sni`][sni].
+//!
+//! [ssl_server]: trait.SslServer.html
+//! [reloadable]: struct.Reloadable.html
+//! [sni]: struct.Sni.html
+
+#[cfg(all(unix, feature = "systemd"))]
+use std::env;
+#[cfg(all(unix, feature = "systemd"))]
+use std::fs::File;
+#[cfg(all(unix, feature = "systemd"))]
+use std::io;
+#[cfg(all(unix, feature = "systemd"))]
+use std::net::TcpListener;
+#[cfg(all(unix, feature = "systemd"))]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(all(unix, feature = "systemd"))]
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+#[cfg(all(unix, feature = "systemd"))]
+use std::process;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use HttpResult;
+use utils::host_matches;
 
 pub use hyper::net::{SslServer, HttpStream, NetworkStream};
+
+///An `SslServer` that can be replaced while the server is running.
+///
+///Every copy of a `Reloadable` shares the same backend, so a copy can be
+///kept after starting the server and used to load a renewed certificate.
+///Connections that are already established keep using the old one.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::net::{Reloadable, SslServer, HttpStream};
+///
+///fn handler(_context: Context, response: Response) {
+///    response.send("Hello, securely!");
+///}
+///
+///# #[derive(Clone)]
+///# struct MyTls;
+///# impl SslServer for MyTls {
+///#     type Stream = HttpStream;
+///#     fn wrap_server(&self, stream: HttpStream) -> rustful::HttpResult<HttpStream> { Ok(stream) }
+///# }
+///# fn load_certificate() -> MyTls { MyTls }
+///let tls = Reloadable::new(load_certificate());
+///
+///let _server = Server {
+///    host: 8443.into(),
+///    ..Server::new(handler)
+///}.run_https(tls.clone());
+///
+/////Later, when the certificate has been renewed:
+///tls.reload(load_certificate());
+///```
+#[derive(Debug)]
+pub struct Reloadable<S> {
+    current: Arc<RwLock<S>>,
+}
+
+impl<S: SslServer + Clone> Reloadable<S> {
+    ///Start with `ssl` as the backend.
+    pub fn new(ssl: S) -> Reloadable<S> {
+        Reloadable {
+            current: Arc::new(RwLock::new(ssl)),
+        }
+    }
+
+    ///Use `ssl` for every new connection from now on.
+    pub fn reload(&self, ssl: S) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = ssl;
+    }
+
+    ///Get a copy of the current backend.
+    pub fn current(&self) -> S {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl<S> Clone for Reloadable<S> {
+    fn clone(&self) -> Reloadable<S> {
+        Reloadable {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<S: SslServer + Clone> SslServer for Reloadable<S> {
+    type Stream = S::Stream;
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<S::Stream> {
+        //Don't block reloads during the handshake
+        self.current().wrap_server(stream)
+    }
+}
+
+///Chooses an `SslServer` from the host name that the client asks for.
+///
+///The host name is read from the Server Name Indication (SNI) in the TLS
+///handshake, before it's handed over to the chosen backend, so it works
+///with any TLS library. Each backend is usually configured with a
+///different certificate. The patterns are either exact host names or
+///wildcards, like `*.example.com`, and they are tried in the order they
+///were added. The default backend is used if none of them matches, or if
+///the client doesn't send a host name.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::net::{Sni, SslServer, HttpStream};
+///
+///fn handler(_context: Context, response: Response) {
+///    response.send("Hello, securely!");
+///}
+///
+///# #[derive(Clone)]
+///# struct MyTls;
+///# impl SslServer for MyTls {
+///#     type Stream = HttpStream;
+///#     fn wrap_server(&self, stream: HttpStream) -> rustful::HttpResult<HttpStream> { Ok(stream) }
+///# }
+///# fn load_certificate(_host: &str) -> MyTls { MyTls }
+///let tls = Sni::new(load_certificate("example.com"))
+///    .host("*.example.com", load_certificate("*.example.com"))
+///    .host("example.org", load_certificate("example.org"));
+///
+///let server_result = Server {
+///    host: 8443.into(),
+///    ..Server::new(handler)
+///}.run_https(tls);
+///```
+///
+///The backends can be wrapped in `Reloadable` to make it possible to renew
+///their certificates.
+#[derive(Clone, Debug)]
+pub struct Sni<S> {
+    hosts: Vec<(String, S)>,
+    default: S,
+}
+
+impl<S: SslServer> Sni<S> {
+    ///Use `default` when no other backend matches.
+    pub fn new(default: S) -> Sni<S> {
+        Sni {
+            hosts: vec![],
+            default: default,
+        }
+    }
+
+    ///Use `ssl` for host names that match `pattern`.
+    pub fn host<P: Into<String>>(mut self, pattern: P, ssl: S) -> Sni<S> {
+        self.hosts.push((pattern.into(), ssl));
+        self
+    }
+
+    ///Find the backend for `host`.
+    pub fn find(&self, host: Option<&str>) -> &S {
+        host.and_then(|host| {
+            self.hosts.iter().find(|&&(ref pattern, _)| host_matches(pattern, host))
+        }).map_or(&self.default, |&(_, ref ssl)| ssl)
+    }
+}
+
+impl<S: SslServer> SslServer for Sni<S> {
+    type Stream = S::Stream;
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<S::Stream> {
+        let host = if self.hosts.is_empty() {
+            None
+        } else {
+            peek_server_name(&stream)
+        };
+
+        self.find(host.as_ref().map(|host| &**host)).wrap_server(stream)
+    }
+}
+
+///Take the sockets that were passed on by systemd socket activation.
+///
+///The sockets are described by the `LISTEN_PID` and `LISTEN_FDS`
+///environment variables, and start at file descriptor 3. The variables are
+///removed, so the sockets are only taken once, and an empty list is
+///returned if they are missing or meant for another process. An error is
+///returned if any of the file descriptors is not a socket. Each socket can
+///be passed to `Server::run_on_listener`:
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::net::listen_fds;
+///
+///fn handler(_context: Context, response: Response) {
+///    response.send("Hello, world!");
+///}
+///
+///let listener = listen_fds().unwrap().pop().expect("no socket from systemd");
+///let server_result = Server::new(handler).run_on_listener(listener);
+///```
+///
+///It's only available on Unix, with the `systemd` feature.
+#[cfg(all(unix, feature = "systemd"))]
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    const FIRST_FD: i32 = 3;
+
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok());
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let count = match (pid, count) {
+        (Some(pid), Some(count)) if pid == process::id() => count,
+        _ => return Ok(vec![])
+    };
+
+    if count < 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is negative"));
+    }
+
+    let end = FIRST_FD.checked_add(count).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is too large")
+    })?;
+
+    //All of them are checked before any is taken, so nothing is closed if
+    //one of them turns out to be something else.
+    for fd in FIRST_FD..end {
+        //The file is only borrowed for the check, and released without
+        //closing the file descriptor.
+        let file = unsafe { File::from_raw_fd(fd) };
+        let is_socket = file.metadata().map(|metadata| metadata.file_type().is_socket());
+        let _ = file.into_raw_fd();
+
+        if !is_socket? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("file descriptor {} is not a socket", fd)));
+        }
+    }
+
+    //The file descriptors are handed over by systemd, and not owned by
+    //anything else in the process.
+    Ok((FIRST_FD..end).map(|fd| unsafe { TcpListener::from_raw_fd(fd) }).collect())
+}
+
+//Reads the host name from the ClientHello, without consuming it.
+fn peek_server_name(stream: &HttpStream) -> Option<String> {
+    //The largest possible TLS record, with its header.
+    let mut buffer = vec![0; 5 + 16384];
+    let mut previous = 0;
+
+    //The ClientHello may arrive in more than one packet.
+    for _ in 0..10 {
+        let length = match stream.0.peek(&mut buffer) {
+            Ok(0) | Err(_) => return None,
+            Ok(length) => length,
+        };
+
+        match parse_server_name(&buffer[..length]) {
+            Ok(host) => return host,
+            Err(Incomplete) if length > previous => previous = length,
+            Err(Incomplete) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    None
+}
+
+struct Incomplete;
+
+//Finds the server_name extension in a TLS record with a ClientHello.
+fn parse_server_name(record: &[u8]) -> Result<Option<String>, Incomplete> {
+    //Handshake record header: type, version and length.
+    if record.len() < 5 {
+        return Err(Incomplete);
+    }
+
+    if record[0] != 0x16 {
+        return Ok(None);
+    }
+
+    let length = (record[3] as usize) << 8 | record[4] as usize;
+    let body = match record.get(5..5 + length) {
+        Some(body) => body,
+        None => return Err(Incomplete),
+    };
+
+    Ok(parse_client_hello(body))
+}
+
+fn parse_client_hello(handshake: &[u8]) -> Option<String> {
+    let mut reader = ByteReader(handshake);
+
+    //Handshake type 1 is ClientHello.
+    if reader.take(1)? != [1] {
+        return None;
+    }
+
+    reader.take(3)?; //Length
+    reader.take(2 + 32)?; //Version and random
+    reader.skip_prefixed(1)?; //Session ID
+    reader.skip_prefixed(2)?; //Cipher suites
+    reader.skip_prefixed(1)?; //Compression methods
+
+    let mut extensions = ByteReader(reader.take_prefixed(2)?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.take(2)?;
+        let extension = extensions.take_prefixed(2)?;
+
+        if kind == [0, 0] {
+            let mut names = ByteReader(ByteReader(extension).take_prefixed(2)?);
+            while !names.0.is_empty() {
+                let name_type = names.take(1)?;
+                let name = names.take_prefixed(2)?;
+
+                //Type 0 is a host name.
+                if name_type == [0] {
+                    return String::from_utf8(name.to_vec()).ok();
+                }
+            }
+        }
+    }
+
+    None
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn take_prefixed(&mut self, prefix_length: usize) -> Option<&'a [u8]> {
+        let length = self.take(prefix_length)?.iter().fold(0, |length, &byte| length << 8 | byte as usize);
+        self.take(length)
+    }
+
+    fn skip_prefixed(&mut self, prefix_length: usize) -> Option<()> {
+        self.take_prefixed(prefix_length).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_server_name;
+
+    fn client_hello(host: &str) -> Vec<u8> {
+        let mut server_name = vec![0, 0];
+        let list_length = host.len() + 3;
+        let extension_length = list_length + 2;
+        server_name.extend_from_slice(&[(extension_length >> 8) as u8, extension_length as u8]);
+        server_name.extend_from_slice(&[(list_length >> 8) as u8, list_length as u8, 0]);
+        server_name.extend_from_slice(&[(host.len() >> 8) as u8, host.len() as u8]);
+        server_name.extend_from_slice(host.as_bytes());
+
+        //Some other extension before the server name.
+        let mut extensions = vec![0, 10, 0, 2, 0, 29];
+        extensions.extend(server_name);
+
+        let mut hello = vec![3, 3];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[1, 7]); //Session ID
+        hello.extend_from_slice(&[0, 2, 0x13, 0x01]); //Cipher suites
+        hello.extend_from_slice(&[1, 0]); //Compression methods
+        hello.extend_from_slice(&[(extensions.len() >> 8) as u8, extensions.len() as u8]);
+        hello.extend(extensions);
+
+        let mut handshake = vec![1, 0, (hello.len() >> 8) as u8, hello.len() as u8];
+        handshake.extend(hello);
+
+        let mut record = vec![0x16, 3, 1, (handshake.len() >> 8) as u8, handshake.len() as u8];
+        record.extend(handshake);
+        record
+    }
+
+    #[test]
+    fn server_name() {
+        let record = client_hello("www.example.com");
+        assert_eq!(parse_server_name(&record).ok(), Some(Some("www.example.com".into())));
+        assert!(parse_server_name(&record[..record.len() - 1]).is_err());
+        assert_eq!(parse_server_name(b"GET / HTTP/1.1\r\n").ok(), Some(None));
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "systemd"))]
+    fn listen_fds_count() {
+        use std::{env, io, process};
+        use super::listen_fds;
+
+        let pid = process::id().to_string();
+        let listen_fds_with = |pid: &str, count: &str| {
+            env::set_var("LISTEN_PID", pid);
+            env::set_var("LISTEN_FDS", count);
+            listen_fds().map(|listeners| listeners.len()).map_err(|e| e.kind())
+        };
+
+        assert_eq!(listen_fds_with("0", "1"), Ok(0));
+        assert_eq!(listen_fds_with(&pid, "0"), Ok(0));
+        assert_eq!(listen_fds_with(&pid, "-1"), Err(io::ErrorKind::InvalidInput));
+        assert_eq!(listen_fds_with(&pid, "2147483647"), Err(io::ErrorKind::InvalidInput));
+        assert!(env::var("LISTEN_FDS").is_err());
+    }
+}
@@ -41,6 +41,9 @@ use std::string::{FromUtf8Error};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::fmt;
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use hyper;
 
@@ -49,6 +52,8 @@ use anymap::AnyMap;
 use StatusCode;
 
 use header::{
+    Header,
+    HeaderFormat,
     Headers,
     ContentType,
     Connection,
@@ -57,8 +62,15 @@ use header::{
 use filter::{FilterContext, ResponseFilter};
 use filter::ResponseAction as Action;
 use mime::{Mime, TopLevel, SubLevel};
-use server::Global;
-use utils::BytesExt;
+use server::{Global, ErrorPages, ContentTypes};
+use cookie::{self, Cookie};
+use utils::{self, BytesExt};
+use file;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+#[cfg(feature = "json")]
+use serde_json;
 
 ///The result of a response action.
 #[derive(Debug)]
@@ -107,6 +119,51 @@ impl error::Error for Error {
     }
 }
 
+///How `Response::set_header_str` should treat header values with control
+///characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    ///Reject the value with an error. This is the default.
+    Strict,
+
+    ///Remove the offending characters and use the rest of the value.
+    Lenient
+}
+
+impl Default for HeaderPolicy {
+    fn default() -> HeaderPolicy {
+        HeaderPolicy::Strict
+    }
+}
+
+///An invalid header, from `Response::set_header_str`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderError {
+    ///The header name is not a valid token.
+    InvalidName(String),
+
+    ///The value for the named header contains control characters.
+    InvalidValue(String)
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::InvalidName(ref name) => write!(f, "invalid header name: {:?}", name),
+            HeaderError::InvalidValue(ref name) => write!(f, "invalid value for header {}", name)
+        }
+    }
+}
+
+impl error::Error for HeaderError {
+    fn description(&self) -> &str {
+        match *self {
+            HeaderError::InvalidName(_) => "invalid header name",
+            HeaderError::InvalidValue(_) => "invalid header value"
+        }
+    }
+}
+
 ///Error that may occure while sending a file.
 pub enum FileError<'a, 'b> {
     ///Failed to open the file.
@@ -376,6 +433,440 @@ impl<'a, 'b> SendResponse<'a, 'b> for File {
     }
 }
 
+///A value that will be serialized and sent as JSON.
+///
+///The `Content-Type` header will be set to `application/json` and the
+///status code will be set to `500 Internal Server Error` if the value can't
+///be serialized. This requires the `json` feature.
+///
+///```
+///# extern crate rustful;
+///#[macro_use] extern crate serde_derive;
+///use rustful::{Context, Response};
+///use rustful::response::Json;
+///
+///#[derive(Serialize)]
+///struct User {
+///    id: u32,
+///    name: String,
+///}
+///
+///fn my_handler(context: Context, response: Response) {
+///    response.send(Json(User {
+///        id: 7,
+///        name: "Alice".into(),
+///    }));
+///}
+///# fn main() {}
+///```
+#[cfg(feature = "json")]
+#[derive(Clone, Debug)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<'a, 'b, T: Serialize> SendResponse<'a, 'b> for Json<T> {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => {
+                response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![])));
+                response.try_send_data(body)
+            },
+            Err(e) => {
+                response.set_status(StatusCode::InternalServerError);
+                Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+            }
+        }
+    }
+}
+
+///A stream of newline delimited JSON values, on top of a chunked response.
+///
+///Each value is serialized on its own line, as `application/x-ndjson`, so
+///large results can be sent without collecting them first. The response is
+///flushed after every value by default, and `flush_every` can make it less
+///frequent when there are many small values. This requires the `json`
+///feature.
+///
+///```
+///# extern crate rustful;
+///#[macro_use] extern crate serde_derive;
+///use rustful::{Context, Response};
+///use rustful::response::NdJson;
+///
+///#[derive(Serialize)]
+///struct Row {
+///    id: u32,
+///}
+///
+///fn export(context: Context, response: Response) {
+///    let rows = (0..10_000).map(|id| Row { id: id });
+///
+///    let mut lines = NdJson::new(response).flush_every(100);
+///    if lines.send_all(rows).is_err() {
+///        //The client is gone.
+///        return;
+///    }
+///}
+///# fn main() {}
+///```
+#[cfg(feature = "json")]
+pub struct NdJson<'a, 'b> {
+    writer: Chunked<'a, 'b>,
+    flush_every: usize,
+    unflushed: usize,
+}
+
+#[cfg(feature = "json")]
+impl<'a, 'b> NdJson<'a, 'b> {
+    ///Set the `application/x-ndjson` content type and start sending the
+    ///response.
+    pub fn new(mut response: Response<'a, 'b>) -> NdJson<'a, 'b> {
+        response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Ext("x-ndjson".into()), vec![])));
+
+        NdJson {
+            writer: response.into_chunked(),
+            flush_every: 1,
+            unflushed: 0,
+        }
+    }
+
+    ///Flush the response after every `count` values, instead of after
+    ///every value. Zero is treated as one.
+    pub fn flush_every(mut self, count: usize) -> NdJson<'a, 'b> {
+        self.flush_every = count;
+        self
+    }
+
+    ///Serialize and send a value.
+    pub fn send<T: Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(value).map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        line.push(b'\n');
+
+        self.writer.try_send(line)?;
+        self.unflushed += 1;
+
+        if self.unflushed >= self.flush_every {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    ///Serialize and send every value from an iterator, and flush the
+    ///response at the end. The number of values is returned.
+    pub fn send_all<I: IntoIterator>(&mut self, values: I) -> Result<usize, Error> where I::Item: Serialize {
+        let mut count = 0;
+        for value in values {
+            self.send(&value)?;
+            count += 1;
+        }
+
+        self.flush()?;
+        Ok(count)
+    }
+
+    ///Flush any values that haven't been flushed yet.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    ///Get a reference to the underlying chunked response.
+    pub fn chunked(&mut self) -> &mut Chunked<'a, 'b> {
+        &mut self.writer
+    }
+
+    ///Finish writing the stream and collect eventual errors.
+    ///
+    ///This is optional and will happen silently when the stream drops out of
+    ///scope.
+    pub fn end(self) -> Result<(), Error> {
+        self.writer.end()
+    }
+}
+
+///An empty `204 No Content` response.
+///
+///Only the status and the headers are sent. Any `Content-Length` or
+///`Transfer-Encoding` header is removed, and anything that the response
+///filters try to add to the body is dropped, so the response is always
+///valid. The filters may still change the status, and the response is then
+///sent with `Content-Length: 0`.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::response::NoContent;
+///
+///fn delete_user(context: Context, response: Response) {
+///    //Delete the user...
+///
+///    response.send(NoContent);
+///}
+///```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NoContent;
+
+impl<'a, 'b> SendResponse<'a, 'b> for NoContent {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        response.set_status(StatusCode::NoContent);
+        response.send_empty()
+    }
+}
+
+///An empty `304 Not Modified` response.
+///
+///This works like `NoContent`, but with a different status. Headers that
+///describe the cached content, like `ETag` and `Cache-Control`, should be
+///set before it's sent.
+///
+///```
+///use rustful::{Context, Response};
+///use rustful::header::{ETag, EntityTag, IfNoneMatch};
+///use rustful::response::NotModified;
+///
+///fn my_handler(context: Context, mut response: Response) {
+///    let tag = EntityTag::strong("v1".into());
+///    response.headers_mut().set(ETag(tag.clone()));
+///
+///    match context.headers.get::<IfNoneMatch>() {
+///        Some(&IfNoneMatch::Items(ref tags)) if tags.iter().any(|t| t.weak_eq(&tag)) => {
+///            response.send(NotModified)
+///        },
+///        _ => response.send("content")
+///    }
+///}
+///```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NotModified;
+
+impl<'a, 'b> SendResponse<'a, 'b> for NotModified {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        response.set_status(StatusCode::NotModified);
+        response.send_empty()
+    }
+}
+
+///An error that is sent as a response with its own status code.
+///
+///It can be returned from handlers, as in `Result<T, StatusError>`, where
+///it replaces the default `500 Internal Server Error` with its status,
+///headers and message. An error without a message is sent with an empty
+///body, so any error page for the status is used instead. The source
+///error, if any, is logged for server errors, but never sent.
+///
+///[`ResultExt`][result_ext] turns other errors into `StatusError`:
+///
+///```
+///use std::fs::File;
+///use std::io::Read;
+///use rustful::{Context, StatusCode};
+///use rustful::handler::ContentFactory;
+///use rustful::response::{StatusError, ResultExt};
+///
+///fn read_note(context: Context) -> Result<String, StatusError> {
+///    let name = context.variables.get("name").or_status(StatusCode::BadRequest)?;
+///    if name.contains('/') {
+///        return Err(StatusError::new(StatusCode::Forbidden).message("nice try"));
+///    }
+///
+///    let mut file = File::open(format!("notes/{}.txt", name))?;
+///    let mut note = String::new();
+///    file.read_to_string(&mut note).or_status(StatusCode::InternalServerError)?;
+///    Ok(note)
+///}
+///
+///let handler = ContentFactory(read_note);
+///```
+///
+///[result_ext]: trait.ResultExt.html
+#[derive(Debug)]
+pub struct StatusError {
+    status: StatusCode,
+    message: Option<String>,
+    headers: Headers,
+    source: Option<Box<error::Error + Send + Sync>>,
+}
+
+impl StatusError {
+    ///Create an error with `status` and nothing else.
+    pub fn new(status: StatusCode) -> StatusError {
+        StatusError {
+            status: status,
+            message: None,
+            headers: Headers::new(),
+            source: None,
+        }
+    }
+
+    ///Set a message that will be sent as the response body.
+    pub fn message<M: Into<String>>(mut self, message: M) -> StatusError {
+        self.message = Some(message.into());
+        self
+    }
+
+    ///Add a header to the response, such as `Allow` or `WwwAuthenticate`.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> StatusError {
+        self.headers.set(header);
+        self
+    }
+
+    ///Set the error that caused this error.
+    pub fn source<E: Into<Box<error::Error + Send + Sync>>>(mut self, source: E) -> StatusError {
+        self.source = Some(source.into());
+        self
+    }
+
+    ///The response status.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    ///The response message, if any.
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_ref().map(|message| &**message)
+    }
+
+    ///The extra response headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+}
+
+impl From<StatusCode> for StatusError {
+    fn from(status: StatusCode) -> StatusError {
+        StatusError::new(status)
+    }
+}
+
+impl From<io::Error> for StatusError {
+    fn from(error: io::Error) -> StatusError {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => StatusCode::NotFound,
+            io::ErrorKind::PermissionDenied => StatusCode::Forbidden,
+            _ => StatusCode::InternalServerError
+        };
+
+        StatusError::new(status).source(error)
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.message, &self.source) {
+            (&Some(ref message), _) => write!(f, "{}: {}", self.status, message),
+            (&None, &Some(ref source)) => write!(f, "{}: {}", self.status, source),
+            (&None, &None) => write!(f, "{}", self.status)
+        }
+    }
+}
+
+impl error::Error for StatusError {
+    fn description(&self) -> &str {
+        self.get_message().or_else(|| self.status.canonical_reason()).unwrap_or("unknown status")
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        self.source.as_ref().map(|source| &**source as &error::Error)
+    }
+}
+
+impl<'a, 'b> SendResponse<'a, 'b> for StatusError {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        if let (true, Some(source)) = (self.status.is_server_error(), self.source.as_ref()) {
+            error!("{}: {}", self.status, source);
+        }
+
+        response.set_status(self.status);
+        response.headers_mut().extend(self.headers.iter());
+        response.try_send_data(self.message.unwrap_or_default())
+    }
+}
+
+///Turns errors and missing values into `StatusError`.
+pub trait ResultExt<T> {
+    ///Use `status` for the error, and keep the original error as its
+    ///source.
+    fn or_status(self, status: StatusCode) -> Result<T, StatusError>;
+
+    ///Use `status` and `message` for the error, and keep the original error
+    ///as its source.
+    fn or_status_message<M: Into<String>>(self, status: StatusCode, message: M) -> Result<T, StatusError>;
+}
+
+impl<T, E: Into<Box<error::Error + Send + Sync>>> ResultExt<T> for Result<T, E> {
+    fn or_status(self, status: StatusCode) -> Result<T, StatusError> {
+        self.map_err(|error| StatusError::new(status).source(error))
+    }
+
+    fn or_status_message<M: Into<String>>(self, status: StatusCode, message: M) -> Result<T, StatusError> {
+        self.map_err(|error| StatusError::new(status).message(message).source(error))
+    }
+}
+
+impl<T> ResultExt<T> for Option<T> {
+    fn or_status(self, status: StatusCode) -> Result<T, StatusError> {
+        self.ok_or_else(|| StatusError::new(status))
+    }
+
+    fn or_status_message<M: Into<String>>(self, status: StatusCode, message: M) -> Result<T, StatusError> {
+        self.ok_or_else(|| StatusError::new(status).message(message))
+    }
+}
+
+///A shared counter for the number of response body bytes that have been
+///written to the connection.
+#[derive(Clone, Debug, Default)]
+pub struct SentBytes(Arc<AtomicUsize>);
+
+impl SentBytes {
+    ///Create a new counter, starting at 0.
+    pub fn new() -> SentBytes {
+        SentBytes::default()
+    }
+
+    ///The number of bytes that have been written so far.
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, bytes: usize) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+///A shared record of the status code that was sent to the client, after
+///filtering.
+#[derive(Clone, Debug, Default)]
+pub struct SentStatus(Arc<AtomicUsize>);
+
+impl SentStatus {
+    ///Create a new record, where no status has been sent.
+    pub fn new() -> SentStatus {
+        SentStatus::default()
+    }
+
+    ///The status code that was sent, if the headers have been written.
+    pub fn get(&self) -> Option<StatusCode> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            status => Some(StatusCode::from_u16(status as u16))
+        }
+    }
+
+    fn set(&self, status: StatusCode) {
+        self.0.store(status.to_u16() as usize, Ordering::Relaxed);
+    }
+}
+
 ///Helper trait for dealing with errors that may occur while sending a
 ///response. It provides a default method of handling the error.
 pub trait ResponseError {
@@ -441,6 +932,31 @@ impl<'a> MaybeMock<hyper::server::response::Response<'a>> {
         }
     }
 
+    fn send_head(mut self, content_length: usize) -> io::Result<()> {
+        if let MaybeMock::Actual(ref mut response) = self {
+            response.headers_mut().set(::header::ContentLength(content_length as u64));
+        }
+
+        self.start().and_then(|response| response.end())
+    }
+
+    fn send_empty(mut self) -> io::Result<()> {
+        match self.status() {
+            StatusCode::NoContent | StatusCode::NotModified => {
+                self.headers_mut().remove::<::header::ContentLength>();
+                self.headers_mut().remove_raw("content-length");
+                self.headers_mut().remove::<::header::TransferEncoding>();
+                self.headers_mut().remove_raw("transfer-encoding");
+                self.start().and_then(|response| response.end())
+            },
+            _ => {
+                self.headers_mut().remove::<::header::TransferEncoding>();
+                self.headers_mut().remove_raw("transfer-encoding");
+                self.send_head(0)
+            }
+        }
+    }
+
     fn start(self) -> io::Result<MaybeMock<hyper::server::response::Response<'a, hyper::net::Streaming>>> {
         if let MaybeMock::Actual(response) = self {
             response.start().map(MaybeMock::Actual)
@@ -465,7 +981,7 @@ impl<'a> Write for MaybeMock<hyper::server::response::Response<'a, hyper::net::S
         if let MaybeMock::Actual(ref mut response) = *self {
             response.write(buffer)
         } else {
-            Ok(0)
+            Ok(buffer.len())
         }
     }
 
@@ -487,8 +1003,16 @@ pub struct Response<'a, 'b> {
     writer: Option<MaybeMock<hyper::server::response::Response<'a>>>,
     filters: &'b [Box<ResponseFilter>],
     global: &'b Global,
+    error_pages: Option<&'b ErrorPages>,
+    content_types: Option<&'b ContentTypes>,
     filter_storage: Option<AnyMap>,
-    force_close: bool
+    force_close: bool,
+    keep_alive_timeout: Option<Duration>,
+    sent_bytes: SentBytes,
+    sent_status: SentStatus,
+    header_policy: HeaderPolicy,
+    omit_body: bool,
+    deadline: Option<(Instant, StatusCode)>
 }
 
 impl<'a, 'b> Response<'a, 'b> {
@@ -498,14 +1022,25 @@ impl<'a, 'b> Response<'a, 'b> {
         response: hyper::server::response::Response<'a>,
         filters: &'b [Box<ResponseFilter>],
         global: &'b Global,
-        force_close: bool
+        error_pages: &'b ErrorPages,
+        content_types: Option<&'b ContentTypes>,
+        force_close: bool,
+        header_policy: HeaderPolicy
     ) -> Response<'a, 'b> {
         Response {
             writer: Some(MaybeMock::actual(response)),
             filters: filters,
             global: global,
+            error_pages: Some(error_pages),
+            content_types: content_types,
             filter_storage: Some(AnyMap::new()),
-            force_close: force_close
+            force_close: force_close,
+            keep_alive_timeout: None,
+            sent_bytes: SentBytes::new(),
+            sent_status: SentStatus::new(),
+            header_policy: header_policy,
+            omit_body: false,
+            deadline: None
         }
     }
 
@@ -515,8 +1050,31 @@ impl<'a, 'b> Response<'a, 'b> {
             writer: Some(MaybeMock::mock()),
             filters: &[],
             global: global,
+            error_pages: None,
+            content_types: None,
             filter_storage: Some(AnyMap::new()),
-            force_close: false
+            force_close: false,
+            keep_alive_timeout: None,
+            sent_bytes: SentBytes::new(),
+            sent_status: SentStatus::new(),
+            header_policy: HeaderPolicy::default(),
+            omit_body: false,
+            deadline: None
+        }
+    }
+
+    #[doc(hidden)]
+    ///Internal and may change without warning.
+    pub fn set_deadline(&mut self, deadline: Instant, status: StatusCode) {
+        self.deadline = Some((deadline, status));
+    }
+
+    //The status code that replaces the current one if the deadline has
+    //passed.
+    fn deadline_status(&self) -> Option<StatusCode> {
+        match self.deadline {
+            Some((deadline, status)) if Instant::now() >= deadline => Some(status),
+            _ => None
         }
     }
 
@@ -537,11 +1095,148 @@ impl<'a, 'b> Response<'a, 'b> {
         self.writer.as_ref().expect("headers accessed after drop").headers()
     }
 
+    ///Set a header from a string, after checking that it can't be used to
+    ///inject additional headers or break the response. This is useful when
+    ///the value comes from user input.
+    ///
+    ///The name has to be a valid header name, and the value must not
+    ///contain control characters, other than horizontal tab. What happens
+    ///to an invalid value depends on the server's `header_policy`, where
+    ///the default is to return an error.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    let name = context.variables.get("name").unwrap_or("".into()).into_owned();
+    ///
+    ///    if response.set_header_str("X-Greeting", &format!("hello {}", name)).is_err() {
+    ///        response.set_status(StatusCode::BadRequest);
+    ///    }
+    ///}
+    ///```
+    pub fn set_header_str(&mut self, name: &str, value: &str) -> Result<(), HeaderError> {
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(HeaderError::InvalidName(name.to_owned()));
+        }
+
+        let is_valid_byte = |byte: u8| byte == b'\t' || (byte >= 0x20 && byte != 0x7f);
+        let value = if value.bytes().all(&is_valid_byte) {
+            value.as_bytes().to_owned()
+        } else if self.header_policy == HeaderPolicy::Lenient {
+            value.bytes().filter(|&byte| is_valid_byte(byte)).collect()
+        } else {
+            return Err(HeaderError::InvalidValue(name.to_owned()));
+        };
+
+        self.headers_mut().set_raw(name.to_owned(), vec![value]);
+        Ok(())
+    }
+
     ///Get a mutable reference to the headers.
     pub fn headers_mut(&mut self) -> &mut Headers {
         self.writer.as_mut().expect("headers mutably accessed after drop").headers_mut()
     }
 
+    ///Override the `keep-alive` behavior for the current connection.
+    ///
+    ///Setting this to `None` will close the connection after this response,
+    ///which may be useful for error responses. `Some(timeout)` will let the
+    ///connection idle for `timeout`, rounded up to whole seconds, before the
+    ///next request, instead of the server's `keep_alive` timeout. The client
+    ///is told about it through the `Keep-Alive` header. This has no effect
+    ///if the server has decided to close the connection, or if `keep_alive`
+    ///is disabled for the server.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::StatusCode;
+    ///
+    ///fn my_handler(context: Context, mut response: Response) {
+    ///    if context.headers.get_raw("x-api-key").is_none() {
+    ///        response.set_status(StatusCode::Unauthorized);
+    ///        response.set_keep_alive_timeout(None);
+    ///    }
+    ///}
+    ///```
+    pub fn set_keep_alive_timeout(&mut self, timeout: Option<Duration>) {
+        match timeout {
+            Some(timeout) => self.keep_alive_timeout = Some(timeout),
+            None => self.force_close = true
+        }
+    }
+
+    ///Only write the status code and headers, and discard the body. This is
+    ///meant for answering `HEAD` requests with a handler for `GET` requests.
+    ///
+    ///The `Content-Length` header will still be set to the length of the
+    ///body, if it's known. Chunked responses will close the connection
+    ///when they are done, since the client can't know where they end.
+    pub fn set_omit_body(&mut self, omit: bool) {
+        self.omit_body = omit;
+    }
+
+    ///Check if the body will be discarded.
+    pub fn omits_body(&self) -> bool {
+        self.omit_body
+    }
+
+    ///Add a cookie to the response, using the `Set-Cookie` header.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::cookie::Cookie;
+    ///
+    ///fn log_out(context: Context, mut response: Response) {
+    ///    response.set_cookie(Cookie::removal("session").path("/"));
+    ///    response.send("logged out");
+    ///}
+    ///```
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        cookie::append(self.headers_mut(), &cookie);
+    }
+
+    ///Get a counter for the number of body bytes that are written to the
+    ///connection. The counter is shared with the `Chunked` and `Raw`
+    ///writers, and it can be read after the response has been sent, or
+    ///aborted, to see how much of the body actually got through.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let sent_bytes = response.sent_bytes();
+    ///    let mut chunked = response.into_chunked();
+    ///    chunked.send("hello ");
+    ///    chunked.send("world");
+    ///    chunked.end().unwrap();
+    ///
+    ///    assert_eq!(sent_bytes.get(), 11);
+    ///}
+    ///# fn main() {
+    ///#     use rustful::{Method, header::Headers, server::Global};
+    ///#     let global = Global::default();
+    ///#     let context = Context::mock(Method::Get, "/", Headers::new(), &global);
+    ///#     my_handler(context, Response::mock(&global));
+    ///# }
+    ///```
+    pub fn sent_bytes(&self) -> SentBytes {
+        self.sent_bytes.clone()
+    }
+
+    ///Get a record of the final status code, as it's sent to the client.
+    ///It's set when the headers are written, after the response filters
+    ///have had their say.
+    pub fn sent_status(&self) -> SentStatus {
+        self.sent_status.clone()
+    }
+
+    ///Get a reference to the global data.
+    pub fn global(&self) -> &'b Global {
+        self.global
+    }
+
     ///Get a reference to the filter storage.
     pub fn filter_storage(&self) -> &AnyMap {
         self.filter_storage.as_ref().expect("filter storage accessed after drop")
@@ -647,11 +1342,37 @@ impl<'a, 'b> Response<'a, 'b> {
         let mut writer = self.writer.take().expect("response used after drop");
         let mut filter_storage = self.filter_storage.take().expect("response used after drop");
 
+        let mut content = content.into();
+        if let Some(status) = self.deadline_status() {
+            *writer.status_mut() = status;
+            content = Data::Bytes(Cow::Borrowed(&[]));
+        }
+
+        if content.as_bytes().is_empty() {
+            if let Some((content_type, page)) = self.error_pages.and_then(|pages| pages.find(writer.status())) {
+                writer.headers_mut().set(ContentType(content_type.clone()));
+                content = Data::Bytes(page.into_owned().into());
+            }
+        }
+
+        if let (Some(content_types), false) = (self.content_types, content.as_bytes().is_empty()) {
+            let content_type = match content {
+                Data::String(_) => &content_types.text,
+                Data::Bytes(_) => &content_types.binary
+            };
+            set_default_content_type(writer.headers_mut(), content_type);
+        }
+
         if self.filters.is_empty() {
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+            set_connection_headers(writer.headers_mut(), self.force_close, self.keep_alive_timeout);
+            self.sent_status.set(writer.status());
+            if self.omit_body {
+                try!(writer.send_head(content.as_bytes().len()));
+            } else {
+                try!(writer.send(content.as_bytes()));
+                self.sent_bytes.add(content.as_bytes().len());
             }
-            writer.send(content.into().as_bytes()).map_err(|e| e.into())
+            Ok(())
         } else {
             let mut buffer = vec![];
 
@@ -662,10 +1383,9 @@ impl<'a, 'b> Response<'a, 'b> {
                 self.global,
                 &mut filter_storage
             ));
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
-            }
+            set_connection_headers(writer.headers_mut(), self.force_close, self.keep_alive_timeout);
             *writer.status_mut() = status;
+            self.sent_status.set(status);
             for action in write_queue {
                 match action {
                     Action::Next(Some(content)) => buffer.push_bytes(content.as_bytes()),
@@ -692,8 +1412,57 @@ impl<'a, 'b> Response<'a, 'b> {
                 }
             }
 
-            writer.send(&buffer).map_err(|e| e.into())
+            if self.omit_body {
+                try!(writer.send_head(buffer.len()));
+            } else {
+                try!(writer.send(&buffer));
+                self.sent_bytes.add(buffer.len());
+            }
+            Ok(())
+        }
+    }
+
+    //Send only the status and the headers, and make sure that no body or
+    //body length is sent, even if the filters try to add one.
+    fn send_empty(&mut self) -> Result<(), Error> {
+        let mut writer = self.writer.take().expect("response used after drop");
+        let mut filter_storage = self.filter_storage.take().expect("response used after drop");
+
+        if let Some(status) = self.deadline_status() {
+            *writer.status_mut() = status;
+        }
+
+        if !self.filters.is_empty() {
+            let (status, write_queue) = try!(filter_headers(
+                self.filters,
+                writer.status(),
+                writer.headers_mut(),
+                self.global,
+                &mut filter_storage
+            ));
+            *writer.status_mut() = status;
+
+            for action in write_queue {
+                if let Action::Abort(e) = action {
+                    return Err(Error::Filter(e));
+                }
+            }
+
+            if let Action::Abort(e) = filter_content(self.filters, &[][..], self.global, &mut filter_storage) {
+                return Err(Error::Filter(e));
+            }
+
+            for action in try!(filter_end(self.filters, self.global, &mut filter_storage)) {
+                if let Action::Abort(e) = action {
+                    return Err(Error::Filter(e));
+                }
+            }
         }
+
+        set_connection_headers(writer.headers_mut(), self.force_close, self.keep_alive_timeout);
+        self.sent_status.set(writer.status());
+        try!(writer.send_empty());
+        Ok(())
     }
 
     ///Send a static file with a specified MIME type to the client.
@@ -768,15 +1537,79 @@ impl<'a, 'b> Response<'a, 'b> {
         file.send_response(self)
     }
 
+    ///Send a file from a [`Cache`][cache], with a MIME type that is guessed
+    ///from its extension. It's loaded into the cache if it's not already
+    ///there, or if it has been modified.
+    ///
+    ///An error is returned upon failure and the response may be recovered
+    ///from there if the file could not be loaded, just like with
+    ///`send_file_with_mime`.
+    ///
+    ///[cache]: ../file/struct.Cache.html
+    pub fn send_cached_file<P: AsRef<Path>>(mut self, cache: &file::Cache, path: P) -> Result<(), FileError<'a, 'b>> {
+        let path: &Path = path.as_ref();
+        let content = match cache.get(path) {
+            Ok(content) => content,
+            Err(e) => return Err(FileError::Open(e, self))
+        };
+
+        let mime = path
+            .extension()
+            .and_then(|ext| file::ext_to_mime(&ext.to_string_lossy()))
+            .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
+
+        self.headers_mut().set(ContentType(mime));
+        response_to_io_result(self.try_send_data(&content[..])).map_err(FileError::Send)
+    }
+
+    ///Turn the `Response` into a `Buffered` response, that collects the
+    ///body in memory and sends it with a `Content-Length` when it's done.
+    ///
+    ///The response is switched to chunked encoding if the body grows larger
+    ///than `threshold` bytes, to put a limit on the memory usage. It will
+    ///never switch if `threshold` is `None`.
+    ///
+    ///```
+    ///use std::io::Write;
+    ///use rustful::{Context, Response};
+    ///
+    ///fn my_handler(context: Context, response: Response) {
+    ///    let mut buffered = response.into_buffered(Some(64 * 1024));
+    ///
+    ///    for i in 0..10 {
+    ///        let _ = writeln!(buffered, "<p>paragraph #{}</p>", i + 1);
+    ///    }
+    ///}
+    ///```
+    pub fn into_buffered(self, threshold: Option<usize>) -> Buffered<'a, 'b> {
+        Buffered {
+            state: Some(BufferState::Buffering(self, vec![])),
+            threshold: threshold,
+        }
+    }
+
     ///Write the status code and headers to the client and turn the `Response`
     ///into a `Chunked` response.
     pub fn into_chunked(mut self) -> Chunked<'a, 'b> {
         let mut writer = self.writer.take().expect("response used after drop");
 
+        if let Some(status) = self.deadline_status() {
+            *writer.status_mut() = status;
+            self.omit_body = true;
+        }
+
+        if let Some(content_types) = self.content_types {
+            set_default_content_type(writer.headers_mut(), &content_types.binary);
+        }
+
         //Make sure it's chunked
         writer.headers_mut().remove::<::header::ContentLength>();
         writer.headers_mut().remove_raw("content-length");
 
+        let sent_bytes = self.sent_bytes.clone();
+        let sent_status = self.sent_status.clone();
+        let omit_body = self.omit_body;
+        let force_close = self.force_close || omit_body;
         let writer = filter_headers(
             self.filters,
             writer.status(),
@@ -784,15 +1617,14 @@ impl<'a, 'b> Response<'a, 'b> {
             self.global,
             self.filter_storage_mut()
         ).and_then(|(status, write_queue)|{
-            if self.force_close {
-                writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
-            }
+            set_connection_headers(writer.headers_mut(), force_close, self.keep_alive_timeout);
             *writer.status_mut() = status;
+            sent_status.set(status);
             let mut writer = try!(writer.start());
 
             for action in write_queue {
                 match action {
-                    Action::Next(Some(content)) => try!(writer.write_all(content.as_bytes())),
+                    Action::Next(Some(content)) => try!(write_body(&mut writer, content.as_bytes(), &sent_bytes, omit_body)),
                     Action::Next(None) => {},
                     Action::Abort(e) => return Err(Error::Filter(e)),
                     Action::SilentAbort => break
@@ -806,7 +1638,9 @@ impl<'a, 'b> Response<'a, 'b> {
             writer: Some(writer),
             filters: self.filters,
             global: self.global,
-            filter_storage: self.filter_storage.take().expect("response used after drop")
+            filter_storage: self.filter_storage.take().expect("response used after drop"),
+            sent_bytes: self.sent_bytes.clone(),
+            omit_body: omit_body
         }
     }
 
@@ -819,21 +1653,27 @@ impl<'a, 'b> Response<'a, 'b> {
     pub unsafe fn into_raw(mut self, content_length: u64) -> Raw<'a> {
         let mut writer = self.writer.take().expect("response used after drop");
 
-        if self.force_close {
-            writer.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+        if let Some(content_types) = self.content_types {
+            set_default_content_type(writer.headers_mut(), &content_types.binary);
         }
+
+        set_connection_headers(writer.headers_mut(), self.force_close, self.keep_alive_timeout);
         writer.headers_mut().remove_raw("content-length");
         writer.headers_mut().set(::header::ContentLength(content_length));
+        self.sent_status.set(writer.status());
 
         Raw {
-            writer: Some(writer.start())
+            writer: Some(writer.start()),
+            sent_bytes: self.sent_bytes.clone(),
+            filter_storage: self.filter_storage.take().expect("response used after drop"),
+            omit_body: self.omit_body
         }
     }
 }
 
 #[allow(unused_must_use)]
 impl<'a, 'b> Drop for Response<'a, 'b> {
-    ///Writes status code and headers and closes the connection.
+    ///Writes status code and headers, and finishes the response.
     fn drop(&mut self) {
         if self.writer.is_some() {
             self.send_sized(&[][..]);
@@ -851,7 +1691,9 @@ pub struct Chunked<'a, 'b> {
     writer: Option<Result<MaybeMock<hyper::server::response::Response<'a, hyper::net::Streaming>>, Error>>,
     filters: &'b [Box<ResponseFilter>],
     global: &'b Global,
-    filter_storage: AnyMap
+    filter_storage: AnyMap,
+    sent_bytes: SentBytes,
+    omit_body: bool
 }
 
 impl<'a, 'b> Chunked<'a, 'b> {
@@ -866,6 +1708,12 @@ impl<'a, 'b> Chunked<'a, 'b> {
         &mut self.filter_storage
     }
 
+    ///Get a counter for the number of body bytes that has been written to
+    ///the connection, after filtering.
+    pub fn sent_bytes(&self) -> SentBytes {
+        self.sent_bytes.clone()
+    }
+
     ///Send a chunk of data to the client, ignoring any eventual errors. Use
     ///`try_send` to get error information.
     ///
@@ -926,7 +1774,7 @@ impl<'a, 'b> Chunked<'a, 'b> {
         let write_result = match filter_result {
             Action::Next(Some(ref s)) => {
                 let buf = s.as_bytes();
-                match writer.write_all(buf) {
+                match write_body(writer, buf, &self.sent_bytes, self.omit_body) {
                     Ok(()) => Some(Ok(buf.len())),
                     Err(e) => Some(Err(e))
                 }
@@ -960,7 +1808,7 @@ impl<'a, 'b> Chunked<'a, 'b> {
         for action in write_queue {
             try!{
                 match action {
-                    Action::Next(Some(content)) => writer.write_all(content.as_bytes()),
+                    Action::Next(Some(content)) => write_body(&mut writer, content.as_bytes(), &self.sent_bytes, self.omit_body),
                     Action::Abort(e) => return Err(Error::Filter(e)),
                     _ => Ok(())
                 }
@@ -998,7 +1846,7 @@ impl<'a, 'b> Write for Chunked<'a, 'b> {
 
 #[allow(unused_must_use)]
 impl<'a, 'b> Drop for Chunked<'a, 'b> {
-    ///Finishes writing and closes the connection.
+    ///Finishes writing the response.
     fn drop(&mut self) {
         if self.writer.is_some() {
             self.finish();
@@ -1006,6 +1854,116 @@ impl<'a, 'b> Drop for Chunked<'a, 'b> {
     }
 }
 
+///A response body that is collected in memory before it's sent.
+///
+///The body is sent with a `Content-Length` header when the `Buffered`
+///response ends or is dropped, unless it grew larger than its threshold.
+///It's then sent as a `Chunked` response instead, starting with what was
+///already written. Flushing only has an effect after the switch.
+pub struct Buffered<'a, 'b> {
+    state: Option<BufferState<'a, 'b>>,
+    threshold: Option<usize>,
+}
+
+enum BufferState<'a, 'b> {
+    Buffering(Response<'a, 'b>, Vec<u8>),
+    Chunked(Chunked<'a, 'b>),
+}
+
+impl<'a, 'b> Buffered<'a, 'b> {
+    ///Get a reference to the filter storage.
+    pub fn filter_storage(&self) -> &AnyMap {
+        match *self.state.as_ref().expect("response used after drop") {
+            BufferState::Buffering(ref response, _) => response.filter_storage(),
+            BufferState::Chunked(ref chunked) => chunked.filter_storage(),
+        }
+    }
+
+    ///Get a mutable reference to the filter storage. It can be used to
+    ///communicate with the response filters.
+    pub fn filter_storage_mut(&mut self) -> &mut AnyMap {
+        match *self.state.as_mut().expect("response used after drop") {
+            BufferState::Buffering(ref mut response, _) => response.filter_storage_mut(),
+            BufferState::Chunked(ref mut chunked) => chunked.filter_storage_mut(),
+        }
+    }
+
+    ///Check if the body has grown too large and is sent in chunks.
+    pub fn is_chunked(&self) -> bool {
+        if let Some(BufferState::Chunked(_)) = self.state {
+            true
+        } else {
+            false
+        }
+    }
+
+    ///Finish writing the response and collect eventual errors.
+    ///
+    ///This is optional and will happen silently when the writer drops out of
+    ///scope.
+    pub fn end(mut self) -> Result<(), Error> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self.state.take().expect("can only finish once") {
+            BufferState::Buffering(response, buffer) => response.try_send_data(buffer),
+            BufferState::Chunked(chunked) => chunked.end(),
+        }
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        let (response, buffer) = match self.state.take() {
+            Some(BufferState::Buffering(response, buffer)) => (response, buffer),
+            state => {
+                self.state = state;
+                return Ok(());
+            }
+        };
+
+        let mut chunked = response.into_chunked();
+        let result = chunked.try_send(buffer);
+        self.state = Some(BufferState::Chunked(chunked));
+        result.map(|_| ())
+    }
+}
+
+impl<'a, 'b> Write for Buffered<'a, 'b> {
+    fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let spill = match self.state {
+            Some(BufferState::Buffering(_, ref mut buffer)) => {
+                buffer.extend_from_slice(content);
+                self.threshold.map_or(false, |threshold| buffer.len() > threshold)
+            },
+            Some(BufferState::Chunked(ref mut chunked)) => return chunked.write(content),
+            None => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write after close"))
+        };
+
+        if spill {
+            try!(response_to_io_result(self.spill()));
+        }
+
+        Ok(content.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.state {
+            Some(BufferState::Chunked(ref mut chunked)) => chunked.flush(),
+            _ => Ok(())
+        }
+    }
+}
+
+#[allow(unused_must_use)]
+impl<'a, 'b> Drop for Buffered<'a, 'b> {
+    ///Sends the body and finishes the response.
+    fn drop(&mut self) {
+        if self.state.is_some() {
+            self.finish();
+        }
+    }
+}
+
 ///A streaming fixed-size response.
 ///
 ///Everything is written directly to the network stream, without being
@@ -1014,10 +1972,30 @@ impl<'a, 'b> Drop for Chunked<'a, 'b> {
 ///__Unsafety__: The content length is set beforehand, which makes it possible
 ///to send responses that are too short.
 pub struct Raw<'a> {
-    writer: Option<Result<MaybeMock<hyper::server::response::Response<'a, hyper::net::Streaming>>, io::Error>>
+    writer: Option<Result<MaybeMock<hyper::server::response::Response<'a, hyper::net::Streaming>>, io::Error>>,
+    sent_bytes: SentBytes,
+    filter_storage: AnyMap,
+    omit_body: bool
 }
 
 impl<'a> Raw<'a> {
+    ///Get a reference to the filter storage. It's not used by any response
+    ///filters at this point, but it's kept until the response is done.
+    pub fn filter_storage(&self) -> &AnyMap {
+        &self.filter_storage
+    }
+
+    ///Get a mutable reference to the filter storage.
+    pub fn filter_storage_mut(&mut self) -> &mut AnyMap {
+        &mut self.filter_storage
+    }
+
+    ///Get a counter for the number of body bytes that has been written to
+    ///the connection.
+    pub fn sent_bytes(&self) -> SentBytes {
+        self.sent_bytes.clone()
+    }
+
     ///Send a piece of data to the client, ignoring any eventual errors. Use
     ///`try_send` to get error information.
     ///
@@ -1094,13 +2072,22 @@ impl<'a> Raw<'a> {
 
 impl<'a> Write for Raw<'a> {
     fn write(&mut self, content: &[u8]) -> io::Result<usize> {
+        let sent_bytes = self.sent_bytes.clone();
+        let omit_body = self.omit_body;
         let writer = try!(self.borrow_writer());
-        writer.write(content)
+        if omit_body {
+            return Ok(content.len());
+        }
+        let length = try!(writer.write(content));
+        sent_bytes.add(length);
+        Ok(length)
     }
 
     fn write_all(&mut self, content: &[u8]) -> io::Result<()> {
+        let sent_bytes = self.sent_bytes.clone();
+        let omit_body = self.omit_body;
         let writer = try!(self.borrow_writer());
-        writer.write_all(content)
+        write_body(writer, content, &sent_bytes, omit_body)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -1109,6 +2096,56 @@ impl<'a> Write for Raw<'a> {
     }
 }
 
+fn is_token_byte(byte: u8) -> bool {
+    match byte {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+        _ => byte.is_ascii_alphanumeric()
+    }
+}
+
+fn write_counted<W: Write>(writer: &mut W, mut content: &[u8], sent_bytes: &SentBytes) -> io::Result<()> {
+    while !content.is_empty() {
+        match writer.write(content) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(length) => {
+                sent_bytes.add(length);
+                content = &content[length..];
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(())
+}
+
+fn write_body<W: Write>(writer: &mut W, content: &[u8], sent_bytes: &SentBytes, omit: bool) -> io::Result<()> {
+    if omit {
+        Ok(())
+    } else {
+        write_counted(writer, content, sent_bytes)
+    }
+}
+
+fn set_connection_headers(headers: &mut Headers, force_close: bool, keep_alive_timeout: Option<Duration>) {
+    if force_close {
+        headers.set(Connection(vec![ConnectionOption::Close]));
+    } else if let Some(timeout) = keep_alive_timeout {
+        let closing = headers.get::<Connection>().map_or(false, |c| c.contains(&ConnectionOption::Close));
+        if !closing {
+            let seconds = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+            headers.set_raw("Keep-Alive", vec![format!("timeout={}", seconds).into_bytes()]);
+            utils::set_keep_alive_timeout(Duration::from_secs(seconds));
+        }
+    }
+}
+
+fn set_default_content_type(headers: &mut Headers, content_type: &Mime) {
+    if !headers.has::<ContentType>() {
+        headers.set(ContentType(content_type.clone()));
+    }
+}
+
 fn response_to_io_result<T>(res:  Result<T, Error>) -> io::Result<T> {
     match res {
         Ok(v) => Ok(v),
@@ -1245,3 +2282,181 @@ fn filter_end<'a>(filters: &'a [Box<ResponseFilter>], global: &Global, filter_st
 
     Ok(write_queue)
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use StatusCode;
+    use server::Global;
+    use header::{Headers, Connection, ConnectionOption, ContentLength, ETag, EntityTag};
+    use filter::{ResponseFilter, ResponseAction, FilterContext};
+    use testing::Recorder;
+    use super::{Response, HeaderPolicy, HeaderError, Data};
+
+    #[test]
+    fn keep_alive_timeout() {
+        let global = Global::default();
+        let keep_alive = |timeout: Option<Duration>| {
+            let recorded = Recorder::new().respond(&global, |mut response| {
+                response.set_keep_alive_timeout(timeout);
+                response.send("hello");
+            });
+            let header = recorded.headers.get_raw("Keep-Alive").map(|value| String::from_utf8(value[0].clone()).unwrap());
+            (header, recorded.headers.get::<Connection>().map_or(false, |c| c.contains(&ConnectionOption::Close)))
+        };
+
+        assert_eq!(keep_alive(Some(Duration::from_secs(5))), (Some("timeout=5".into()), false));
+        assert_eq!(keep_alive(Some(Duration::from_millis(500))), (Some("timeout=1".into()), false));
+        assert_eq!(keep_alive(Some(Duration::from_millis(2001))), (Some("timeout=3".into()), false));
+        assert_eq!(keep_alive(None), (None, true));
+    }
+
+    #[test]
+    fn strict_header_policy() {
+        let global = Global::default();
+        let mut response = Response::mock(&global);
+
+        assert_eq!(response.set_header_str("X-Greeting", "hello\tthere"), Ok(()));
+        assert_eq!(response.headers().get_raw("X-Greeting"), Some(&[b"hello\tthere".to_vec()][..]));
+
+        assert_eq!(response.set_header_str("X-Greeting", "hi\r\nSet-Cookie: a=b"), Err(HeaderError::InvalidValue("X-Greeting".into())));
+        assert_eq!(response.set_header_str("X-Greeting", "hi\n"), Err(HeaderError::InvalidValue("X-Greeting".into())));
+        assert_eq!(response.set_header_str("X-Greeting", "hi\x7f"), Err(HeaderError::InvalidValue("X-Greeting".into())));
+        assert_eq!(response.headers().get_raw("X-Greeting"), Some(&[b"hello\tthere".to_vec()][..]));
+        assert_eq!(response.headers().get_raw("Set-Cookie"), None);
+
+        for name in &["", "X Greeting", "X-Greeting:", "X-Gr\u{e9}eting", "X-Greeting\r\n", "(X)"] {
+            assert_eq!(response.set_header_str(name, "hi"), Err(HeaderError::InvalidName((*name).into())));
+        }
+    }
+
+    #[test]
+    fn lenient_header_policy() {
+        let global = Global::default();
+        let mut response = Response::mock(&global);
+        response.header_policy = HeaderPolicy::Lenient;
+
+        assert_eq!(response.set_header_str("X-Greeting", "hi\r\nSet-Cookie: a=b"), Ok(()));
+        assert_eq!(response.headers().get_raw("X-Greeting"), Some(&[b"hiSet-Cookie: a=b".to_vec()][..]));
+        assert_eq!(response.headers().get_raw("Set-Cookie"), None);
+
+        assert_eq!(response.set_header_str("X-Greeting:", "hi"), Err(HeaderError::InvalidName("X-Greeting:".into())));
+    }
+
+    #[test]
+    fn status_error_result() {
+        use header::Allow;
+        use Method;
+        use super::{StatusError, ResultExt, SendResponse};
+
+        let result: Result<&str, StatusError> = "x".parse::<u32>()
+            .or_status_message(StatusCode::BadRequest, "not a number")
+            .map(|_| "number");
+        let recorded = Recorder::new().respond(&Global::default(), |response| result.send_response(response).unwrap());
+        assert_eq!(recorded.status, StatusCode::BadRequest);
+        assert_eq!(recorded.body_utf8(), Some("not a number"));
+
+        let error = StatusError::new(StatusCode::MethodNotAllowed).header(Allow(vec![Method::Get]));
+        let result: Result<&str, StatusError> = Err(error);
+        let recorded = Recorder::new().respond(&Global::default(), |response| result.send_response(response).unwrap());
+        assert_eq!(recorded.status, StatusCode::MethodNotAllowed);
+        assert_eq!(recorded.headers.get::<Allow>(), Some(&Allow(vec![Method::Get])));
+        assert_eq!(recorded.body_utf8(), Some(""));
+    }
+
+    #[test]
+    fn buffered_responses() {
+        use std::io::Write;
+
+        let global = Global::default();
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let buffered = |threshold: Option<usize>| Recorder::new().respond(&global, |response| {
+            let mut buffered = response.into_buffered(threshold);
+            for chunk in data.chunks(100) {
+                buffered.write_all(chunk).unwrap();
+            }
+            assert_eq!(buffered.is_chunked(), threshold.map_or(false, |threshold| threshold < data.len()));
+        });
+
+        let recorded = buffered(Some(2000));
+        assert_eq!(recorded.headers.get::<ContentLength>(), Some(&ContentLength(1000)));
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), None);
+        assert_eq!(recorded.body, data);
+
+        let recorded = buffered(Some(250));
+        assert_eq!(recorded.headers.get::<ContentLength>(), None);
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), Some(&[b"chunked".to_vec()][..]));
+        assert_eq!(recorded.body, data);
+
+        let recorded = buffered(None);
+        assert_eq!(recorded.headers.get::<ContentLength>(), Some(&ContentLength(1000)));
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), None);
+        assert_eq!(recorded.body, data);
+    }
+
+    //Tries to give every response a body and a length.
+    struct AddBody;
+
+    impl ResponseFilter for AddBody {
+        fn begin(&self, _context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+            headers.set(ContentLength(5));
+            (status, ResponseAction::next(Some("added")))
+        }
+
+        fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+            ResponseAction::next(content)
+        }
+
+        fn end(&self, _context: FilterContext) -> ResponseAction {
+            ResponseAction::next(Some("more"))
+        }
+    }
+
+    #[test]
+    fn empty_responses_through_filters() {
+        use super::{NoContent, NotModified};
+
+        let global = Global::default();
+        let mut recorder = Recorder::new();
+        recorder.response_filters.push(Box::new(AddBody));
+
+        let recorded = recorder.respond(&global, |response| response.send(""));
+        assert_eq!(recorded.body_utf8(), Some("addedmore"));
+
+        let recorded = recorder.respond(&global, |response| response.send(NoContent));
+        assert_eq!(recorded.status, StatusCode::NoContent);
+        assert_eq!(recorded.headers.get::<ContentLength>(), None);
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), None);
+        assert!(recorded.body.is_empty());
+
+        let recorded = recorder.respond(&global, |mut response| {
+            response.headers_mut().set(ETag(EntityTag::strong("v1".into())));
+            response.send(NotModified)
+        });
+        assert_eq!(recorded.status, StatusCode::NotModified);
+        assert_eq!(recorded.headers.get(), Some(&ETag(EntityTag::strong("v1".into()))));
+        assert_eq!(recorded.headers.get::<ContentLength>(), None);
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), None);
+        assert!(recorded.body.is_empty());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn ndjson_lines() {
+        use std::collections::BTreeMap;
+
+        use header::ContentType;
+        use super::NdJson;
+
+        let recorded = Recorder::new().respond(&Global::default(), |response| {
+            let mut lines = NdJson::new(response).flush_every(2);
+            let mut first = BTreeMap::new();
+            first.insert("a", 1);
+            lines.send(&first).unwrap();
+            assert_eq!(lines.send_all(vec![vec![1, 2], vec![]]).unwrap(), 2);
+        });
+
+        assert_eq!(recorded.headers.get::<ContentType>().map(|mime| mime.to_string()), Some("application/x-ndjson".into()));
+        assert_eq!(recorded.body_utf8(), Some("{\"a\":1}\n[1,2]\n[]\n"));
+    }
+}
@@ -0,0 +1,629 @@
+//!Path rewriting, method overrides and redirects.
+//!
+//!A [`Rewrite`][rewrite] is a context filter that changes the requested
+//!path before it's routed, using an ordered list of rules. Each rule has a
+//!pattern and a replacement, which are written like routes. `:name` matches
+//!a single segment and `*name` matches one or more segments, and they are
+//!inserted where the replacement has the same names:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter};
+//!use rustful::rewrite::Rewrite;
+//!
+//!fn show_post(context: Context, response: Response) {
+//!    let id = context.variables.get("id").unwrap_or_default();
+//!    response.send(format!("post {}", id));
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("posts/:id").then().on_get(show_post);
+//!
+//!let rewrite = Rewrite::new()
+//!    .rule("blog/:year/:id", "posts/:id")
+//!    .rule("p/:id", "posts/:id");
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    context_filters: vec![Box::new(rewrite)],
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!HTML forms can only send `GET` and `POST` requests, so a
+//![`MethodOverride`][method_override] filter can be used to let `POST`
+//!requests say what method they should be routed as, using a header or a
+//!form field. Only the methods that are explicitly allowed can be used.
+//!
+//!The client will not see the new path. A [`Redirect`][redirect] handler
+//!can be used to send the client to it instead:
+//!
+//!```
+//!use rustful::{Handler, DefaultRouter};
+//!use rustful::rewrite::Redirect;
+//!
+//!let mut router = DefaultRouter::<Box<Handler>>::new();
+//!router.build().path("blog/:year/:id").then().on_get(Box::new(Redirect::permanent("/posts/:id")) as Box<Handler>);
+//!```
+//!
+//!A `Redirect` can also be sent as a response, from any handler, after
+//!its variables have been resolved:
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::rewrite::Redirect;
+//!
+//!fn create_post(context: Context, response: Response) {
+//!    //Store the post...
+//!
+//!    response.send(Redirect::see_other("/posts/:id").resolve(&context));
+//!}
+//!```
+//!
+//![rewrite]: struct.Rewrite.html
+//![method_override]: struct.MethodOverride.html
+//![redirect]: struct.Redirect.html
+
+use std::borrow::Cow;
+
+use url::percent_encoding::{percent_encode, EncodeSet, PATH_SEGMENT_ENCODE_SET};
+
+use {StatusCode, Method};
+use header::{Location, ContentType};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use context::{Context, UriPath, MaybeUtf8Owned};
+use filter::{FilterContext, ContextFilter, ContextAction};
+use handler::Handler;
+use handler::routing::Route;
+use response::{Response, SendResponse, Error};
+use utils::push_escaped_html;
+
+///A context filter that rewrites the requested path.
+///
+///The rules are tried in the order they were added, and the first one that
+///matches the whole path is used. The path is left as it is if none of
+///them matches. It has to be added to `Server.context_filters` to run
+///before the request is routed.
+#[derive(Default)]
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    ///Create an empty set of rules.
+    pub fn new() -> Rewrite {
+        Rewrite::default()
+    }
+
+    ///Add a rule that replaces paths that match `pattern` with
+    ///`replacement`.
+    ///
+    ///It will panic if `replacement` uses a variable that is not in
+    ///`pattern`.
+    pub fn rule(mut self, pattern: &str, replacement: &str) -> Rewrite {
+        let pattern = parse(pattern);
+        let replacement = parse(replacement);
+
+        for part in &replacement {
+            match *part {
+                Part::Variable(ref name) | Part::Sequence(ref name) => {
+                    if !pattern.iter().any(|part| part.name() == Some(name)) {
+                        panic!("the variable '{}' is not in the pattern", name);
+                    }
+                },
+                Part::Static(_) => {}
+            }
+        }
+
+        self.rules.push(Rule {
+            pattern: pattern,
+            replacement: replacement,
+        });
+        self
+    }
+
+    fn rewrite(&self, path: &[u8]) -> Option<Vec<u8>> {
+        let segments: Vec<_> = path.segments().collect();
+        let mut captures = vec![];
+
+        for rule in &self.rules {
+            captures.clear();
+            if match_segments(&rule.pattern, &segments, &mut captures) {
+                let mut new_path = vec![];
+                for part in &rule.replacement {
+                    new_path.push(b'/');
+                    match *part {
+                        Part::Static(ref segment) => new_path.extend_from_slice(segment),
+                        Part::Variable(ref name) | Part::Sequence(ref name) => {
+                            if let Some(&(_, ref value)) = captures.iter().find(|&&(capture, _)| capture == name) {
+                                new_path.extend_from_slice(value);
+                            }
+                        }
+                    }
+                }
+
+                if new_path.is_empty() {
+                    new_path.push(b'/');
+                }
+
+                return Some(new_path);
+            }
+        }
+
+        None
+    }
+}
+
+impl ContextFilter for Rewrite {
+    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+        let new_path = context.uri_path.as_path().and_then(|path| self.rewrite(path.as_ref()));
+        if let Some(path) = new_path {
+            context.uri_path = UriPath::Path(MaybeUtf8Owned::from(path));
+        }
+
+        ContextAction::next()
+    }
+}
+
+///A context filter that lets `POST` requests override their method.
+///
+///The new method is taken from the `X-HTTP-Method-Override` header or, if
+///it's missing, the `_method` form field. The form field is only available
+///if `Server.form_body_limit` is set. Only `PUT`, `PATCH` and `DELETE` are
+///allowed by default, and any other method is ignored. It has to be added
+///to `Server.context_filters` to run before the request is routed.
+///
+///```no_run
+///use rustful::{Server, Context, Response, DefaultRouter};
+///use rustful::rewrite::MethodOverride;
+///
+///fn delete_post(context: Context, response: Response) {
+///    //Delete the post...
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("posts/:id").then().on_delete(delete_post);
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    form_body_limit: Some(64 * 1024),
+///    context_filters: vec![Box::new(MethodOverride::new())],
+///    ..Server::new(router)
+///}.run();
+///```
+#[derive(Clone, Debug)]
+pub struct MethodOverride {
+    methods: Vec<Method>,
+    header: Option<String>,
+    form_field: Option<String>,
+}
+
+impl MethodOverride {
+    ///Allow `PUT`, `PATCH` and `DELETE` from the `X-HTTP-Method-Override`
+    ///header and the `_method` form field.
+    pub fn new() -> MethodOverride {
+        MethodOverride {
+            methods: vec![Method::Put, Method::Patch, Method::Delete],
+            header: Some("X-HTTP-Method-Override".into()),
+            form_field: Some("_method".into()),
+        }
+    }
+
+    ///Replace the allowed methods.
+    pub fn methods(mut self, methods: Vec<Method>) -> MethodOverride {
+        self.methods = methods;
+        self
+    }
+
+    ///Read the method from the header `name`, or `None` to not use a
+    ///header.
+    pub fn header<N: Into<String>>(mut self, name: Option<N>) -> MethodOverride {
+        self.header = name.map(Into::into);
+        self
+    }
+
+    ///Read the method from the form field `name`, or `None` to not use a
+    ///form field.
+    pub fn form_field<N: Into<String>>(mut self, name: Option<N>) -> MethodOverride {
+        self.form_field = name.map(Into::into);
+        self
+    }
+
+    fn find_method(&self, context: &Context) -> Option<Method> {
+        let header = self.header.as_ref()
+            .and_then(|name| context.headers.get_raw(name))
+            .and_then(|values| values.last())
+            .and_then(|value| ::std::str::from_utf8(value).ok())
+            .map(|value| value.trim().to_owned());
+
+        let value = header.or_else(|| {
+            self.form_field.as_ref()
+                .and_then(|name| context.post.get(&**name))
+                .map(|value| value.trim().to_owned())
+        });
+
+        value
+            .and_then(|value| value.to_uppercase().parse::<Method>().ok())
+            .filter(|method| self.methods.contains(method))
+    }
+}
+
+impl Default for MethodOverride {
+    fn default() -> MethodOverride {
+        MethodOverride::new()
+    }
+}
+
+impl ContextFilter for MethodOverride {
+    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if context.method == Method::Post {
+            if let Some(method) = self.find_method(context) {
+                context.method = method;
+            }
+        }
+
+        ContextAction::next()
+    }
+}
+
+struct Rule {
+    pattern: Vec<Part>,
+    replacement: Vec<Part>,
+}
+
+enum Part {
+    Static(Vec<u8>),
+    Variable(String),
+    Sequence(String),
+}
+
+impl Part {
+    fn name(&self) -> Option<&String> {
+        match *self {
+            Part::Static(_) => None,
+            Part::Variable(ref name) | Part::Sequence(ref name) => Some(name),
+        }
+    }
+}
+
+fn parse(route: &str) -> Vec<Part> {
+    route.segments().map(|segment| match segment[0] {
+        b':' => Part::Variable(String::from_utf8_lossy(&segment[1..]).into_owned()),
+        b'*' => Part::Sequence(String::from_utf8_lossy(&segment[1..]).into_owned()),
+        _ => Part::Static(segment.to_owned()),
+    }).collect()
+}
+
+fn match_segments<'p>(pattern: &'p [Part], path: &[&[u8]], captures: &mut Vec<(&'p str, Vec<u8>)>) -> bool {
+    let (part, pattern) = match pattern.split_first() {
+        Some(first) => first,
+        None => return path.is_empty()
+    };
+
+    match *part {
+        Part::Static(ref segment) => {
+            path.first() == Some(&&segment[..]) && match_segments(pattern, &path[1..], captures)
+        },
+        Part::Variable(ref name) => {
+            if path.is_empty() {
+                return false;
+            }
+
+            captures.push((name, path[0].to_owned()));
+            if match_segments(pattern, &path[1..], captures) {
+                true
+            } else {
+                captures.pop();
+                false
+            }
+        },
+        Part::Sequence(ref name) => {
+            for end in 1..path.len() + 1 {
+                captures.push((name, path[..end].join(&b'/')));
+                if match_segments(pattern, &path[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+
+            false
+        }
+    }
+}
+
+///A handler and response that redirects the client to another URL.
+///
+///The target can be a path or an absolute URL, and `:name` or `*name`
+///segments are replaced with route variables with the same names, as in
+///`/posts/:id`. The variables are percent encoded, except for the slashes
+///in `*name`, and a location that would start with `//` only after the
+///variables are inserted gets its second slash encoded, so it can't be turned into a
+///redirect to another host. Colons are encoded too, so a variable can't
+///add a scheme, such as `https:` or `javascript:`, to the target. This is done automatically when it's used as a handler, but
+///`resolve` has to be called before it's sent as a response.
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    status: StatusCode,
+    target: String,
+    html: bool,
+}
+
+impl Redirect {
+    ///Redirect with a custom status code.
+    pub fn new<T: Into<String>>(status: StatusCode, target: T) -> Redirect {
+        Redirect {
+            status: status,
+            target: target.into(),
+            html: false,
+        }
+    }
+
+    ///Redirect with `301 Moved Permanently`.
+    pub fn permanent<T: Into<String>>(target: T) -> Redirect {
+        Redirect::new(StatusCode::MovedPermanently, target)
+    }
+
+    ///Redirect with `302 Found`.
+    pub fn temporary<T: Into<String>>(target: T) -> Redirect {
+        Redirect::new(StatusCode::Found, target)
+    }
+
+    ///Redirect with `303 See Other`, which tells the client to follow it
+    ///with a `GET` request, such as after a form has been posted.
+    pub fn see_other<T: Into<String>>(target: T) -> Redirect {
+        Redirect::new(StatusCode::SeeOther, target)
+    }
+
+    ///Send a small HTML page with a link to the target, for clients that
+    ///don't follow redirects by themselves. Default is an empty body.
+    ///
+    ///The link is only added if the target is relative, or if it uses
+    ///`http` or `https`. Any other target gets an empty body.
+    pub fn with_html(mut self) -> Redirect {
+        self.html = true;
+        self
+    }
+
+    ///Replace the variable segments in the target with the route variables
+    ///in `context`. Segments without a matching variable are left as they
+    ///are.
+    pub fn resolve(&self, context: &Context) -> Redirect {
+        Redirect {
+            status: self.status,
+            target: self.location(context),
+            html: self.html,
+        }
+    }
+
+    ///The target URL.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn location(&self, context: &Context) -> String {
+        let mut location = String::with_capacity(self.target.len());
+
+        for (i, segment) in self.target.split('/').enumerate() {
+            if i > 0 {
+                location.push('/');
+            }
+
+            let value = match segment.as_bytes().get(0) {
+                Some(&b':') | Some(&b'*') => context.variables.get_raw(&segment[1..]),
+                _ => None
+            };
+
+            match value {
+                Some(value) => {
+                    let value: &[u8] = value.as_ref();
+                    if segment.starts_with('*') {
+                        for (i, part) in value.split(|&b| b == b'/').enumerate() {
+                            if i > 0 {
+                                location.push('/');
+                            }
+                            location.extend(percent_encode(part, RedirectEncodeSet));
+                        }
+                    } else {
+                        location.extend(percent_encode(value, RedirectEncodeSet));
+                    }
+                },
+                None => location.push_str(segment)
+            }
+        }
+
+        //Browsers treat both `//host` and `/\host` as a different host, so a
+        //variable must not be able to turn the target into one of them.
+        if location.starts_with("//") && !self.target.starts_with("//") {
+            location.replace_range(1..2, "%2F");
+        }
+
+        location
+    }
+}
+
+//Backslashes are encoded as well, since browsers treat them as slashes, and
+//so are colons, since they would turn a leading variable into a scheme.
+#[derive(Clone, Copy)]
+struct RedirectEncodeSet;
+
+impl EncodeSet for RedirectEncodeSet {
+    fn contains(&self, byte: u8) -> bool {
+        byte == b'\\' || byte == b':' || PATH_SEGMENT_ENCODE_SET.contains(byte)
+    }
+}
+
+impl Handler for Redirect {
+    fn handle(&self, context: Context, response: Response) {
+        response.send(self.resolve(&context));
+    }
+
+    fn description(&self) -> Option<Cow<'static, str>> {
+        Some(format!("Redirects to {}", self.target).into())
+    }
+}
+
+//Checks if `target` is relative or has an `http` or `https` scheme, so it's
+//safe to use as a link.
+fn is_web_link(target: &str) -> bool {
+    match target.find(|c| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(index) if target[index..].starts_with(':') => {
+            let scheme = &target[..index];
+            scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+        },
+        _ => true
+    }
+}
+
+impl<'a, 'b> SendResponse<'a, 'b> for Redirect {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        response.set_status(self.status);
+        response.headers_mut().set(Location(self.target.clone()));
+
+        if self.html && is_web_link(&self.target) {
+            let mut body = String::from("<!DOCTYPE html>\n<html><head><title>Redirect</title></head><body><a href=\"");
+            push_escaped_html(&mut body, &self.target);
+            body.push_str("\">Continue</a></body></html>\n");
+
+            response.headers_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![(Attr::Charset, Value::Utf8)])));
+            response.try_send_data(body)
+        } else {
+            response.try_send_data("")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::method::Method;
+
+    use header::Headers;
+    use context::Context;
+    use server::Global;
+    use filter::{ContextFilter, FilterContext};
+    use anymap::AnyMap;
+    use StatusCode;
+    use header::Location;
+    use testing::Recorder;
+    use super::{Rewrite, Redirect, MethodOverride};
+
+    #[test]
+    fn rewrite_rules() {
+        let rewrite = Rewrite::new()
+            .rule("blog/:year/:id", "posts/:id")
+            .rule("files/*path/raw", "raw/*path")
+            .rule("blog", "/")
+            .rule("blog/*rest", "never");
+
+        assert_eq!(rewrite.rewrite(b"/blog/2017/hello/"), Some(b"/posts/hello".to_vec()));
+        assert_eq!(rewrite.rewrite(b"/files/a/b/c/raw"), Some(b"/raw/a/b/c".to_vec()));
+        assert_eq!(rewrite.rewrite(b"/files/raw"), None);
+        assert_eq!(rewrite.rewrite(b"/blog"), Some(b"/".to_vec()));
+        assert_eq!(rewrite.rewrite(b"/blog/2017"), Some(b"/never".to_vec()));
+        assert_eq!(rewrite.rewrite(b"/other"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_variable() {
+        Rewrite::new().rule("blog/:id", "posts/:name");
+    }
+
+    #[test]
+    fn method_overrides() {
+        let global = Global::default();
+        let filter = MethodOverride::new();
+        let mut storage = AnyMap::new();
+
+        let mut override_method = |method: Method, header: Option<&str>, field: Option<&str>| {
+            let mut context = Context::mock(method, "/", Headers::new(), &global);
+            if let Some(header) = header {
+                context.headers.set_raw("X-HTTP-Method-Override", vec![header.as_bytes().to_vec()]);
+            }
+            if let Some(field) = field {
+                context.post.insert("_method", field);
+            }
+
+            filter.modify(FilterContext { storage: &mut storage, global: &global }, &mut context);
+            context.method
+        };
+
+        assert_eq!(override_method(Method::Post, Some("DELETE"), None), Method::Delete);
+        assert_eq!(override_method(Method::Post, None, Some("put")), Method::Put);
+        assert_eq!(override_method(Method::Post, Some("PATCH"), Some("PUT")), Method::Patch);
+        assert_eq!(override_method(Method::Post, Some("CONNECT"), None), Method::Post);
+        assert_eq!(override_method(Method::Post, Some("PURGE"), None), Method::Post);
+        assert_eq!(override_method(Method::Get, Some("DELETE"), None), Method::Get);
+    }
+
+    #[test]
+    fn redirect_location() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        context.variables.insert("id", "a b");
+        context.variables.insert("path", "x/y z");
+
+        let redirect = Redirect::permanent("https://example.com:8080/posts/:id/*path/:missing");
+        assert_eq!(redirect.location(&context), "https://example.com:8080/posts/a%20b/x/y%20z/:missing");
+    }
+
+    #[test]
+    fn redirect_to_same_host() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        context.variables.insert("id", "\\evil.com");
+        context.variables.insert("path", "/evil.com/x");
+
+        assert_eq!(Redirect::permanent("/:id").location(&context), "/%5Cevil.com");
+        assert_eq!(Redirect::permanent("/*path").location(&context), "/%2Fevil.com/x");
+        assert_eq!(Redirect::permanent("*path").location(&context), "/evil.com/x");
+        assert_eq!(Redirect::permanent("//example.com/:id").location(&context), "//example.com/%5Cevil.com");
+    }
+
+    #[test]
+    fn redirect_without_scheme_from_variables() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        context.variables.insert("path", "https://evil.com");
+        context.variables.insert("id", "javascript:alert(1)");
+
+        assert_eq!(Redirect::permanent("*path").location(&context), "https%3A//evil.com");
+        assert_eq!(Redirect::permanent(":id").location(&context), "javascript%3Aalert(1)");
+        assert_eq!(Redirect::permanent("https://example.com/:id").location(&context), "https://example.com/javascript%3Aalert(1)");
+    }
+
+    #[test]
+    fn send_redirect() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Post, "/posts", Headers::new(), &global);
+        context.variables.insert("id", "<5>");
+
+        let redirect = Redirect::see_other("/posts/:id").with_html().resolve(&context);
+        assert_eq!(redirect.target(), "/posts/%3C5%3E");
+
+        let recorded = Recorder::new().respond(&global, |response| response.send(redirect));
+        assert_eq!(recorded.status, StatusCode::SeeOther);
+        assert_eq!(recorded.headers.get(), Some(&Location("/posts/%3C5%3E".into())));
+        assert!(recorded.body_utf8().unwrap().contains("<a href=\"/posts/%3C5%3E\">"));
+    }
+
+    #[test]
+    fn html_only_links_to_web_targets() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        context.variables.insert("id", "javascript:alert(1)");
+
+        let redirect = Redirect::temporary(":id").with_html().resolve(&context);
+        let recorded = Recorder::new().respond(&global, |response| response.send(redirect));
+        assert!(recorded.body_utf8().unwrap().contains("<a href=\"javascript%3Aalert(1)\">"));
+
+        let redirect = Redirect::temporary("JavaScript:alert(1)").with_html();
+        let recorded = Recorder::new().respond(&global, |response| response.send(redirect));
+        assert_eq!(recorded.status, StatusCode::Found);
+        assert!(recorded.body.is_empty());
+
+        let redirect = Redirect::temporary("HTTPS://example.com/a:b").with_html();
+        let recorded = Recorder::new().respond(&global, |response| response.send(redirect));
+        assert!(recorded.body_utf8().unwrap().contains("<a href=\"HTTPS://example.com/a:b\">"));
+    }
+}
@@ -0,0 +1,155 @@
+//!Request sampling, for expensive diagnostics.
+//!
+//!Some diagnostics are too expensive to run for every request. A
+//![`Sampler`][sampler] picks a part of the requests, either randomly or
+//!when the client asks for it with a header, and marks them as
+//![`Sampled`][sampled]. The mark is stored in both `context.extensions`
+//!and the filter storage, so handlers, context filters and response filters
+//!can all see the same decision.
+//!
+//!Samplers can be used both as global context filters and as route
+//!filters. A request is sampled if any of the samplers on its way picks it,
+//!so a route can have a higher rate than the rest of the server:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter};
+//!use rustful::sampling::{Sampler, Sampled};
+//!use rustful::timing::Timing;
+//!
+//!fn search(context: Context, response: Response) {
+//!    if Sampled::check(&context.extensions) {
+//!        //Log the details of the query...
+//!    }
+//!
+//!    response.send("results");
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("search").with_filter(Sampler::new(0.1)).then().on_get(search);
+//!
+//!let timing = Timing::new().sampled_only(true);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    context_filters: vec![
+//!        Box::new(Sampler::new(0.01).header("X-Debug")),
+//!        Box::new(timing.clone())
+//!    ],
+//!    response_filters: vec![Box::new(timing)],
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//![sampler]: struct.Sampler.html
+//![sampled]: struct.Sampled.html
+
+use anymap::AnyMap;
+
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction};
+use trace::TraceContext;
+use utils::random_u64;
+
+///The mark for sampled requests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sampled;
+
+impl Sampled {
+    ///Check if `Sampled` is in `storage`, which can be either
+    ///`context.extensions` or the filter storage.
+    pub fn check(storage: &AnyMap) -> bool {
+        storage.contains::<Sampled>()
+    }
+}
+
+///A context filter that decides which requests should be sampled.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    rate: f64,
+    header: Option<String>,
+    follow_trace: bool,
+}
+
+impl Sampler {
+    ///Sample a fraction of the requests, where `0.0` is none of them and
+    ///`1.0` is all of them.
+    pub fn new(rate: f64) -> Sampler {
+        Sampler {
+            rate: rate,
+            header: None,
+            follow_trace: false,
+        }
+    }
+
+    ///Also sample requests that have the header `name`, whatever its value
+    ///is.
+    pub fn header<N: Into<String>>(mut self, name: N) -> Sampler {
+        self.header = Some(name.into());
+        self
+    }
+
+    ///Also sample requests that belong to a sampled trace. This requires a
+    ///`TraceFilter` to run before the `Sampler`.
+    pub fn follow_trace(mut self, follow: bool) -> Sampler {
+        self.follow_trace = follow;
+        self
+    }
+
+    fn decide(&self, context: &Context) -> bool {
+        if let Some(ref header) = self.header {
+            if context.headers.get_raw(header).is_some() {
+                return true;
+            }
+        }
+
+        if self.follow_trace {
+            if let Some(trace) = context.extensions.get::<TraceContext>() {
+                if trace.sampled {
+                    return true;
+                }
+            }
+        }
+
+        self.rate > 0.0 && (random_u64() as f64 / ::std::u64::MAX as f64) < self.rate
+    }
+}
+
+impl ContextFilter for Sampler {
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if Sampled::check(&context.extensions) || self.decide(context) {
+            context.extensions.insert(Sampled);
+            ctx.storage.insert(Sampled);
+        }
+
+        ContextAction::next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::method::Method;
+
+    use header::Headers;
+    use context::Context;
+    use server::Global;
+    use trace::TraceContext;
+    use super::Sampler;
+
+    #[test]
+    fn sampling_decisions() {
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+
+        assert!(Sampler::new(1.0).decide(&context));
+        assert!(!Sampler::new(0.0).decide(&context));
+        assert!(!Sampler::new(0.0).header("X-Debug").decide(&context));
+
+        context.headers.set_raw("X-Debug", vec![b"1".to_vec()]);
+        assert!(Sampler::new(0.0).header("X-Debug").decide(&context));
+
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        context.extensions.insert(TraceContext::new());
+        assert!(!Sampler::new(0.0).decide(&context));
+        assert!(Sampler::new(0.0).follow_trace(true).decide(&context));
+    }
+}
@@ -0,0 +1,24 @@
+//Keeps a copy of the socket of each new connection, so the handlers can
+//tell if the client has disconnected, even when the connection is
+//encrypted.
+//
+//Hyper accepts and handles each connection on the same worker thread, so
+//the socket is kept in a thread local, until the connection ends.
+
+use hyper::net::{SslServer, HttpStream};
+
+use HttpResult;
+use utils;
+
+//Remembers the socket of each connection, before it's wrapped by `S`.
+#[derive(Clone)]
+pub struct TrackClient<S>(pub S);
+
+impl<S: SslServer> SslServer for TrackClient<S> {
+    type Stream = S::Stream;
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<S::Stream> {
+        utils::set_client_socket(stream.0.try_clone().ok());
+        self.0.wrap_server(stream)
+    }
+}
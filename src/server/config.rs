@@ -1,12 +1,18 @@
 use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr};
 use std::str::FromStr;
-use std::any::TypeId;
+use std::any::{TypeId, type_name};
+use std::error::Error;
+use std::fmt;
+use std::borrow::Cow;
 use std::mem::swap;
 use std::time::Duration;
 
 use anymap::Map;
 use anymap::any::{Any, UncheckedAnyExt};
 
+use StatusCode;
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+
 ///A host address and a port.
 ///
 ///Can be conveniently converted from an existing address-port pair or just a port:
@@ -122,6 +128,34 @@ impl FromStr for Host {
 pub struct Global(GlobalState);
 
 impl Global {
+    ///Create a `Global` that holds a single `GlobalData` value. This is the
+    ///same as `Box::new(data).into()`, but makes the intention clear.
+    ///
+    ///```
+    ///use rustful::server::{Global, GlobalData};
+    ///
+    ///struct AppData {
+    ///    greeting: String,
+    ///}
+    ///
+    ///impl GlobalData for AppData {}
+    ///
+    ///let global = Global::typed(AppData {
+    ///    greeting: "Hello".into(),
+    ///});
+    ///assert_eq!(global.data::<AppData>().map(|data| &*data.greeting), Some("Hello"));
+    ///```
+    pub fn typed<T: GlobalData>(data: T) -> Global {
+        Box::new(data).into()
+    }
+
+    ///Borrow the `GlobalData` of type `T`, if there is one. This is a
+    ///single `TypeId` comparison if it's the only value, as when it's
+    ///created using `Global::typed`.
+    pub fn data<T: GlobalData>(&self) -> Option<&T> {
+        self.get()
+    }
+
     ///Borrow a value of type `T` if the there is one.
     pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
         match self.0 {
@@ -136,6 +170,14 @@ impl Global {
         }
     }
 
+    fn contains_type(&self, id: TypeId) -> bool {
+        match self.0 {
+            GlobalState::None => false,
+            GlobalState::One(one, _) => one == id,
+            GlobalState::Many(ref map) => map.as_ref().contains_key(&id)
+        }
+    }
+
     ///Insert a new value, returning the previous value of the same type, if
     ///any.
     pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
@@ -221,6 +263,121 @@ impl Default for Global {
     }
 }
 
+///A builder for `Global`, that checks that the required types are there.
+///
+///```
+///use rustful::server::GlobalBuilder;
+///
+///struct Database;
+///
+///let global = GlobalBuilder::new()
+///    .insert(Database)
+///    .insert("cat")
+///    .require::<Database>()
+///    .build();
+///assert!(global.is_ok());
+///
+///let error = GlobalBuilder::new().require::<Database>().build().err().unwrap();
+///assert_eq!(error.0.len(), 1);
+///```
+#[derive(Default)]
+pub struct GlobalBuilder {
+    global: Global,
+    requirements: Requirements,
+}
+
+impl GlobalBuilder {
+    ///Create an empty builder.
+    pub fn new() -> GlobalBuilder {
+        GlobalBuilder::default()
+    }
+
+    ///Insert a value, replacing any previous value of the same type.
+    pub fn insert<T: Any + Send + Sync>(mut self, value: T) -> GlobalBuilder {
+        self.global.insert(value);
+        self
+    }
+
+    ///Require a value of type `T`.
+    pub fn require<T: Any + Send + Sync>(mut self) -> GlobalBuilder {
+        self.requirements.require::<T>();
+        self
+    }
+
+    ///Build the `Global`, or list the required types that are missing.
+    pub fn build(self) -> Result<Global, MissingGlobals> {
+        self.requirements.check(&self.global)?;
+        Ok(self.global)
+    }
+}
+
+///The types of global data that are required by the handlers.
+///
+///Handlers can declare their requirements with `Handler::requirements`,
+///which lets the server refuse to start if anything is missing from
+///`Server::global`, instead of failing when a request is handled.
+#[derive(Clone, Default, Debug)]
+pub struct Requirements {
+    types: Vec<(TypeId, &'static str)>,
+}
+
+impl Requirements {
+    ///Create an empty list of requirements.
+    pub fn new() -> Requirements {
+        Requirements::default()
+    }
+
+    ///Require a value of type `T`.
+    pub fn require<T: Any + Send + Sync>(&mut self) {
+        let id = TypeId::of::<T>();
+        if !self.types.iter().any(|&(other, _)| other == id) {
+            self.types.push((id, type_name::<T>()));
+        }
+    }
+
+    ///Check that `global` has every required type.
+    pub fn check(&self, global: &Global) -> Result<(), MissingGlobals> {
+        let missing: Vec<_> = self.types.iter()
+            .filter(|&&(id, _)| !global.contains_type(id))
+            .map(|&(_, name)| name)
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingGlobals(missing))
+        }
+    }
+}
+
+///The names of required global data types that are missing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MissingGlobals(pub Vec<&'static str>);
+
+impl fmt::Display for MissingGlobals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "missing global data: {}", self.0.join(", "))
+    }
+}
+
+impl Error for MissingGlobals {
+    fn description(&self) -> &str {
+        "missing global data"
+    }
+}
+
+///Application defined global data.
+///
+///The idea is to put everything the handlers need in a single struct,
+///instead of many separate values, and implement `GlobalData` for it. It
+///can then be stored using `Global::typed`, and borrowed using
+///`Context::global_as` or `Global::data`. Each lookup is still a `TypeId`
+///comparison, but it's the only one when the struct is the only value.
+///
+///Both return `None` if the value is missing. Declare it in the handler's
+///`requirements` to make the server refuse to start without it instead.
+pub trait GlobalData: Any + Send + Sync {}
+
 enum GlobalState {
     None,
     One(TypeId, Box<Any + Send + Sync>),
@@ -237,3 +394,530 @@ pub struct KeepAlive {
     ///threads goes below this.
     pub free_threads: usize,
 }
+
+///Limits for how many connections the server will accept at once.
+///
+///Connections are handed to the threads in the thread pool as they become
+///free. Connections that arrive while the limits are reached are rejected
+///with `503 Service Unavailable`, instead of being left waiting, so an
+///overloaded server sheds load instead of letting the latency grow.
+///
+///```no_run
+///use std::time::Duration;
+///use rustful::{Server, Context, Response};
+///use rustful::server::ConnectionLimit;
+///
+///fn my_handler(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    threads: Some(8),
+///    connection_limit: Some(ConnectionLimit {
+///        retry_after: Some(Duration::from_secs(10)),
+///        ..ConnectionLimit::new(64, 32)
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///```
+///
+///HTTPS connections can't be answered before the TLS handshake is done,
+///which happens in the thread pool, so they are closed without a response
+///when they are rejected.
+#[derive(Clone, Debug)]
+pub struct ConnectionLimit {
+    ///The maximum number of open connections, including both the ones that
+    ///are being handled and the ones that are waiting for a free thread.
+    pub max_connections: usize,
+
+    ///The maximum number of connections that may wait for a free thread.
+    pub max_pending: usize,
+
+    ///The value of the `Retry-After` header in the `503` response, if any.
+    ///Only whole seconds are sent. Default is `None`.
+    pub retry_after: Option<Duration>,
+
+    ///The body of the `503` response. Default is `"The server is too busy.
+    ///Please try again later."`.
+    pub message: String,
+}
+
+impl ConnectionLimit {
+    ///Create a connection limit with the default response.
+    pub fn new(max_connections: usize, max_pending: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            max_connections: max_connections,
+            max_pending: max_pending,
+            retry_after: None,
+            message: "The server is too busy. Please try again later.".into(),
+        }
+    }
+}
+
+///Limits for the request target, which are checked before it's parsed and
+///routed.
+///
+///Requests with targets that are longer than `max_length` bytes, or that
+///have more than `max_segments` path segments, are rejected with `414 URI
+///Too Long`. This puts a bound on how much work is spent on junk requests.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::server::UriLimit;
+///
+///fn my_handler(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    uri_limit: Some(UriLimit {
+///        max_length: 2048,
+///        max_segments: 16,
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UriLimit {
+    ///The longest request target, in bytes, including the query string.
+    pub max_length: usize,
+
+    ///The highest number of non-empty segments in the path.
+    pub max_segments: usize,
+}
+
+///HTTP Strict Transport Security (HSTS), which tells browsers to only use
+///HTTPS for the host.
+///
+///The `Strict-Transport-Security` header is added to every response that
+///is sent over HTTPS, including the ones from HTTPS listeners in
+///`Listeners`. Browsers ignore it in plain HTTP responses, so those are
+///left without it. It's often combined with `Listeners::https_redirect`:
+///
+///```no_run
+///use std::time::Duration;
+///# use rustful::{Server, Context, Response, HttpResult};
+///# use rustful::server::Listeners;
+///# use rustful::net::{SslServer, HttpStream};
+///use rustful::server::Hsts;
+///# #[derive(Clone)]
+///# struct MyTls;
+///# impl SslServer for MyTls {
+///#     type Stream = HttpStream;
+///#     fn wrap_server(&self, stream: HttpStream) -> HttpResult<HttpStream> { Ok(stream) }
+///# }
+///
+///fn handler(_context: Context, response: Response) {
+///    response.send("Hello, securely!");
+///}
+///
+///# fn start() -> HttpResult<Listeners<fn(Context, Response)>> {
+///let mut listeners = Server {
+///    hsts: Some(Hsts::new(Duration::from_secs(365 * 24 * 60 * 60))),
+///    ..Server::new(handler as fn(Context, Response))
+///}.listeners()?;
+///
+///listeners
+///    .https(443.into(), MyTls)?
+///    .https_redirect(80.into(), None)?;
+///# Ok(listeners)
+///# }
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hsts {
+    ///How long the browser should remember to only use HTTPS.
+    pub max_age: Duration,
+
+    ///Apply the policy to all subdomains as well. Default is `false`.
+    pub include_subdomains: bool,
+
+    ///Ask to be included in the browsers' preload lists. Default is
+    ///`false`.
+    pub preload: bool,
+}
+
+impl Hsts {
+    ///Only use HTTPS for `max_age`, for this host.
+    pub fn new(max_age: Duration) -> Hsts {
+        Hsts {
+            max_age: max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    ///Format the header value.
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+
+        if self.preload {
+            value.push_str("; preload");
+        }
+
+        value
+    }
+}
+
+///Limits for the request headers, which are checked before the request is
+///routed.
+///
+///Requests with more than `max_count` header lines, a header line that is
+///longer than `max_length` bytes, or more than `max_total` bytes of headers
+///in total, are rejected with `431 Request Header Fields Too Large`. The
+///lengths include the names, but not the separators and line breaks.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::server::HeaderLimit;
+///
+///fn my_handler(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    header_limit: Some(HeaderLimit {
+///        max_count: 50,
+///        ..HeaderLimit::default()
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///```
+///
+///The HTTP parser has limits of its own, which can't be raised. It accepts
+///at most 100 header lines, and a request head of about 400 kB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderLimit {
+    ///The highest number of header lines. Default is 100.
+    pub max_count: usize,
+
+    ///The longest header line, in bytes. Default is 8 kB.
+    pub max_length: usize,
+
+    ///The largest total size of the headers, in bytes. Default is 32 kB.
+    pub max_total: usize,
+}
+
+impl Default for HeaderLimit {
+    fn default() -> HeaderLimit {
+        HeaderLimit {
+            max_count: 100,
+            max_length: 8 * 1024,
+            max_total: 32 * 1024,
+        }
+    }
+}
+
+///Stricter checks for requests that may be interpreted differently by
+///other servers, such as a proxy in front of this one.
+///
+///Requests are rejected with `400 Bad Request`, and the connection is
+///closed, if they have both `Content-Length` and `Transfer-Encoding`,
+///conflicting or invalid `Content-Length` values, a `Transfer-Encoding`
+///that doesn't end with `chunked`, control characters in the header values,
+///or characters that are not allowed in the request target. Folded header
+///lines are already rejected by the HTTP parser.
+///
+///```no_run
+///use rustful::{Server, Context, Response};
+///use rustful::server::StrictParsing;
+///
+///fn my_handler(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    strict_parsing: Some(StrictParsing {
+///        log_rejections: true,
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct StrictParsing {
+    ///Log a warning, with the client address and the reason, for each
+    ///rejected request. Default is `false`.
+    pub log_rejections: bool,
+}
+
+///How the request path is percent-decoded before it's routed.
+///
+///The whole path is decoded by default, so an encoded slash (`%2F`) will
+///split path segments, just like a literal `/`. Some APIs use `%2F` to put
+///slashes in a single segment, such as `/files/a%2Fb.txt`, and
+///`KeepSlashes` makes this possible:
+///
+///```no_run
+///use rustful::{Server, Context, Response, DefaultRouter};
+///use rustful::server::PathDecoding;
+///
+///fn show_file(context: Context, response: Response) {
+///    //"/files/a%2Fb.txt" gives "a/b.txt"
+///    let name = context.variables.get("name").unwrap_or_default();
+///    response.send(format!("showing {}", name));
+///}
+///
+///let mut router = DefaultRouter::<fn(Context, Response)>::new();
+///router.build().path("files/:name").then().on_get(show_file);
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    path_decoding: PathDecoding::KeepSlashes,
+///    ..Server::new(router)
+///}.run();
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathDecoding {
+    ///Decode the whole path before routing. This is the default.
+    Full,
+
+    ///Keep `%2F` and `%25` encoded in `context.uri_path`, so they won't
+    ///split the path, and decode the route variables after routing instead.
+    ///Everything else is decoded before routing, as usual.
+    KeepSlashes
+}
+
+impl Default for PathDecoding {
+    fn default() -> PathDecoding {
+        PathDecoding::Full
+    }
+}
+
+///Deadlines for handling requests.
+///
+///Each request gets a deadline, which is available to the handlers as
+///`context.deadline`, so they can set their own time limits when they call
+///other services. It's counted from when the request was received, and it's
+///the shortest of `timeout` and the time limit in the `header`, if the client
+///sent one. A response that is sent after the deadline gets the `status`
+///code instead, and its body is left out.
+///
+///```no_run
+///use std::time::Duration;
+///use rustful::{Server, Context, Response};
+///use rustful::server::Deadlines;
+///
+///fn my_handler(context: Context, response: Response) {
+///    let time_left = context.time_left();
+///    //Ask the upstream service with time_left as the timeout...
+///
+///    response.send("Hello!");
+///}
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    deadlines: Some(Deadlines {
+///        header: Some("X-Request-Timeout".into()),
+///        ..Deadlines::new(Duration::from_secs(10))
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///```
+///
+///Handlers are not interrupted when the deadline has passed, and chunked
+///responses that were started in time are sent as usual. Raw responses are
+///never replaced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deadlines {
+    ///The longest time a request may take. Default is `None`, which only
+    ///uses the header.
+    pub timeout: Option<Duration>,
+
+    ///A request header with the client's time limit, in milliseconds. It
+    ///can only make the deadline shorter. Default is `None`.
+    pub header: Option<String>,
+
+    ///The status code for responses that are sent after the deadline.
+    ///Default is `503 Service Unavailable`.
+    pub status: StatusCode,
+}
+
+impl Deadlines {
+    ///Give each request at most `timeout` to finish.
+    pub fn new(timeout: Duration) -> Deadlines {
+        Deadlines {
+            timeout: Some(timeout),
+            ..Deadlines::default()
+        }
+    }
+}
+
+impl Default for Deadlines {
+    fn default() -> Deadlines {
+        Deadlines {
+            timeout: None,
+            header: None,
+            status: StatusCode::ServiceUnavailable,
+        }
+    }
+}
+
+///Response bodies for error status codes.
+///
+///The pages are used when a response with a `4xx` or `5xx` status code is
+///sent with an empty body, such as when nothing was found by the router,
+///or when a handler only sets the status code. A page replaces the empty
+///body and sets the `Content-Type` header. Responses with a body, and
+///chunked or raw responses, are left as they are.
+///
+///```no_run
+///#[macro_use]
+///extern crate rustful;
+///use rustful::{Server, Context, Response, StatusCode};
+///use rustful::server::ErrorPages;
+///
+///fn my_handler(_context: Context, response: Response) {
+///    response.send("Hello!");
+///}
+///
+///# fn main() {
+///let html = content_type!(Text / Html; Charset = Utf8);
+///
+///let server_result = Server {
+///    host: 8080.into(),
+///    error_pages: ErrorPages::new()
+///        .page(StatusCode::NotFound, html.clone(), "<h1>There's nothing here</h1>")
+///        .fallback(html, "<h1>{status}</h1>"),
+///    ..Server::new(my_handler)
+///}.run();
+///# }
+///```
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    pages: Vec<(StatusCode, Mime, Vec<u8>)>,
+    fallback: Option<(Mime, Vec<u8>)>,
+}
+
+impl ErrorPages {
+    ///Create an empty set of error pages.
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    ///Use `body` for responses with `status`.
+    pub fn page<B: Into<Vec<u8>>>(mut self, status: StatusCode, content_type: Mime, body: B) -> ErrorPages {
+        self.pages.retain(|&(other, _, _)| other != status);
+        self.pages.push((status, content_type, body.into()));
+        self
+    }
+
+    ///Use `body` for error status codes without their own pages. Any
+    ///`{status}` in the body is replaced with the status code and its
+    ///reason, such as `404 Not Found`.
+    pub fn fallback<B: Into<Vec<u8>>>(mut self, content_type: Mime, body: B) -> ErrorPages {
+        self.fallback = Some((content_type, body.into()));
+        self
+    }
+
+    ///Find the content type and body for `status`, if it's an error status
+    ///code with a page.
+    pub fn find<'a>(&'a self, status: StatusCode) -> Option<(&'a Mime, Cow<'a, [u8]>)> {
+        if !status.is_client_error() && !status.is_server_error() {
+            return None;
+        }
+
+        if let Some(&(_, ref content_type, ref body)) = self.pages.iter().find(|&&(other, _, _)| other == status) {
+            return Some((content_type, body.as_slice().into()));
+        }
+
+        self.fallback.as_ref().map(|&(ref content_type, ref body)| {
+            let status = status.to_string();
+            let mut output = Vec::with_capacity(body.len());
+            let mut rest = &body[..];
+
+            while let Some(index) = rest.windows(8).position(|window| window == b"{status}") {
+                output.extend_from_slice(&rest[..index]);
+                output.extend_from_slice(status.as_bytes());
+                rest = &rest[index + 8..];
+            }
+
+            output.extend_from_slice(rest);
+            (content_type, output.into())
+        })
+    }
+}
+
+///Default media types that depends on the kind of response data.
+///
+///Responses that are sent as strings, like `&str` and `String`, get the
+///`text` type, while byte vectors, slices and files get the `binary` type.
+///It's only used when the handler hasn't set a `Content-Type` header.
+///
+///```no_run
+///#[macro_use]
+///extern crate rustful;
+///use rustful::{Server, Context, Response};
+///use rustful::server::ContentTypes;
+///
+///fn my_handler(context: Context, response: Response) {
+///    //Sent as `text/html; charset=UTF-8`
+///    response.send("<h1>Hello!</h1>");
+///}
+///
+///# fn main() {
+///let server_result = Server {
+///    host: 8080.into(),
+///    content_types: Some(ContentTypes {
+///        text: content_type!(Text / Html; Charset = Utf8),
+///        ..ContentTypes::default()
+///    }),
+///    ..Server::new(my_handler)
+///}.run();
+///# }
+///```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentTypes {
+    ///The media type of strings. Default is `text/plain; charset=UTF-8`.
+    pub text: Mime,
+
+    ///The media type of bytes. Default is `application/octet-stream`.
+    pub binary: Mime,
+}
+
+impl Default for ContentTypes {
+    fn default() -> ContentTypes {
+        ContentTypes {
+            text: Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Charset, Value::Utf8)]),
+            binary: Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use StatusCode;
+    use mime::{Mime, TopLevel, SubLevel};
+    use super::{ErrorPages, Hsts};
+
+    #[test]
+    fn find_error_pages() {
+        let html = Mime(TopLevel::Text, SubLevel::Html, vec![]);
+        let pages = ErrorPages::new()
+            .page(StatusCode::NotFound, html.clone(), "missing")
+            .fallback(html.clone(), "{status}!");
+
+        assert_eq!(pages.find(StatusCode::NotFound).map(|(_, body)| body.into_owned()), Some(b"missing".to_vec()));
+        assert_eq!(pages.find(StatusCode::Gone).map(|(_, body)| body.into_owned()), Some(b"410 Gone!".to_vec()));
+        assert_eq!(pages.find(StatusCode::Ok), None);
+        assert_eq!(ErrorPages::new().find(StatusCode::NotFound), None);
+    }
+
+    #[test]
+    fn hsts_header() {
+        let mut hsts = Hsts::new(Duration::from_secs(3600));
+        assert_eq!(hsts.header_value(), "max-age=3600");
+
+        hsts.include_subdomains = true;
+        hsts.preload = true;
+        assert_eq!(hsts.header_value(), "max-age=3600; includeSubDomains; preload");
+    }
+}
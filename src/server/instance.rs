@@ -1,4 +1,8 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use time;
@@ -10,29 +14,43 @@ use url::Url;
 
 use hyper;
 use hyper::server::Handler as HyperHandler;
-use hyper::header::{Date, ContentType};
-use hyper::mime::Mime;
+use hyper::server::{Request as HyperRequest, Response as HyperResponse};
+use hyper::header::{Date, ContentType, ContentEncoding, ContentLength, Encoding};
+use hyper::mime::{Mime, TopLevel, SubLevel};
 use hyper::uri::RequestUri;
+use hyper::buffer::BufReader;
+use hyper::http::h1::{self, Incoming};
+use hyper::net::{NetworkListener, HttpListener, HttpsListener};
 
-pub use hyper::server::Listening;
+use hyper::server::Listening as HyperListening;
 
 use anymap::AnyMap;
 
-use StatusCode;
+use {StatusCode, Method, HttpVersion, HttpError};
 
 use context::{self, Context, UriPath, MaybeUtf8Owned, Parameters};
+use context::body::{FormError, FORM_PAIR_LIMIT};
 use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter};
 use handler::{HandleRequest, Environment};
-use response::Response;
-use header::HttpDate;
-use server::{Global, KeepAlive};
+use response::{Response, HeaderPolicy};
+use header::{Headers, HttpDate};
+use server::{Host, Global, KeepAlive, ConnectionLimit, UriLimit, HeaderLimit, Hsts, StrictParsing, PathDecoding, Deadlines, ErrorPages, ContentTypes};
+use server::limit::{LimitedListener, Plain};
+use server::client::TrackClient;
+use server::proxy;
+use server::tasks::{self, Tasks, RunningTasks};
+use server::strict;
+use server::timeout::{HeadTimeout, HeadTimeoutHandler, KeepAliveTimeout, KeepAliveHandler};
 use net::SslServer;
+use trace::{Tracer, Spans};
 
 use HttpResult;
 use Server;
 
 use utils;
 
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 ///A runnable instance of a server.
 ///
 ///It's not meant to be used directly,
@@ -59,51 +77,157 @@ pub struct ServerInstance<R> {
 
     server: String,
     content_type: Mime,
+    content_types: Option<ContentTypes>,
 
     threads: usize,
     keep_alive: Option<KeepAlive>,
+    request_timeout: Option<Duration>,
+    head_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+    connection_limit: Option<ConnectionLimit>,
+    uri_limit: Option<UriLimit>,
+    header_limit: Option<HeaderLimit>,
+    hsts: Option<Hsts>,
+    strict_parsing: Option<StrictParsing>,
+    deadlines: Option<Deadlines>,
+    trusted_proxies: Vec<IpAddr>,
+    error_pages: ErrorPages,
     threads_in_use: AtomicUsize,
+    //The total number of threads, in every thread pool.
+    thread_capacity: AtomicUsize,
 
     context_filters: Vec<Box<ContextFilter>>,
     response_filters: Vec<Box<ResponseFilter>>,
+    tracers: Vec<Box<Tracer>>,
 
     global: Global,
+    worker_init: Option<Box<Fn() -> AnyMap + Send + Sync>>,
+    tasks: Tasks,
+    //Tells the worker state of different servers apart.
+    id: usize,
+
+    form_body_limit: Option<u64>,
+    gzip_body_limit: Option<u64>,
+    query_plus_as_space: bool,
+    path_decoding: PathDecoding,
+    matrix_parameters: bool,
+    header_policy: HeaderPolicy,
 }
 
 impl<R: HandleRequest + 'static> ServerInstance<R> {
     ///Create a new server instance, with the provided configuration. This is
     ///the same as `Server{...}.build()`.
     pub fn new(config: Server<R>) -> ServerInstance<R> {
+        let threads = config.threads.unwrap_or_else(|| (num_cpus::get() * 5) / 4);
+
         ServerInstance {
             handlers: config.handlers,
             host: config.host.into(),
             server: config.server,
             content_type: config.content_type,
-            threads: config.threads.unwrap_or_else(|| (num_cpus::get() * 5) / 4),
+            content_types: config.content_types,
+            threads: threads,
             keep_alive: config.keep_alive,
+            request_timeout: config.request_timeout,
+            head_timeout: config.head_timeout,
+            response_timeout: config.response_timeout,
+            connection_limit: config.connection_limit,
+            uri_limit: config.uri_limit,
+            header_limit: config.header_limit,
+            hsts: config.hsts,
+            strict_parsing: config.strict_parsing,
+            deadlines: config.deadlines,
+            trusted_proxies: config.trusted_proxies,
+            error_pages: config.error_pages,
             threads_in_use: AtomicUsize::new(0),
+            thread_capacity: AtomicUsize::new(threads),
             context_filters: config.context_filters,
             response_filters: config.response_filters,
+            tracers: config.tracers,
             global: config.global,
+            worker_init: config.worker_init,
+            tasks: config.tasks,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            form_body_limit: config.form_body_limit,
+            gzip_body_limit: config.gzip_body_limit,
+            query_plus_as_space: config.query_plus_as_space,
+            path_decoding: config.path_decoding,
+            matrix_parameters: config.matrix_parameters,
+            header_policy: config.header_policy,
         }
     }
 
     ///Start the server.
     pub fn run(self) -> HttpResult<Listening> {
-        let host = self.host;
-        let threads = self.threads;
-        let mut server = hyper::server::Server::http(host)?;
-        server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.handle_threads(self, threads)
+        let listener = TcpListener::bind(self.host)?;
+        self.run_on_listener(listener)
     }
 
     ///Start the server with SSL.
     pub fn run_https<S: SslServer + Clone + Send + 'static>(self, ssl: S) -> HttpResult<Listening> {
-        let host = self.host;
-        let threads = self.threads;
-        let mut server = hyper::server::Server::https(host, ssl)?;
-        server.keep_alive(self.keep_alive.as_ref().map(|k| k.timeout));
-        server.handle_threads(self, threads)
+        let listener = TcpListener::bind(self.host)?;
+        self.run_https_on_listener(listener, ssl)
+    }
+
+    ///Start the server on an already bound socket. The `host` setting is
+    ///ignored.
+    pub fn run_on_listener(self, listener: TcpListener) -> HttpResult<Listening> {
+        let options = self.listen_options();
+        let instance = Arc::new(self);
+        let listening = options.http(Shared(instance.clone(), false), listener)?;
+        let tasks = tasks::start(&instance.tasks, SharedGlobal(instance.clone()));
+        Ok(Listening::new(listening, tasks))
+    }
+
+    ///Start the server with SSL, on an already bound socket. The `host`
+    ///setting is ignored.
+    pub fn run_https_on_listener<S: SslServer + Clone + Send + 'static>(self, listener: TcpListener, ssl: S) -> HttpResult<Listening> {
+        let options = self.listen_options();
+        let instance = Arc::new(self);
+        let listening = options.https(Shared(instance.clone(), true), listener, ssl)?;
+        let tasks = tasks::start(&instance.tasks, SharedGlobal(instance.clone()));
+        Ok(Listening::new(listening, tasks))
+    }
+
+    ///Prepare to listen on more than one address. The `host` setting is
+    ///ignored, and the addresses are added to the returned `Listeners`
+    ///instead:
+    ///
+    ///```no_run
+    ///# use rustful::{Server, Context, Response, HttpResult};
+    ///# use rustful::server::Listeners;
+    ///fn handler(_context: Context, response: Response) {
+    ///    response.send("Hello, world!");
+    ///}
+    ///
+    ///# fn start() -> HttpResult<Listeners<fn(Context, Response)>> {
+    ///let mut listeners = Server::new(handler as fn(Context, Response)).build().listeners();
+    ///listeners
+    ///    .http("127.0.0.1:8080".parse().unwrap())?
+    ///    .http("[::1]:8080".parse().unwrap())?;
+    ///# Ok(listeners)
+    ///# }
+    ///```
+    pub fn listeners(self) -> Listeners<R> {
+        self.thread_capacity.store(0, Ordering::SeqCst);
+
+        Listeners {
+            options: self.listen_options(),
+            instance: Arc::new(self),
+            listening: vec![],
+            tasks: None,
+        }
+    }
+
+    fn listen_options(&self) -> ListenOptions {
+        ListenOptions {
+            threads: self.threads,
+            keep_alive: self.keep_alive.as_ref().map(|k| k.timeout),
+            request_timeout: self.request_timeout,
+            head_timeout: self.head_timeout,
+            response_timeout: self.response_timeout,
+            connection_limit: self.connection_limit.clone(),
+        }
     }
 
     fn modify_context(&self, filter_storage: &mut AnyMap, context: &mut Context) -> ContextAction {
@@ -125,17 +249,498 @@ impl<R: HandleRequest + 'static> ServerInstance<R> {
         result
     }
 
+    fn read_form_body(&self, context: &mut Context) -> Result<(), StatusCode> {
+        let limit = match self.form_body_limit {
+            Some(limit) => limit,
+            None => return Ok(())
+        };
+
+        let is_form = match context.body.content_type() {
+            Some(&Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, _)) => true,
+            _ => false
+        };
+
+        if !is_form {
+            return Ok(());
+        }
+
+        match context.body.read_form_limited(limit, FORM_PAIR_LIMIT) {
+            Ok(form) => {
+                context.post = form;
+                Ok(())
+            },
+            Err(FormError::ContentType(_)) | Err(FormError::Charset(_)) => Err(StatusCode::UnsupportedMediaType),
+            Err(FormError::TooLarge) | Err(FormError::TooManyPairs) => Err(StatusCode::PayloadTooLarge),
+            Err(FormError::Io(_)) => Err(StatusCode::BadRequest),
+        }
+    }
+}
+
+///A server that listens on more than one address.
+///
+///Every address shares the same handlers, filters and global data, but gets
+///its own thread pool, with `Server::threads` threads. Plain HTTP and HTTPS
+///addresses can be mixed. It's created with `ServerInstance::listeners` or
+///`Server::listeners`.
+///
+///```no_run
+///# use rustful::{Server, Context, Response, HttpResult};
+///# use rustful::server::Listeners;
+///# use rustful::net::{SslServer, HttpStream};
+///# #[derive(Clone)]
+///# struct MyTls;
+///# impl SslServer for MyTls {
+///#     type Stream = HttpStream;
+///#     fn wrap_server(&self, stream: HttpStream) -> HttpResult<HttpStream> { Ok(stream) }
+///# }
+///fn handler(_context: Context, response: Response) {
+///    response.send("Hello, world!");
+///}
+///
+///# fn start() -> HttpResult<Listeners<fn(Context, Response)>> {
+///let mut listeners = Server::new(handler as fn(Context, Response)).listeners()?;
+///listeners
+///    .http(8080.into())?
+///    .https(8443.into(), MyTls)?;
+///
+///for address in listeners.addresses() {
+///    println!("listening on {}", address);
+///}
+///# Ok(listeners)
+///# }
+///```
+///
+///Dropping it will block until the server has stopped, just like when
+///dropping a `Listening` handle. The background tasks are started with the
+///first listener, and stopped when the listeners are closed.
+pub struct Listeners<R> {
+    instance: Arc<ServerInstance<R>>,
+    options: ListenOptions,
+    listening: Vec<HyperListening>,
+    tasks: Option<RunningTasks>,
+}
+
+impl<R: HandleRequest + 'static> Listeners<R> {
+    ///Start listening for plain HTTP requests on `host`.
+    pub fn http(&mut self, host: Host) -> HttpResult<&mut Listeners<R>> {
+        let listener = TcpListener::bind(SocketAddr::from(host))?;
+        self.http_listener(listener)
+    }
+
+    ///Start listening for HTTPS requests on `host`.
+    pub fn https<S: SslServer + Clone + Send + 'static>(&mut self, host: Host, ssl: S) -> HttpResult<&mut Listeners<R>> {
+        let listener = TcpListener::bind(SocketAddr::from(host))?;
+        self.https_listener(listener, ssl)
+    }
+
+    ///Accept plain HTTP requests from an already bound socket.
+    pub fn http_listener(&mut self, listener: TcpListener) -> HttpResult<&mut Listeners<R>> {
+        let listening = self.options.http(Shared(self.instance.clone(), false), listener)?;
+        self.add(listening);
+        Ok(self)
+    }
+
+    ///Accept HTTPS requests from an already bound socket.
+    pub fn https_listener<S: SslServer + Clone + Send + 'static>(&mut self, listener: TcpListener, ssl: S) -> HttpResult<&mut Listeners<R>> {
+        let listening = self.options.https(Shared(self.instance.clone(), true), listener, ssl)?;
+        self.add(listening);
+        Ok(self)
+    }
+
+    ///Start listening for plain HTTP requests on `host`, and redirect them
+    ///to the same host and path over HTTPS, with `301 Moved Permanently`.
+    ///The HTTPS port is left out of the redirect URLs if it's `None`.
+    ///
+    ///The requests are not seen by the handlers or the filters, and the
+    ///listener's threads are not counted towards the `keep_alive` limits.
+    ///Requests without a valid `Host` header are answered with `400 Bad
+    ///Request`.
+    pub fn https_redirect(&mut self, host: Host, https_port: Option<u16>) -> HttpResult<&mut Listeners<R>> {
+        let listener = TcpListener::bind(SocketAddr::from(host))?;
+        let redirect = HttpsRedirect {
+            port: https_port,
+            server: self.instance.server.clone(),
+        };
+
+        let listening = self.options.http(redirect, listener)?;
+        self.listening.push(listening);
+        Ok(self)
+    }
+
+    fn add(&mut self, listening: HyperListening) {
+        self.instance.thread_capacity.fetch_add(self.options.threads, Ordering::SeqCst);
+        self.listening.push(listening);
+
+        if self.tasks.is_none() {
+            self.tasks = Some(tasks::start(&self.instance.tasks, SharedGlobal(self.instance.clone())));
+        }
+    }
+}
+
+impl<R> Listeners<R> {
+    ///The addresses the server is listening on.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.listening.iter().map(|listening| listening.socket).collect()
+    }
+
+    ///Stop listening, and stop the background tasks. This has the same
+    ///limitations as `Listening::close`, and it will wait for the tasks to
+    ///finish.
+    pub fn close(&mut self) -> HttpResult<()> {
+        for listening in &mut self.listening {
+            listening.close()?;
+        }
+
+        if let Some(mut tasks) = self.tasks.take() {
+            tasks.stop();
+        }
+
+        Ok(())
+    }
+}
+
+///A handle to a running server.
+///
+///Dropping it will block until the server has stopped, and then stop the
+///background tasks. Closing it will stop the background tasks right away.
+pub struct Listening {
+    listening: Option<HyperListening>,
+    tasks: RunningTasks,
+
+    ///The address the server is listening on.
+    pub socket: SocketAddr,
+}
+
+impl Listening {
+    fn new(listening: HyperListening, tasks: RunningTasks) -> Listening {
+        Listening {
+            socket: listening.socket,
+            listening: Some(listening),
+            tasks: tasks,
+        }
+    }
+
+    ///Stop listening, and stop the background tasks. This has the same
+    ///limitations as `hyper::server::Listening::close`, and it will wait for
+    ///the tasks to finish.
+    pub fn close(&mut self) -> HttpResult<()> {
+        if let Some(ref mut listening) = self.listening {
+            listening.close()?;
+        }
+
+        self.tasks.stop();
+        Ok(())
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        //Blocks until the server has stopped
+        self.listening.take();
+        self.tasks.stop();
+    }
+}
+
+//The settings that are needed when starting a listener.
+#[derive(Clone)]
+struct ListenOptions {
+    threads: usize,
+    keep_alive: Option<Duration>,
+    request_timeout: Option<Duration>,
+    head_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+    connection_limit: Option<ConnectionLimit>,
+}
+
+impl ListenOptions {
+    fn http<H: HyperHandler + 'static>(&self, handler: H, listener: TcpListener) -> HttpResult<HyperListening> {
+        match self.connection_limit.clone() {
+            Some(limit) => {
+                let listener = LimitedListener::new(listener, TrackClient(Plain), limit, true)?;
+                self.start(handler, listener)
+            },
+            None => self.start(handler, HttpsListener::with_listener(HttpListener::from(listener), TrackClient(Plain)))
+        }
+    }
+
+    fn https<H: HyperHandler + 'static, S: SslServer + Clone + Send + 'static>(&self, handler: H, listener: TcpListener, ssl: S) -> HttpResult<HyperListening> {
+        match self.connection_limit.clone() {
+            Some(limit) => {
+                let listener = LimitedListener::new(listener, TrackClient(ssl), limit, false)?;
+                self.start(handler, listener)
+            },
+            None => self.start(handler, HttpsListener::with_listener(HttpListener::from(listener), TrackClient(ssl)))
+        }
+    }
+
+    fn start<H: HyperHandler + 'static, L: NetworkListener + Send + 'static>(&self, handler: H, listener: L) -> HttpResult<HyperListening> {
+        match self.head_timeout {
+            Some(timeout) => self.start_server(HeadTimeoutHandler(handler), HeadTimeout::new(listener, timeout)),
+            None => self.start_server(handler, listener)
+        }
+    }
+
+    fn start_server<H: HyperHandler + 'static, L: NetworkListener + Send + 'static>(&self, handler: H, listener: L) -> HttpResult<HyperListening> {
+        let mut server = hyper::server::Server::new(KeepAliveTimeout(listener));
+        server.keep_alive(self.keep_alive);
+        server.set_read_timeout(self.request_timeout);
+        server.set_write_timeout(self.response_timeout);
+        server.handle_threads(KeepAliveHandler(handler), self.threads)
+    }
+}
+
+//A server instance that is shared between listeners, and if the listener
+//is encrypted.
+struct Shared<R>(Arc<ServerInstance<R>>, bool);
+
+impl<R: HandleRequest + 'static> HyperHandler for Shared<R> {
+    fn handle<'a, 'b>(&'a self, request: HyperRequest<'a, 'b>, writer: HyperResponse<'a>) {
+        self.0.serve(request, writer, self.1);
+    }
+
+    fn on_connection_start(&self) {
+        self.0.on_connection_start();
+    }
+
+    fn on_connection_end(&self) {
+        self.0.on_connection_end();
+    }
+}
+
+//Lets the background tasks borrow the global data from a shared instance.
+struct SharedGlobal<R>(Arc<ServerInstance<R>>);
+
+impl<R> Clone for SharedGlobal<R> {
+    fn clone(&self) -> SharedGlobal<R> {
+        SharedGlobal(self.0.clone())
+    }
+}
+
+impl<R> AsRef<Global> for SharedGlobal<R> {
+    fn as_ref(&self) -> &Global {
+        &self.0.global
+    }
+}
+
+//Redirects every request to the same host and path over HTTPS.
+struct HttpsRedirect {
+    port: Option<u16>,
+    server: String,
+}
+
+impl HyperHandler for HttpsRedirect {
+    fn handle<'a, 'b>(&'a self, request: HyperRequest<'a, 'b>, mut writer: HyperResponse<'a>) {
+        writer.headers_mut().set(Date(HttpDate(time::now_utc())));
+        writer.headers_mut().set(hyper::header::Server(self.server.clone()));
+        writer.headers_mut().set(hyper::header::Connection::close());
+
+        match https_location(&request.headers, &request.uri, self.port) {
+            Some(location) => {
+                *writer.status_mut() = StatusCode::MovedPermanently;
+                writer.headers_mut().set(hyper::header::Location(location));
+            },
+            None => *writer.status_mut() = StatusCode::BadRequest
+        }
+
+        if let Err(e) = writer.send(b"") {
+            error!("failed to send HTTPS redirect: {}", e);
+        }
+    }
+}
+
+//The HTTPS URL for a request, or `None` if the host is missing or invalid.
+fn https_location(headers: &Headers, uri: &RequestUri, port: Option<u16>) -> Option<String> {
+    let (hostname, path) = match *uri {
+        RequestUri::AbsolutePath(ref path) => {
+            let host = match headers.get::<::header::Host>() {
+                Some(host) => host.hostname.clone(),
+                None => return None
+            };
+            (host, path.clone())
+        },
+        RequestUri::AbsoluteUri(ref url) => {
+            let mut path = url.path().to_owned();
+            if let Some(query) = url.query() {
+                path.push('?');
+                path.push_str(query);
+            }
+            (url.host_str().unwrap_or("").to_owned(), path)
+        },
+        _ => return None
+    };
+
+    let valid_host = !hostname.is_empty() && hostname.bytes().all(|byte| {
+        byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'-' || byte == b'[' || byte == b']' || byte == b':'
+    });
+
+    if !valid_host {
+        return None;
+    }
+
+    match port {
+        Some(port) if port != 443 => Some(format!("https://{}:{}{}", hostname, port, path)),
+        _ => Some(format!("https://{}{}", hostname, path))
+    }
 }
 
 struct ParsedUri {
     host: Option<(String, Option<u16>)>,
     uri_path: UriPath,
     query: Parameters,
-    fragment: Option<MaybeUtf8Owned>
+    raw_query: Option<String>,
+    fragment: Option<MaybeUtf8Owned>,
+    matrix: Vec<Parameters>
+}
+
+///A request head, as the server sees it before it's routed.
+#[derive(Clone)]
+pub struct ParsedRequest {
+    ///The HTTP method.
+    pub method: Method,
+
+    ///The HTTP version.
+    pub http_version: HttpVersion,
+
+    ///The request headers. The `Host` header is set from the request
+    ///target if it's an absolute URL.
+    pub headers: Headers,
+
+    ///The percent decoded path.
+    pub uri_path: UriPath,
+
+    ///The decoded query parameters.
+    pub query: Parameters,
+
+    ///The query string, as it was sent.
+    pub raw_query: Option<String>,
+
+    ///The decoded fragment.
+    pub fragment: Option<MaybeUtf8Owned>,
+}
+
+///An error from `parse_request`.
+#[derive(Debug)]
+pub enum ParseError {
+    ///The HTTP parser rejected the request head.
+    Http(HttpError),
+
+    ///The request target is an authority, which is only used by `CONNECT`.
+    UnsupportedTarget,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Http(ref e) => write!(f, "invalid request: {}", e),
+            ParseError::UnsupportedTarget => write!(f, "unsupported request target")
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::Http(ref e) => e.description(),
+            ParseError::UnsupportedTarget => "unsupported request target"
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ParseError::Http(ref e) => Some(e),
+            ParseError::UnsupportedTarget => None
+        }
+    }
+}
+
+impl From<HttpError> for ParseError {
+    fn from(error: HttpError) -> ParseError {
+        ParseError::Http(error)
+    }
+}
+
+///Parse a request head, the same way as the server does before the request
+///is routed. The body, if any, is not read.
+///
+///The server only uses this logic together with a connection, so this is
+///meant to make it easy to reach for fuzzers and property tests. The query
+///is parsed with `+` as space, which is the default for `Server`.
+///
+///```
+///use rustful::Method;
+///use rustful::server::parse_request;
+///
+///let request = parse_request(b"GET /a%20b?c=d HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+///assert_eq!(request.method, Method::Get);
+///assert_eq!(request.uri_path.as_utf8_path(), Some("/a b"));
+///assert_eq!(request.query.get("c"), Some("d".into()));
+///```
+pub fn parse_request(bytes: &[u8]) -> Result<ParsedRequest, ParseError> {
+    let Incoming { version, subject: (method, uri), mut headers } = h1::parse_request(&mut BufReader::new(bytes))?;
+
+    match parse_target(uri, true, PathDecoding::Full, false) {
+        Some(ParsedUri { host, uri_path, query, raw_query, fragment, .. }) => {
+            if let Some((name, port)) = host {
+                headers.set(::header::Host {
+                    hostname: name,
+                    port: port
+                });
+            }
+
+            Ok(ParsedRequest {
+                method: method,
+                http_version: version,
+                headers: headers,
+                uri_path: uri_path,
+                query: query,
+                raw_query: raw_query,
+                fragment: fragment,
+            })
+        },
+        None => Err(ParseError::UnsupportedTarget)
+    }
+}
+
+fn parse_target(uri: RequestUri, plus_as_space: bool, decoding: PathDecoding, matrix: bool) -> Option<ParsedUri> {
+    match uri {
+        RequestUri::AbsoluteUri(url) => Some(parse_url(&url, plus_as_space, decoding, matrix)),
+        RequestUri::AbsolutePath(path) => Some(parse_path(&path, plus_as_space, decoding, matrix)),
+        RequestUri::Star => {
+            Some(ParsedUri {
+                host: None,
+                uri_path: UriPath::Asterisk,
+                query: Parameters::new(),
+                raw_query: None,
+                fragment: None,
+                matrix: vec![]
+            })
+        },
+        _ => None
+    }
 }
 
 impl<R: HandleRequest + 'static> HyperHandler for ServerInstance<R> {
-    fn handle<'a, 'b>(&'a self, request: hyper::server::request::Request<'a, 'b>, writer: hyper::server::response::Response<'a>) {
+    fn handle<'a, 'b>(&'a self, request: HyperRequest<'a, 'b>, writer: HyperResponse<'a>) {
+        self.serve(request, writer, false);
+    }
+
+    fn on_connection_start(&self) {
+        self.threads_in_use.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(ref worker_init) = self.worker_init {
+            utils::init_worker_state(self.id, || worker_init());
+        }
+    }
+
+    fn on_connection_end(&self) {
+        self.threads_in_use.fetch_sub(1, Ordering::SeqCst);
+        utils::set_client_socket(None);
+    }
+}
+
+impl<R: HandleRequest + 'static> ServerInstance<R> {
+    fn serve<'a, 'b>(&'a self, request: HyperRequest<'a, 'b>, writer: HyperResponse<'a>, https: bool) {
+        let start = Instant::now();
         let (
             request_addr,
             request_method,
@@ -146,32 +751,57 @@ impl<R: HandleRequest + 'static> HyperHandler for ServerInstance<R> {
         ) = request.deconstruct();
 
         let force_close = if let Some(ref keep_alive) = self.keep_alive {
-            self.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.threads
+            self.threads_in_use.load(Ordering::SeqCst) + keep_alive.free_threads > self.thread_capacity.load(Ordering::SeqCst)
         } else {
             false
         };
 
-        let mut response = Response::new(writer, &self.response_filters, &self.global, force_close);
+        let mut response = Response::new(
+            writer,
+            &self.response_filters,
+            &self.global,
+            &self.error_pages,
+            self.content_types.as_ref(),
+            force_close,
+            self.header_policy
+        );
         response.headers_mut().set(Date(HttpDate(time::now_utc())));
-        response.headers_mut().set(ContentType(self.content_type.clone()));
+        if self.content_types.is_none() {
+            response.headers_mut().set(ContentType(self.content_type.clone()));
+        }
         response.headers_mut().set(hyper::header::Server(self.server.clone()));
+        if let (Some(hsts), true) = (self.hsts.as_ref(), https) {
+            response.headers_mut().set_raw("Strict-Transport-Security", vec![hsts.header_value().into_bytes()]);
+        }
 
-        let path_components = match request_uri {
-            RequestUri::AbsoluteUri(url) => Some(parse_url(&url)),
-            RequestUri::AbsolutePath(path) => Some(parse_path(&path)),
-            RequestUri::Star => {
-                Some(ParsedUri {
-                    host: None,
-                    uri_path: UriPath::Asterisk,
-                    query: Parameters::new(),
-                    fragment: None
-                })
-            },
-            _ => None
-        };
+        if let Some(ref limit) = self.uri_limit {
+            if !within_uri_limit(limit, &request_uri) {
+                response.set_status(StatusCode::UriTooLong);
+                return;
+            }
+        }
+
+        if let Some(ref limit) = self.header_limit {
+            if !within_header_limit(limit, &request_headers) {
+                response.set_status(StatusCode::RequestHeaderFieldsTooLarge);
+                return;
+            }
+        }
+
+        if let Some(ref strict_parsing) = self.strict_parsing {
+            if let Err(reason) = strict::check(&request_headers, &request_uri) {
+                if strict_parsing.log_rejections {
+                    warn!("rejected {} {} from {}: {}", request_method, request_uri, request_addr, reason);
+                }
+
+                response.set_status(StatusCode::BadRequest);
+                response.set_keep_alive_timeout(None);
+                return;
+            }
+        }
 
-        match path_components {
-            Some(ParsedUri{ host, uri_path, query, fragment }) => {
+        match parse_target(request_uri, self.query_plus_as_space, self.path_decoding, self.matrix_parameters) {
+            Some(ParsedUri{ host, uri_path, query, raw_query, fragment, matrix }) => {
                 if let Some((name, port)) = host {
                     request_headers.set(::header::Host {
                         hostname: name,
@@ -179,23 +809,62 @@ impl<R: HandleRequest + 'static> HyperHandler for ServerInstance<R> {
                     });
                 }
 
-                let body = context::body::BodyReader::from_reader(request_reader, &request_headers);
+                let deadline = self.deadlines.as_ref().and_then(|deadlines| find_deadline(deadlines, start, &request_headers));
+                if let (Some(deadline), Some(deadlines)) = (deadline, self.deadlines.as_ref()) {
+                    response.set_deadline(deadline, deadlines.status);
+                }
+
+                let mut body = context::body::BodyReader::from_reader(request_reader, &request_headers);
+
+                if let Some(limit) = self.gzip_body_limit {
+                    if is_gzipped(&request_headers) {
+                        //The length of the decompressed body is unknown
+                        request_headers.remove::<ContentEncoding>();
+                        request_headers.remove::<ContentLength>();
+                        body.decode_gzip(limit);
+                    }
+                }
+                let forwarded = proxy::forwarded(request_addr.ip(), &request_headers, &self.trusted_proxies);
+
+                let mut extensions = AnyMap::new();
+                if self.path_decoding != PathDecoding::Full {
+                    //The variables are decoded by the router
+                    extensions.insert(self.path_decoding);
+                }
 
                 let mut context = Context {
                     headers: request_headers,
                     http_version: request_version,
                     method: request_method,
                     address: request_addr,
+                    client_address: forwarded.address,
+                    is_https: forwarded.https.unwrap_or(https),
                     uri_path: uri_path,
                     hyperlinks: vec![],
                     variables: Parameters::new(),
+                    matrix: matrix,
                     query: query.into(),
+                    raw_query: raw_query,
                     fragment: fragment,
+                    post: Parameters::new(),
                     global: &self.global,
-                    body: body
+                    body: body,
+                    extensions: extensions,
+                    deadline: deadline,
                 };
 
-                let mut filter_storage = AnyMap::new();
+                if !self.tracers.is_empty() {
+                    let spans = self.tracers.iter().map(|tracer| tracer.start(&context)).collect();
+                    let spans = Spans::new(spans, response.sent_status(), response.sent_bytes());
+                    response.filter_storage_mut().insert(spans);
+                }
+
+                if let Err(status) = self.read_form_body(&mut context) {
+                    response.set_status(status);
+                    return;
+                }
+
+                let mut filter_storage = ::std::mem::replace(response.filter_storage_mut(), AnyMap::new());
 
                 match self.modify_context(&mut filter_storage, &mut context) {
                     ContextAction::Next => {
@@ -227,51 +896,171 @@ impl<R: HandleRequest + 'static> HyperHandler for ServerInstance<R> {
             }
         }
     }
+}
 
-    fn on_connection_start(&self) {
-        self.threads_in_use.fetch_add(1, Ordering::SeqCst);
+//The shortest of the timeout and the client's time limit, if any.
+fn find_deadline(deadlines: &Deadlines, start: Instant, headers: &Headers) -> Option<Instant> {
+    let requested = deadlines.header.as_ref()
+        .and_then(|name| headers.get_raw(name))
+        .and_then(|values| values.first())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_millis);
+
+    let timeout = match (deadlines.timeout, requested) {
+        (Some(timeout), Some(requested)) => Some(::std::cmp::min(timeout, requested)),
+        (timeout, requested) => timeout.or(requested)
+    };
+
+    timeout.map(|timeout| start + timeout)
+}
+
+fn within_uri_limit(limit: &UriLimit, uri: &RequestUri) -> bool {
+    let (length, path) = match *uri {
+        RequestUri::AbsolutePath(ref path) => (path.len(), &**path),
+        RequestUri::AbsoluteUri(ref url) => (url.as_str().len(), url.path()),
+        RequestUri::Authority(ref authority) => (authority.len(), ""),
+        RequestUri::Star => (1, ""),
+    };
+
+    if length > limit.max_length {
+        return false;
     }
 
-    fn on_connection_end(&self) {
-        self.threads_in_use.fetch_sub(1, Ordering::SeqCst);
+    let path = path.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    path.split('/').filter(|segment| !segment.is_empty()).take(limit.max_segments + 1).count() <= limit.max_segments
+}
+
+fn within_header_limit(limit: &HeaderLimit, headers: &Headers) -> bool {
+    let mut count = 0;
+    let mut total = 0;
+
+    for header in headers.iter() {
+        let name = header.name();
+        for value in headers.get_raw(name).unwrap_or(&[]) {
+            let length = name.len() + value.len();
+            count += 1;
+            total += length;
+
+            if count > limit.max_count || length > limit.max_length || total > limit.max_total {
+                return false;
+            }
+        }
     }
+
+    true
 }
 
-fn parse_path(path: &str) -> ParsedUri {
+fn parse_path(path: &str, plus_as_space: bool, decoding: PathDecoding, matrix: bool) -> ParsedUri {
     match path.find('?') {
         Some(index) => {
             let (query, fragment) = parse_fragment(&path[index+1..]);
 
-            let mut path: Vec<_> = percent_decode(path[..index].as_bytes()).collect();
-            if path.is_empty() {
-                path.push(b'/');
-            }
+            let (path, matrix) = decode_path(&path[..index], decoding, matrix);
 
             ParsedUri {
                 host: None,
                 uri_path: UriPath::Path(path.into()),
-                query: utils::parse_parameters(query.as_bytes()),
+                query: utils::parse_query(query.as_bytes(), plus_as_space),
+                raw_query: Some(query.into()),
                 fragment: fragment.map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into()),
+                matrix: matrix,
             }
         },
         None => {
             let (path, fragment) = parse_fragment(&path);
 
-            let mut path: Vec<_> = percent_decode(path.as_bytes()).collect();
-            if path.is_empty() {
-                path.push(b'/');
-            }
+            let (path, matrix) = decode_path(path, decoding, matrix);
 
             ParsedUri {
                 host: None,
                 uri_path: UriPath::Path(path.into()),
                 query: Parameters::new(),
-                fragment: fragment.map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into())
+                raw_query: None,
+                fragment: fragment.map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into()),
+                matrix: matrix,
+            }
+        }
+    }
+}
+
+fn decode_path(path: &str, decoding: PathDecoding, matrix: bool) -> (Vec<u8>, Vec<Parameters>) {
+    let (mut decoded, matrix) = if matrix {
+        let (path, matrix) = split_matrix(path);
+        (percent_decode_path(path.as_bytes(), decoding), matrix)
+    } else {
+        (percent_decode_path(path.as_bytes(), decoding), vec![])
+    };
+
+    if decoded.is_empty() {
+        decoded.push(b'/');
+    }
+
+    (decoded, matrix)
+}
+
+//Matrix parameters are split out before decoding, so %3B stays a part of the segment
+fn split_matrix(path: &str) -> (String, Vec<Parameters>) {
+    let mut stripped = String::with_capacity(path.len());
+    let mut matrix = vec![];
+
+    let segments = if path.starts_with('/') {
+        stripped.push('/');
+        &path[1..]
+    } else {
+        path
+    };
+
+    for (index, segment) in segments.split('/').enumerate() {
+        if index > 0 {
+            stripped.push('/');
+        }
+
+        let mut parts = segment.splitn(2, ';');
+        stripped.push_str(parts.next().unwrap_or(""));
+        matrix.push(parts.next().map_or_else(Parameters::new, |parameters| utils::parse_matrix(parameters.as_bytes())));
+    }
+
+    (stripped, matrix)
+}
+
+fn percent_decode_path(path: &[u8], decoding: PathDecoding) -> Vec<u8> {
+    match decoding {
+        PathDecoding::Full => percent_decode(path).collect(),
+        PathDecoding::KeepSlashes => {
+            let mut decoded = Vec::with_capacity(path.len());
+            let mut start = 0;
+            let mut index = 0;
+
+            while index + 3 <= path.len() {
+                let sequence = &path[index..index + 3];
+                if sequence.eq_ignore_ascii_case(b"%2f") || sequence == b"%25" {
+                    decoded.extend(percent_decode(&path[start..index]));
+                    decoded.extend_from_slice(sequence);
+                    index += 3;
+                    start = index;
+                } else {
+                    index += 1;
+                }
             }
+
+            decoded.extend(percent_decode(&path[start..]));
+            decoded
         }
     }
 }
 
+fn is_gzipped(headers: &Headers) -> bool {
+    match headers.get::<ContentEncoding>() {
+        Some(&ContentEncoding(ref encodings)) => encodings.len() == 1 && match encodings[0] {
+            Encoding::Gzip => true,
+            Encoding::EncodingExt(ref name) => name.eq_ignore_ascii_case("x-gzip"),
+            _ => false
+        },
+        None => false
+    }
+}
+
 fn parse_fragment(path: &str) -> (&str, Option<&str>) {
     match path.find('#') {
         Some(index) => (&path[..index], Some(&path[index+1..])),
@@ -279,13 +1068,10 @@ fn parse_fragment(path: &str) -> (&str, Option<&str>) {
     }
 }
 
-fn parse_url(url: &Url) -> ParsedUri {
-    let mut path = vec![];
-    path.extend(percent_decode(url.path().as_bytes()));
+fn parse_url(url: &Url, plus_as_space: bool, decoding: PathDecoding, matrix: bool) -> ParsedUri {
+    let (path, matrix) = decode_path(url.path(), decoding, matrix);
 
-    let query = url.query_pairs()
-        .map(|(k, v)| (k.into_owned(), v.into_owned()))
-        .collect();
+    let query = url.query().map(|query| utils::parse_query(query.as_bytes(), plus_as_space)).unwrap_or_default();
 
     let host = url.host_str().map(|host| (host.into(), url.port()));
 
@@ -293,16 +1079,130 @@ fn parse_url(url: &Url) -> ParsedUri {
         host: host,
         uri_path: UriPath::Path(path.into()),
         query: query,
-        fragment: url.fragment().as_ref().map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into())
+        raw_query: url.query().map(Into::into),
+        fragment: url.fragment().as_ref().map(|f| percent_decode(f.as_bytes()).collect::<Vec<_>>().into()),
+        matrix: matrix,
     }
 }
 
 
+#[test]
+fn uri_limits() {
+    let limit = UriLimit {
+        max_length: 20,
+        max_segments: 3,
+    };
+
+    assert!(within_uri_limit(&limit, &RequestUri::AbsolutePath("/a/b//c/?d/e/f".into())));
+    assert!(!within_uri_limit(&limit, &RequestUri::AbsolutePath("/a/b/c/d".into())));
+    assert!(!within_uri_limit(&limit, &RequestUri::AbsolutePath("/a?long=query-string&more".into())));
+    assert!(within_uri_limit(&limit, &RequestUri::AbsoluteUri("http://a.b/c/d".parse().unwrap())));
+    assert!(within_uri_limit(&limit, &RequestUri::Star));
+}
+
+#[test]
+fn keep_encoded_slashes() {
+    let ParsedUri { uri_path, .. } = parse_path("/a%2Fb/c%2fd/%2541/%C3%A4%20e", true, PathDecoding::KeepSlashes, false);
+    assert_eq!(uri_path.as_utf8_path(), Some("/a%2Fb/c%2fd/%2541/ä e"));
+
+    let ParsedUri { uri_path, .. } = parse_path("/a%2Fb/%2541", true, PathDecoding::Full, false);
+    assert_eq!(uri_path.as_utf8_path(), Some("/a/b/%41"));
+}
+
+#[test]
+fn split_matrix_parameters() {
+    let ParsedUri { uri_path, matrix, .. } = parse_path("/users;role=admin;active/7%3Bx;a=b%20c?d=e", true, PathDecoding::Full, true);
+    assert_eq!(uri_path.as_utf8_path(), Some("/users/7;x"));
+    assert_eq!(matrix.len(), 2);
+    assert_eq!(matrix[0].get("role"), Some("admin".into()));
+    assert_eq!(matrix[0].get("active"), Some("".into()));
+    assert_eq!(matrix[1].get("a"), Some("b c".into()));
+
+    let ParsedUri { uri_path, matrix, .. } = parse_path("/users;role=admin/7", true, PathDecoding::Full, false);
+    assert_eq!(uri_path.as_utf8_path(), Some("/users;role=admin/7"));
+    assert!(matrix.is_empty());
+}
+
+#[test]
+fn parse_request_heads() {
+    let request = parse_request(b"POST http://example.com:8080/a/b?c=d+e#f HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").unwrap();
+    assert_eq!(request.method, Method::Post);
+    assert_eq!(request.uri_path.as_utf8_path(), Some("/a/b"));
+    assert_eq!(request.query.get("c"), Some("d e".into()));
+    assert_eq!(request.raw_query, Some("c=d+e".into()));
+    assert_eq!(request.fragment, Some("f".to_owned().into()));
+    assert_eq!(request.headers.get::<::header::Host>().map(|host| host.port), Some(Some(8080)));
+
+    assert!(parse_request(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap().uri_path.is_asterisk());
+
+    match parse_request(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n") {
+        Err(ParseError::UnsupportedTarget) => {},
+        _ => panic!("expected an unsupported target")
+    }
+
+    match parse_request(b"GET / HTTP/1.1\r\nHost: exa") {
+        Err(ParseError::Http(_)) => {},
+        _ => panic!("expected an HTTP error")
+    }
+}
+
+#[test]
+fn https_locations() {
+    let mut headers = Headers::new();
+    headers.set(::header::Host {
+        hostname: "example.com".into(),
+        port: Some(80),
+    });
+
+    let path = RequestUri::AbsolutePath("/a/b?c=d".into());
+    assert_eq!(https_location(&headers, &path, None), Some("https://example.com/a/b?c=d".into()));
+    assert_eq!(https_location(&headers, &path, Some(443)), Some("https://example.com/a/b?c=d".into()));
+    assert_eq!(https_location(&headers, &path, Some(8443)), Some("https://example.com:8443/a/b?c=d".into()));
+    assert_eq!(https_location(&Headers::new(), &path, None), None);
+
+    let url = RequestUri::AbsoluteUri("http://example.org:8080/a?b".parse().unwrap());
+    assert_eq!(https_location(&Headers::new(), &url, None), Some("https://example.org/a?b".into()));
+
+    headers.set(::header::Host {
+        hostname: "example.com/evil".into(),
+        port: None,
+    });
+    assert_eq!(https_location(&headers, &path, None), None);
+}
+
+#[test]
+fn header_limits() {
+    let limit = HeaderLimit {
+        max_count: 3,
+        max_length: 10,
+        max_total: 20,
+    };
+
+    let mut headers = Headers::new();
+    headers.set_raw("Host", vec![b"a.b".to_vec()]);
+    headers.set_raw("X-A", vec![b"1".to_vec(), b"2".to_vec()]);
+    assert!(within_header_limit(&limit, &headers));
+
+    headers.append_raw("X-A", b"3".to_vec());
+    assert!(!within_header_limit(&limit, &headers));
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-Long", vec![b"12345".to_vec()]);
+    assert!(!within_header_limit(&limit, &headers));
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-A", vec![b"123456".to_vec()]);
+    headers.set_raw("X-B", vec![b"123456".to_vec()]);
+    assert!(within_header_limit(&limit, &headers));
+    headers.set_raw("X-C", vec![b"1".to_vec()]);
+    assert!(!within_header_limit(&limit, &headers));
+}
+
 #[test]
 fn parse_path_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that#lol", true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -313,7 +1213,7 @@ fn parse_path_parts() {
 fn parse_strange_path() {
     let with = "this".to_owned().into();
     let and = "what?".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=what?#", true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -324,20 +1224,20 @@ fn parse_strange_path() {
 fn parse_missing_path_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something?with=this&and=that", true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, None);
 
 
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("/path/to/something#lol", true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
-    let ParsedUri { uri_path, query, fragment, .. } = parse_path("?with=this&and=that#lol");
+    let ParsedUri { uri_path, query, fragment, .. } = parse_path("?with=this&and=that#lol", true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -350,7 +1250,7 @@ fn parse_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url, true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -362,7 +1262,7 @@ fn parse_strange_url() {
     let with = "this".to_owned().into();
     let and = "what?".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=what?#").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url, true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -374,7 +1274,7 @@ fn parse_missing_url_parts() {
     let with = "this".to_owned().into();
     let and = "that".to_owned().into();
     let url = Url::parse("http://example.com/path/to/something?with=this&and=that").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url, true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
@@ -382,16 +1282,122 @@ fn parse_missing_url_parts() {
 
 
     let url = Url::parse("http://example.com/path/to/something#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url, true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/path/to/something".into()));
     assert_eq!(query.len(), 0);
     assert_eq!(fragment, Some("lol".to_owned().into()));
 
 
     let url = Url::parse("http://example.com?with=this&and=that#lol").unwrap();
-    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url);
+    let ParsedUri { uri_path, query, fragment, .. } = parse_url(&url, true, PathDecoding::Full, false);
     assert_eq!(uri_path.as_path(), Some("/".into()));
     assert_eq!(query.get_raw("with"), Some(&with));
     assert_eq!(query.get_raw("and"), Some(&and));
     assert_eq!(fragment, Some("lol".to_owned().into()));
 }
+
+#[test]
+fn per_worker_state() {
+    use std::cell::Cell;
+    use testing::TestClient;
+
+    struct Count(Cell<u32>);
+
+    fn count(context: Context, response: Response) {
+        let count = context.worker_state(|state| {
+            let count = state.get::<Count>().unwrap();
+            count.0.set(count.0.get() + 1);
+            count.0.get()
+        });
+        response.send(format!("{:?}", count));
+    }
+
+    let client = TestClient::new(Server {
+        worker_init: Some(Box::new(|| {
+            let mut state = AnyMap::new();
+            state.insert(Count(Cell::new(0)));
+            state
+        })),
+        ..Server::new(count as fn(Context, Response))
+    });
+
+    assert_eq!(client.get("/").unwrap().body_utf8(), Some("Some(1)"));
+    assert_eq!(client.get("/").unwrap().body_utf8(), Some("Some(2)"));
+}
+
+#[test]
+fn stop_tasks_on_close() {
+    use std::sync::mpsc::channel;
+    use server::Shutdown;
+
+    fn handler(_context: Context, response: Response) {
+        response.send("");
+    }
+
+    let (sender, receiver) = channel();
+    let mut server = Server::new(handler as fn(Context, Response));
+    server.spawn_background(move |_global: &Global, shutdown: &Shutdown| {
+        while !shutdown.wait(Duration::from_secs(60)) {}
+        sender.send(()).unwrap();
+    });
+
+    let mut listening = server.run_on_listener(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+    assert!(receiver.try_recv().is_err());
+
+    listening.close().unwrap();
+    assert_eq!(receiver.try_recv(), Ok(()));
+}
+
+#[test]
+fn gzip_bodies() {
+    use std::io::{Read, Write};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use header::{ContentType, Encoding};
+    use testing::TestClient;
+
+    fn echo(mut context: Context, mut response: Response) {
+        if context.headers.has::<ContentEncoding>() || context.headers.has::<ContentLength>() {
+            response.set_status(StatusCode::InternalServerError);
+            return;
+        }
+
+        let mut body = vec![];
+        match context.body.read_to_end(&mut body) {
+            Ok(_) => response.send(body),
+            Err(_) => response.set_status(StatusCode::BadRequest)
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    let client = TestClient::new(Server {
+        gzip_body_limit: Some(10),
+        form_body_limit: Some(1000),
+        ..Server::new(echo as fn(Context, Response))
+    });
+
+    let mut headers = Headers::new();
+    headers.set(ContentEncoding(vec![Encoding::Gzip]));
+
+    let response = client.post("/", headers.clone(), &gzip(b"hello")).unwrap();
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.body_utf8(), Some("hello"));
+
+    let response = client.post("/", headers.clone(), &gzip(b"hello world")).unwrap();
+    assert_eq!(response.status, StatusCode::BadRequest);
+
+    let mut corrupt = gzip(b"hello");
+    let length = corrupt.len();
+    corrupt[length - 8] ^= 1;
+    let response = client.post("/", headers.clone(), &corrupt).unwrap();
+    assert_eq!(response.status, StatusCode::BadRequest);
+
+    headers.set(ContentType("application/x-www-form-urlencoded".parse().unwrap()));
+    let response = client.post("/", headers, &gzip(b"message=hello+world")).unwrap();
+    assert_eq!(response.status, StatusCode::PayloadTooLarge);
+}
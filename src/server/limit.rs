@@ -0,0 +1,236 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, Shutdown};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use hyper::net::{NetworkListener, NetworkStream, SslServer, HttpStream};
+
+use server::ConnectionLimit;
+
+use HttpResult;
+
+///Leaves the connections unencrypted.
+#[derive(Clone)]
+pub struct Plain;
+
+impl SslServer for Plain {
+    type Stream = HttpStream;
+
+    fn wrap_server(&self, stream: HttpStream) -> HttpResult<HttpStream> {
+        Ok(stream)
+    }
+}
+
+///A listener that accepts connections in a separate thread and rejects
+///them when the limits are reached.
+#[derive(Clone)]
+pub struct LimitedListener<S> {
+    address: SocketAddr,
+    incoming: Arc<Mutex<Receiver<TcpStream>>>,
+    pending: Arc<AtomicUsize>,
+    open: Arc<AtomicUsize>,
+    ssl: S,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<S: SslServer + Clone + Send + 'static> LimitedListener<S> {
+    ///Start accepting connections from `listener`. The rejected
+    ///connections are sent a `503` response if `respond` is `true`.
+    pub fn new(listener: TcpListener, ssl: S, limit: ConnectionLimit, respond: bool) -> io::Result<LimitedListener<S>> {
+        let address = listener.local_addr()?;
+        let pending = Arc::new(AtomicUsize::new(0));
+        let open = Arc::new(AtomicUsize::new(0));
+        let (send, receive) = channel();
+
+        let acceptor_pending = pending.clone();
+        let acceptor_open = open.clone();
+        let rejection = if respond {
+            Some(rejection_response(&limit))
+        } else {
+            None
+        };
+
+        thread::Builder::new().name("rustful connection acceptor".into()).spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        info!("connection failed: {}", e);
+                        continue;
+                    }
+                };
+
+                let saturated = acceptor_open.load(Ordering::SeqCst) >= limit.max_connections ||
+                    acceptor_pending.load(Ordering::SeqCst) >= limit.max_pending;
+
+                if saturated {
+                    reject(stream, rejection.as_ref().map(|r| &r[..]));
+                    continue;
+                }
+
+                acceptor_open.fetch_add(1, Ordering::SeqCst);
+                acceptor_pending.fetch_add(1, Ordering::SeqCst);
+                if send.send(stream).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+        Ok(LimitedListener {
+            address: address,
+            incoming: Arc::new(Mutex::new(receive)),
+            pending: pending,
+            open: open,
+            ssl: ssl,
+            read_timeout: None,
+            write_timeout: None,
+        })
+    }
+}
+
+impl<S: SslServer + Clone + Send + 'static> NetworkListener for LimitedListener<S> {
+    type Stream = Connection<S::Stream>;
+
+    fn accept(&mut self) -> HttpResult<Connection<S::Stream>> {
+        let stream = {
+            let incoming = self.incoming.lock().unwrap_or_else(|e| e.into_inner());
+            incoming.recv().map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "the connection acceptor has stopped"))?
+        };
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+
+        let guard = Arc::new(OpenConnection(self.open.clone()));
+
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        let stream = self.ssl.wrap_server(HttpStream(stream))?;
+
+        Ok(Connection {
+            stream: stream,
+            _guard: guard,
+        })
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.address)
+    }
+
+    fn set_read_timeout(&mut self, duration: Option<Duration>) {
+        self.read_timeout = duration;
+    }
+
+    fn set_write_timeout(&mut self, duration: Option<Duration>) {
+        self.write_timeout = duration;
+    }
+}
+
+fn rejection_response(limit: &ConnectionLimit) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: {}\r\n",
+        limit.message.len()
+    );
+
+    if let Some(retry_after) = limit.retry_after {
+        response.push_str(&format!("Retry-After: {}\r\n", retry_after.as_secs()));
+    }
+
+    response.push_str("\r\n");
+    response.push_str(&limit.message);
+    response.into_bytes()
+}
+
+fn reject(mut stream: TcpStream, response: Option<&[u8]>) {
+    debug!("rejecting a connection, because the server is saturated");
+
+    if let Some(response) = response {
+        //Don't let a slow client block the acceptor.
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let _ = stream.write_all(response);
+    }
+
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+//Counts the connection as open until every copy of it is dropped.
+struct OpenConnection(Arc<AtomicUsize>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+///A connection that was accepted by a `LimitedListener`.
+#[derive(Clone)]
+pub struct Connection<S> {
+    stream: S,
+    _guard: Arc<OpenConnection>,
+}
+
+impl<S: NetworkStream> Read for Connection<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: NetworkStream> Write for Connection<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for Connection<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(duration)
+    }
+
+    fn set_write_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(duration)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.stream.close(how)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::Ordering;
+
+    use hyper::net::NetworkListener;
+
+    use server::ConnectionLimit;
+    use super::{LimitedListener, Plain};
+
+    #[test]
+    fn reject_when_saturated() {
+        let mut listener = LimitedListener::new(TcpListener::bind("127.0.0.1:0").unwrap(), Plain, ConnectionLimit::new(1, 1), true).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let _first = TcpStream::connect(address).unwrap();
+        let mut second = TcpStream::connect(address).unwrap();
+
+        let mut response = String::new();
+        second.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "unexpected response: {}", response);
+        assert!(response.ends_with("The server is too busy. Please try again later."));
+
+        let first = listener.accept().unwrap();
+        assert_eq!(listener.open.load(Ordering::SeqCst), 1);
+        drop(first);
+        assert_eq!(listener.open.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,23 +1,35 @@
 //!Server configuration and instance.
 
 use std::borrow::ToOwned;
+use std::io;
+use std::net::{IpAddr, TcpListener};
+use std::time::Duration;
 
 use hyper;
 use hyper::mime::Mime;
 
-pub use hyper::server::Listening;
+use anymap::AnyMap;
 
 use filter::{ContextFilter, ResponseFilter};
 use handler::HandleRequest;
 use net::SslServer;
+use response::HeaderPolicy;
+use trace::Tracer;
 
-use HttpResult;
+use {HttpResult, HttpError};
 
-pub use self::instance::ServerInstance;
-pub use self::config::{Host, Global, KeepAlive};
+pub use self::instance::{ServerInstance, Listening, Listeners, ParsedRequest, ParseError, parse_request};
+pub use self::tasks::{Tasks, Shutdown};
+pub use self::config::{Host, Global, GlobalData, GlobalBuilder, Requirements, MissingGlobals, KeepAlive, ConnectionLimit, UriLimit, HeaderLimit, Hsts, StrictParsing, PathDecoding, Deadlines, ErrorPages, ContentTypes};
 
 mod instance;
 mod config;
+mod limit;
+mod client;
+mod proxy;
+mod strict;
+mod tasks;
+mod timeout;
 
 ///Used to set up and run a server.
 ///
@@ -56,23 +68,174 @@ pub struct Server<R> {
 
     ///The server's `keep-alive` policy. Setting this to `Some(...)` will
     ///allow `keep-alive` connections with a timeout, and keeping it as `None`
-    ///will force connections to close after each request. Default is `None`.
+    ///will force connections to close after each request. The idle time
+    ///between requests is limited by `KeepAlive::timeout`, rather than
+    ///`request_timeout`. Default is `None`.
     pub keep_alive: Option<KeepAlive>,
 
+    ///The longest time the server will wait for more data from the client,
+    ///while reading a request. Stalled clients are disconnected when it runs
+    ///out. The timeout applies to each read, so a client that keeps sending
+    ///a trickle of data will not be cut off by it, but `head_timeout` limits
+    ///the total time for the request head. Default is 30 seconds.
+    pub request_timeout: Option<Duration>,
+
+    ///The longest time the client may take to send the whole request head,
+    ///which is the request line and the headers, counted from its first
+    ///byte. Clients that send their requests very slowly are disconnected
+    ///when it runs out, so they can't occupy a thread forever. The body is
+    ///only limited by `request_timeout`. Default is 30 seconds.
+    pub head_timeout: Option<Duration>,
+
+    ///The longest time the server will wait for the client to accept more
+    ///response data. Writing to the response fails with a timeout error when
+    ///it runs out. Default is 30 seconds.
+    pub response_timeout: Option<Duration>,
+
+    ///Limits for how many connections may be open, or wait for a free
+    ///thread, at the same time. Connections beyond the limits are rejected
+    ///with `503 Service Unavailable`, except for HTTPS, where they are closed
+    ///without a response. Default is `None`, which leaves any excess
+    ///connections waiting until a thread is free.
+    pub connection_limit: Option<ConnectionLimit>,
+
+    ///Limits for the length and the number of path segments of the request
+    ///target. Default is `None`, which only leaves the limits of the HTTP
+    ///parser.
+    pub uri_limit: Option<UriLimit>,
+
+    ///Limits for the number and the size of the request headers. Default is
+    ///`None`, which only leaves the limits of the HTTP parser.
+    pub header_limit: Option<HeaderLimit>,
+
+    ///Add a `Strict-Transport-Security` header to responses that are sent
+    ///over HTTPS. Default is `None`.
+    pub hsts: Option<Hsts>,
+
+    ///Reject requests that may be used for request smuggling. Default is
+    ///`None`, which leaves the checks to the HTTP parser.
+    pub strict_parsing: Option<StrictParsing>,
+
+    ///Deadlines for handling requests, that are shown to the handlers and
+    ///enforced when the response is sent. Default is `None`.
+    pub deadlines: Option<Deadlines>,
+
+    ///Proxies that are trusted to report the client address and protocol.
+    ///Requests from these addresses will have `context.client_address` and
+    ///`context.is_https` set from the `Forwarded` header, or the
+    ///`X-Forwarded-For` and `X-Forwarded-Proto` headers. Default is empty,
+    ///which ignores the headers.
+    pub trusted_proxies: Vec<IpAddr>,
+
+    ///Bodies for error responses that would otherwise be empty. Default is
+    ///no pages.
+    pub error_pages: ErrorPages,
+
     ///The content of the server header. Default is `"rustful"`.
     pub server: String,
 
     ///The default media type. Default is `text/plain, charset: UTF-8`.
     pub content_type: Mime,
 
+    ///Default media types for text and binary data, that are used instead
+    ///of `content_type` if they are set. Default is `None`.
+    pub content_types: Option<ContentTypes>,
+
     ///Globally accessible data.
     pub global: Global,
 
+    ///Create state for each worker thread, such as database connections or
+    ///random number generators that can't be shared between threads. It's
+    ///called once per thread, before its first connection, and the state
+    ///can be borrowed using `Context::worker_state`. Default is `None`.
+    ///
+    ///```
+    ///# extern crate rustful;
+    ///extern crate anymap;
+    ///
+    ///use std::cell::Cell;
+    ///use rustful::{Server, Context, Response};
+    ///use anymap::AnyMap;
+    ///# fn main() {
+    ///
+    ///struct RequestCount(Cell<u64>);
+    ///
+    ///fn count(context: Context, response: Response) {
+    ///    let count = context.worker_state(|state| {
+    ///        let count = state.get::<RequestCount>().unwrap();
+    ///        count.0.set(count.0.get() + 1);
+    ///        count.0.get()
+    ///    });
+    ///    response.send(format!("request {} on this thread", count.unwrap_or(0)));
+    ///}
+    ///
+    ///let server = Server {
+    ///    worker_init: Some(Box::new(|| {
+    ///        let mut state = AnyMap::new();
+    ///        state.insert(RequestCount(Cell::new(0)));
+    ///        state
+    ///    })),
+    ///    ..Server::new(count)
+    ///};
+    ///# }
+    ///```
+    pub worker_init: Option<Box<Fn() -> AnyMap + Send + Sync>>,
+
+    ///Tasks that run in the background while the server is running. They
+    ///are easiest to add with `spawn_periodic` and `spawn_background`.
+    pub tasks: Tasks,
+
     ///The context filter stack.
     pub context_filters: Vec<Box<ContextFilter>>,
 
     ///The response filter stack.
-    pub response_filters: Vec<Box<ResponseFilter>>
+    pub response_filters: Vec<Box<ResponseFilter>>,
+
+    ///Tracers that follow each request through the server. Requests that
+    ///are rejected before the context is created are not traced. Default is
+    ///empty.
+    pub tracers: Vec<Box<Tracer>>,
+
+    ///Eagerly read and parse `application/x-www-form-urlencoded` request
+    ///bodies into `context.post`, if they are at most this many bytes.
+    ///Larger bodies, or bodies with more than `FORM_PAIR_LIMIT` pairs, are
+    ///rejected with `413 Payload Too Large`, and unsupported charsets with
+    ///`415 Unsupported Media Type`. See `BodyReader::read_form` for more
+    ///details. Default is `None`, which leaves the body unread.
+    pub form_body_limit: Option<u64>,
+
+    ///Decompress request bodies with `Content-Encoding: gzip` while they are
+    ///read, and allow at most this many bytes after decompression. The
+    ///handlers will then read the decompressed body, and the
+    ///`Content-Encoding` and `Content-Length` headers are removed. Reading
+    ///fails with an `io::Error` if the body is invalid, or if it grows beyond
+    ///the limit, in which case the inner error is
+    ///`context::body::DecompressedTooLarge`. `read_form` and `read_json`
+    ///report it as `TooLarge`. Default is `None`, which leaves them
+    ///compressed.
+    pub gzip_body_limit: Option<u64>,
+
+    ///Decode `+` as space in `context.query`, as in HTML forms. Some clients
+    ///mean a literal `+`, so turning this off will keep them. The raw query
+    ///string is always available as `context.raw_query`, and the choice can
+    ///be changed for parts of a router with the `PlusAsSpace` filter.
+    ///Default is `true`.
+    pub query_plus_as_space: bool,
+
+    ///How the request path is percent-decoded before it's routed. Default
+    ///is `PathDecoding::Full`, which decodes everything, including `%2F`.
+    pub path_decoding: PathDecoding,
+
+    ///Split matrix parameters, such as `;role=admin` in
+    ///`/users;role=admin/7`, out of the path segments and into
+    ///`context.matrix`, before the path is routed. Default is `false`, which
+    ///leaves them in the path.
+    pub matrix_parameters: bool,
+
+    ///How header values with control characters are handled by
+    ///`Response::set_header_str`. Default is `HeaderPolicy::Strict`, which
+    ///rejects them.
+    pub header_policy: HeaderPolicy
 }
 
 impl<R: HandleRequest> Server<R> {
@@ -97,31 +260,193 @@ impl<R: HandleRequest> Server<R> {
             host: 80.into(),
             threads: None,
             keep_alive: None,
+            request_timeout: Some(Duration::from_secs(30)),
+            head_timeout: Some(Duration::from_secs(30)),
+            response_timeout: Some(Duration::from_secs(30)),
+            connection_limit: None,
+            uri_limit: None,
+            header_limit: None,
+            hsts: None,
+            strict_parsing: None,
+            deadlines: None,
+            trusted_proxies: vec![],
+            error_pages: ErrorPages::new(),
             server: "rustful".to_owned(),
             content_type: Mime(
                 hyper::mime::TopLevel::Text,
                 hyper::mime::SubLevel::Html,
                 vec![(hyper::mime::Attr::Charset, hyper::mime::Value::Utf8)]
             ),
+            content_types: None,
             global: Global::default(),
+            worker_init: None,
+            tasks: Tasks::new(),
             context_filters: Vec::new(),
             response_filters: Vec::new(),
+            tracers: Vec::new(),
+            form_body_limit: None,
+            gzip_body_limit: None,
+            query_plus_as_space: true,
+            path_decoding: PathDecoding::default(),
+            matrix_parameters: false,
+            header_policy: HeaderPolicy::default(),
         }
     }
 
+    ///Run `task` every `interval` in its own thread, while the server is
+    ///running. It starts when the server has started listening, with the
+    ///first run one `interval` later, and stops when the server is closed.
+    ///
+    ///```no_run
+    ///use std::sync::RwLock;
+    ///use std::time::Duration;
+    ///use rustful::{Server, Context, Response};
+    ///use rustful::server::Global;
+    ///
+    ///struct Prices(RwLock<Vec<u32>>);
+    ///
+    ///fn fetch_prices() -> Vec<u32> {
+    ///    //...
+    ///    # vec![]
+    ///}
+    ///
+    ///fn show_prices(context: Context, response: Response) {
+    ///    let prices = context.global.get::<Prices>().unwrap().0.read().unwrap();
+    ///    response.send(format!("{:?}", *prices));
+    ///}
+    ///
+    ///let mut server = Server {
+    ///    host: 8080.into(),
+    ///    global: Box::new(Prices(RwLock::new(fetch_prices()))).into(),
+    ///    ..Server::new(show_prices)
+    ///};
+    ///
+    ///server.spawn_periodic(Duration::from_secs(60), |global: &Global| {
+    ///    let prices = fetch_prices();
+    ///    *global.get::<Prices>().unwrap().0.write().unwrap() = prices;
+    ///});
+    ///
+    ///let server_result = server.run();
+    ///```
+    ///
+    ///The tasks are stopped when the returned `Listening` handle is closed
+    ///or dropped, or by `Listeners::close` when the server is started using
+    ///`listeners`.
+    pub fn spawn_periodic<F: Fn(&Global) + Send + 'static>(&mut self, interval: Duration, task: F) {
+        self.tasks.periodic(interval, task);
+    }
+
+    ///Run `task` in its own thread, while the server is running. It starts
+    ///when the server has started listening, and it should return soon
+    ///after `Shutdown` says that the server is shutting down, as described
+    ///for `spawn_periodic`.
+    pub fn spawn_background<F: FnOnce(&Global, &Shutdown) + Send + 'static>(&mut self, task: F) {
+        self.tasks.background(task);
+    }
+
     ///Start the server.
     pub fn run(self) -> HttpResult<Listening> {
-        self.build().run()
+        self.checked_build()?.run()
     }
 
-    ///Start the server with SSL.
+    ///Start the server with SSL. Any TLS backend that implements
+    ///`SslServer` can be used, as described in the [`net`][net] module.
+    ///
+    ///[net]: ../net/index.html
     pub fn run_https<S: SslServer + Clone + Send + 'static>(self, ssl: S) -> HttpResult<Listening> {
-        self.build().run_https(ssl)
+        self.checked_build()?.run_https(ssl)
+    }
+
+    ///Start the server on an already bound socket, such as one that was
+    ///bound before dropping privileges, or one that was passed on by a
+    ///supervisor. The `host` setting is ignored.
+    ///
+    ///```no_run
+    ///use std::net::TcpListener;
+    ///use rustful::{Server, Context, Response};
+    ///
+    ///fn handler(_context: Context, response: Response) {
+    ///    response.send("Hello, world!");
+    ///}
+    ///
+    ///let listener = TcpListener::bind("0.0.0.0:80").unwrap();
+    /////Drop the privileges here...
+    ///
+    ///let server_result = Server::new(handler).run_on_listener(listener);
+    ///```
+    ///
+    ///Sockets from systemd socket activation can be received using
+    ///`net::listen_fds`, with the `systemd` feature.
+    pub fn run_on_listener(self, listener: TcpListener) -> HttpResult<Listening> {
+        self.checked_build()?.run_on_listener(listener)
+    }
+
+    ///Start the server with SSL, on an already bound socket. The `host`
+    ///setting is ignored.
+    pub fn run_https_on_listener<S: SslServer + Clone + Send + 'static>(self, listener: TcpListener, ssl: S) -> HttpResult<Listening> {
+        self.checked_build()?.run_https_on_listener(listener, ssl)
+    }
+
+    ///Prepare to listen on more than one address, as described for
+    ///[`Listeners`][listeners]. The `host` setting is ignored.
+    ///
+    ///[listeners]: struct.Listeners.html
+    pub fn listeners(self) -> HttpResult<Listeners<R>> {
+        Ok(self.checked_build()?.listeners())
     }
 
     ///Build a runnable instance of the server.
+    ///
+    ///It will panic if the handlers require global data that is missing,
+    ///since that's a mistake in the server setup. See `try_build` for a
+    ///non-panicking alternative.
     pub fn build(self) -> ServerInstance<R> {
-        ServerInstance::new(self)
+        match self.try_build() {
+            Ok(instance) => instance,
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    ///Build a runnable instance of the server, or list the global data
+    ///types that the handlers require, but are missing from `global`.
+    ///
+    ///```
+    ///use rustful::{Server, Handler, Context, Response};
+    ///use rustful::server::{Requirements, GlobalBuilder};
+    ///
+    ///struct Database;
+    ///
+    ///struct ListUsers;
+    ///
+    ///impl Handler for ListUsers {
+    ///    fn handle(&self, context: Context, response: Response) {
+    ///        let database: &Database = context.global.get().unwrap();
+    ///        //...
+    ///    }
+    ///
+    ///    fn requirements(&self, requirements: &mut Requirements) {
+    ///        requirements.require::<Database>();
+    ///    }
+    ///}
+    ///
+    ///let error = Server::new(ListUsers).try_build().err().unwrap();
+    ///assert_eq!(error.0.len(), 1);
+    ///
+    ///let server = Server {
+    ///    global: GlobalBuilder::new().insert(Database).build().unwrap(),
+    ///    ..Server::new(ListUsers)
+    ///};
+    ///assert!(server.try_build().is_ok());
+    ///```
+    pub fn try_build(self) -> Result<ServerInstance<R>, MissingGlobals> {
+        let mut requirements = Requirements::new();
+        self.handlers.requirements(&mut requirements);
+        requirements.check(&self.global)?;
+        Ok(ServerInstance::new(self))
+    }
+
+    fn checked_build(self) -> HttpResult<ServerInstance<R>> {
+        self.try_build().map_err(|e| HttpError::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))
     }
 }
 
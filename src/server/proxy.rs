@@ -0,0 +1,175 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str;
+
+use header::Headers;
+
+///The client information that was reported by trusted proxies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Forwarded {
+    ///The address of the original client.
+    pub address: IpAddr,
+
+    ///`Some(true)` if the client used HTTPS, `Some(false)` if it used plain
+    ///HTTP and `None` if it's not known.
+    pub https: Option<bool>,
+}
+
+///Find the original client of a request that came from `peer`.
+///
+///The proxy headers are only used if `peer` is trusted. The hops in
+///`Forwarded`, or `X-Forwarded-For` if there is no `Forwarded` header, are
+///then walked from the closest to the farthest, until an untrusted address
+///is found. That address is the client. The walk stops at the last known
+///address if a hop is obfuscated or malformed.
+pub fn forwarded(peer: IpAddr, headers: &Headers, trusted: &[IpAddr]) -> Forwarded {
+    let mut result = Forwarded {
+        address: peer,
+        https: None,
+    };
+
+    if !trusted.contains(&peer) {
+        return result;
+    }
+
+    if let Some(hops) = headers.get_raw("Forwarded") {
+        for element in list(hops).into_iter().rev() {
+            let mut address = None;
+            let mut https = None;
+
+            for pair in element.split(';') {
+                let mut pair = pair.splitn(2, '=');
+                let key = pair.next().unwrap_or("").trim();
+                let value = pair.next().unwrap_or("").trim().trim_matches('"');
+
+                if key.eq_ignore_ascii_case("for") {
+                    address = parse_node(value);
+                } else if key.eq_ignore_ascii_case("proto") {
+                    https = Some(value.eq_ignore_ascii_case("https"));
+                }
+            }
+
+            match address {
+                Some(address) => {
+                    result.address = address;
+                    result.https = https;
+                    if !trusted.contains(&address) {
+                        break;
+                    }
+                },
+                None => break
+            }
+        }
+    } else if let Some(hops) = headers.get_raw("X-Forwarded-For") {
+        //The number of hops from the end of the list to the client.
+        let mut client_hop = None;
+
+        for (index, hop) in list(hops).into_iter().rev().enumerate() {
+            match parse_node(hop) {
+                Some(address) => {
+                    result.address = address;
+                    client_hop = Some(index);
+                    if !trusted.contains(&address) {
+                        break;
+                    }
+                },
+                None => break
+            }
+        }
+
+        //Each proxy appends to both lists, so the protocol of the client is
+        //at the same position from the end.
+        if let (Some(index), Some(protos)) = (client_hop, headers.get_raw("X-Forwarded-Proto")) {
+            result.https = list(protos).into_iter().rev().nth(index)
+                .map(|proto| proto.eq_ignore_ascii_case("https"));
+        }
+    }
+
+    result
+}
+
+//Iterate over the comma separated items in every header line.
+fn list(lines: &[Vec<u8>]) -> Vec<&str> {
+    lines.iter()
+        .filter_map(|line| str::from_utf8(line).ok())
+        .flat_map(|line| line.split(','))
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+//Parse an IP address, optionally with a port, as in `192.0.2.1`,
+//`192.0.2.1:8080`, `2001:db8::1` or `[2001:db8::1]:8080`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(address) = node.parse() {
+        Some(address)
+    } else if let Ok(address) = node.parse::<SocketAddr>() {
+        Some(address.ip())
+    } else if node.starts_with('[') && node.ends_with(']') {
+        node[1..node.len() - 1].parse::<Ipv6Addr>().ok().map(IpAddr::V6)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use header::Headers;
+    use super::{forwarded, Forwarded};
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn x_forwarded_for() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", vec![b"192.0.2.60, 203.0.113.7".to_vec(), b"10.0.0.2".to_vec()]);
+        headers.set_raw("X-Forwarded-Proto", vec![b"http, https".to_vec(), b"http".to_vec()]);
+
+        assert_eq!(forwarded(ip("10.0.0.1"), &headers, &trusted), Forwarded {
+            address: ip("203.0.113.7"),
+            https: Some(true),
+        });
+
+        headers.set_raw("X-Forwarded-Proto", vec![b"https".to_vec()]);
+        assert_eq!(forwarded(ip("10.0.0.1"), &headers, &trusted), Forwarded {
+            address: ip("203.0.113.7"),
+            https: None,
+        });
+
+        headers.set_raw("X-Forwarded-For", vec![b"192.0.2.60".to_vec()]);
+        assert_eq!(forwarded(ip("10.0.0.1"), &headers, &trusted), Forwarded {
+            address: ip("192.0.2.60"),
+            https: Some(true),
+        });
+
+        assert_eq!(forwarded(ip("198.51.100.1"), &headers, &trusted), Forwarded {
+            address: ip("198.51.100.1"),
+            https: None,
+        });
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let trusted = [ip("10.0.0.1"), ip("2001:db8::2")];
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", vec![b"192.0.2.60".to_vec()]);
+        headers.set_raw("Forwarded", vec![
+            b"for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\";proto=https".to_vec(),
+            b"For=\"[2001:db8::2]\";by=10.0.0.1".to_vec()
+        ]);
+
+        assert_eq!(forwarded(ip("10.0.0.1"), &headers, &trusted), Forwarded {
+            address: ip("2001:db8:cafe::17"),
+            https: Some(true),
+        });
+
+        headers.set_raw("Forwarded", vec![b"for=unknown, for=10.0.0.1".to_vec()]);
+        assert_eq!(forwarded(ip("10.0.0.1"), &headers, &trusted), Forwarded {
+            address: ip("10.0.0.1"),
+            https: None,
+        });
+    }
+}
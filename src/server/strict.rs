@@ -0,0 +1,107 @@
+use hyper::uri::RequestUri;
+
+use header::Headers;
+
+///Check a request for things that different servers and proxies may
+///interpret differently, which can be used to smuggle requests past them.
+///The reason is returned if the request should be rejected.
+pub fn check(headers: &Headers, uri: &RequestUri) -> Result<(), &'static str> {
+    match *uri {
+        RequestUri::AbsolutePath(ref target) | RequestUri::Authority(ref target) => {
+            if !target.bytes().all(is_target_byte) {
+                return Err("invalid characters in the request target");
+            }
+        },
+        RequestUri::AbsoluteUri(_) | RequestUri::Star => {}
+    }
+
+    for header in headers.iter() {
+        let values = headers.get_raw(header.name()).unwrap_or(&[]);
+        if values.iter().any(|value| value.iter().any(|&byte| byte != b'\t' && (byte < 0x20 || byte == 0x7f))) {
+            return Err("control characters in a header value");
+        }
+    }
+
+    let content_length = headers.get_raw("content-length");
+    let transfer_encoding = headers.get_raw("transfer-encoding");
+
+    if content_length.is_some() && transfer_encoding.is_some() {
+        return Err("both Content-Length and Transfer-Encoding");
+    }
+
+    if let Some(values) = content_length {
+        let mut lengths = values.iter().flat_map(|value| value.split(|&byte| byte == b',')).map(trim);
+        let first = lengths.next().unwrap_or(b"");
+
+        if first.is_empty() || !first.iter().all(u8::is_ascii_digit) {
+            return Err("invalid Content-Length");
+        }
+
+        if lengths.any(|length| length != first) {
+            return Err("conflicting Content-Length values");
+        }
+    }
+
+    if let Some(values) = transfer_encoding {
+        let last = values.iter().flat_map(|value| value.split(|&byte| byte == b',')).map(trim).last();
+        if !last.map_or(false, |coding| coding.eq_ignore_ascii_case(b"chunked")) {
+            return Err("Transfer-Encoding doesn't end with chunked");
+        }
+    }
+
+    Ok(())
+}
+
+fn is_target_byte(byte: u8) -> bool {
+    match byte {
+        b'"' | b'<' | b'>' | b'\\' | b'^' | b'`' | b'{' | b'|' | b'}' => false,
+        0x21..=0x7e => true,
+        _ => false
+    }
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|&byte| byte != b' ' && byte != b'\t').unwrap_or(value.len());
+    let end = value.iter().rposition(|&byte| byte != b' ' && byte != b'\t').map_or(start, |end| end + 1);
+    &value[start..end]
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::uri::RequestUri;
+
+    use header::Headers;
+    use super::check;
+
+    fn headers(raw: &[(&'static str, &[u8])]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in raw {
+            headers.append_raw(name, value.to_vec());
+        }
+        headers
+    }
+
+    #[test]
+    fn request_targets() {
+        let valid = headers(&[]);
+        assert!(check(&valid, &RequestUri::AbsolutePath("/a/b?c=d%20e".into())).is_ok());
+        assert!(check(&valid, &RequestUri::Star).is_ok());
+        assert!(check(&valid, &RequestUri::AbsolutePath("/a b".into())).is_err());
+        assert!(check(&valid, &RequestUri::AbsolutePath("/a/{b}".into())).is_err());
+        assert!(check(&valid, &RequestUri::AbsolutePath("/ä".into())).is_err());
+    }
+
+    #[test]
+    fn message_framing() {
+        let uri = RequestUri::AbsolutePath("/".into());
+        assert!(check(&headers(&[("Content-Length", b"10")]), &uri).is_ok());
+        assert!(check(&headers(&[("Content-Length", b"10"), ("Content-Length", b"10")]), &uri).is_ok());
+        assert!(check(&headers(&[("Content-Length", b"10, 11")]), &uri).is_err());
+        assert!(check(&headers(&[("Content-Length", b"+10")]), &uri).is_err());
+        assert!(check(&headers(&[("Transfer-Encoding", b"gzip, chunked")]), &uri).is_ok());
+        assert!(check(&headers(&[("Transfer-Encoding", b"chunked, gzip")]), &uri).is_err());
+        assert!(check(&headers(&[("Transfer-Encoding", b"chunked"), ("Content-Length", b"10")]), &uri).is_err());
+        assert!(check(&headers(&[("X-Folded", b"a\r\n b")]), &uri).is_err());
+        assert!(check(&headers(&[("X-Tab", b"a\tb")]), &uri).is_ok());
+    }
+}
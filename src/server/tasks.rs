@@ -0,0 +1,165 @@
+use std::mem::replace;
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use server::Global;
+
+type Job = Box<FnOnce(&Global, &Shutdown) + Send>;
+
+///Tasks that run in the background, while the server is running.
+///
+///They are added with `Server::spawn_periodic` and
+///`Server::spawn_background`, and each of them gets its own thread when
+///the server has started listening.
+#[derive(Default)]
+pub struct Tasks {
+    jobs: Mutex<Vec<Job>>,
+}
+
+impl Tasks {
+    ///Create an empty set of tasks.
+    pub fn new() -> Tasks {
+        Tasks::default()
+    }
+
+    ///Check if there are no tasks.
+    pub fn is_empty(&self) -> bool {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).is_empty()
+    }
+
+    ///Add a task that runs until it returns, or until the server shuts
+    ///down.
+    pub fn background<F: FnOnce(&Global, &Shutdown) + Send + 'static>(&mut self, task: F) {
+        self.jobs.get_mut().unwrap_or_else(|e| e.into_inner()).push(Box::new(task));
+    }
+
+    ///Add a task that runs every `interval`, until the server shuts down.
+    ///The first run is one `interval` after the server has started.
+    pub fn periodic<F: Fn(&Global) + Send + 'static>(&mut self, interval: Duration, task: F) {
+        self.background(move |global: &Global, shutdown: &Shutdown| {
+            while !shutdown.wait(interval) {
+                task(global);
+            }
+        });
+    }
+}
+
+//Start the tasks, unless they are already started. The tasks will keep a
+//copy of `global` while they are running.
+pub fn start<G: AsRef<Global> + Clone + Send + 'static>(tasks: &Tasks, global: G) -> RunningTasks {
+    let jobs = replace(&mut *tasks.jobs.lock().unwrap_or_else(|e| e.into_inner()), vec![]);
+    let shutdown = Shutdown::new();
+
+    let threads = jobs.into_iter().map(|job| {
+        let global = global.clone();
+        let shutdown = shutdown.clone();
+        thread::spawn(move || job(global.as_ref(), &shutdown))
+    }).collect();
+
+    RunningTasks {
+        shutdown: shutdown,
+        threads: threads,
+    }
+}
+
+//Background tasks that have been started.
+pub struct RunningTasks {
+    shutdown: Shutdown,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl RunningTasks {
+    //Tell the tasks to stop, and wait for them.
+    pub fn stop(&mut self) {
+        self.shutdown.trigger();
+
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("a background task panicked");
+            }
+        }
+    }
+}
+
+impl Drop for RunningTasks {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+///Tells a background task when the server is shutting down.
+#[derive(Clone)]
+pub struct Shutdown(Arc<(Mutex<bool>, Condvar)>);
+
+impl Shutdown {
+    fn new() -> Shutdown {
+        Shutdown(Arc::new((Mutex::new(false), Condvar::new())))
+    }
+
+    ///Check if the server is shutting down.
+    pub fn is_shutting_down(&self) -> bool {
+        *(self.0).0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    ///Wait for `timeout`, or until the server is shutting down. It returns
+    ///`true` if the server is shutting down.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (ref lock, ref condvar) = *self.0;
+        let mut shutting_down = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        while !*shutting_down {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            shutting_down = condvar.wait_timeout(shutting_down, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+
+        *shutting_down
+    }
+
+    fn trigger(&self) {
+        let (ref lock, ref condvar) = *self.0;
+        *lock.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use server::Global;
+    use super::{Tasks, Shutdown, start};
+
+    #[test]
+    fn run_and_stop() {
+        let mut tasks = Tasks::new();
+        tasks.periodic(Duration::from_millis(1), |global: &Global| {
+            global.get::<AtomicUsize>().unwrap().fetch_add(1, Ordering::SeqCst);
+        });
+        tasks.background(|global: &Global, shutdown: &Shutdown| {
+            while !shutdown.wait(Duration::from_secs(60)) {}
+            global.get::<AtomicUsize>().unwrap().fetch_add(1000, Ordering::SeqCst);
+        });
+
+        let global: Arc<Global> = Arc::new(Box::new(AtomicUsize::new(0)).into());
+        let mut running = start(&tasks, global.clone());
+        assert!(tasks.is_empty());
+
+        thread::sleep(Duration::from_millis(20));
+        running.stop();
+
+        let count = global.get::<AtomicUsize>().unwrap().load(Ordering::SeqCst);
+        assert!(count > 1000);
+
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(global.get::<AtomicUsize>().unwrap().load(Ordering::SeqCst), count);
+    }
+}
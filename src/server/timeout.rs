@@ -0,0 +1,360 @@
+//Limits the total time it may take to receive a request head. The read
+//timeout alone is restarted by each read, so a client that sends a byte
+//now and then could otherwise keep a thread busy forever.
+//
+//Hyper accepts and handles each connection on the same worker thread, so
+//the state of the current connection is kept in a thread local, where the
+//handler can tell it when the head has been received. The keep-alive
+//timeout from a response is passed on to the connection in the same way.
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, Shutdown};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::net::{NetworkListener, NetworkStream};
+use hyper::server::{Handler as HyperHandler, Request, Response};
+
+use HttpResult;
+use utils;
+
+thread_local!(static CURRENT: RefCell<Option<Arc<Mutex<HeadState>>>> = RefCell::new(None));
+thread_local!(static KEEP_ALIVE: Cell<Option<Duration>> = Cell::new(None));
+
+struct HeadState {
+    deadline: Option<Instant>,
+    in_handler: bool,
+    read_timeout: Option<Duration>,
+}
+
+//A listener that gives each connection a deadline for the request heads.
+#[derive(Clone)]
+pub struct HeadTimeout<L> {
+    listener: L,
+    timeout: Duration,
+    read_timeout: Option<Duration>,
+}
+
+impl<L: NetworkListener> HeadTimeout<L> {
+    pub fn new(listener: L, timeout: Duration) -> HeadTimeout<L> {
+        HeadTimeout {
+            listener: listener,
+            timeout: timeout,
+            read_timeout: None,
+        }
+    }
+}
+
+impl<L: NetworkListener> NetworkListener for HeadTimeout<L> {
+    type Stream = HeadTimeoutStream<L::Stream>;
+
+    fn accept(&mut self) -> HttpResult<HeadTimeoutStream<L::Stream>> {
+        let stream = self.listener.accept()?;
+        let state = Arc::new(Mutex::new(HeadState {
+            deadline: None,
+            in_handler: false,
+            read_timeout: self.read_timeout,
+        }));
+
+        CURRENT.with(|current| *current.borrow_mut() = Some(state.clone()));
+
+        Ok(HeadTimeoutStream {
+            stream: stream,
+            timeout: self.timeout,
+            state: state,
+        })
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn set_read_timeout(&mut self, duration: Option<Duration>) {
+        self.read_timeout = duration;
+        self.listener.set_read_timeout(duration);
+    }
+
+    fn set_write_timeout(&mut self, duration: Option<Duration>) {
+        self.listener.set_write_timeout(duration);
+    }
+}
+
+//A connection where the reads outside the handler, which are the request
+//heads, have to be done before a deadline. The deadline starts with the
+//first byte of each head, so waiting for a new request is only limited by
+//the read and keep-alive timeouts.
+#[derive(Clone)]
+pub struct HeadTimeoutStream<S> {
+    stream: S,
+    timeout: Duration,
+    state: Arc<Mutex<HeadState>>,
+}
+
+impl<S: NetworkStream> Read for HeadTimeoutStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (deadline, read_timeout) = {
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            (if state.in_handler { None } else { state.deadline }, state.read_timeout)
+        };
+
+        let length = if let Some(deadline) = deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the request head took too long to receive"));
+            }
+
+            let remaining = deadline - now;
+            self.stream.set_read_timeout(Some(read_timeout.map_or(remaining, |timeout| timeout.min(remaining))))?;
+            let result = self.stream.read(buf);
+            self.stream.set_read_timeout(read_timeout)?;
+            result?
+        } else {
+            self.stream.read(buf)?
+        };
+
+        if length > 0 {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if !state.in_handler && state.deadline.is_none() {
+                state.deadline = Some(Instant::now() + self.timeout);
+            }
+        }
+
+        Ok(length)
+    }
+}
+
+impl<S: NetworkStream> Write for HeadTimeoutStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for HeadTimeoutStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).read_timeout = duration;
+        self.stream.set_read_timeout(duration)
+    }
+
+    fn set_write_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(duration)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.stream.close(how)
+    }
+}
+
+//A listener whose connections idle for as long as the latest response
+//says, if it has set a keep-alive timeout, instead of the server's
+//keep-alive timeout.
+#[derive(Clone)]
+pub struct KeepAliveTimeout<L>(pub L);
+
+impl<L: NetworkListener> NetworkListener for KeepAliveTimeout<L> {
+    type Stream = KeepAliveStream<L::Stream>;
+
+    fn accept(&mut self) -> HttpResult<KeepAliveStream<L::Stream>> {
+        let stream = self.0.accept()?;
+
+        //Don't let a response from the previous connection affect this one
+        KEEP_ALIVE.with(|timeout| timeout.set(None));
+
+        Ok(KeepAliveStream(stream))
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+
+    fn set_read_timeout(&mut self, duration: Option<Duration>) {
+        self.0.set_read_timeout(duration);
+    }
+
+    fn set_write_timeout(&mut self, duration: Option<Duration>) {
+        self.0.set_write_timeout(duration);
+    }
+}
+
+//Hyper sets the read timeout to the keep-alive timeout after each
+//request, which is where the one from the response takes its place.
+#[derive(Clone)]
+pub struct KeepAliveStream<S>(S);
+
+impl<S: NetworkStream> Read for KeepAliveStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: NetworkStream> Write for KeepAliveStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<S: NetworkStream> NetworkStream for KeepAliveStream<S> {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.0.peer_addr()
+    }
+
+    fn set_read_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(KEEP_ALIVE.with(|timeout| timeout.take()).or(duration))
+    }
+
+    fn set_write_timeout(&self, duration: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(duration)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.0.close(how)
+    }
+}
+
+//Picks up the keep-alive timeout from the response when the handler is
+//done, so it's not used for reading the body.
+pub struct KeepAliveHandler<H>(pub H);
+
+impl<H: HyperHandler> HyperHandler for KeepAliveHandler<H> {
+    fn handle<'a, 'k>(&'a self, request: Request<'a, 'k>, response: Response<'a>) {
+        utils::take_keep_alive_timeout();
+        self.0.handle(request, response);
+        KEEP_ALIVE.with(|timeout| timeout.set(utils::take_keep_alive_timeout()));
+    }
+
+    fn on_connection_start(&self) {
+        self.0.on_connection_start();
+    }
+
+    fn on_connection_end(&self) {
+        self.0.on_connection_end();
+    }
+}
+
+//Tells the current connection when the handler is running, so the body
+//is read without the deadline, and the next head gets a new one.
+pub struct HeadTimeoutHandler<H>(pub H);
+
+impl<H: HyperHandler> HyperHandler for HeadTimeoutHandler<H> {
+    fn handle<'a, 'k>(&'a self, request: Request<'a, 'k>, response: Response<'a>) {
+        set_in_handler(true);
+        self.0.handle(request, response);
+        set_in_handler(false);
+    }
+
+    fn on_connection_start(&self) {
+        self.0.on_connection_start();
+    }
+
+    fn on_connection_end(&self) {
+        self.0.on_connection_end();
+    }
+}
+
+fn set_in_handler(in_handler: bool) {
+    CURRENT.with(|current| {
+        if let Some(ref state) = *current.borrow() {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.in_handler = in_handler;
+            state.deadline = None;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use {Server, Context, Response};
+    use server::KeepAlive;
+
+    #[test]
+    fn cut_off_slow_heads() {
+        fn handler(_context: Context, response: Response) {
+            response.send("hello");
+        }
+
+        let mut listening = Server {
+            threads: Some(1),
+            request_timeout: Some(Duration::from_secs(5)),
+            head_timeout: Some(Duration::from_millis(200)),
+            ..Server::new(handler as fn(Context, Response))
+        }.run_on_listener(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "unexpected response: {}", response);
+        assert!(response.ends_with("hello"));
+
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..20 {
+            thread::sleep(Duration::from_millis(50));
+            if stream.write_all(b"X").is_err() {
+                break;
+            }
+        }
+
+        let mut response = vec![];
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn keep_alive_timeout_from_response() {
+        fn handler(_context: Context, mut response: Response) {
+            response.set_keep_alive_timeout(Some(Duration::from_millis(200)));
+            response.send("hello");
+        }
+
+        let mut listening = Server {
+            threads: Some(1),
+            keep_alive: Some(KeepAlive {
+                timeout: Duration::from_secs(30),
+                free_threads: 0,
+            }),
+            ..Server::new(handler as fn(Context, Response))
+        }.run_on_listener(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut response = vec![];
+        let mut buffer = [0; 1024];
+        while !response.ends_with(b"hello") {
+            let length = stream.read(&mut buffer).unwrap();
+            assert!(length > 0, "the connection was closed too early");
+            response.extend_from_slice(&buffer[..length]);
+        }
+        assert!(String::from_utf8_lossy(&response).contains("Keep-Alive: timeout=1\r\n"));
+
+        let start = Instant::now();
+        assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        listening.close().unwrap();
+    }
+}
@@ -0,0 +1,486 @@
+//!Cookie based sessions.
+//!
+//!A [`SessionFilter`][session_filter] is both a context filter and a
+//!response filter. The context filter part loads the session, using the
+//!session ID from a cookie, and puts a [`Session`][session] in
+//!`context.extensions`. The response filter part saves any changes to the
+//![`SessionStore`][session_store] and sets the session cookie when needed,
+//!so the same filter has to be added to both filter stacks:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response};
+//!use rustful::session::{Session, SessionFilter, MemoryStore};
+//!
+//!fn count_visits(context: Context, response: Response) {
+//!    if let Some(session) = context.extensions.get::<Session>() {
+//!        let visits = session.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0u32) + 1;
+//!        session.set("visits", visits.to_string());
+//!        response.send(format!("visit number {}", visits));
+//!    }
+//!}
+//!
+//!let sessions = SessionFilter::new(MemoryStore::new());
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    context_filters: vec![Box::new(sessions.clone())],
+//!    response_filters: vec![Box::new(sessions)],
+//!    ..Server::new(count_visits)
+//!}.run();
+//!```
+//!
+//![session_filter]: struct.SessionFilter.html
+//![session]: struct.Session.html
+//![session_store]: trait.SessionStore.html
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use StatusCode;
+use header::Headers;
+
+use context::Context;
+use cookie::{self, Cookie, SameSite};
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+use response::Data;
+use utils::random_id;
+
+///The content of a session.
+pub type SessionData = HashMap<String, String>;
+
+///A storage backend for sessions.
+pub trait SessionStore: Send + Sync {
+    ///Load the data for a session, if it exists.
+    fn load(&self, id: &str) -> Option<SessionData>;
+
+    ///Store the data for a session, replacing any previous data.
+    fn save(&self, id: &str, data: SessionData);
+
+    ///Remove a session from the store.
+    fn remove(&self, id: &str);
+}
+
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        (**self).load(id)
+    }
+
+    fn save(&self, id: &str, data: SessionData) {
+        (**self).save(id, data)
+    }
+
+    fn remove(&self, id: &str) {
+        (**self).remove(id)
+    }
+}
+
+///A session store that keeps every session in memory. The sessions will
+///disappear when the server is restarted.
+///
+///Sessions that haven't been used for the time to live, 24 hours by
+///default, are treated as missing. They are removed from the store every
+///time it has passed, when a session is saved, or by `remove_expired`.
+pub struct MemoryStore {
+    sessions: RwLock<HashMap<String, StoredSession>>,
+    ttl: Option<Duration>,
+    last_sweep: Mutex<Instant>,
+}
+
+struct StoredSession {
+    data: SessionData,
+    used: Instant,
+}
+
+impl MemoryStore {
+    ///Create an empty memory store.
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: RwLock::new(HashMap::new()),
+            ttl: Some(Duration::from_secs(24 * 60 * 60)),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    ///Set how long an unused session is kept, or keep them until they are
+    ///destroyed if it's `None`.
+    pub fn ttl(mut self, ttl: Option<Duration>) -> MemoryStore {
+        self.ttl = ttl;
+        self
+    }
+
+    ///Remove every expired session.
+    pub fn remove_expired(&self) {
+        if let Some(ttl) = self.ttl {
+            *self.last_sweep.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+            self.sessions.write().unwrap_or_else(|e| e.into_inner()).retain(|_, session| session.used.elapsed() < ttl);
+        }
+    }
+
+    ///The number of stored sessions, including expired sessions that are
+    ///not yet removed.
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    ///Check if there are any stored sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.write().unwrap_or_else(|e| e.into_inner());
+
+        match (sessions.get_mut(id), self.ttl) {
+            (Some(ref session), Some(ttl)) if session.used.elapsed() >= ttl => {},
+            (Some(session), _) => {
+                session.used = Instant::now();
+                return Some(session.data.clone());
+            },
+            (None, _) => return None
+        }
+
+        sessions.remove(id);
+        None
+    }
+
+    fn save(&self, id: &str, data: SessionData) {
+        let sweep = self.ttl.map_or(false, |ttl| self.last_sweep.lock().unwrap_or_else(|e| e.into_inner()).elapsed() >= ttl);
+        if sweep {
+            self.remove_expired();
+        }
+
+        self.sessions.write().unwrap_or_else(|e| e.into_inner()).insert(id.to_owned(), StoredSession {
+            data: data,
+            used: Instant::now(),
+        });
+    }
+
+    fn remove(&self, id: &str) {
+        self.sessions.write().unwrap_or_else(|e| e.into_inner()).remove(id);
+    }
+}
+
+struct SessionState {
+    id: String,
+    data: SessionData,
+    is_new: bool,
+    modified: bool,
+    destroyed: bool,
+    previous_id: Option<String>,
+}
+
+///The session for the current request.
+///
+///It's a shared handle, so changes made by the request handler will be
+///visible to the response filter, which saves them after the handler is
+///done. Only modified sessions are saved, and a new session will not be
+///stored, and no cookie will be set, until something is added to it.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: String, data: Option<SessionData>) -> Session {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id: id,
+                is_new: data.is_none(),
+                data: data.unwrap_or_default(),
+                modified: false,
+                destroyed: false,
+                previous_id: None,
+            }))
+        }
+    }
+
+    fn with_state<T, F: FnOnce(&mut SessionState) -> T>(&self, action: F) -> T {
+        action(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    ///The session ID.
+    pub fn id(&self) -> String {
+        self.with_state(|state| state.id.clone())
+    }
+
+    ///Check if the session was created during this request.
+    pub fn is_new(&self) -> bool {
+        self.with_state(|state| state.is_new)
+    }
+
+    ///Get a session value.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.with_state(|state| state.data.get(key).cloned())
+    }
+
+    ///Set a session value.
+    pub fn set<K: Into<String>, V: Into<String>>(&self, key: K, value: V) {
+        self.with_state(|state| {
+            state.data.insert(key.into(), value.into());
+            state.modified = true;
+        })
+    }
+
+    ///Remove a session value.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.with_state(|state| {
+            let value = state.data.remove(key);
+            state.modified |= value.is_some();
+            value
+        })
+    }
+
+    ///Remove every value from the session.
+    pub fn clear(&self) {
+        self.with_state(|state| {
+            state.modified |= !state.data.is_empty();
+            state.data.clear();
+        })
+    }
+
+    ///Remove the session from the store and tell the client to forget the
+    ///session cookie.
+    pub fn destroy(&self) {
+        self.with_state(|state| {
+            state.data.clear();
+            state.destroyed = true;
+        })
+    }
+
+    ///Move the session to a new ID, while keeping its content. This should
+    ///be done when the privilege level changes, such as when a user logs
+    ///in, to prevent session fixation.
+    pub fn regenerate(&self) {
+        self.with_state(|state| {
+            let old_id = ::std::mem::replace(&mut state.id, generate_id());
+            if !state.is_new && state.previous_id.is_none() {
+                state.previous_id = Some(old_id);
+            }
+            state.modified = true;
+        })
+    }
+}
+
+///A filter that loads and saves sessions. It has to be added as both a
+///context filter and a response filter.
+pub struct SessionFilter<S> {
+    store: Arc<S>,
+    cookie_name: String,
+    path: String,
+    secure: bool,
+    max_age: Option<Duration>,
+}
+
+impl<S: SessionStore> SessionFilter<S> {
+    ///Create a session filter with the cookie name `"session"`, for the path
+    ///`/`.
+    pub fn new(store: S) -> SessionFilter<S> {
+        SessionFilter {
+            store: Arc::new(store),
+            cookie_name: "session".into(),
+            path: "/".into(),
+            secure: false,
+            max_age: None,
+        }
+    }
+
+    ///Set the name of the session cookie.
+    pub fn cookie_name<N: Into<String>>(mut self, name: N) -> SessionFilter<S> {
+        self.cookie_name = name.into();
+        self
+    }
+
+    ///Set the path of the session cookie.
+    pub fn path<P: Into<String>>(mut self, path: P) -> SessionFilter<S> {
+        self.path = path.into();
+        self
+    }
+
+    ///Only send the session cookie over secure connections.
+    pub fn secure(mut self, secure: bool) -> SessionFilter<S> {
+        self.secure = secure;
+        self
+    }
+
+    ///Let the session cookie outlive the browser session.
+    pub fn max_age(mut self, max_age: Duration) -> SessionFilter<S> {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    ///Get a reference to the session store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn cookie(&self, id: String) -> Cookie {
+        let cookie = Cookie::new(self.cookie_name.clone(), id)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        if let Some(max_age) = self.max_age {
+            cookie.max_age(max_age)
+        } else {
+            cookie
+        }
+    }
+}
+
+impl<S> Clone for SessionFilter<S> {
+    fn clone(&self) -> SessionFilter<S> {
+        SessionFilter {
+            store: self.store.clone(),
+            cookie_name: self.cookie_name.clone(),
+            path: self.path.clone(),
+            secure: self.secure,
+            max_age: self.max_age,
+        }
+    }
+}
+
+impl<S: SessionStore> ContextFilter for SessionFilter<S> {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        let existing = request_context.cookies().get(&*self.cookie_name).and_then(|id| {
+            self.store.load(&id).map(|data| (id.into_owned(), data))
+        });
+
+        let session = match existing {
+            Some((id, data)) => Session::new(id, Some(data)),
+            None => Session::new(generate_id(), None)
+        };
+
+        context.storage.insert(session.clone());
+        request_context.extensions.insert(session);
+        ContextAction::next()
+    }
+}
+
+impl<S: SessionStore> ResponseFilter for SessionFilter<S> {
+    fn begin(&self, context: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(session) = context.storage.get::<Session>() {
+            let mut state = session.state.lock().unwrap_or_else(|e| e.into_inner());
+
+            if let Some(previous_id) = state.previous_id.take() {
+                self.store.remove(&previous_id);
+            }
+
+            if state.destroyed {
+                if !state.is_new {
+                    self.store.remove(&state.id);
+                    cookie::append(headers, &Cookie::removal(self.cookie_name.clone()).path(self.path.clone()));
+                }
+            } else if state.modified {
+                self.store.save(&state.id, state.data.clone());
+                cookie::append(headers, &self.cookie(state.id.clone()));
+            }
+        }
+
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, _context: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _context: FilterContext) -> ResponseAction {
+        ResponseAction::next(None::<Data>)
+    }
+}
+
+fn generate_id() -> String {
+    random_id()
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use anymap::AnyMap;
+    use StatusCode;
+    use Method;
+    use header::{Headers, Cookie as CookieHeader, SetCookie};
+    use context::Context;
+    use filter::{FilterContext, ContextFilter, ResponseFilter};
+    use server::Global;
+    use super::{Session, SessionFilter, SessionStore, SessionData, MemoryStore, generate_id};
+
+    fn run(filter: &SessionFilter<MemoryStore>, request_headers: Headers, handler: fn(&Session)) -> Headers {
+        let global = Global::default();
+        let mut storage = AnyMap::new();
+        let mut context = Context::mock(Method::Get, "/", request_headers, &global);
+
+        filter.modify(FilterContext { storage: &mut storage, global: &global }, &mut context);
+        handler(context.extensions.get::<Session>().expect("no session"));
+
+        let mut response_headers = Headers::new();
+        filter.begin(FilterContext { storage: &mut storage, global: &global }, StatusCode::Ok, &mut response_headers);
+        response_headers
+    }
+
+    #[test]
+    fn untouched_session_is_not_stored() {
+        let filter = SessionFilter::new(MemoryStore::new());
+        let headers = run(&filter, Headers::new(), |_| {});
+
+        assert!(filter.store().is_empty());
+        assert!(headers.get::<SetCookie>().is_none());
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let filter = SessionFilter::new(MemoryStore::new());
+        let headers = run(&filter, Headers::new(), |session| session.set("user", "alice"));
+        assert_eq!(filter.store().len(), 1);
+
+        let set_cookie = headers.get::<SetCookie>().expect("no session cookie");
+        let pair = set_cookie[0].split(';').next().unwrap().to_owned();
+        let mut request_headers = Headers::new();
+        request_headers.set(CookieHeader(vec![pair]));
+
+        let headers = run(&filter, request_headers.clone(), |session| {
+            assert!(!session.is_new());
+            assert_eq!(session.get("user"), Some("alice".into()));
+            session.destroy();
+        });
+
+        assert!(filter.store().is_empty());
+        assert!(headers.get::<SetCookie>().expect("no removal cookie")[0].contains("Max-Age=0"));
+    }
+
+    #[test]
+    fn expire_sessions() {
+        let store = MemoryStore::new().ttl(Some(Duration::from_millis(20)));
+        store.save("a", SessionData::new());
+        assert!(store.load("a").is_some());
+
+        thread::sleep(Duration::from_millis(30));
+        assert!(store.load("a").is_none());
+        assert!(store.is_empty());
+
+        store.save("b", SessionData::new());
+        store.save("c", SessionData::new());
+        thread::sleep(Duration::from_millis(30));
+        store.save("d", SessionData::new());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn random_ids() {
+        let id = generate_id();
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert!(id != generate_id());
+    }
+}
@@ -0,0 +1,248 @@
+//!Server-Sent Events.
+//!
+//!An [`EventStream`][event_stream] is created from a `Response`. It sets the
+//!`text/event-stream` content type, turns the response into a chunked
+//!response and formats each [`Event`][event] according to the
+//![specification][spec]:
+//!
+//!```
+//!use std::time::Duration;
+//!use rustful::{Context, Response};
+//!use rustful::sse::{self, EventStream, Event};
+//!
+//!fn my_handler(context: Context, response: Response) {
+//!    //Continue after the last event the client received, if it reconnected.
+//!    let first = sse::last_event_id(&context.headers)
+//!        .and_then(|id| id.parse().ok())
+//!        .map_or(0, |id: u32| id + 1);
+//!
+//!    let mut events = EventStream::new(response);
+//!    events.set_retry(Duration::from_secs(5)).ok();
+//!
+//!    for i in first..first + 10 {
+//!        let event = Event::new(format!("tick number {}", i)).name("tick").id(i.to_string());
+//!        if events.send(event).is_err() {
+//!            //The client is gone.
+//!            break;
+//!        }
+//!
+//!        //Nothing is sent while idle, so send a comment now and then to
+//!        //keep the connection open.
+//!        events.keep_alive_if_idle(Duration::from_secs(15)).ok();
+//!    }
+//!}
+//!```
+//!
+//!Each event is flushed as soon as it has been written, so it doesn't get
+//!stuck in a buffer on its way to the client.
+//!
+//![event_stream]: struct.EventStream.html
+//![event]: struct.Event.html
+//![spec]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use header::{Headers, ContentType, CacheControl, CacheDirective};
+use mime::{Mime, TopLevel, SubLevel};
+
+use response::{Response, Chunked, Error};
+
+///Get the ID of the last event a reconnecting client received, from the
+///`Last-Event-ID` header.
+pub fn last_event_id(headers: &Headers) -> Option<String> {
+    headers.get_raw("last-event-id")
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+        .filter(|id| !id.is_empty())
+}
+
+///A single event.
+///
+///Line breaks in the data are preserved, by splitting it into multiple
+///`data` fields. The name and ID can't contain line breaks, so any line
+///breaks are left out when the event is formatted.
+///
+///```
+///use rustful::sse::Event;
+///
+///let event = Event::new("first line\nsecond line").name("message").id("1");
+///assert_eq!(event.to_string(), "event: message\nid: 1\ndata: first line\ndata: second line\n\n");
+///```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    name: Option<String>,
+    id: Option<String>,
+    data: String,
+}
+
+impl Event {
+    ///Create an unnamed event with some data.
+    pub fn new<D: Into<String>>(data: D) -> Event {
+        Event {
+            name: None,
+            id: None,
+            data: data.into(),
+        }
+    }
+
+    ///Set the event type. Unnamed events are dispatched as `message` events
+    ///by the client.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Event {
+        self.name = Some(name.into());
+        self
+    }
+
+    ///Set the event ID. It will be sent back in the `Last-Event-ID` header
+    ///if the client reconnects.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref name) = self.name {
+            write_field(f, "event", name)?;
+        }
+
+        if let Some(ref id) = self.id {
+            write_field(f, "id", &id.replace('\0', ""))?;
+        }
+
+        for line in self.data.split("\r\n").flat_map(|line| line.split(|c| c == '\r' || c == '\n')) {
+            writeln!(f, "data: {}", line)?;
+        }
+
+        f.write_str("\n")
+    }
+}
+
+fn write_field(f: &mut fmt::Formatter, field: &str, value: &str) -> fmt::Result {
+    f.write_str(field)?;
+    f.write_str(": ")?;
+    for c in value.chars().filter(|&c| c != '\r' && c != '\n') {
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\n")
+}
+
+///A stream of events, on top of a chunked response.
+pub struct EventStream<'a, 'b> {
+    writer: Chunked<'a, 'b>,
+    last_sent: Instant,
+}
+
+impl<'a, 'b> EventStream<'a, 'b> {
+    ///Set the `text/event-stream` content type, disable caching and start
+    ///sending the response.
+    pub fn new(mut response: Response<'a, 'b>) -> EventStream<'a, 'b> {
+        response.headers_mut().set(ContentType(Mime(TopLevel::Text, SubLevel::EventStream, vec![])));
+        response.headers_mut().set(CacheControl(vec![CacheDirective::NoCache]));
+
+        EventStream {
+            writer: response.into_chunked(),
+            last_sent: Instant::now(),
+        }
+    }
+
+    ///Send an event.
+    pub fn send(&mut self, event: Event) -> Result<(), Error> {
+        self.write(&event.to_string())
+    }
+
+    ///Send an event with a name and some data.
+    pub fn send_event<N: Into<String>, D: Into<String>>(&mut self, name: N, data: D) -> Result<(), Error> {
+        self.send(Event::new(data).name(name))
+    }
+
+    ///Send an unnamed event with some data.
+    pub fn send_data<D: Into<String>>(&mut self, data: D) -> Result<(), Error> {
+        self.send(Event::new(data))
+    }
+
+    ///Send a comment. Comments are ignored by the client, but they can be
+    ///used to keep the connection alive.
+    pub fn send_comment(&mut self, comment: &str) -> Result<(), Error> {
+        let mut message = String::new();
+        for line in comment.split("\r\n").flat_map(|line| line.split(|c| c == '\r' || c == '\n')) {
+            message.push(':');
+            message.push_str(line);
+            message.push('\n');
+        }
+        message.push('\n');
+
+        self.write(&message)
+    }
+
+    ///Send an empty comment, to prevent the connection from timing out.
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        self.write(":\n\n")
+    }
+
+    ///Send an empty comment if nothing has been sent during the last
+    ///`interval`.
+    pub fn keep_alive_if_idle(&mut self, interval: Duration) -> Result<(), Error> {
+        if self.idle_time() >= interval {
+            self.keep_alive()
+        } else {
+            Ok(())
+        }
+    }
+
+    ///The time since anything was sent.
+    pub fn idle_time(&self) -> Duration {
+        self.last_sent.elapsed()
+    }
+
+    ///Tell the client how long to wait before reconnecting, if the connection
+    ///is lost.
+    pub fn set_retry(&mut self, retry: Duration) -> Result<(), Error> {
+        let millis = retry.as_secs() * 1000 + u64::from(retry.subsec_nanos() / 1_000_000);
+        self.write(&format!("retry: {}\n\n", millis))
+    }
+
+    ///Get a reference to the underlying chunked response.
+    pub fn chunked(&mut self) -> &mut Chunked<'a, 'b> {
+        &mut self.writer
+    }
+
+    ///Finish writing the stream and collect eventual errors.
+    ///
+    ///This is optional and will happen silently when the stream drops out of
+    ///scope.
+    pub fn end(self) -> Result<(), Error> {
+        self.writer.end()
+    }
+
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        self.writer.try_send(message)?;
+        self.writer.flush()?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use header::Headers;
+    use super::{Event, last_event_id};
+
+    #[test]
+    fn format_event() {
+        let event = Event::new("a\r\nb\rc\n").name("na\nme").id("4\02");
+        assert_eq!(event.to_string(), "event: name\nid: 42\ndata: a\ndata: b\ndata: c\ndata: \n\n");
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn read_last_event_id() {
+        let mut headers = Headers::new();
+        assert_eq!(last_event_id(&headers), None);
+
+        headers.set_raw("Last-Event-ID", vec![b"17".to_vec()]);
+        assert_eq!(last_event_id(&headers), Some("17".into()));
+    }
+}
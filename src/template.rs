@@ -0,0 +1,348 @@
+//!Response body templating.
+//!
+//!A [`Template`][template] is a response type that renders a named template
+//!with some data, using a [`RenderEngine`][render_engine] that is stored in
+//!the server's global data, wrapped in a [`Renderer`][renderer]. The engine
+//!decides what the data should look like, so integrations with other
+//!template libraries can be implemented outside of rustful.
+//!
+//!The [`Placeholders`][placeholders] engine is a minimal engine that
+//!replaces `{name}` with values from a map:
+//!
+//!```no_run
+//!use std::collections::HashMap;
+//!use rustful::{Server, Context, Response};
+//!use rustful::template::{Template, Renderer, Placeholders};
+//!
+//!fn show_user(context: Context, response: Response) {
+//!    let mut data = HashMap::new();
+//!    data.insert("name".to_owned(), context.variables.get("name").unwrap_or_default().into_owned());
+//!
+//!    response.send(Template("user.html", data));
+//!}
+//!
+//!let engine = Placeholders::new().template("user.html", "<p>Hello, {name}!</p>");
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    global: Box::new(Renderer::<HashMap<String, String>>::new(engine)).into(),
+//!    ..Server::new(show_user as fn(Context, Response))
+//!}.run();
+//!```
+//!
+//![template]: struct.Template.html
+//![render_engine]: trait.RenderEngine.html
+//![renderer]: struct.Renderer.html
+//![placeholders]: struct.Placeholders.html
+
+use std::borrow::Cow;
+use std::collections::{HashMap, BTreeMap};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use StatusCode;
+use context::Parameters;
+use header::ContentType;
+use response::{Response, SendResponse, Error};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use file::Cache;
+pub use utils::push_escaped_html;
+
+///Something that renders templates with data of type `D`.
+pub trait RenderEngine<D>: Send + Sync + 'static {
+    ///Render the template called `name`.
+    fn render(&self, name: &str, data: &D) -> Result<String, RenderError>;
+}
+
+///A template rendering error.
+#[derive(Debug)]
+pub enum RenderError {
+    ///The template could not be found.
+    NotFound(String),
+
+    ///The data didn't contain a value that the template uses.
+    MissingValue(String),
+
+    ///The template could not be loaded.
+    Io(io::Error),
+
+    ///Any other error, such as a syntax error.
+    Other(Box<error::Error + Send + Sync>)
+}
+
+impl From<io::Error> for RenderError {
+    fn from(err: io::Error) -> RenderError {
+        RenderError::Io(err)
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::NotFound(ref name) => write!(f, "the template '{}' could not be found", name),
+            RenderError::MissingValue(ref name) => write!(f, "missing template value '{}'", name),
+            RenderError::Io(ref e) => write!(f, "io error: {}", e),
+            RenderError::Other(ref e) => e.fmt(f)
+        }
+    }
+}
+
+impl error::Error for RenderError {
+    fn description(&self) -> &str {
+        match *self {
+            RenderError::NotFound(_) => "the template could not be found",
+            RenderError::MissingValue(_) => "missing template value",
+            RenderError::Io(ref e) => e.description(),
+            RenderError::Other(ref e) => e.description()
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RenderError::Io(ref e) => Some(e),
+            RenderError::Other(ref e) => Some(&**e),
+            _ => None
+        }
+    }
+}
+
+///Makes a `RenderEngine` available to `Template`, through the global data.
+///
+///There can be one renderer per data type, which makes it possible to use
+///more than one engine at the same time.
+pub struct Renderer<D> {
+    engine: Box<RenderEngine<D>>,
+}
+
+impl<D: 'static> Renderer<D> {
+    ///Wrap a rendering engine.
+    pub fn new<E: RenderEngine<D>>(engine: E) -> Renderer<D> {
+        Renderer {
+            engine: Box::new(engine),
+        }
+    }
+
+    ///Render the template called `name`.
+    pub fn render(&self, name: &str, data: &D) -> Result<String, RenderError> {
+        self.engine.render(name, data)
+    }
+}
+
+///A template name and its data, to be rendered and sent as a response.
+///
+///The template is rendered by the `Renderer<D>` in the global data. The
+///`Content-Type` header is guessed from the template name, unless it's
+///already set, and defaults to `text/html`. The status code is set to `500
+///Internal Server Error` if the rendering fails, or if there is no
+///renderer.
+#[derive(Clone, Debug)]
+pub struct Template<'t, D>(pub &'t str, pub D);
+
+impl<'t, 'a, 'b, D: 'static> SendResponse<'a, 'b> for Template<'t, D> {
+    type Error = Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), Error> {
+        let Template(name, data) = self;
+
+        let result = match response.global().get::<Renderer<D>>() {
+            Some(renderer) => renderer.render(name, &data),
+            None => {
+                response.set_status(StatusCode::InternalServerError);
+                return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "no template renderer for the data type")));
+            }
+        };
+
+        match result {
+            Ok(body) => {
+                if !response.headers().has::<ContentType>() {
+                    response.headers_mut().set(ContentType(template_mime(name)));
+                }
+                response.try_send_data(body)
+            },
+            Err(e) => {
+                response.set_status(StatusCode::InternalServerError);
+                Err(Error::Io(io::Error::new(io::ErrorKind::Other, e)))
+            }
+        }
+    }
+}
+
+fn template_mime(name: &str) -> Mime {
+    let mime = Path::new(name)
+        .extension()
+        .and_then(|ext| ::file::ext_to_mime(&ext.to_string_lossy()))
+        .unwrap_or_else(|| Mime(TopLevel::Text, SubLevel::Html, vec![]));
+
+    match mime {
+        Mime(TopLevel::Text, sub, ref attrs) if attrs.is_empty() => Mime(TopLevel::Text, sub, vec![(Attr::Charset, Value::Utf8)]),
+        mime => mime
+    }
+}
+
+///Named values for the `Placeholders` engine.
+pub trait Values {
+    ///Get the value called `name`, if it exists.
+    fn value(&self, name: &str) -> Option<Cow<str>>;
+}
+
+impl<S: AsRef<str>> Values for HashMap<String, S> {
+    fn value(&self, name: &str) -> Option<Cow<str>> {
+        self.get(name).map(|value| value.as_ref().into())
+    }
+}
+
+impl<S: AsRef<str>> Values for BTreeMap<String, S> {
+    fn value(&self, name: &str) -> Option<Cow<str>> {
+        self.get(name).map(|value| value.as_ref().into())
+    }
+}
+
+impl Values for Parameters {
+    fn value(&self, name: &str) -> Option<Cow<str>> {
+        self.get(name)
+    }
+}
+
+///A minimal engine that replaces `{name}` with the value called `name`.
+///
+///A literal `{` is written as `{{`. The values are HTML escaped if the
+///template name ends with `.html` or `.htm`. Templates can be added
+///directly, or loaded from a directory each time they are rendered.
+#[derive(Clone, Debug, Default)]
+pub struct Placeholders {
+    templates: HashMap<String, String>,
+    directory: Option<PathBuf>,
+    cache: Option<Arc<Cache<String>>>,
+}
+
+impl Placeholders {
+    ///Create an engine without any templates.
+    pub fn new() -> Placeholders {
+        Placeholders::default()
+    }
+
+    ///Add a template called `name`.
+    pub fn template<N: Into<String>, T: Into<String>>(mut self, name: N, template: T) -> Placeholders {
+        self.templates.insert(name.into(), template.into());
+        self
+    }
+
+    ///Load templates that haven't been added with `template` from
+    ///`directory`. The template names are treated as relative paths, and
+    ///names that would escape the directory are not found.
+    pub fn directory<P: Into<PathBuf>>(mut self, directory: P) -> Placeholders {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    ///Keep the templates from the directory in `cache`, instead of loading
+    ///them each time they are rendered. They are still reloaded when they
+    ///are modified.
+    pub fn cache(mut self, cache: Arc<Cache<String>>) -> Placeholders {
+        self.cache = Some(cache);
+        self
+    }
+
+    fn load(&self, name: &str) -> Result<Cow<str>, RenderError> {
+        if let Some(template) = self.templates.get(name) {
+            return Ok(template.as_str().into());
+        }
+
+        let directory = match self.directory {
+            Some(ref directory) if ::file::check_path(name).is_ok() => directory,
+            _ => return Err(RenderError::NotFound(name.into()))
+        };
+
+        if let Some(ref cache) = self.cache {
+            return match cache.get(directory.join(name)) {
+                Ok(template) => Ok((*template).clone().into()),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(RenderError::NotFound(name.into())),
+                Err(e) => Err(RenderError::Io(e))
+            };
+        }
+
+        let mut file = match File::open(directory.join(name)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(RenderError::NotFound(name.into())),
+            Err(e) => return Err(RenderError::Io(e))
+        };
+
+        let mut template = String::new();
+        file.read_to_string(&mut template)?;
+        Ok(template.into())
+    }
+}
+
+impl<D: Values> RenderEngine<D> for Placeholders {
+    fn render(&self, name: &str, data: &D) -> Result<String, RenderError> {
+        let template = self.load(name)?;
+        let escape = name.ends_with(".html") || name.ends_with(".htm");
+        let mut output = String::with_capacity(template.len());
+        let mut rest = &*template;
+
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            if rest.starts_with('{') {
+                output.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => return Err(RenderError::Other("unclosed placeholder".into()))
+            };
+
+            let key = rest[..end].trim();
+            let value = data.value(key).ok_or_else(|| RenderError::MissingValue(key.into()))?;
+            if escape {
+                push_escaped_html(&mut output, &value);
+            } else {
+                output.push_str(&value);
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{RenderEngine, RenderError, Placeholders};
+
+    #[test]
+    fn placeholders() {
+        let engine = Placeholders::new()
+            .template("page.html", "<p>{{ {greeting}, { name }!</p>")
+            .template("page.txt", "{greeting}, {name}!");
+
+        let mut data = HashMap::new();
+        data.insert("greeting".to_owned(), "Hello");
+        data.insert("name".to_owned(), "<Alice>");
+
+        assert_eq!(engine.render("page.html", &data).unwrap(), "<p>{ Hello, &lt;Alice&gt;!</p>");
+        assert_eq!(engine.render("page.txt", &data).unwrap(), "Hello, <Alice>!");
+
+        data.remove("name");
+        match engine.render("page.txt", &data) {
+            Err(RenderError::MissingValue(ref name)) if name == "name" => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+
+        match engine.render("other.txt", &data) {
+            Err(RenderError::NotFound(ref name)) if name == "other.txt" => {},
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+}
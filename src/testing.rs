@@ -0,0 +1,852 @@
+//!Tools for testing handlers and routers.
+//!
+//!`Context::mock` and `Response::mock` are enough for testing the logic of a
+//!handler, but the mock response discards everything that is sent. A
+//![`Recorder`][recorder] runs a handler, or a whole router, with a response
+//!that writes to memory instead of a connection, and returns the
+//![`Recorded`][recorded] status, headers and body, as the client would see
+//!them. Response filters are applied, and chunked and raw responses are
+//!recorded too:
+//!
+//!```
+//!use rustful::{Context, Response, Method, StatusCode, DefaultRouter};
+//!use rustful::header::Headers;
+//!use rustful::server::Global;
+//!use rustful::testing::Recorder;
+//!
+//!fn say_hello(context: Context, response: Response) {
+//!    let name = context.variables.get("name").unwrap_or("stranger".into());
+//!    response.send(format!("Hello, {}!", name));
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("hello/:name").then().on_get(say_hello);
+//!
+//!let global = Global::default();
+//!let recorder = Recorder::new();
+//!
+//!let context = Context::mock(Method::Get, "/hello/Ferris", Headers::new(), &global);
+//!let recorded = recorder.run(&router, context);
+//!assert_eq!(recorded.status, StatusCode::Ok);
+//!assert_eq!(recorded.body_utf8(), Some("Hello, Ferris!"));
+//!
+//!let context = Context::mock(Method::Get, "/goodbye", Headers::new(), &global);
+//!assert_eq!(recorder.run(&router, context).status, StatusCode::NotFound);
+//!```
+//!
+//!A [`TestClient`][test_client] goes one step further and sends requests
+//!through a whole `Server`, with its context filters, response filters and
+//!other settings, without binding to a port:
+//!
+//!```
+//!use std::io::Read;
+//!use rustful::{Server, Context, Response, StatusCode};
+//!use rustful::header::Headers;
+//!use rustful::testing::TestClient;
+//!
+//!fn echo(mut context: Context, response: Response) {
+//!    let mut body = String::new();
+//!    let _ = context.body.read_to_string(&mut body);
+//!    response.send(body);
+//!}
+//!
+//!let client = TestClient::new(Server::new(echo));
+//!
+//!let recorded = client.post("/", Headers::new(), b"Hello!").unwrap();
+//!assert_eq!(recorded.status, StatusCode::Ok);
+//!assert_eq!(recorded.body_utf8(), Some("Hello!"));
+//!```
+//!
+//!The output of a handler can be tested against a stored file with
+//![`snapshot`][snapshot]. The file is created the first time, and it's
+//!replaced instead of compared if the `RUSTFUL_UPDATE_SNAPSHOTS`
+//!environment variable is set:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, Method};
+//!use rustful::header::Headers;
+//!use rustful::testing::{self, TestClient};
+//!
+//!fn hello(_context: Context, response: Response) {
+//!    response.send("Hello!");
+//!}
+//!
+//!let client = TestClient::new(Server::new(hello));
+//!testing::snapshot(&client, Method::Get, "/", Headers::new(), b"", "tests/snapshots/hello.txt").unwrap();
+//!```
+//!
+//!A `TestClient` can also [export][export] the responses from an app to a
+//!directory, to deploy it as a static site. The paths can be taken from
+//![`TreeRouter::routes`][routes], using [`static_paths`][static_paths], and
+//![`TreeRouter::url_for`][url_for] for routes with variables:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter};
+//!use rustful::testing::{self, TestClient};
+//!
+//!fn page(context: Context, response: Response) {
+//!    response.send(format!("This is {}", context.uri_path));
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().many(|node| {
+//!    node.then().on_get(page);
+//!    node.path("about").then().on_get(page);
+//!    node.path("posts/:id").name("post").then().on_get(page);
+//!});
+//!
+//!let mut paths = testing::static_paths(&router.routes());
+//!paths.extend(router.url_for("post", &[("id", "1")]));
+//!
+//!let client = TestClient::new(Server::new(router));
+//!client.export(&paths, "site").unwrap();
+//!```
+//!
+//![recorder]: struct.Recorder.html
+//![recorded]: struct.Recorded.html
+//![test_client]: struct.TestClient.html
+//![snapshot]: fn.snapshot.html
+//![export]: struct.TestClient.html#method.export
+//![static_paths]: fn.static_paths.html
+//![routes]: ../handler/struct.TreeRouter.html#method.routes
+//![url_for]: ../handler/struct.TreeRouter.html#method.url_for
+
+use std::io::{self, Read, Write, Cursor};
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::str::{self, FromStr};
+use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::error;
+use std::fmt;
+use std::env;
+
+use hyper;
+use hyper::buffer::BufReader;
+use hyper::server::Handler as HyperHandler;
+use hyper::server::request::Request as HyperRequest;
+
+use url::percent_encoding::{percent_encode, percent_decode, PATH_SEGMENT_ENCODE_SET};
+
+use {Method, StatusCode, HttpResult, HttpError, Server};
+use header::{Headers, ContentLength, Host};
+use context::Context;
+use context::hypermedia::{Link, SegmentType};
+use filter::ResponseFilter;
+use handler::{HandleRequest, Environment};
+use net::NetworkStream;
+use response::{Response, HeaderPolicy};
+use server::{Global, ErrorPages, ServerInstance};
+
+///Runs handlers and records their responses.
+///
+///The responses are written the same way as by the server, but the
+///server's default headers, such as `Date` and `Content-Type`, are not
+///added.
+pub struct Recorder {
+    ///The response filter stack.
+    pub response_filters: Vec<Box<ResponseFilter>>,
+
+    ///Bodies for error responses that would otherwise be empty.
+    pub error_pages: ErrorPages,
+}
+
+impl Recorder {
+    ///Create a recorder without filters or error pages.
+    pub fn new() -> Recorder {
+        Recorder {
+            response_filters: vec![],
+            error_pages: ErrorPages::new(),
+        }
+    }
+
+    ///Let `handler` handle `context`, and record the response. Unhandled
+    ///requests are `404 Not Found`, just like in the server.
+    pub fn run<H: HandleRequest>(&self, handler: &H, context: Context) -> Recorded {
+        let mut output = vec![];
+
+        {
+            let mut headers = Headers::new();
+            let path = context.uri_path.clone();
+            let response = self.response(&mut output, &mut headers, context.global);
+
+            if let Some(path) = path.as_path() {
+                let result = handler.handle_request(Environment {
+                    context: context,
+                    response: response,
+                    route_state: (&path[..]).into(),
+                });
+
+                if let Err(mut environment) = result {
+                    if environment.response.status() == StatusCode::Ok {
+                        environment.response.set_status(StatusCode::NotFound);
+                    }
+                }
+            }
+        }
+
+        Recorded::parse(&output)
+    }
+
+    ///Let `respond` write a response, and record it.
+    ///
+    ///```
+    ///use rustful::StatusCode;
+    ///use rustful::server::Global;
+    ///use rustful::testing::Recorder;
+    ///
+    ///let global = Global::default();
+    ///let recorded = Recorder::new().respond(&global, |response| {
+    ///    let mut chunked = response.into_chunked();
+    ///    chunked.send("Hello, ");
+    ///    chunked.send("world!");
+    ///});
+    ///
+    ///assert_eq!(recorded.status, StatusCode::Ok);
+    ///assert_eq!(recorded.body, b"Hello, world!");
+    ///```
+    pub fn respond<F: FnOnce(Response)>(&self, global: &Global, respond: F) -> Recorded {
+        let mut output = vec![];
+
+        {
+            let mut headers = Headers::new();
+            respond(self.response(&mut output, &mut headers, global));
+        }
+
+        Recorded::parse(&output)
+    }
+
+    fn response<'a, 'b>(&'b self, output: &'a mut Vec<u8>, headers: &'a mut Headers, global: &'b Global) -> Response<'a, 'b> {
+        Response::new(
+            hyper::server::response::Response::new(output, headers),
+            &self.response_filters,
+            global,
+            &self.error_pages,
+            None,
+            false,
+            HeaderPolicy::default()
+        )
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Recorder {
+        Recorder::new()
+    }
+}
+
+///A recorded response.
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    ///The status code.
+    pub status: StatusCode,
+
+    ///The headers, as they were sent.
+    pub headers: Headers,
+
+    ///The body, without any chunked transfer encoding.
+    pub body: Vec<u8>,
+}
+
+impl Recorded {
+    ///Get the body as a string, if it's valid UTF-8.
+    pub fn body_utf8(&self) -> Option<&str> {
+        str::from_utf8(&self.body).ok()
+    }
+
+    //Write the response in a stable form, with `Date` and `Server`
+    //replaced by `*` and the headers sorted by name.
+    fn snapshot(&self) -> Vec<u8> {
+        let mut headers: Vec<_> = self.headers.iter().map(|header| {
+            let name = header.name().to_lowercase();
+            let value = match &*name {
+                "date" | "server" => "*".into(),
+                _ => header.value_string()
+            };
+            (name, value)
+        }).collect();
+        headers.sort();
+
+        let mut snapshot = format!("{}\n", self.status).into_bytes();
+        for (name, value) in headers {
+            snapshot.extend_from_slice(format!("{}: {}\n", name, value).as_bytes());
+        }
+        snapshot.push(b'\n');
+        snapshot.extend_from_slice(&self.body);
+        snapshot
+    }
+
+    fn parse(output: &[u8]) -> Recorded {
+        let head_end = output.windows(4).position(|window| window == b"\r\n\r\n").unwrap_or(output.len());
+        let head = String::from_utf8_lossy(&output[..head_end]);
+        let body = output.get(head_end + 4..).unwrap_or(&[]);
+
+        let mut lines = head.split("\r\n");
+        let status = lines.next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|code| u16::from_str(code).ok())
+            .map_or(StatusCode::Ok, StatusCode::from_u16);
+
+        let mut headers = Headers::new();
+        for line in lines {
+            if let Some(index) = line.find(':') {
+                headers.append_raw(line[..index].to_owned(), line[index + 1..].trim().as_bytes().to_owned());
+            }
+        }
+
+        let chunked = headers.get_raw("transfer-encoding")
+            .and_then(|values| values.last())
+            .map_or(false, |value| value.ends_with(b"chunked"));
+
+        Recorded {
+            status: status,
+            headers: headers,
+            body: if chunked { dechunk(body) } else { body.to_owned() },
+        }
+    }
+}
+
+///Sends requests through a whole server, in memory.
+///
+///The requests are parsed from their HTTP form, and handled just like they
+///would be if they came from a connection, except that each request is
+///sent by itself, from `127.0.0.1`. The `host` and thread settings are
+///ignored.
+pub struct TestClient<R> {
+    instance: ServerInstance<R>,
+}
+
+impl<R: HandleRequest + 'static> TestClient<R> {
+    ///Send requests to `server`.
+    pub fn new(server: Server<R>) -> TestClient<R> {
+        TestClient {
+            instance: server.build(),
+        }
+    }
+
+    ///Send a `GET` request for `path`.
+    pub fn get(&self, path: &str) -> HttpResult<Recorded> {
+        self.request(Method::Get, path, Headers::new(), &[])
+    }
+
+    ///Send a `POST` request for `path`, with `body`.
+    pub fn post(&self, path: &str, headers: Headers, body: &[u8]) -> HttpResult<Recorded> {
+        self.request(Method::Post, path, headers, body)
+    }
+
+    ///Send a request and record the response. `path` is the request
+    ///target, including any query string. The `Host` header is set to
+    ///`localhost` and `Content-Length` is set to the length of the body,
+    ///unless they are already set.
+    ///
+    ///An error is returned if the request can't be parsed.
+    pub fn request(&self, method: Method, path: &str, mut headers: Headers, body: &[u8]) -> HttpResult<Recorded> {
+        if !headers.has::<Host>() {
+            headers.set(Host {
+                hostname: "localhost".into(),
+                port: None,
+            });
+        }
+
+        if !body.is_empty() && !headers.has::<ContentLength>() {
+            headers.set(ContentLength(body.len() as u64));
+        }
+
+        let mut input = format!("{} {} HTTP/1.1\r\n{}\r\n", method, path, headers).into_bytes();
+        input.extend_from_slice(body);
+
+        let mut stream = MemoryStream(Cursor::new(input));
+        let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+        let request = HyperRequest::new(&mut reader, MemoryStream::peer())?;
+
+        let mut output = vec![];
+
+        {
+            let mut headers = Headers::new();
+            let response = hyper::server::response::Response::new(&mut output, &mut headers);
+
+            self.instance.on_connection_start();
+            self.instance.handle(request, response);
+            self.instance.on_connection_end();
+        }
+
+        Ok(Recorded::parse(&output))
+    }
+
+    ///Send a `GET` request for each of `paths` and write the response
+    ///bodies to files in `directory`, which makes it possible to deploy the
+    ///app as a static site.
+    ///
+    ///A path where the last segment has a file extension, such as
+    ///`/style.css`, is written to a file with that name. Any other path is
+    ///treated as a directory, so `/about` is written to `about/index.html`.
+    ///Every response has to be successful, and the list of written files is
+    ///returned.
+    pub fn export<S: AsRef<str>, P: AsRef<Path>>(&self, paths: &[S], directory: P) -> Result<Vec<PathBuf>, ExportError> {
+        let mut files = vec![];
+
+        for path in paths {
+            let path = path.as_ref();
+            let recorded = self.get(path)?;
+            if !recorded.status.is_success() {
+                return Err(ExportError::Status(path.to_owned(), recorded.status));
+            }
+
+            let file = directory.as_ref().join(export_file(path));
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(&file)?.write_all(&recorded.body)?;
+            files.push(file);
+        }
+
+        Ok(files)
+    }
+}
+
+///Get the paths of the `GET` routes in `routes` that don't have any
+///variables. This is meant to be used with `TreeRouter::routes` and
+///`TestClient::export`.
+pub fn static_paths(routes: &[Link]) -> Vec<String> {
+    let mut paths = vec![];
+
+    for route in routes {
+        if route.method != Some(Method::Get) || route.path.iter().any(|segment| segment.ty != SegmentType::Static) {
+            continue;
+        }
+
+        let mut path = String::new();
+        for segment in &route.path {
+            path.push('/');
+            path.extend(percent_encode(segment.label.as_ref(), PATH_SEGMENT_ENCODE_SET));
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    paths
+}
+
+//Where the response for `path` is stored, relative to the export
+//directory. The query is ignored, and so are segments that could lead
+//outside the directory.
+fn export_file(path: &str) -> PathBuf {
+    let path = path.split('?').next().unwrap_or("");
+    let mut file = PathBuf::new();
+    let mut is_file = false;
+
+    for segment in path.split('/') {
+        let segment = percent_decode(segment.as_bytes()).decode_utf8_lossy();
+        is_file = segment.contains('.');
+
+        if segment != "" && segment != "." && segment != ".." && !segment.contains(|c| c == '/' || c == '\\') {
+            file.push(&*segment);
+        }
+    }
+
+    if !is_file {
+        file.push("index.html");
+    }
+
+    file
+}
+
+///An error from `TestClient::export`.
+#[derive(Debug)]
+pub enum ExportError {
+    ///A request could not be parsed.
+    Request(HttpError),
+
+    ///The response for a path was not successful.
+    Status(String, StatusCode),
+
+    ///A file could not be written.
+    Io(io::Error),
+}
+
+impl From<HttpError> for ExportError {
+    fn from(err: HttpError) -> ExportError {
+        ExportError::Request(err)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> ExportError {
+        ExportError::Io(err)
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExportError::Request(ref e) => write!(f, "request error: {}", e),
+            ExportError::Status(ref path, ref status) => write!(f, "'{}' responded with {}", path, status),
+            ExportError::Io(ref e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl error::Error for ExportError {
+    fn description(&self) -> &str {
+        match *self {
+            ExportError::Request(ref e) => e.description(),
+            ExportError::Status(..) => "unsuccessful response",
+            ExportError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ExportError::Request(ref e) => Some(e),
+            ExportError::Status(..) => None,
+            ExportError::Io(ref e) => Some(e),
+        }
+    }
+}
+
+///The environment variable that makes `snapshot` replace the stored
+///snapshots instead of comparing them.
+pub const UPDATE_SNAPSHOTS: &'static str = "RUSTFUL_UPDATE_SNAPSHOTS";
+
+///Send a request through `client` and compare the response with the
+///snapshot in `file`.
+///
+///The snapshot contains the status, the headers and the body. The values of
+///`Date` and `Server` are replaced by `*`, since they may change between
+///runs. `file` is created if it doesn't exist, and it's replaced if the
+///[`RUSTFUL_UPDATE_SNAPSHOTS`][update] environment variable is set to
+///anything but `0`. The recorded response is returned if it matches, for
+///further checks.
+///
+///[update]: constant.UPDATE_SNAPSHOTS.html
+pub fn snapshot<R, P>(client: &TestClient<R>, method: Method, path: &str, headers: Headers, body: &[u8], file: P) -> Result<Recorded, SnapshotError> where
+    R: HandleRequest + 'static,
+    P: AsRef<Path>
+{
+    let update = env::var_os(UPDATE_SNAPSHOTS).map_or(false, |value| !value.is_empty() && value != "0");
+    let recorded = client.request(method, path, headers, body)?;
+    compare_snapshot(&recorded, file.as_ref(), update)?;
+    Ok(recorded)
+}
+
+fn compare_snapshot(recorded: &Recorded, file: &Path, update: bool) -> Result<(), SnapshotError> {
+    let actual = recorded.snapshot();
+
+    if !update {
+        match File::open(file) {
+            Ok(mut stored) => {
+                let mut expected = vec![];
+                stored.read_to_end(&mut expected)?;
+
+                return if expected == actual {
+                    Ok(())
+                } else {
+                    Err(SnapshotError::Mismatch {
+                        file: file.to_owned(),
+                        expected: expected,
+                        actual: actual,
+                    })
+                };
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(file)?.write_all(&actual)?;
+    Ok(())
+}
+
+///An error from `snapshot`.
+#[derive(Debug)]
+pub enum SnapshotError {
+    ///The request could not be parsed.
+    Request(HttpError),
+
+    ///The snapshot file could not be read or written.
+    Io(io::Error),
+
+    ///The response didn't match the stored snapshot.
+    Mismatch {
+        ///The snapshot file.
+        file: PathBuf,
+
+        ///The stored snapshot.
+        expected: Vec<u8>,
+
+        ///The response, in the same form as the stored snapshot.
+        actual: Vec<u8>,
+    }
+}
+
+impl From<HttpError> for SnapshotError {
+    fn from(err: HttpError) -> SnapshotError {
+        SnapshotError::Request(err)
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Request(ref e) => write!(f, "request error: {}", e),
+            SnapshotError::Io(ref e) => write!(f, "io error: {}", e),
+            SnapshotError::Mismatch { ref file, ref expected, ref actual } => write!(
+                f,
+                "the response doesn't match the snapshot in '{}'\n--- expected\n{}\n--- actual\n{}",
+                file.display(),
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(actual)
+            )
+        }
+    }
+}
+
+impl error::Error for SnapshotError {
+    fn description(&self) -> &str {
+        match *self {
+            SnapshotError::Request(ref e) => e.description(),
+            SnapshotError::Io(ref e) => e.description(),
+            SnapshotError::Mismatch { .. } => "the response doesn't match the snapshot"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SnapshotError::Request(ref e) => Some(e),
+            SnapshotError::Io(ref e) => Some(e),
+            SnapshotError::Mismatch { .. } => None
+        }
+    }
+}
+
+//A connection that only has a request to read.
+struct MemoryStream(Cursor<Vec<u8>>);
+
+impl MemoryStream {
+    fn peer() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0))
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MemoryStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(MemoryStream::peer())
+    }
+
+    fn set_read_timeout(&self, _duration: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _duration: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn dechunk(mut input: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+
+    while let Some(line_end) = input.windows(2).position(|window| window == b"\r\n") {
+        let size = str::from_utf8(&input[..line_end]).ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).ok())
+            .unwrap_or(0);
+
+        input = &input[line_end + 2..];
+        if size == 0 || size > input.len() {
+            break;
+        }
+
+        body.extend_from_slice(&input[..size]);
+        input = input.get(size + 2..).unwrap_or(&[]);
+    }
+
+    body
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use hyper::method::Method;
+
+    use {Context, Response, StatusCode, Server};
+    use header::{Headers, ContentLength};
+    use rewrite::MethodOverride;
+    use server::Global;
+    use handler::TreeRouter;
+    use super::{Recorder, TestClient, SnapshotError, ExportError, compare_snapshot, static_paths};
+
+    fn sized(_context: Context, response: Response) {
+        response.send("sized");
+    }
+
+    fn chunked(_context: Context, response: Response) {
+        let mut chunked = response.into_chunked();
+        chunked.send("chunked ");
+        chunked.send("response");
+    }
+
+    fn raw(_context: Context, response: Response) {
+        use std::io::Write;
+        let mut raw = unsafe { response.into_raw(3) };
+        let _ = raw.write_all(b"raw");
+    }
+
+    #[test]
+    fn record_responses() {
+        let mut router = TreeRouter::<Option<fn(Context, Response)>>::new();
+        router.build().many(|node| {
+            node.on_path("sized", sized as fn(Context, Response));
+            node.on_path("chunked", chunked as fn(Context, Response));
+            node.on_path("raw", raw as fn(Context, Response));
+        });
+
+        let global = Global::default();
+        let recorder = Recorder::new();
+        let run = |path: &str| recorder.run(&router, Context::mock(Method::Get, path, Headers::new(), &global));
+
+        let recorded = run("sized");
+        assert_eq!(recorded.status, StatusCode::Ok);
+        assert_eq!(recorded.headers.get_raw("content-length"), Some(&[b"5".to_vec()][..]));
+        assert_eq!(recorded.body, b"sized");
+
+        let recorded = run("chunked");
+        assert_eq!(recorded.headers.get_raw("transfer-encoding"), Some(&[b"chunked".to_vec()][..]));
+        assert_eq!(recorded.body_utf8(), Some("chunked response"));
+
+        let recorded = run("raw");
+        assert_eq!(recorded.headers.get::<ContentLength>(), Some(&ContentLength(3)));
+        assert_eq!(recorded.body, b"raw");
+
+        let recorded = run("missing");
+        assert_eq!(recorded.status, StatusCode::NotFound);
+        assert!(recorded.body.is_empty());
+    }
+
+    fn method(mut context: Context, response: Response) {
+        let mut body = String::new();
+        let _ = context.body.read_to_string(&mut body);
+        response.send(format!("{} {}", context.method, body));
+    }
+
+    #[test]
+    fn test_client() {
+        let client = TestClient::new(Server {
+            context_filters: vec![Box::new(MethodOverride::new())],
+            ..Server::new(method as fn(Context, Response))
+        });
+
+        let recorded = client.get("/").unwrap();
+        assert_eq!(recorded.status, StatusCode::Ok);
+        assert_eq!(recorded.headers.get_raw("server"), Some(&[b"rustful".to_vec()][..]));
+        assert_eq!(recorded.body_utf8(), Some("GET "));
+
+        let mut headers = Headers::new();
+        headers.set_raw("X-HTTP-Method-Override", vec![b"DELETE".to_vec()]);
+        let recorded = client.post("/", headers, b"body").unwrap();
+        assert_eq!(recorded.body_utf8(), Some("DELETE body"));
+    }
+
+    #[test]
+    fn compare_snapshots() {
+        use std::env;
+        use std::fs;
+
+        let file = env::temp_dir().join(format!("rustful-snapshot-{}", ::utils::random_u64())).join("hello.txt");
+        let client = TestClient::new(Server::new(method as fn(Context, Response)));
+
+        let recorded = client.post("/", Headers::new(), b"hello").unwrap();
+        compare_snapshot(&recorded, &file, false).unwrap();
+
+        let stored = String::from_utf8(fs::read(&file).unwrap()).unwrap();
+        assert!(stored.starts_with("200 OK\n"));
+        assert!(stored.contains("\ndate: *\n"));
+        assert!(stored.contains("\nserver: *\n"));
+        assert!(stored.ends_with("\n\nPOST hello"));
+
+        let recorded = client.post("/", Headers::new(), b"hello").unwrap();
+        compare_snapshot(&recorded, &file, false).unwrap();
+
+        let changed = client.post("/", Headers::new(), b"goodbye").unwrap();
+        match compare_snapshot(&changed, &file, false) {
+            Err(SnapshotError::Mismatch { actual, .. }) => assert!(actual.ends_with(b"POST goodbye")),
+            other => panic!("expected a mismatch, but got {:?}", other)
+        }
+
+        compare_snapshot(&changed, &file, true).unwrap();
+        compare_snapshot(&changed, &file, false).unwrap();
+
+        let _ = fs::remove_dir_all(file.parent().unwrap());
+    }
+
+    fn page(context: Context, response: Response) {
+        response.send(format!("page {}", context.uri_path));
+    }
+
+    #[test]
+    fn export_site() {
+        use std::env;
+        use std::fs;
+        use DefaultRouter;
+
+        let mut router = DefaultRouter::<fn(Context, Response)>::new();
+        router.build().many(|node| {
+            node.then().on_get(page);
+            node.path("about").then().on_get(page);
+            node.path("style.css").then().on_get(page);
+            node.path("posts/:id").name("post").then().on_get(page);
+            node.path("upload").then().on_post(page);
+        });
+
+        let mut paths = static_paths(&router.routes());
+        assert_eq!(paths, vec!["/".to_owned(), "/about".to_owned(), "/style.css".to_owned()]);
+        paths.extend(router.url_for("post", &[("id", "1")]));
+
+        let directory = env::temp_dir().join(format!("rustful-export-{}", ::utils::random_u64()));
+        let client = TestClient::new(Server::new(router));
+        let files = client.export(&paths, &directory).unwrap();
+
+        assert_eq!(files.len(), 4);
+        assert_eq!(fs::read(directory.join("index.html")).unwrap(), b"page /");
+        assert_eq!(fs::read(directory.join("about/index.html")).unwrap(), b"page /about");
+        assert_eq!(fs::read(directory.join("style.css")).unwrap(), b"page /style.css");
+        assert_eq!(fs::read(directory.join("posts/1/index.html")).unwrap(), b"page /posts/1");
+
+        match client.export(&["/upload"], &directory) {
+            Err(ExportError::Status(_, status)) => assert_eq!(status, StatusCode::MethodNotAllowed),
+            other => panic!("expected an error status, but got {:?}", other)
+        }
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,202 @@
+//!Response timing headers, for debugging.
+//!
+//!A [`Timing`][timing] filter measures how long it takes to handle each
+//!request, and reports it to the client in an `X-Runtime` header, in
+//!seconds. It can optionally add a `Server-Timing` header as well, with the
+//!total time and any [`Timings`][timings] that the handlers have recorded.
+//!It has to be added as both a context filter and a response filter, and
+//!it can be turned on and off while the server is running:
+//!
+//!```no_run
+//!use std::time::Instant;
+//!use rustful::{Server, Context, Response};
+//!use rustful::timing::{Timing, Timings};
+//!
+//!fn my_handler(_context: Context, mut response: Response) {
+//!    let start = Instant::now();
+//!    //Query the database...
+//!
+//!    if let Some(timings) = response.filter_storage_mut().get_mut::<Timings>() {
+//!        timings.record("db", start.elapsed());
+//!    }
+//!
+//!    response.send("Hello!");
+//!}
+//!
+//!let timing = Timing::new().server_timing(true);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    context_filters: vec![Box::new(timing.clone())],
+//!    response_filters: vec![Box::new(timing.clone())],
+//!    ..Server::new(my_handler)
+//!}.run();
+//!
+//!//Stop sending the headers.
+//!timing.set_enabled(false);
+//!```
+//!
+//!The time is measured from when the context filter runs, so it should be
+//!the first context filter, until the response headers are written.
+//!
+//![timing]: struct.Timing.html
+//![timings]: struct.Timings.html
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use StatusCode;
+use header::Headers;
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+use response::Data;
+use sampling::Sampled;
+
+///A filter that adds `X-Runtime` and, optionally, `Server-Timing` headers.
+///
+///Clones share the same on/off switch, so a clone can be kept to control
+///the filters after the server has started. It's enabled by default.
+#[derive(Clone)]
+pub struct Timing {
+    enabled: Arc<AtomicBool>,
+    server_timing: bool,
+    sampled_only: bool,
+}
+
+impl Timing {
+    ///Create an enabled timing filter that only adds `X-Runtime`.
+    pub fn new() -> Timing {
+        Timing {
+            enabled: Arc::new(AtomicBool::new(true)),
+            server_timing: false,
+            sampled_only: false,
+        }
+    }
+
+    ///Add a `Server-Timing` header, with the total time and the recorded
+    ///`Timings`, in milliseconds.
+    pub fn server_timing(mut self, enabled: bool) -> Timing {
+        self.server_timing = enabled;
+        self
+    }
+
+    ///Only time requests that have been picked by a `Sampler`. The sampler
+    ///has to run before the `Timing` context filter.
+    pub fn sampled_only(mut self, sampled_only: bool) -> Timing {
+        self.sampled_only = sampled_only;
+        self
+    }
+
+    ///Turn the headers on or off, for this filter and all of its clones.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    ///Check if the headers are turned on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing::new()
+    }
+}
+
+impl ContextFilter for Timing {
+    fn modify(&self, ctx: FilterContext, context: &mut Context) -> ContextAction {
+        if self.is_enabled() && (!self.sampled_only || Sampled::check(&context.extensions)) {
+            ctx.storage.insert(Timings {
+                start: Instant::now(),
+                metrics: vec![],
+            });
+        }
+
+        ContextAction::next()
+    }
+}
+
+impl ResponseFilter for Timing {
+    fn begin(&self, ctx: FilterContext, status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        if let Some(timings) = ctx.storage.remove::<Timings>() {
+            if self.is_enabled() {
+                let total = timings.start.elapsed();
+                headers.set_raw("X-Runtime", vec![format!("{:.6}", seconds(total)).into_bytes()]);
+
+                if self.server_timing {
+                    headers.set_raw("Server-Timing", vec![timings.server_timing(total).into_bytes()]);
+                }
+            }
+        }
+
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, _ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, _ctx: FilterContext) -> ResponseAction {
+        ResponseAction::next(None::<Data>)
+    }
+}
+
+///Measurements that are reported in the `Server-Timing` header.
+///
+///It's stored in the filter storage by the `Timing` filter, and handlers
+///can add their own measurements to it before the response is sent.
+#[derive(Clone, Debug)]
+pub struct Timings {
+    start: Instant,
+    metrics: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    ///When the request started to be handled.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    ///Record that `name` took `duration`. The name should be a valid
+    ///header token, such as `db` or `cache-lookup`.
+    pub fn record<N: Into<String>>(&mut self, name: N, duration: Duration) {
+        self.metrics.push((name.into(), duration));
+    }
+
+    fn server_timing(&self, total: Duration) -> String {
+        let mut header = String::new();
+
+        for &(ref name, duration) in &self.metrics {
+            let _ = write!(header, "{};dur={:.3}, ", name, seconds(duration) * 1000.0);
+        }
+
+        let _ = write!(header, "total;dur={:.3}", seconds(total) * 1000.0);
+        header
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use super::Timings;
+
+    #[test]
+    fn server_timing_header() {
+        let mut timings = Timings {
+            start: Instant::now(),
+            metrics: vec![],
+        };
+
+        timings.record("db", Duration::from_millis(12));
+        timings.record("render", Duration::new(1, 500_000));
+
+        assert_eq!(timings.server_timing(Duration::from_millis(1020)), "db;dur=12.000, render;dur=1000.500, total;dur=1020.000");
+    }
+}
@@ -0,0 +1,658 @@
+//!Distributed tracing context propagation and request tracing hooks.
+//!
+//!Incoming trace information can be extracted from either the W3C
+//!`traceparent` and `tracestate` headers, or the B3 (`X-B3-*`) headers, using
+//!a [`TraceFilter`][trace_filter]. The extracted [`TraceContext`][trace_context]
+//!is stored in `context.extensions`, where handlers can pick it up and
+//!propagate it to outbound requests:
+//!
+//!```
+//!use rustful::{Context, Response};
+//!use rustful::header::Headers;
+//!use rustful::trace::{TraceContext, Format};
+//!
+//!fn my_handler(context: Context, response: Response) {
+//!    //Headers for a request to some other service
+//!    let mut outbound_headers = Headers::new();
+//!
+//!    if let Some(trace) = context.extensions.get::<TraceContext>() {
+//!        trace.child().inject(&mut outbound_headers, Format::W3c);
+//!    }
+//!
+//!    //...
+//!    # response.send("");
+//!}
+//!```
+//!
+//!
+//!Each request can also be followed through the server by a
+//![`Tracer`][tracer], which is added to `Server.tracers`. It starts a
+//![`Span`][span] for each request, which is told when the request has been
+//!routed, when the handler starts and ends, and when the response has been
+//!written. This makes it possible to connect rustful to a tracing library,
+//!without depending on any particular one:
+//!
+//!```no_run
+//!use std::time::Instant;
+//!use rustful::{Server, Context, Response, StatusCode};
+//!use rustful::trace::{Tracer, Span};
+//!
+//!struct LogTracer;
+//!
+//!impl Tracer for LogTracer {
+//!    fn start(&self, context: &Context) -> Box<Span> {
+//!        Box::new(LogSpan {
+//!            name: format!("{} {}", context.method, context.uri_path),
+//!            start: Instant::now(),
+//!        })
+//!    }
+//!}
+//!
+//!struct LogSpan {
+//!    name: String,
+//!    start: Instant,
+//!}
+//!
+//!impl Span for LogSpan {
+//!    fn routed(&mut self, route: &str) {
+//!        self.name = route.to_owned();
+//!    }
+//!
+//!    fn response_flushed(&mut self, status: Option<StatusCode>, sent_bytes: usize) {
+//!        println!("{}: {:?}, {} bytes in {:?}", self.name, status, sent_bytes, self.start.elapsed());
+//!    }
+//!}
+//!
+//!fn my_handler(_context: Context, response: Response) {
+//!    response.send("Hello!");
+//!}
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    tracers: vec![Box::new(LogTracer)],
+//!    ..Server::new(my_handler)
+//!}.run();
+//!```
+//!
+//![trace_filter]: struct.TraceFilter.html
+//![trace_context]: struct.TraceContext.html
+//![tracer]: trait.Tracer.html
+//![span]: trait.Span.html
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::from_utf8;
+
+use hyper;
+use header::{Header, HeaderFormat, Headers};
+
+use StatusCode;
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction};
+use response::{SentStatus, SentBytes};
+use utils::random_u64;
+
+///A trace identifier, shared by every span in a trace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TraceId(pub [u8; 16]);
+
+impl TraceId {
+    ///Generate a new, random, trace identifier.
+    pub fn generate() -> TraceId {
+        let mut id = [0; 16];
+        write_u64(&mut id[..8], random_u64());
+        write_u64(&mut id[8..], random_u64());
+        TraceId(id)
+    }
+
+    ///Parse a trace identifier from 32 hexadecimal digits. B3 style 16 digit
+    ///identifiers are accepted as well, and will be padded with zeros.
+    pub fn parse(hex: &str) -> Option<TraceId> {
+        let mut id = [0; 16];
+        let valid = match hex.len() {
+            32 => parse_hex(hex, &mut id),
+            16 => parse_hex(hex, &mut id[8..]),
+            _ => false
+        };
+
+        if valid && id != [0; 16] {
+            Some(TraceId(id))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(&self.0, f)
+    }
+}
+
+///A span identifier, unique for each unit of work in a trace.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SpanId(pub [u8; 8]);
+
+impl SpanId {
+    ///Generate a new, random, span identifier.
+    pub fn generate() -> SpanId {
+        let mut id = [0; 8];
+        write_u64(&mut id, random_u64());
+        SpanId(id)
+    }
+
+    ///Parse a span identifier from 16 hexadecimal digits.
+    pub fn parse(hex: &str) -> Option<SpanId> {
+        let mut id = [0; 8];
+        if hex.len() == 16 && parse_hex(hex, &mut id) && id != [0; 8] {
+            Some(SpanId(id))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_hex(&self.0, f)
+    }
+}
+
+///The header format used when propagating trace information.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    ///W3C Trace Context (`traceparent` and `tracestate`).
+    W3c,
+
+    ///Zipkin B3 (`X-B3-TraceId`, `X-B3-SpanId` and `X-B3-Sampled`).
+    B3,
+}
+
+///The trace information for the current request.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceContext {
+    ///The identifier of the whole trace.
+    pub trace_id: TraceId,
+
+    ///The identifier of the span that made the request.
+    pub span_id: SpanId,
+
+    ///Should this trace be recorded?
+    pub sampled: bool,
+
+    ///Vendor specific trace state (`tracestate`), if any.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    ///Start a new, sampled, trace.
+    pub fn new() -> TraceContext {
+        TraceContext {
+            trace_id: TraceId::generate(),
+            span_id: SpanId::generate(),
+            sampled: true,
+            state: None,
+        }
+    }
+
+    ///Try to extract trace information from request headers.
+    pub fn extract(headers: &Headers, format: Format) -> Option<TraceContext> {
+        match format {
+            Format::W3c => headers.get::<TraceParent>().map(|parent| TraceContext {
+                trace_id: parent.trace_id,
+                span_id: parent.parent_id,
+                sampled: parent.flags & 1 == 1,
+                state: headers.get::<TraceState>().map(|state| state.0.clone()),
+            }),
+            Format::B3 => {
+                let trace_id = raw_str(headers, "X-B3-TraceId").and_then(TraceId::parse);
+                let span_id = raw_str(headers, "X-B3-SpanId").and_then(SpanId::parse);
+                let sampled = raw_str(headers, "X-B3-Sampled").map_or(true, |s| s == "1" || s == "true");
+
+                if let (Some(trace_id), Some(span_id)) = (trace_id, span_id) {
+                    Some(TraceContext {
+                        trace_id: trace_id,
+                        span_id: span_id,
+                        sampled: sampled,
+                        state: None,
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    ///Create a child context for an outbound request. It belongs to the same
+    ///trace, but has a new span identifier.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: SpanId::generate(),
+            sampled: self.sampled,
+            state: self.state.clone(),
+        }
+    }
+
+    ///Write the trace information to a set of, typically outbound, headers.
+    pub fn inject(&self, headers: &mut Headers, format: Format) {
+        match format {
+            Format::W3c => {
+                headers.set(TraceParent {
+                    trace_id: self.trace_id,
+                    parent_id: self.span_id,
+                    flags: if self.sampled { 1 } else { 0 },
+                });
+
+                if let Some(ref state) = self.state {
+                    headers.set(TraceState(state.clone()));
+                } else {
+                    headers.remove::<TraceState>();
+                }
+            },
+            Format::B3 => {
+                headers.set_raw("X-B3-TraceId", vec![self.trace_id.to_string().into_bytes()]);
+                headers.set_raw("X-B3-SpanId", vec![self.span_id.to_string().into_bytes()]);
+                headers.set_raw("X-B3-Sampled", vec![if self.sampled { b"1".to_vec() } else { b"0".to_vec() }]);
+            }
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> TraceContext {
+        TraceContext::new()
+    }
+}
+
+///The W3C `traceparent` header.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceParent {
+    ///The identifier of the whole trace.
+    pub trace_id: TraceId,
+
+    ///The identifier of the calling span.
+    pub parent_id: SpanId,
+
+    ///Trace flags, where the lowest bit marks the trace as sampled.
+    pub flags: u8,
+}
+
+impl Header for TraceParent {
+    fn header_name() -> &'static str {
+        "traceparent"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<TraceParent> {
+        let value = match raw.first().and_then(|line| from_utf8(line).ok()) {
+            Some(value) if raw.len() == 1 => value.trim(),
+            _ => return Err(hyper::Error::Header)
+        };
+
+        let mut parts = value.split('-');
+        let (version, trace_id, parent_id, flags) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(v), Some(t), Some(p), Some(f)) => (v, t, p, f),
+            _ => return Err(hyper::Error::Header)
+        };
+
+        let mut version_byte = [0];
+        let mut flags_byte = [0];
+        if version.len() != 2 || !parse_hex(version, &mut version_byte) || version_byte[0] == 0xff {
+            return Err(hyper::Error::Header);
+        }
+
+        //Only version 00 is known, so any additional fields are rejected for it
+        if version_byte[0] == 0 && parts.next().is_some() {
+            return Err(hyper::Error::Header);
+        }
+
+        if flags.len() != 2 || !parse_hex(flags, &mut flags_byte) {
+            return Err(hyper::Error::Header);
+        }
+
+        match (trace_id.len(), TraceId::parse(trace_id), SpanId::parse(parent_id)) {
+            (32, Some(trace_id), Some(parent_id)) => Ok(TraceParent {
+                trace_id: trace_id,
+                parent_id: parent_id,
+                flags: flags_byte[0],
+            }),
+            _ => Err(hyper::Error::Header)
+        }
+    }
+}
+
+impl HeaderFormat for TraceParent {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", self.trace_id, self.parent_id, self.flags)
+    }
+}
+
+///The W3C `tracestate` header.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceState(pub String);
+
+impl Header for TraceState {
+    fn header_name() -> &'static str {
+        "tracestate"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<TraceState> {
+        let mut entries = vec![];
+        for line in raw {
+            let line = from_utf8(line).map_err(|_| hyper::Error::Header)?;
+            entries.extend(line.split(',').map(str::trim).filter(|entry| !entry.is_empty()));
+        }
+
+        Ok(TraceState(entries.join(",")))
+    }
+}
+
+impl HeaderFormat for TraceState {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+///A context filter that extracts incoming trace information.
+///
+///The resulting `TraceContext` is inserted into `context.extensions`.
+///
+///```
+///use rustful::Server;
+///use rustful::trace::TraceFilter;
+///# fn handler(_: rustful::Context, _: rustful::Response) {}
+///
+///let server = Server {
+///    context_filters: vec![Box::new(TraceFilter::w3c())],
+///    ..Server::new(handler)
+///};
+///```
+pub struct TraceFilter {
+    ///The expected header format.
+    pub format: Format,
+
+    ///Start a new trace if the request doesn't belong to one. Default is
+    ///`true`.
+    pub start_traces: bool,
+}
+
+impl TraceFilter {
+    ///Extract W3C Trace Context headers.
+    pub fn w3c() -> TraceFilter {
+        TraceFilter {
+            format: Format::W3c,
+            start_traces: true,
+        }
+    }
+
+    ///Extract B3 headers.
+    pub fn b3() -> TraceFilter {
+        TraceFilter {
+            format: Format::B3,
+            start_traces: true,
+        }
+    }
+}
+
+impl ContextFilter for TraceFilter {
+    fn modify(&self, _ctx: FilterContext, context: &mut Context) -> ContextAction {
+        let trace = TraceContext::extract(&context.headers, self.format).or_else(|| if self.start_traces {
+            Some(TraceContext::new())
+        } else {
+            None
+        });
+
+        if let Some(trace) = trace {
+            debug!("{} {} [trace {}, span {}]", context.method, context.uri_path, trace.trace_id, trace.span_id);
+            context.extensions.insert(trace);
+        }
+
+        ContextAction::next()
+    }
+}
+
+///Starts a `Span` for each request.
+pub trait Tracer: Send + Sync + 'static {
+    ///Start a span for a new request. It's called as soon as the context
+    ///has been created, before the context filters.
+    fn start(&self, context: &Context) -> Box<Span>;
+}
+
+impl<F: Fn(&Context) -> Box<Span> + Send + Sync + 'static> Tracer for F {
+    fn start(&self, context: &Context) -> Box<Span> {
+        self(context)
+    }
+}
+
+///Follows a single request through the server.
+///
+///The callbacks are called in the order they are listed, but only the ones
+///that apply to the request. A request that doesn't reach a route will not
+///be routed, and a request that is stopped by a context filter will not
+///reach a handler. The span is dropped after `response_flushed`.
+pub trait Span: 'static {
+    ///The request was routed to `route`, such as `/users/:id`. This is
+    ///reported by the `Variables` handler.
+    fn routed(&mut self, _route: &str) {}
+
+    ///The handler got the request.
+    fn handler_start(&mut self) {}
+
+    ///The handler returned. The response has usually been sent at this
+    ///point, but it may still be in the send buffer.
+    fn handler_end(&mut self) {}
+
+    ///The response has been written, with `status`, if the headers were
+    ///written, and `sent_bytes` bytes of body.
+    fn response_flushed(&mut self, _status: Option<StatusCode>, _sent_bytes: usize) {}
+}
+
+///The spans of the current request.
+///
+///It's stored in the filter storage, where custom routers and handlers can
+///find it and report their own progress. `response_flushed` is called when
+///the last copy is dropped, which is when the response has been written.
+#[derive(Clone)]
+pub struct Spans(Rc<RefCell<SpanState>>);
+
+impl Spans {
+    ///Collect the spans for a response, that reports its status and the
+    ///number of body bytes through `sent_status` and `sent_bytes`.
+    pub fn new(spans: Vec<Box<Span>>, sent_status: SentStatus, sent_bytes: SentBytes) -> Spans {
+        Spans(Rc::new(RefCell::new(SpanState {
+            spans: spans,
+            sent_status: sent_status,
+            sent_bytes: sent_bytes,
+        })))
+    }
+
+    ///Tell the spans that the request was routed to `route`.
+    pub fn routed(&self, route: &str) {
+        for span in &mut self.0.borrow_mut().spans {
+            span.routed(route);
+        }
+    }
+
+    ///Tell the spans that the handler got the request.
+    pub fn handler_start(&self) {
+        for span in &mut self.0.borrow_mut().spans {
+            span.handler_start();
+        }
+    }
+
+    ///Tell the spans that the handler returned.
+    pub fn handler_end(&self) {
+        for span in &mut self.0.borrow_mut().spans {
+            span.handler_end();
+        }
+    }
+}
+
+struct SpanState {
+    spans: Vec<Box<Span>>,
+    sent_status: SentStatus,
+    sent_bytes: SentBytes,
+}
+
+impl Drop for SpanState {
+    fn drop(&mut self) {
+        let status = self.sent_status.get();
+        let sent_bytes = self.sent_bytes.get();
+
+        for span in &mut self.spans {
+            span.response_flushed(status, sent_bytes);
+        }
+    }
+}
+
+fn raw_str<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers.get_raw(name)
+        .and_then(|lines| lines.first())
+        .and_then(|line| from_utf8(line).ok())
+        .map(str::trim)
+}
+
+fn parse_hex(hex: &str, target: &mut [u8]) -> bool {
+    let hex = hex.as_bytes();
+    if hex.len() != target.len() * 2 {
+        return false;
+    }
+
+    for (byte, pair) in target.iter_mut().zip(hex.chunks(2)) {
+        match (hex_value(pair[0]), hex_value(pair[1])) {
+            (Some(high), Some(low)) => *byte = (high << 4) | low,
+            _ => return false
+        }
+    }
+
+    true
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None
+    }
+}
+
+fn write_hex(bytes: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+
+    Ok(())
+}
+
+fn write_u64(target: &mut [u8], value: u64) {
+    for (i, byte) in target.iter_mut().enumerate() {
+        *byte = (value >> (56 - i * 8)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use StatusCode;
+    use header::Headers;
+    use response::{SentStatus, SentBytes};
+    use super::{TraceContext, TraceParent, TraceId, SpanId, Format, Span, Spans};
+
+    struct RecordedSpan(Arc<Mutex<Vec<String>>>);
+
+    impl Span for RecordedSpan {
+        fn routed(&mut self, route: &str) {
+            self.0.lock().unwrap().push(format!("routed {}", route));
+        }
+
+        fn handler_start(&mut self) {
+            self.0.lock().unwrap().push("handler start".into());
+        }
+
+        fn handler_end(&mut self) {
+            self.0.lock().unwrap().push("handler end".into());
+        }
+
+        fn response_flushed(&mut self, status: Option<StatusCode>, sent_bytes: usize) {
+            self.0.lock().unwrap().push(format!("flushed {:?} {}", status, sent_bytes));
+        }
+    }
+
+    #[test]
+    fn span_callbacks() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let spans = Spans::new(vec![Box::new(RecordedSpan(events.clone()))], SentStatus::new(), SentBytes::new());
+        let copy = spans.clone();
+
+        spans.routed("/users/:id");
+        spans.handler_start();
+        drop(spans);
+        copy.handler_end();
+        assert_eq!(events.lock().unwrap().len(), 3);
+
+        drop(copy);
+        assert_eq!(*events.lock().unwrap(), vec![
+            "routed /users/:id".to_owned(),
+            "handler start".into(),
+            "handler end".into(),
+            "flushed None 0".into()
+        ]);
+    }
+
+    #[test]
+    fn parse_traceparent() {
+        let mut headers = Headers::new();
+        headers.set_raw("traceparent", vec![b"00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_vec()]);
+        headers.set_raw("tracestate", vec![b"congo=t61rcWkgMzE, rojo=00f067aa0ba902b7".to_vec()]);
+
+        let trace = TraceContext::extract(&headers, Format::W3c).expect("no trace context");
+        assert_eq!(trace.trace_id.to_string(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(trace.span_id.to_string(), "b7ad6b7169203331");
+        assert!(trace.sampled);
+        assert_eq!(trace.state, Some("congo=t61rcWkgMzE,rojo=00f067aa0ba902b7".to_owned()));
+    }
+
+    #[test]
+    fn reject_invalid_traceparent() {
+        let invalid = [
+            &b"00-00000000000000000000000000000000-b7ad6b7169203331-01"[..],
+            b"00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            b"ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            b"00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+            b"00-0af7651916cd43dd-b7ad6b7169203331-01",
+            b"00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        ];
+
+        for value in &invalid {
+            let mut headers = Headers::new();
+            headers.set_raw("traceparent", vec![value.to_vec()]);
+            assert_eq!(headers.get::<TraceParent>(), None);
+        }
+    }
+
+    #[test]
+    fn b3_round_trip() {
+        let trace = TraceContext {
+            trace_id: TraceId::parse("463ac35c9f6413ad").unwrap(),
+            span_id: SpanId::parse("a2fb4a1d1a96d312").unwrap(),
+            sampled: false,
+            state: None,
+        };
+
+        let mut headers = Headers::new();
+        trace.inject(&mut headers, Format::B3);
+
+        assert_eq!(TraceContext::extract(&headers, Format::B3), Some(trace));
+    }
+
+    #[test]
+    fn child_keeps_trace() {
+        let trace = TraceContext::new();
+        let child = trace.child();
+        assert_eq!(trace.trace_id, child.trace_id);
+        assert!(trace.span_id != child.span_id);
+    }
+}
@@ -0,0 +1,430 @@
+//!Request and response transformations.
+//!
+//!A [`Transform`][transform] is a set of rules that rewrite the requests
+//!and responses of a part of a router, without touching its handlers. It
+//!can add, remove and rename headers in both directions, rewrite response
+//!status codes and, with the `json` feature, move, remove and set fields in
+//!JSON response bodies. This makes it possible to adapt an existing API, or
+//!a proxied service, to what the clients expect.
+//!
+//!The request rules are applied by using the `Transform` as a context
+//!filter on a route, and the response rules are applied by the
+//![`TransformFilter`][transform_filter] response filter, which has to be
+//!added to the server:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter, StatusCode};
+//!use rustful::transform::{Transform, TransformFilter};
+//!
+//!fn legacy_api(_context: Context, response: Response) {
+//!    response.send("{\"data\": {\"name\": \"Alice\"}}");
+//!}
+//!
+//!let transform = Transform::new()
+//!    .rename_request_header("X-Api-Key", "Authorization")
+//!    .remove_response_header("Server")
+//!    .map_status(StatusCode::NotFound, StatusCode::Gone);
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("v2").with_filter(transform).many(|mut node| {
+//!    node.path("users/:id").then().on_get(legacy_api);
+//!});
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    response_filters: vec![Box::new(TransformFilter)],
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!Transforms on nested routes are all applied. The request rules are
+//!applied from the outermost to the innermost transform, and the response
+//!rules are applied in the opposite order.
+//!
+//![transform]: struct.Transform.html
+//![transform_filter]: struct.TransformFilter.html
+
+use std::sync::Arc;
+
+#[cfg(feature = "json")]
+use serde_json::{self, Value};
+
+use StatusCode;
+use header::Headers;
+#[cfg(feature = "json")]
+use header::{ContentType, ContentLength};
+#[cfg(feature = "json")]
+use mime::{Mime, TopLevel, SubLevel};
+
+use context::Context;
+use filter::{FilterContext, ContextFilter, ContextAction, ResponseFilter, ResponseAction};
+use response::Data;
+
+///A set of request and response rewriting rules.
+///
+///The rules are applied in the order they were added. See the [module
+///documentation](index.html) for more details.
+#[derive(Clone, Default)]
+pub struct Transform {
+    rules: Arc<Rules>,
+}
+
+#[derive(Clone, Default)]
+struct Rules {
+    request_headers: Vec<HeaderRule>,
+    response_headers: Vec<HeaderRule>,
+    statuses: Vec<(StatusCode, StatusCode)>,
+    #[cfg(feature = "json")]
+    fields: Vec<FieldRule>,
+}
+
+#[derive(Clone)]
+enum HeaderRule {
+    Set(String, Vec<u8>),
+    Remove(String),
+    Rename(String, String),
+}
+
+#[cfg(feature = "json")]
+#[derive(Clone)]
+enum FieldRule {
+    Move(String, String),
+    Remove(String),
+    Set(String, Value),
+}
+
+impl Transform {
+    ///Create a transform without any rules.
+    pub fn new() -> Transform {
+        Transform::default()
+    }
+
+    ///Set the request header `name` to `value`, replacing any previous
+    ///value.
+    pub fn set_request_header<N: Into<String>, V: Into<Vec<u8>>>(self, name: N, value: V) -> Transform {
+        self.request_header(HeaderRule::Set(name.into(), value.into()))
+    }
+
+    ///Remove the request header `name`.
+    pub fn remove_request_header<N: Into<String>>(self, name: N) -> Transform {
+        self.request_header(HeaderRule::Remove(name.into()))
+    }
+
+    ///Rename the request header `from` to `to`, if it exists.
+    pub fn rename_request_header<F: Into<String>, T: Into<String>>(self, from: F, to: T) -> Transform {
+        self.request_header(HeaderRule::Rename(from.into(), to.into()))
+    }
+
+    ///Set the response header `name` to `value`, replacing any previous
+    ///value.
+    pub fn set_response_header<N: Into<String>, V: Into<Vec<u8>>>(self, name: N, value: V) -> Transform {
+        self.response_header(HeaderRule::Set(name.into(), value.into()))
+    }
+
+    ///Remove the response header `name`.
+    pub fn remove_response_header<N: Into<String>>(self, name: N) -> Transform {
+        self.response_header(HeaderRule::Remove(name.into()))
+    }
+
+    ///Rename the response header `from` to `to`, if it exists.
+    pub fn rename_response_header<F: Into<String>, T: Into<String>>(self, from: F, to: T) -> Transform {
+        self.response_header(HeaderRule::Rename(from.into(), to.into()))
+    }
+
+    ///Replace the response status `from` with `to`.
+    pub fn map_status(mut self, from: StatusCode, to: StatusCode) -> Transform {
+        Arc::make_mut(&mut self.rules).statuses.push((from, to));
+        self
+    }
+
+    ///Move the field at the JSON pointer `from` to `to`, in JSON response
+    ///bodies. A pointer is a path, such as `/data/name`, as described in
+    ///RFC 6901. Missing fields are ignored.
+    #[cfg(feature = "json")]
+    pub fn move_field<F: Into<String>, T: Into<String>>(self, from: F, to: T) -> Transform {
+        self.field(FieldRule::Move(from.into(), to.into()))
+    }
+
+    ///Remove the field at the JSON pointer `pointer`, in JSON response
+    ///bodies.
+    #[cfg(feature = "json")]
+    pub fn remove_field<P: Into<String>>(self, pointer: P) -> Transform {
+        self.field(FieldRule::Remove(pointer.into()))
+    }
+
+    ///Set the field at the JSON pointer `pointer` to `value`, in JSON
+    ///response bodies. The parent of the field has to exist.
+    #[cfg(feature = "json")]
+    pub fn set_field<P: Into<String>, V: Into<Value>>(self, pointer: P, value: V) -> Transform {
+        self.field(FieldRule::Set(pointer.into(), value.into()))
+    }
+
+    fn request_header(mut self, rule: HeaderRule) -> Transform {
+        Arc::make_mut(&mut self.rules).request_headers.push(rule);
+        self
+    }
+
+    fn response_header(mut self, rule: HeaderRule) -> Transform {
+        Arc::make_mut(&mut self.rules).response_headers.push(rule);
+        self
+    }
+
+    #[cfg(feature = "json")]
+    fn field(mut self, rule: FieldRule) -> Transform {
+        Arc::make_mut(&mut self.rules).fields.push(rule);
+        self
+    }
+}
+
+impl ContextFilter for Transform {
+    fn modify(&self, context: FilterContext, request_context: &mut Context) -> ContextAction {
+        apply_header_rules(&self.rules.request_headers, &mut request_context.headers);
+        context.storage.entry::<Applied>().or_insert_with(|| Applied(vec![])).0.push(self.rules.clone());
+        ContextAction::next()
+    }
+}
+
+fn apply_header_rules(rules: &[HeaderRule], headers: &mut Headers) {
+    for rule in rules {
+        match *rule {
+            HeaderRule::Set(ref name, ref value) => headers.set_raw(name.clone(), vec![value.clone()]),
+            HeaderRule::Remove(ref name) => headers.remove_raw(name),
+            HeaderRule::Rename(ref from, ref to) => {
+                let values = headers.get_raw(from).map(|values| values.to_vec());
+                if let Some(values) = values {
+                    headers.remove_raw(from);
+                    headers.set_raw(to.clone(), values);
+                }
+            }
+        }
+    }
+}
+
+//The rules of the transforms that were applied to the current request.
+struct Applied(Vec<Arc<Rules>>);
+
+///A response filter that applies the response rules of the transforms that
+///were used for the current request.
+#[derive(Clone, Copy, Debug)]
+pub struct TransformFilter;
+
+impl ResponseFilter for TransformFilter {
+    fn begin(&self, ctx: FilterContext, mut status: StatusCode, headers: &mut Headers) -> (StatusCode, ResponseAction) {
+        let applied = match ctx.storage.get::<Applied>() {
+            Some(applied) => applied,
+            None => return (status, ResponseAction::next(None::<Data>))
+        };
+
+        for rules in applied.0.iter().rev() {
+            if let Some(&(_, to)) = rules.statuses.iter().find(|&&(from, _)| from == status) {
+                status = to;
+            }
+
+            apply_header_rules(&rules.response_headers, headers);
+        }
+
+        #[cfg(feature = "json")]
+        {
+            let rules: Vec<_> = applied.0.iter().rev().filter(|rules| !rules.fields.is_empty()).cloned().collect();
+            let is_json = match headers.get::<ContentType>() {
+                Some(&ContentType(Mime(TopLevel::Application, SubLevel::Json, _))) => true,
+                Some(&ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _))) => sub.ends_with("+json"),
+                _ => false
+            };
+
+            if is_json && !rules.is_empty() {
+                headers.remove::<ContentLength>();
+                ctx.storage.insert(JsonBody {
+                    rules: rules,
+                    buffer: vec![],
+                });
+            }
+        }
+
+        (status, ResponseAction::next(None::<Data>))
+    }
+
+    fn write<'a>(&'a self, ctx: FilterContext, content: Option<Data<'a>>) -> ResponseAction {
+        #[cfg(feature = "json")]
+        {
+            if let Some(body) = ctx.storage.get_mut::<JsonBody>() {
+                if let Some(content) = content {
+                    body.buffer.extend_from_slice(content.as_bytes());
+                }
+                return ResponseAction::next(None::<Data>);
+            }
+        }
+
+        #[cfg(not(feature = "json"))]
+        let _ = ctx;
+
+        ResponseAction::next(content)
+    }
+
+    fn end(&self, ctx: FilterContext) -> ResponseAction {
+        #[cfg(feature = "json")]
+        {
+            if let Some(body) = ctx.storage.remove::<JsonBody>() {
+                return ResponseAction::next(Some(body.transform()));
+            }
+        }
+
+        #[cfg(not(feature = "json"))]
+        let _ = ctx;
+
+        ResponseAction::next(None::<Data>)
+    }
+}
+
+//Holds a JSON body until it's complete.
+#[cfg(feature = "json")]
+struct JsonBody {
+    rules: Vec<Arc<Rules>>,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "json")]
+impl JsonBody {
+    fn transform(self) -> Vec<u8> {
+        let mut value: Value = match serde_json::from_slice(&self.buffer) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("could not transform the JSON body: {}", e);
+                return self.buffer;
+            }
+        };
+
+        for rules in &self.rules {
+            for rule in &rules.fields {
+                match *rule {
+                    FieldRule::Move(ref from, ref to) => if let Some(field) = take_field(&mut value, from) {
+                        set_field(&mut value, to, field);
+                    },
+                    FieldRule::Remove(ref pointer) => {
+                        take_field(&mut value, pointer);
+                    },
+                    FieldRule::Set(ref pointer, ref field) => set_field(&mut value, pointer, field.clone()),
+                }
+            }
+        }
+
+        serde_json::to_vec(&value).unwrap_or(self.buffer)
+    }
+}
+
+//Splits a JSON pointer into the pointer to the parent and the unescaped
+//key of the field.
+#[cfg(feature = "json")]
+fn split_pointer(pointer: &str) -> Option<(&str, String)> {
+    pointer.rfind('/').map(|index| {
+        let key = pointer[index + 1..].replace("~1", "/").replace("~0", "~");
+        (&pointer[..index], key)
+    })
+}
+
+#[cfg(feature = "json")]
+fn take_field(value: &mut Value, pointer: &str) -> Option<Value> {
+    let (parent, key) = split_pointer(pointer)?;
+
+    match value.pointer_mut(parent) {
+        Some(&mut Value::Object(ref mut object)) => object.remove(&key),
+        Some(&mut Value::Array(ref mut array)) => match key.parse::<usize>() {
+            Ok(index) if index < array.len() => Some(array.remove(index)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+#[cfg(feature = "json")]
+fn set_field(value: &mut Value, pointer: &str, field: Value) {
+    let (parent, key) = match split_pointer(pointer) {
+        Some(split) => split,
+        None => {
+            *value = field;
+            return;
+        }
+    };
+
+    match value.pointer_mut(parent) {
+        Some(&mut Value::Object(ref mut object)) => {
+            object.insert(key, field);
+        },
+        Some(&mut Value::Array(ref mut array)) => if key == "-" {
+            array.push(field);
+        } else if let Ok(index) = key.parse::<usize>() {
+            if index < array.len() {
+                array[index] = field;
+            } else if index == array.len() {
+                array.push(field);
+            }
+        },
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anymap::AnyMap;
+    use StatusCode;
+    use Method;
+    use header::{Headers, Server as ServerHeader};
+    use context::Context;
+    use filter::{FilterContext, ContextFilter, ResponseFilter};
+    use server::Global;
+    use super::{Transform, TransformFilter};
+
+    #[test]
+    fn headers_and_status() {
+        let global = Global::default();
+        let mut headers = Headers::new();
+        headers.set_raw("X-Api-Key", vec![b"secret".to_vec()]);
+        let mut context = Context::mock(Method::Get, "/", headers, &global);
+        let mut storage = AnyMap::new();
+
+        let outer = Transform::new().map_status(StatusCode::Gone, StatusCode::NotFound);
+        let inner = Transform::new()
+            .rename_request_header("X-Api-Key", "Authorization")
+            .remove_response_header("Server")
+            .map_status(StatusCode::NotFound, StatusCode::Gone);
+
+        outer.modify(FilterContext { storage: &mut storage, global: &global }, &mut context);
+        inner.modify(FilterContext { storage: &mut storage, global: &global }, &mut context);
+        assert!(context.headers.get_raw("X-Api-Key").is_none());
+        assert_eq!(context.headers.get_raw("Authorization"), Some(&[b"secret".to_vec()][..]));
+
+        let mut headers = Headers::new();
+        headers.set(ServerHeader("backend".into()));
+        let (status, _) = TransformFilter.begin(FilterContext { storage: &mut storage, global: &global }, StatusCode::NotFound, &mut headers);
+        assert_eq!(status, StatusCode::NotFound);
+        assert!(!headers.has::<ServerHeader>());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_fields() {
+        use header::ContentType;
+        use filter::ResponseAction;
+
+        let global = Global::default();
+        let mut context = Context::mock(Method::Get, "/", Headers::new(), &global);
+        let mut storage = AnyMap::new();
+
+        let transform = Transform::new()
+            .move_field("/data/name", "/name")
+            .remove_field("/data")
+            .set_field("/version", 2);
+        transform.modify(FilterContext { storage: &mut storage, global: &global }, &mut context);
+
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+        TransformFilter.begin(FilterContext { storage: &mut storage, global: &global }, StatusCode::Ok, &mut headers);
+        TransformFilter.write(FilterContext { storage: &mut storage, global: &global }, Some("{\"data\": {\"na".into()));
+        TransformFilter.write(FilterContext { storage: &mut storage, global: &global }, Some("me\": \"Alice\"}}".into()));
+
+        match TransformFilter.end(FilterContext { storage: &mut storage, global: &global }) {
+            ResponseAction::Next(Some(body)) => assert_eq!(body.as_bytes(), &b"{\"name\":\"Alice\",\"version\":2}"[..]),
+            _ => panic!("expected a body")
+        }
+    }
+}
@@ -0,0 +1,685 @@
+//!Resumable uploads, with the tus protocol.
+//!
+//![`Tus`][tus] is a handler for the core tus 1.0.0 protocol and its
+//!`creation` extension. A client creates an upload with a `POST` request to
+//!a collection, such as `/files`, and gets a `Location` for the new upload,
+//!such as `/files/:id`. The data is then sent with `PATCH` requests, each
+//!continuing from the `Upload-Offset` of the previous one, and an
+//!interrupted upload can ask for the current offset with a `HEAD` request
+//!before it resumes. The uploads are kept in an [`UploadStore`][upload_store]:
+//!
+//!```no_run
+//!use rustful::{Server, Handler, DefaultRouter};
+//!use rustful::tus::{Tus, Directory};
+//!
+//!let tus = Tus::new(Directory::new("path/to/uploads")).max_size(1 << 30);
+//!
+//!let mut router = DefaultRouter::<Box<Handler>>::new();
+//!router.build().path("files").many(|mut node| {
+//!    node.then().on_options(Box::new(tus.clone()) as Box<Handler>);
+//!    node.then().on_post(Box::new(tus.clone()) as Box<Handler>);
+//!    node.path(":id").many(|mut node| {
+//!        node.then().on_head(Box::new(tus.clone()) as Box<Handler>);
+//!        node.then().on_patch(Box::new(tus.clone()) as Box<Handler>);
+//!    });
+//!});
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!What to do with a finished upload, when its offset has reached its
+//!length, is up to the application.
+//!
+//![tus]: struct.Tus.html
+//![upload_store]: trait.UploadStore.html
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+use std::sync::{Arc, Mutex};
+
+use hyper;
+use {Method, StatusCode};
+use header::{Header, HeaderFormat, ContentType, ContentLength, Location, CacheControl, CacheDirective};
+use mime::{Mime, TopLevel, SubLevel};
+use context::Context;
+use handler::Handler;
+use response::{Response, NoContent};
+use utils::random_id;
+
+///The supported version of the tus protocol.
+pub const VERSION: &'static str = "1.0.0";
+
+macro_rules! number_header {
+    ($(#[$attr:meta])* $ty:ident, $name:expr) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub struct $ty(pub u64);
+
+        impl Header for $ty {
+            fn header_name() -> &'static str {
+                $name
+            }
+
+            fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<$ty> {
+                match raw.first().and_then(|line| from_utf8(line).ok()) {
+                    Some(value) if raw.len() == 1 => value.trim().parse().map($ty).map_err(|_| hyper::Error::Header),
+                    _ => Err(hyper::Error::Header)
+                }
+            }
+        }
+
+        impl HeaderFormat for $ty {
+            fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    }
+}
+
+macro_rules! text_header {
+    ($(#[$attr:meta])* $ty:ident, $name:expr) => {
+        $(#[$attr])*
+        #[derive(Clone, PartialEq, Eq, Debug)]
+        pub struct $ty(pub String);
+
+        impl Header for $ty {
+            fn header_name() -> &'static str {
+                $name
+            }
+
+            fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<$ty> {
+                match raw.first().and_then(|line| from_utf8(line).ok()) {
+                    Some(value) if raw.len() == 1 => Ok($ty(value.trim().to_owned())),
+                    _ => Err(hyper::Error::Header)
+                }
+            }
+        }
+
+        impl HeaderFormat for $ty {
+            fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    }
+}
+
+text_header! {
+    ///The `Tus-Resumable` header, with the protocol version that a request
+    ///or response is using.
+    TusResumable, "Tus-Resumable"
+}
+
+text_header! {
+    ///The `Upload-Metadata` header, with comma separated pairs of keys and
+    ///base64 encoded values. It's stored as it is.
+    UploadMetadata, "Upload-Metadata"
+}
+
+number_header! {
+    ///The `Upload-Offset` header, with the number of bytes that have been
+    ///received.
+    UploadOffset, "Upload-Offset"
+}
+
+number_header! {
+    ///The `Upload-Length` header, with the total size of an upload.
+    UploadLength, "Upload-Length"
+}
+
+///The progress of an upload.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Upload {
+    ///The number of bytes that have been received.
+    pub offset: u64,
+
+    ///The total size of the upload.
+    pub length: u64,
+
+    ///The `Upload-Metadata` from when the upload was created.
+    pub metadata: Option<String>,
+}
+
+impl Upload {
+    ///Check if all of the data has been received.
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+}
+
+///Storage for uploads.
+pub trait UploadStore: Send + Sync + 'static {
+    ///Create an empty upload of `length` bytes, and return its ID. The ID
+    ///becomes part of the upload's URL.
+    fn create(&self, length: u64, metadata: Option<&str>) -> io::Result<String>;
+
+    ///Get the progress of the upload `id`. A missing upload is `Ok(None)`.
+    fn upload(&self, id: &str) -> io::Result<Option<Upload>>;
+
+    ///Append `data` to the upload `id`, which has received `offset` bytes
+    ///so far, and return the number of bytes that were stored. What has
+    ///been stored should be kept, even if the data ends with an error.
+    ///
+    ///The offset has to be checked while no other data is appended to the
+    ///same upload. An `InvalidInput` error means that it didn't match, and a
+    ///`NotFound` error means that the upload doesn't exist.
+    fn append(&self, id: &str, offset: u64, data: &mut Read) -> io::Result<u64>;
+}
+
+///Uploads that are stored in memory.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    uploads: Arc<Mutex<HashMap<String, (Upload, Vec<u8>)>>>,
+}
+
+impl MemoryStore {
+    ///Create an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    ///Get the data that has been received for the upload `id`.
+    pub fn data(&self, id: &str) -> Option<Vec<u8>> {
+        self.uploads.lock().unwrap_or_else(|e| e.into_inner()).get(id).map(|&(_, ref data)| data.clone())
+    }
+
+    ///Remove the upload `id`, and return its data.
+    pub fn remove(&self, id: &str) -> Option<Vec<u8>> {
+        self.uploads.lock().unwrap_or_else(|e| e.into_inner()).remove(id).map(|(_, data)| data)
+    }
+}
+
+impl UploadStore for MemoryStore {
+    fn create(&self, length: u64, metadata: Option<&str>) -> io::Result<String> {
+        let id = random_id();
+        let upload = Upload {
+            offset: 0,
+            length: length,
+            metadata: metadata.map(ToOwned::to_owned),
+        };
+        self.uploads.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone(), (upload, vec![]));
+        Ok(id)
+    }
+
+    fn upload(&self, id: &str) -> io::Result<Option<Upload>> {
+        Ok(self.uploads.lock().unwrap_or_else(|e| e.into_inner()).get(id).map(|&(ref upload, _)| upload.clone()))
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &mut Read) -> io::Result<u64> {
+        let mut buffer = vec![];
+        let result = data.read_to_end(&mut buffer);
+
+        let mut uploads = self.uploads.lock().unwrap_or_else(|e| e.into_inner());
+        match uploads.get_mut(id) {
+            Some(&mut (ref mut upload, ref mut stored)) if upload.offset == offset => {
+                stored.extend_from_slice(&buffer);
+                upload.offset += buffer.len() as u64;
+            },
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "the offset has changed")),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "the upload doesn't exist"))
+        }
+
+        result.map(|_| buffer.len() as u64)
+    }
+}
+
+///Uploads that are stored as files in a directory.
+///
+///The data of an upload is in a file that is named by its ID, and its
+///length and metadata are in a file with the extension `.info`. Appending
+///to an upload is only serialized within the same `Directory` and its
+///copies, so it shouldn't be shared with other processes.
+#[derive(Clone, Debug)]
+pub struct Directory {
+    path: PathBuf,
+    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl Directory {
+    ///Store the uploads in `path`, which should already exist.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Directory {
+        Directory {
+            path: path.into(),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    ///Get the path to the data of the upload `id`, if it's a valid ID.
+    pub fn path(&self, id: &str) -> Option<PathBuf> {
+        if is_valid_id(id) {
+            Some(self.path.join(id))
+        } else {
+            None
+        }
+    }
+}
+
+impl UploadStore for Directory {
+    fn create(&self, length: u64, metadata: Option<&str>) -> io::Result<String> {
+        let id = random_id();
+        let info = format!("{}\n{}", length, metadata.unwrap_or(""));
+        File::create(self.path.join(format!("{}.info", id)))?.write_all(info.as_bytes())?;
+        File::create(self.path.join(&id))?;
+        Ok(id)
+    }
+
+    fn upload(&self, id: &str) -> io::Result<Option<Upload>> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+
+        let mut info = String::new();
+        match File::open(self.path.join(format!("{}.info", id))) {
+            Ok(mut file) => file.read_to_string(&mut info)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        let mut lines = info.splitn(2, '\n');
+        let length = lines.next()
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid upload info"))?;
+        let metadata = lines.next().filter(|metadata| !metadata.is_empty()).map(ToOwned::to_owned);
+
+        Ok(Some(Upload {
+            offset: fs::metadata(self.path.join(id))?.len(),
+            length: length,
+            metadata: metadata,
+        }))
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &mut Read) -> io::Result<u64> {
+        if !is_valid_id(id) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "the upload doesn't exist"));
+        }
+
+        let lock = self.locks.lock().unwrap_or_else(|e| e.into_inner())
+            .entry(id.to_owned())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone();
+
+        let result = {
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            append_file(&self.path.join(id), offset, data)
+        };
+
+        //Forget the lock if no one else is waiting for it.
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(id);
+        }
+
+        result
+    }
+}
+
+fn append_file(path: &Path, offset: u64, data: &mut Read) -> io::Result<u64> {
+    let mut file = match OpenOptions::new().append(true).open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(io::Error::new(io::ErrorKind::NotFound, "the upload doesn't exist")),
+        Err(e) => return Err(e)
+    };
+
+    if file.metadata()?.len() != offset {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the offset has changed"));
+    }
+
+    io::copy(data, &mut file)
+}
+
+///A handler for the tus protocol.
+///
+///It answers `OPTIONS` with the protocol details, creates uploads with
+///`POST`, reports their offsets with `HEAD` and appends to them with
+///`PATCH`. The upload ID is taken from the route variable `id`, by
+///default, and unknown uploads are `404 Not Found`. Every copy shares the
+///same store.
+pub struct Tus<S> {
+    store: Arc<S>,
+    variable: String,
+    max_size: Option<u64>,
+}
+
+impl<S: UploadStore> Tus<S> {
+    ///Keep the uploads in `store`.
+    pub fn new(store: S) -> Tus<S> {
+        Tus {
+            store: Arc::new(store),
+            variable: "id".into(),
+            max_size: None,
+        }
+    }
+
+    ///Take the upload ID from the route variable `name`.
+    pub fn variable<N: Into<String>>(mut self, name: N) -> Tus<S> {
+        self.variable = name.into();
+        self
+    }
+
+    ///Reject uploads that are larger than `size` bytes with `413 Payload
+    ///Too Large`. Default is no limit.
+    pub fn max_size(mut self, size: u64) -> Tus<S> {
+        self.max_size = Some(size);
+        self
+    }
+
+    ///Get the store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn create(&self, context: &Context, response: &mut Response) -> io::Result<()> {
+        let length = match context.headers.get::<UploadLength>() {
+            Some(&UploadLength(length)) => length,
+            None => {
+                response.set_status(StatusCode::BadRequest);
+                return Ok(());
+            }
+        };
+
+        if self.max_size.map_or(false, |max_size| length > max_size) {
+            response.set_status(StatusCode::PayloadTooLarge);
+            return Ok(());
+        }
+
+        let metadata = context.headers.get::<UploadMetadata>().map(|metadata| &*metadata.0);
+        let id = self.store.create(length, metadata)?;
+
+        let path = context.uri_path.as_utf8_path_lossy().unwrap_or_default();
+        response.headers_mut().set(Location(format!("{}/{}", path.trim_end_matches('/'), id)));
+        response.set_status(StatusCode::Created);
+        Ok(())
+    }
+
+    fn offset(&self, id: &str, response: &mut Response) -> io::Result<()> {
+        match self.store.upload(id)? {
+            Some(upload) => {
+                response.headers_mut().set(UploadOffset(upload.offset));
+                response.headers_mut().set(UploadLength(upload.length));
+                if let Some(metadata) = upload.metadata {
+                    response.headers_mut().set(UploadMetadata(metadata));
+                }
+                response.headers_mut().set(CacheControl(vec![CacheDirective::NoStore]));
+            },
+            None => response.set_status(StatusCode::NotFound)
+        }
+
+        Ok(())
+    }
+
+    fn append(&self, id: &str, context: &mut Context, response: &mut Response) -> io::Result<()> {
+        let upload = match self.store.upload(id)? {
+            Some(upload) => upload,
+            None => {
+                response.set_status(StatusCode::NotFound);
+                return Ok(());
+            }
+        };
+
+        let is_offset_stream = match context.headers.get::<ContentType>() {
+            Some(&ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _))) => sub == "offset+octet-stream",
+            _ => false
+        };
+
+        if !is_offset_stream {
+            response.set_status(StatusCode::UnsupportedMediaType);
+            return Ok(());
+        }
+
+        let offset = match context.headers.get::<UploadOffset>() {
+            Some(&UploadOffset(offset)) => offset,
+            None => {
+                response.set_status(StatusCode::BadRequest);
+                return Ok(());
+            }
+        };
+
+        if offset != upload.offset {
+            response.set_status(StatusCode::Conflict);
+            return Ok(());
+        }
+
+        let remaining = upload.length.saturating_sub(offset);
+        if context.headers.get::<ContentLength>().map_or(false, |length| length.0 > remaining) {
+            response.set_status(StatusCode::PayloadTooLarge);
+            return Ok(());
+        }
+
+        match self.store.append(id, offset, &mut (&mut context.body).take(remaining)) {
+            Ok(written) => {
+                response.headers_mut().set(UploadOffset(offset + written));
+                response.set_status(StatusCode::NoContent);
+            },
+            //Someone else appended to it since the offset was checked.
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => response.set_status(StatusCode::Conflict),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => response.set_status(StatusCode::NotFound),
+            Err(e) => return Err(e)
+        }
+
+        Ok(())
+    }
+}
+
+impl<S> Clone for Tus<S> {
+    fn clone(&self) -> Tus<S> {
+        Tus {
+            store: self.store.clone(),
+            variable: self.variable.clone(),
+            max_size: self.max_size,
+        }
+    }
+}
+
+impl<S: UploadStore> Handler for Tus<S> {
+    fn handle(&self, mut context: Context, mut response: Response) {
+        response.headers_mut().set(TusResumable(VERSION.into()));
+
+        if context.method == Method::Options {
+            response.headers_mut().set_raw("Tus-Version", vec![VERSION.into()]);
+            response.headers_mut().set_raw("Tus-Extension", vec![b"creation".to_vec()]);
+            if let Some(max_size) = self.max_size {
+                response.headers_mut().set_raw("Tus-Max-Size", vec![max_size.to_string().into_bytes()]);
+            }
+            return response.send(NoContent);
+        }
+
+        if context.headers.get::<TusResumable>().map(|version| &*version.0) != Some(VERSION) {
+            response.headers_mut().set_raw("Tus-Version", vec![VERSION.into()]);
+            response.set_status(StatusCode::PreconditionFailed);
+            return;
+        }
+
+        let id = context.variables.get(&*self.variable).map(|id| id.into_owned());
+
+        let result = match (context.method.clone(), id) {
+            (Method::Post, _) => self.create(&context, &mut response),
+            (Method::Head, Some(id)) => self.offset(&id, &mut response),
+            (Method::Patch, Some(id)) => self.append(&id, &mut context, &mut response),
+            (Method::Head, None) | (Method::Patch, None) => {
+                response.set_status(StatusCode::NotFound);
+                Ok(())
+            },
+            _ => {
+                response.set_status(StatusCode::MethodNotAllowed);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            error!("failed to handle {} upload request: {}", context.method, e);
+            response.set_status(StatusCode::InternalServerError);
+        }
+
+        //A dropped response would get a `Content-Length`, which a 204 must not have
+        if response.status() == StatusCode::NoContent {
+            response.send(NoContent);
+        }
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::{self, Read};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    use {Server, Method, StatusCode, DefaultRouter, Handler};
+    use header::{Headers, ContentType, ContentLength, Location};
+    use testing::TestClient;
+    use super::{Tus, MemoryStore, Directory, Upload, UploadStore, TusResumable, UploadLength, UploadOffset, VERSION};
+
+    //Waits for a message before it returns its data.
+    struct SlowReader(Receiver<()>, &'static [u8]);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let _ = self.0.recv();
+            self.1.read(buf)
+        }
+    }
+
+    //Pretends that someone else always appends first.
+    struct ConflictStore(MemoryStore);
+
+    impl UploadStore for ConflictStore {
+        fn create(&self, length: u64, metadata: Option<&str>) -> io::Result<String> {
+            self.0.create(length, metadata)
+        }
+
+        fn upload(&self, id: &str) -> io::Result<Option<Upload>> {
+            self.0.upload(id)
+        }
+
+        fn append(&self, _id: &str, _offset: u64, _data: &mut Read) -> io::Result<u64> {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "the offset has changed"))
+        }
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        let path = env::temp_dir().join(format!("rustful-tus-{}", ::utils::random_u64()));
+        fs::create_dir(&path).unwrap();
+        let directory = Directory::new(path.clone());
+        let id = directory.create(10, None).unwrap();
+
+        let (send, receive) = channel();
+        let first = {
+            let (directory, id) = (directory.clone(), id.clone());
+            thread::spawn(move || directory.append(&id, 0, &mut SlowReader(receive, b"hello")))
+        };
+        thread::sleep(Duration::from_millis(50));
+
+        let second = {
+            let (directory, id) = (directory.clone(), id.clone());
+            thread::spawn(move || directory.append(&id, 0, &mut &b"world"[..]))
+        };
+        thread::sleep(Duration::from_millis(50));
+        send.send(()).unwrap();
+        drop(send);
+
+        let first = first.join().unwrap();
+        let second = second.join().unwrap();
+        let data = fs::read(directory.path(&id).unwrap());
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(first.unwrap(), 5);
+        assert_eq!(second.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(data.unwrap(), b"hello");
+        assert!(directory.locks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn store_conflict() {
+        let tus = Tus::new(ConflictStore(MemoryStore::new()));
+        let id = tus.store().create(10, None).unwrap();
+
+        let mut router = DefaultRouter::<Tus<ConflictStore>>::new();
+        router.build().path("files/:id").then().on_patch(tus);
+        let client = TestClient::new(Server::new(router));
+
+        let mut headers = Headers::new();
+        headers.set(TusResumable(VERSION.into()));
+        headers.set(ContentType("application/offset+octet-stream".parse().unwrap()));
+        headers.set(UploadOffset(0));
+        let response = client.request(Method::Patch, &format!("/files/{}", id), headers, b"hello").unwrap();
+        assert_eq!(response.status, StatusCode::Conflict);
+    }
+
+    #[test]
+    fn resumable_upload() {
+        let tus = Tus::new(MemoryStore::new()).max_size(100);
+        let store = tus.store().clone();
+
+        let mut router = DefaultRouter::<Box<Handler>>::new();
+        router.build().path("files").many(|mut node| {
+            node.then().on_post(Box::new(tus.clone()) as Box<Handler>);
+            node.then().on_options(Box::new(tus.clone()) as Box<Handler>);
+            node.path(":id").many(|mut node| {
+                node.then().on_head(Box::new(tus.clone()) as Box<Handler>);
+                node.then().on_patch(Box::new(tus.clone()) as Box<Handler>);
+            });
+        });
+        let client = TestClient::new(Server::new(router));
+
+        let mut headers = Headers::new();
+        headers.set(UploadLength(10));
+        let response = client.post("/files", headers.clone(), &[]).unwrap();
+        assert_eq!(response.status, StatusCode::PreconditionFailed);
+
+        headers.set(TusResumable(VERSION.into()));
+        let response = client.post("/files", headers.clone(), &[]).unwrap();
+        assert_eq!(response.status, StatusCode::Created);
+        let location = response.headers.get::<Location>().unwrap().0.clone();
+        assert!(location.starts_with("/files/"));
+        let id = &location["/files/".len()..];
+
+        headers.set(UploadLength(1000));
+        let response = client.post("/files", headers.clone(), &[]).unwrap();
+        assert_eq!(response.status, StatusCode::PayloadTooLarge);
+
+        let mut headers = Headers::new();
+        headers.set(TusResumable(VERSION.into()));
+        headers.set(ContentType("application/offset+octet-stream".parse().unwrap()));
+        headers.set(UploadOffset(0));
+        let response = client.request(Method::Patch, &location, headers.clone(), b"hello").unwrap();
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(response.headers.get(), Some(&UploadOffset(5)));
+        assert_eq!(response.headers.get::<ContentLength>(), None);
+
+        let response = client.request(Method::Patch, &location, headers.clone(), b"hello").unwrap();
+        assert_eq!(response.status, StatusCode::Conflict);
+
+        let response = client.request(Method::Head, &location, headers.clone(), &[]).unwrap();
+        assert_eq!(response.headers.get(), Some(&UploadOffset(5)));
+        assert_eq!(response.headers.get(), Some(&UploadLength(10)));
+
+        headers.set(UploadOffset(5));
+        let response = client.request(Method::Patch, &location, headers.clone(), b"world!").unwrap();
+        assert_eq!(response.status, StatusCode::PayloadTooLarge);
+        let response = client.request(Method::Patch, &location, headers.clone(), b"world").unwrap();
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(response.headers.get::<ContentLength>(), None);
+        assert_eq!(store.data(id), Some(b"helloworld".to_vec()));
+
+        let response = client.request(Method::Head, "/files/missing", headers, &[]).unwrap();
+        assert_eq!(response.status, StatusCode::NotFound);
+
+        let response = client.request(Method::Options, "/files", Headers::new(), &[]).unwrap();
+        assert_eq!(response.status, StatusCode::NoContent);
+        assert_eq!(response.headers.get_raw("tus-max-size"), Some(&[b"100".to_vec()][..]));
+        assert_eq!(response.headers.get::<ContentLength>(), None);
+    }
+}
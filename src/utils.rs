@@ -1,14 +1,37 @@
 use std::io::Write;
+use std::fmt::Write as FmtWrite;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use url::percent_encoding::percent_decode;
+use time;
+use anymap::AnyMap;
+use sha2::Sha256;
+use hmac::{Hmac, Mac};
 use context::Parameters;
 
 pub fn parse_parameters(source: &[u8]) -> Parameters {
+    parse_query(source, true)
+}
+
+pub fn parse_query(source: &[u8], plus_as_space: bool) -> Parameters {
+    parse_pairs(source, b'&', plus_as_space)
+}
+
+pub fn parse_matrix(source: &[u8]) -> Parameters {
+    parse_pairs(source, b';', false)
+}
+
+fn parse_pairs(source: &[u8], separator: u8, plus_as_space: bool) -> Parameters {
     let mut parameters = Parameters::new();
     let source: Vec<u8> = source.iter()
-                                .map(|&e| if e == b'+' { b' ' } else { e })
+                                .map(|&e| if plus_as_space && e == b'+' { b' ' } else { e })
                                 .collect();
 
-    for parameter in source.split(|&e| e == b'&') {
+    for parameter in source.split(|&e| e == separator) {
         let mut parts = parameter.split(|&e| e == b'=');
 
         match (parts.next(), parts.next()) {
@@ -28,6 +51,150 @@ pub fn parse_parameters(source: &[u8]) -> Parameters {
     parameters
 }
 
+thread_local! {
+    //The worker state of the current thread, and the ID of its server.
+    static WORKER_STATE: RefCell<Option<(usize, AnyMap)>> = RefCell::new(None);
+
+    //The keep-alive timeout from the current response.
+    static KEEP_ALIVE_TIMEOUT: Cell<Option<Duration>> = Cell::new(None);
+
+    //A copy of the socket of the connection that is currently handled.
+    static CLIENT_SOCKET: RefCell<Option<TcpStream>> = RefCell::new(None);
+}
+
+///Create the worker state of the current thread, unless it's already been
+///created for the server `id`.
+pub fn init_worker_state<F: FnOnce() -> AnyMap>(id: usize, init: F) {
+    WORKER_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.as_ref().map(|&(current, _)| current) != Some(id) {
+            *state = Some((id, init()));
+        }
+    });
+}
+
+///Borrow the worker state of the current thread, unless it's missing or
+///already borrowed.
+pub fn with_worker_state<T, F: FnOnce(&mut AnyMap) -> T>(f: F) -> Option<T> {
+    WORKER_STATE.with(|state| {
+        match state.try_borrow_mut() {
+            Ok(mut state) => state.as_mut().map(|&mut (_, ref mut state)| f(state)),
+            Err(_) => None
+        }
+    })
+}
+
+///Let the current connection idle for `timeout` after the current request,
+///instead of the server's keep-alive timeout.
+pub fn set_keep_alive_timeout(timeout: Duration) {
+    KEEP_ALIVE_TIMEOUT.with(|current| current.set(Some(timeout)));
+}
+
+///Take the keep-alive timeout that was set by the current response.
+pub fn take_keep_alive_timeout() -> Option<Duration> {
+    KEEP_ALIVE_TIMEOUT.with(|current| current.take())
+}
+
+///Remember `socket` as the socket of the connection that is handled by the
+///current thread, or forget it with `None`.
+pub fn set_client_socket(socket: Option<TcpStream>) {
+    CLIENT_SOCKET.with(|current| *current.borrow_mut() = socket);
+}
+
+///Get a copy of the socket of the connection that is handled by the current
+///thread, if it's known.
+pub fn client_socket() -> Option<TcpStream> {
+    CLIENT_SOCKET.with(|current| current.borrow().as_ref().and_then(|socket| socket.try_clone().ok()))
+}
+
+///Append `text` to `buffer`, with the HTML special characters escaped.
+pub fn push_escaped_html(buffer: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => buffer.push_str("&amp;"),
+            '<' => buffer.push_str("&lt;"),
+            '>' => buffer.push_str("&gt;"),
+            '"' => buffer.push_str("&quot;"),
+            '\'' => buffer.push_str("&#39;"),
+            c => buffer.push(c)
+        }
+    }
+}
+
+///Append `text` to `buffer`, as a quoted and escaped JSON string.
+pub fn push_json_string(buffer: &mut String, text: &str) {
+    buffer.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\r' => buffer.push_str("\\r"),
+            '\t' => buffer.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(buffer, "\\u{:04x}", c as u32); },
+            c => buffer.push(c)
+        }
+    }
+    buffer.push('"');
+}
+
+static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+///Generate a random number, using the randomly keyed hasher from the
+///standard library. It's cheap, but not unpredictable enough for secrets.
+pub fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(ID_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u64(time::precise_time_ns());
+    hasher.finish()
+}
+
+///Generate a random, 128 bit, hex encoded ID, using the random number
+///generator of the operating system. It's meant for IDs that must not be
+///guessable, such as session IDs.
+pub fn random_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("the random number generator of the operating system failed");
+    to_hex(&bytes)
+}
+
+///Calculate the HMAC-SHA256 of `message`, using `key`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+///Compare two byte slices in constant time, with regard to their content.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+///Encode bytes as lowercase hexadecimal digits.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+///Check if `host` matches `pattern`, which is either an exact host name or
+///a wildcard, like `*.example.com`, that matches exactly one label. The
+///comparison is case insensitive and ignores any trailing dot.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+
+    if pattern.starts_with("*.") {
+        let suffix = &pattern[1..];
+        host.len() > suffix.len() &&
+            host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix) &&
+            !host[..host.len() - suffix.len()].contains('.')
+    } else {
+        pattern.trim_end_matches('.').eq_ignore_ascii_case(host)
+    }
+}
+
 ///Extension trait for byte vectors.
 pub trait BytesExt {
     ///Copy a number of bytes to the vector.
@@ -43,7 +210,7 @@ impl BytesExt for Vec<u8> {
 #[cfg(test)]
 mod test {
     use std::borrow::ToOwned;
-    use super::parse_parameters;
+    use super::{parse_parameters, parse_query, hmac_sha256, to_hex, host_matches};
 
     #[test]
     fn parsing_parameters() {
@@ -67,6 +234,26 @@ mod test {
         assert_eq!(parameters.get_raw("ab"), Some(&ab));
     }
 
+    #[test]
+    fn matching_hosts() {
+        assert!(host_matches("example.com", "Example.COM"));
+        assert!(host_matches("example.com", "example.com."));
+        assert!(!host_matches("example.com", "www.example.com"));
+        assert!(host_matches("*.example.com", "www.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "wwwexample.com"));
+    }
+
+    #[test]
+    fn parsing_query_with_literal_plus() {
+        let parameters = parse_query(b"a=1+1&b=2%2B2%20", false);
+        let a = "1+1".to_owned().into();
+        let b = "2+2 ".to_owned().into();
+        assert_eq!(parameters.get_raw("a"), Some(&a));
+        assert_eq!(parameters.get_raw("b"), Some(&b));
+    }
+
     #[test]
     fn parsing_strange_parameters() {
         let parameters = parse_parameters(b"a=1=2&=2&ab=");
@@ -77,4 +264,34 @@ mod test {
         assert_eq!(parameters.get_raw(""), Some(&aa));
         assert_eq!(parameters.get_raw("ab"), Some(&ab));
     }
+
+    #[test]
+    fn hmac_sha256_digests() {
+        //The test cases from RFC 4231
+        assert_eq!(
+            to_hex(&hmac_sha256(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(&[0xaa; 20], &[0xdd; 50])),
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"
+        );
+        let key: Vec<u8> = (1..26).collect();
+        assert_eq!(
+            to_hex(&hmac_sha256(&key, &[0xcd; 50])),
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            to_hex(&hmac_sha256(&[0xaa; 131], b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.")),
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+        );
+    }
 }
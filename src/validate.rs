@@ -0,0 +1,422 @@
+//!Declarative request validation.
+//!
+//!A [`Validate`][validate] wraps a handler and checks the query
+//!parameters, route variables and headers of each request before it
+//!reaches the inner handler. Every rule is checked, so a rejected
+//!request is answered with `400 Bad Request` and a JSON body that lists all
+//!of the [`Violation`][violation]s:
+//!
+//!```text
+//!{"errors":[{"location":"query","name":"page","message":"invalid digit found in string"}]}
+//!```
+//!
+//!The parsed values are stored as [`Validated`][validated] in
+//!`context.extensions`, so the handler doesn't have to parse them again:
+//!
+//!```no_run
+//!use rustful::{Server, Context, Response, DefaultRouter};
+//!use rustful::validate::{Validate, Validated};
+//!
+//!fn list_posts(context: Context, response: Response) {
+//!    let validated = context.extensions.get::<Validated>().unwrap();
+//!    let user: &u64 = validated.variable("user").unwrap();
+//!    let page = validated.query::<u32>("page").cloned().unwrap_or(1);
+//!
+//!    response.send(format!("page {} of the posts by user {}", page, user));
+//!}
+//!
+//!let mut router = DefaultRouter::<Validate<fn(Context, Response)>>::new();
+//!router.build().path("users/:user/posts").then().on_get(
+//!    Validate::new(list_posts as fn(Context, Response))
+//!        .variable::<u64>("user")
+//!        .optional_query::<u32>("page")
+//!        .header("Authorization")
+//!);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!Route variables are only available after routing, so a `Validate` with
+//!variable rules has to be added inside the router, as above. Wrapping the
+//!whole router would report every variable as missing, and answer unknown
+//!paths with `400 Bad Request` instead of `404 Not Found`.
+//!
+//![validate]: struct.Validate.html
+//![violation]: struct.Violation.html
+//![validated]: struct.Validated.html
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use StatusCode;
+use context::Context;
+use context::hypermedia::Link;
+use header::ContentType;
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use server::Requirements;
+use handler::{HandleRequest, Environment, FromHandler, BuilderContext, ApplyContext, Merge};
+use utils::push_json_string;
+
+type Check = Fn(&str) -> Result<Box<Any + Send + Sync>, String> + Send + Sync;
+
+///Where a validated value comes from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Location {
+    ///A query parameter.
+    Query,
+
+    ///A route variable.
+    Variable,
+
+    ///A request header.
+    Header,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Location::Query => f.write_str("query"),
+            Location::Variable => f.write_str("variable"),
+            Location::Header => f.write_str("header"),
+        }
+    }
+}
+
+///A broken validation rule.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Violation {
+    ///Where the value was expected.
+    pub location: Location,
+
+    ///The name of the parameter, variable or header.
+    pub name: String,
+
+    ///What's wrong with the value.
+    pub message: String,
+}
+
+///The values that were parsed by `Validate`.
+///
+///It's stored in `context.extensions`, and the values are looked up by
+///their location, name and type.
+#[derive(Default)]
+pub struct Validated {
+    values: HashMap<(Location, String), Box<Any + Send + Sync>>,
+}
+
+impl Validated {
+    ///Get a value from any location.
+    pub fn get<T: Any>(&self, location: Location, name: &str) -> Option<&T> {
+        self.values.get(&(location, name.to_owned())).and_then(|value| value.downcast_ref())
+    }
+
+    ///Get a parsed query parameter.
+    pub fn query<T: Any>(&self, name: &str) -> Option<&T> {
+        self.get(Location::Query, name)
+    }
+
+    ///Get a parsed route variable.
+    pub fn variable<T: Any>(&self, name: &str) -> Option<&T> {
+        self.get(Location::Variable, name)
+    }
+
+    ///Get a header value, as a string.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.get::<String>(Location::Header, name).map(|value| &**value)
+    }
+}
+
+struct Rule {
+    location: Location,
+    name: String,
+    required: bool,
+    check: Arc<Check>,
+}
+
+///Checks each request against a set of rules, before passing it to the
+///inner handler.
+pub struct Validate<H> {
+    handler: H,
+    rules: Vec<Rule>,
+}
+
+impl<H: HandleRequest> Validate<H> {
+    ///Wrap `handler`, without any rules.
+    pub fn new(handler: H) -> Validate<H> {
+        Validate {
+            handler: handler,
+            rules: vec![],
+        }
+    }
+
+    ///Require a query parameter that can be parsed as `T`.
+    pub fn query<T>(self, name: &str) -> Validate<H> where
+        T: FromStr + Send + Sync + 'static,
+        T::Err: fmt::Display
+    {
+        self.rule(Location::Query, name, true, parse::<T>)
+    }
+
+    ///Allow a query parameter that can be parsed as `T`, but don't require
+    ///it.
+    pub fn optional_query<T>(self, name: &str) -> Validate<H> where
+        T: FromStr + Send + Sync + 'static,
+        T::Err: fmt::Display
+    {
+        self.rule(Location::Query, name, false, parse::<T>)
+    }
+
+    ///Require a route variable that can be parsed as `T`.
+    pub fn variable<T>(self, name: &str) -> Validate<H> where
+        T: FromStr + Send + Sync + 'static,
+        T::Err: fmt::Display
+    {
+        self.rule(Location::Variable, name, true, parse::<T>)
+    }
+
+    ///Require a header to be present. Its value is stored as a `String`.
+    pub fn header(self, name: &str) -> Validate<H> {
+        self.rule(Location::Header, name, true, |value: &str| Ok(value.to_owned()))
+    }
+
+    ///Add a custom rule. The `check` function returns the value that should
+    ///be stored in `Validated`, or a message that describes the problem.
+    ///
+    ///```
+    ///use rustful::{Context, Response};
+    ///use rustful::validate::{Validate, Location};
+    ///
+    ///fn show_color(_context: Context, response: Response) {
+    ///    response.send("a nice color");
+    ///}
+    ///
+    ///let handler = Validate::new(show_color as fn(Context, Response))
+    ///    .rule(Location::Query, "color", true, |value: &str| {
+    ///        if value.len() == 6 && value.chars().all(|c| c.is_digit(16)) {
+    ///            Ok(value.to_lowercase())
+    ///        } else {
+    ///            Err("expected a hex color, such as 00ff00".into())
+    ///        }
+    ///    });
+    ///```
+    pub fn rule<T, F>(mut self, location: Location, name: &str, required: bool, check: F) -> Validate<H> where
+        T: Send + Sync + 'static,
+        F: Fn(&str) -> Result<T, String> + Send + Sync + 'static
+    {
+        self.rules.push(Rule {
+            location: location,
+            name: name.to_owned(),
+            required: required,
+            check: Arc::new(move |value: &str| check(value).map(|value| Box::new(value) as Box<Any + Send + Sync>)),
+        });
+        self
+    }
+
+    ///Get a reference to the inner handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    fn validate(&self, context: &Context) -> Result<Validated, Vec<Violation>> {
+        let mut validated = Validated::default();
+        let mut violations = vec![];
+
+        for rule in &self.rules {
+            let value = match rule.location {
+                Location::Query => context.query.get(&rule.name).map(|value| value.into_owned()),
+                Location::Variable => context.variables.get(&rule.name).map(|value| value.into_owned()),
+                Location::Header => context.headers.get_raw(&rule.name)
+                    .and_then(|lines| lines.first())
+                    .map(|line| String::from_utf8_lossy(line).into_owned()),
+            };
+
+            let message = match value {
+                Some(value) => match (rule.check)(&value) {
+                    Ok(value) => {
+                        validated.values.insert((rule.location, rule.name.clone()), value);
+                        continue;
+                    },
+                    Err(message) => message
+                },
+                None if rule.required => "missing".to_owned(),
+                None => continue
+            };
+
+            violations.push(Violation {
+                location: rule.location,
+                name: rule.name.clone(),
+                message: message,
+            });
+        }
+
+        if violations.is_empty() {
+            Ok(validated)
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+impl<H: HandleRequest> HandleRequest for Validate<H> {
+    fn handle_request<'a, 'b, 'l, 'g>(&self, mut environment: Environment<'a, 'b, 'l, 'g>) -> Result<(), Environment<'a, 'b, 'l, 'g>> {
+        match self.validate(&environment.context) {
+            Ok(validated) => {
+                environment.context.extensions.insert(validated);
+                self.handler.handle_request(environment)
+            },
+            Err(violations) => {
+                let mut response = environment.response;
+                response.set_status(StatusCode::BadRequest);
+                response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)])));
+                response.send(violations_json(&violations));
+                Ok(())
+            }
+        }
+    }
+
+    fn hyperlinks<'a>(&'a self, base: Link<'a>) -> Vec<Link<'a>> {
+        self.handler.hyperlinks(base)
+    }
+
+    fn requirements(&self, requirements: &mut Requirements) {
+        self.handler.requirements(requirements);
+    }
+}
+
+impl<H> FromHandler<Validate<H>> for Validate<H> {
+    fn from_handler(_context: BuilderContext, handler: Validate<H>) -> Validate<H> {
+        handler
+    }
+}
+
+impl<H: ApplyContext> ApplyContext for Validate<H> {
+    fn apply_context(&mut self, context: BuilderContext) {
+        self.handler.apply_context(context);
+    }
+
+    fn prepend_context(&mut self, context: BuilderContext) {
+        self.handler.prepend_context(context);
+    }
+}
+
+impl<H> Merge for Validate<H> {
+    fn merge(&mut self, other: Validate<H>) {
+        *self = other;
+    }
+}
+
+fn parse<T>(value: &str) -> Result<T, String> where
+    T: FromStr,
+    T::Err: fmt::Display
+{
+    value.parse().map_err(|e: T::Err| e.to_string())
+}
+
+fn violations_json(violations: &[Violation]) -> String {
+    let mut json = String::from("{\"errors\":[");
+
+    for (i, violation) in violations.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+
+        json.push_str("{\"location\":");
+        push_json_string(&mut json, &violation.location.to_string());
+        json.push_str(",\"name\":");
+        push_json_string(&mut json, &violation.name);
+        json.push_str(",\"message\":");
+        push_json_string(&mut json, &violation.message);
+        json.push('}');
+    }
+
+    json.push_str("]}");
+    json
+}
+
+#[cfg(test)]
+mod test {
+    use {Context, Response, Method, StatusCode, Server, DefaultRouter};
+    use header::Headers;
+    use server::Global;
+    use testing::{Recorder, TestClient};
+    use handler::{HandleRequest, Environment};
+    use super::{Validate, Validated};
+
+    fn handler(context: Context, response: Response) {
+        let validated = context.extensions.get::<Validated>().unwrap();
+        let page = validated.query::<u32>("page").unwrap();
+        response.send(format!("page {} for {}", page, validated.header("X-User").unwrap()));
+    }
+
+    #[test]
+    fn validate_request() {
+        let validate = Validate::new(handler as fn(Context, Response))
+            .query::<u32>("page")
+            .optional_query::<bool>("draft")
+            .header("X-User");
+        let global = Global::default();
+
+        let recorded = Recorder::new().respond(&global, |response| {
+            let mut headers = Headers::new();
+            headers.set_raw("X-User", vec![b"alice".to_vec()]);
+            let mut context = Context::mock(Method::Get, "/posts", headers, &global);
+            context.query.insert("page", "2");
+
+            assert!(validate.handle_request(Environment {
+                context: context,
+                response: response,
+                route_state: "/posts".into(),
+            }).is_ok());
+        });
+        assert_eq!(recorded.status, StatusCode::Ok);
+        assert_eq!(recorded.body_utf8(), Some("page 2 for alice"));
+
+        let recorded = Recorder::new().respond(&global, |response| {
+            let mut context = Context::mock(Method::Get, "/posts", Headers::new(), &global);
+            context.query.insert("page", "two");
+            context.query.insert("draft", "yes");
+
+            assert!(validate.handle_request(Environment {
+                context: context,
+                response: response,
+                route_state: "/posts".into(),
+            }).is_ok());
+        });
+        assert_eq!(recorded.status, StatusCode::BadRequest);
+        assert_eq!(recorded.body_utf8(), Some(concat!(
+            "{\"errors\":[",
+            "{\"location\":\"query\",\"name\":\"page\",\"message\":\"invalid digit found in string\"},",
+            "{\"location\":\"query\",\"name\":\"draft\",\"message\":\"provided string was not `true` or `false`\"},",
+            "{\"location\":\"header\",\"name\":\"X-User\",\"message\":\"missing\"}",
+            "]}"
+        )));
+    }
+
+    #[test]
+    fn validate_variables() {
+        fn show_user(context: Context, response: Response) {
+            let user = *context.extensions.get::<Validated>().unwrap().variable::<u64>("user").unwrap();
+            response.send(format!("user {}", user));
+        }
+
+        let mut router = DefaultRouter::<Validate<fn(Context, Response)>>::new();
+        router.build().path("users/:user").then().on_get(Validate::new(show_user as fn(Context, Response)).variable::<u64>("user"));
+        let client = TestClient::new(Server::new(router));
+
+        let recorded = client.get("/users/7").unwrap();
+        assert_eq!(recorded.status, StatusCode::Ok);
+        assert_eq!(recorded.body_utf8(), Some("user 7"));
+
+        let recorded = client.get("/users/me").unwrap();
+        assert_eq!(recorded.status, StatusCode::BadRequest);
+        assert_eq!(recorded.body_utf8(), Some("{\"errors\":[{\"location\":\"variable\",\"name\":\"user\",\"message\":\"invalid digit found in string\"}]}"));
+
+        assert_eq!(client.get("/posts").unwrap().status, StatusCode::NotFound);
+    }
+}
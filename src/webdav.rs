@@ -0,0 +1,458 @@
+//!WebDAV building blocks.
+//!
+//!This module has the parts that a WebDAV endpoint needs on top of plain
+//!HTTP: the extra methods, which can be routed with `on_propfind`,
+//!`on_mkcol` and so on, the [`Depth`][depth], [`Destination`][destination]
+//!and [`Overwrite`][overwrite] headers, and [`MultiStatus`][multi_status]
+//!for `207 Multi-Status` responses. Storing and listing the resources is up
+//!to the handlers, and [`file_properties`][file_properties] helps when
+//!they are files:
+//!
+//!```no_run
+//!use std::fs;
+//!use std::path::Path;
+//!use rustful::{Server, Context, Response, DefaultRouter, StatusCode};
+//!use rustful::file;
+//!use rustful::webdav::{self, Depth, MultiStatus, Resource};
+//!
+//!fn propfind(context: Context, mut response: Response) {
+//!    let name = context.variables.get("path").unwrap_or_default().into_owned();
+//!    if file::check_path(&*name).is_err() {
+//!        return response.set_status(StatusCode::Forbidden);
+//!    }
+//!
+//!    let path = Path::new("dav").join(&name);
+//!    let metadata = match fs::metadata(&path) {
+//!        Ok(metadata) => metadata,
+//!        Err(_) => return response.set_status(StatusCode::NotFound)
+//!    };
+//!
+//!    let mut resource = Resource::new(format!("/{}", name));
+//!    for property in webdav::file_properties(&path, &metadata) {
+//!        resource = resource.property(property);
+//!    }
+//!    let mut status = MultiStatus::new().resource(resource);
+//!
+//!    if metadata.is_dir() && context.headers.get() != Some(&Depth::Zero) {
+//!        //List the directory entries as well...
+//!    }
+//!
+//!    response.send(status);
+//!}
+//!
+//!let mut router = DefaultRouter::<fn(Context, Response)>::new();
+//!router.build().path("*path").then().on_propfind(propfind);
+//!
+//!let server_result = Server {
+//!    host: 8080.into(),
+//!    ..Server::new(router)
+//!}.run();
+//!```
+//!
+//!Properties are always in the `DAV:` namespace.
+//!
+//![depth]: enum.Depth.html
+//![destination]: struct.Destination.html
+//![overwrite]: struct.Overwrite.html
+//![multi_status]: struct.MultiStatus.html
+//![file_properties]: fn.file_properties.html
+
+use std::fmt;
+use std::fs::Metadata;
+use std::path::Path;
+use std::str::from_utf8;
+use std::time::UNIX_EPOCH;
+
+use time;
+use url::Url;
+
+use hyper;
+use {Method, StatusCode};
+use header::{Header, HeaderFormat, ContentType, HttpDate};
+use mime::{Mime, TopLevel, SubLevel, Attr, Value};
+use file::ext_to_mime;
+use response::{self, Response, SendResponse};
+use utils::push_escaped_html;
+
+///The `PROPFIND` method, for reading properties.
+pub fn propfind() -> Method {
+    Method::Extension("PROPFIND".into())
+}
+
+///The `PROPPATCH` method, for changing properties.
+pub fn proppatch() -> Method {
+    Method::Extension("PROPPATCH".into())
+}
+
+///The `MKCOL` method, for creating collections.
+pub fn mkcol() -> Method {
+    Method::Extension("MKCOL".into())
+}
+
+///The `COPY` method, for copying resources to the `Destination`.
+pub fn copy() -> Method {
+    Method::Extension("COPY".into())
+}
+
+///The `MOVE` method, for moving resources to the `Destination`.
+pub fn move_to() -> Method {
+    Method::Extension("MOVE".into())
+}
+
+///The `LOCK` method, for locking resources.
+pub fn lock() -> Method {
+    Method::Extension("LOCK".into())
+}
+
+///The `UNLOCK` method, for unlocking resources.
+pub fn unlock() -> Method {
+    Method::Extension("UNLOCK".into())
+}
+
+///The `Depth` header, which tells how far into a collection a request
+///should go. A missing header usually means `Infinity`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Depth {
+    ///Only the resource itself.
+    Zero,
+
+    ///The resource and its direct members.
+    One,
+
+    ///The resource and all of its members, recursively.
+    Infinity
+}
+
+impl Header for Depth {
+    fn header_name() -> &'static str {
+        "Depth"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<Depth> {
+        match raw.first().and_then(|line| from_utf8(line).ok()) {
+            Some(value) if raw.len() == 1 => match value.trim() {
+                "0" => Ok(Depth::Zero),
+                "1" => Ok(Depth::One),
+                value if value.eq_ignore_ascii_case("infinity") => Ok(Depth::Infinity),
+                _ => Err(hyper::Error::Header)
+            },
+            _ => Err(hyper::Error::Header)
+        }
+    }
+}
+
+impl HeaderFormat for Depth {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Depth::Zero => f.write_str("0"),
+            Depth::One => f.write_str("1"),
+            Depth::Infinity => f.write_str("infinity")
+        }
+    }
+}
+
+///The `Destination` header, which is the target of `COPY` and `MOVE`.
+///
+///```
+///use rustful::webdav::Destination;
+///
+///let destination = Destination("http://example.com/files/a%20b.txt".into());
+///assert_eq!(destination.path(), Some("/files/a%20b.txt".into()));
+///```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Destination(pub String);
+
+impl Destination {
+    ///Get the path part of the destination, without decoding it. It's
+    ///`None` if the destination is neither a URL nor an absolute path.
+    pub fn path(&self) -> Option<String> {
+        if self.0.starts_with('/') {
+            Some(self.0.split(|c| c == '?' || c == '#').next().unwrap_or("/").to_owned())
+        } else {
+            Url::parse(&self.0).ok().map(|url| url.path().to_owned())
+        }
+    }
+}
+
+impl Header for Destination {
+    fn header_name() -> &'static str {
+        "Destination"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<Destination> {
+        match raw.first().and_then(|line| from_utf8(line).ok()) {
+            Some(value) if raw.len() == 1 && !value.trim().is_empty() => Ok(Destination(value.trim().to_owned())),
+            _ => Err(hyper::Error::Header)
+        }
+    }
+}
+
+impl HeaderFormat for Destination {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+///The `Overwrite` header, which tells if `COPY` and `MOVE` may replace an
+///existing resource. A missing header means `true`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Overwrite(pub bool);
+
+impl Header for Overwrite {
+    fn header_name() -> &'static str {
+        "Overwrite"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> hyper::Result<Overwrite> {
+        match raw.first().and_then(|line| from_utf8(line).ok()) {
+            Some(value) if raw.len() == 1 => match value.trim() {
+                "T" | "t" => Ok(Overwrite(true)),
+                "F" | "f" => Ok(Overwrite(false)),
+                _ => Err(hyper::Error::Header)
+            },
+            _ => Err(hyper::Error::Header)
+        }
+    }
+}
+
+impl HeaderFormat for Overwrite {
+    fn fmt_header(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0 { "T" } else { "F" })
+    }
+}
+
+///A property, in the `DAV:` namespace.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Property {
+    name: String,
+    xml: String,
+}
+
+impl Property {
+    ///A property with a text value, which will be escaped.
+    pub fn text<N: Into<String>, V: AsRef<str>>(name: N, value: V) -> Property {
+        let mut xml = String::new();
+        push_escaped_html(&mut xml, value.as_ref());
+        Property {
+            name: name.into(),
+            xml: xml,
+        }
+    }
+
+    ///A property with an XML value, such as `<D:collection/>`, which will
+    ///be written as it is.
+    pub fn xml<N: Into<String>, V: Into<String>>(name: N, xml: V) -> Property {
+        Property {
+            name: name.into(),
+            xml: xml.into(),
+        }
+    }
+
+    ///A property without a value.
+    pub fn empty<N: Into<String>>(name: N) -> Property {
+        Property::xml(name, "")
+    }
+
+    ///The name of the property, without the namespace prefix.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+///A resource in a `MultiStatus` response.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Resource {
+    href: String,
+    status: Option<StatusCode>,
+    found: Vec<Property>,
+    missing: Vec<String>,
+}
+
+impl Resource {
+    ///Describe the resource at `href`, which should be a path or a URL.
+    pub fn new<H: Into<String>>(href: H) -> Resource {
+        Resource {
+            href: href.into(),
+            status: None,
+            found: vec![],
+            missing: vec![],
+        }
+    }
+
+    ///Add a property that was found.
+    pub fn property(mut self, property: Property) -> Resource {
+        self.found.push(property);
+        self
+    }
+
+    ///Add the name of a requested property that doesn't exist. It will get
+    ///the status `404 Not Found`.
+    pub fn missing<N: Into<String>>(mut self, name: N) -> Resource {
+        self.missing.push(name.into());
+        self
+    }
+
+    ///Set a status for the whole resource, such as for the result of a
+    ///`COPY` or `DELETE`. The properties are left out if it's set.
+    pub fn status(mut self, status: StatusCode) -> Resource {
+        self.status = Some(status);
+        self
+    }
+}
+
+///A `207 Multi-Status` response, with the status of multiple resources.
+///
+///```
+///use rustful::StatusCode;
+///use rustful::webdav::{MultiStatus, Resource, Property};
+///
+///let status = MultiStatus::new()
+///    .resource(Resource::new("/a.txt").property(Property::text("getcontentlength", "3")).missing("author"))
+///    .resource(Resource::new("/b.txt").status(StatusCode::Locked));
+///
+///assert_eq!(status.to_string(), concat!(
+///    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+///    "<D:multistatus xmlns:D=\"DAV:\">",
+///    "<D:response><D:href>/a.txt</D:href>",
+///    "<D:propstat><D:prop><D:getcontentlength>3</D:getcontentlength></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>",
+///    "<D:propstat><D:prop><D:author/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat>",
+///    "</D:response>",
+///    "<D:response><D:href>/b.txt</D:href><D:status>HTTP/1.1 423 Locked</D:status></D:response>",
+///    "</D:multistatus>\n"
+///));
+///```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MultiStatus {
+    resources: Vec<Resource>,
+}
+
+impl MultiStatus {
+    ///Create an empty response.
+    pub fn new() -> MultiStatus {
+        MultiStatus::default()
+    }
+
+    ///Add a resource.
+    pub fn resource(mut self, resource: Resource) -> MultiStatus {
+        self.resources.push(resource);
+        self
+    }
+
+    ///Add a resource, without consuming `self`.
+    pub fn push(&mut self, resource: Resource) {
+        self.resources.push(resource);
+    }
+}
+
+impl fmt::Display for MultiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">")?;
+
+        for resource in &self.resources {
+            let mut href = String::new();
+            push_escaped_html(&mut href, &resource.href);
+            write!(f, "<D:response><D:href>{}</D:href>", href)?;
+
+            if let Some(status) = resource.status {
+                write!(f, "<D:status>HTTP/1.1 {}</D:status>", status)?;
+            } else {
+                if !resource.found.is_empty() {
+                    f.write_str("<D:propstat><D:prop>")?;
+                    for property in &resource.found {
+                        write_property(f, &property.name, &property.xml)?;
+                    }
+                    write!(f, "</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>", StatusCode::Ok)?;
+                }
+
+                if !resource.missing.is_empty() {
+                    f.write_str("<D:propstat><D:prop>")?;
+                    for name in &resource.missing {
+                        write_property(f, name, "")?;
+                    }
+                    write!(f, "</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>", StatusCode::NotFound)?;
+                }
+            }
+
+            f.write_str("</D:response>")?;
+        }
+
+        f.write_str("</D:multistatus>\n")
+    }
+}
+
+impl<'a, 'b> SendResponse<'a, 'b> for MultiStatus {
+    type Error = response::Error;
+
+    fn send_response(self, mut response: Response<'a, 'b>) -> Result<(), response::Error> {
+        response.set_status(StatusCode::MultiStatus);
+        response.headers_mut().set(ContentType(Mime(TopLevel::Application, SubLevel::Xml, vec![(Attr::Charset, Value::Utf8)])));
+        response.try_send_data(self.to_string())
+    }
+}
+
+fn write_property(f: &mut fmt::Formatter, name: &str, xml: &str) -> fmt::Result {
+    if xml.is_empty() {
+        write!(f, "<D:{}/>", name)
+    } else {
+        write!(f, "<D:{0}>{1}</D:{0}>", name, xml)
+    }
+}
+
+///The standard live properties of a file or directory: `resourcetype`,
+///`getlastmodified` and, for files, `getcontentlength` and
+///`getcontenttype`.
+pub fn file_properties(path: &Path, metadata: &Metadata) -> Vec<Property> {
+    let mut properties = vec![];
+
+    if metadata.is_dir() {
+        properties.push(Property::xml("resourcetype", "<D:collection/>"));
+    } else {
+        properties.push(Property::empty("resourcetype"));
+        properties.push(Property::text("getcontentlength", metadata.len().to_string()));
+
+        let mime = path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ext_to_mime)
+            .unwrap_or_else(|| Mime(TopLevel::Application, SubLevel::Ext("octet-stream".into()), vec![]));
+        properties.push(Property::text("getcontenttype", mime.to_string()));
+    }
+
+    let modified = metadata.modified().ok().and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+    if let Some(modified) = modified {
+        let date = HttpDate(time::at_utc(time::Timespec::new(modified.as_secs() as i64, 0)));
+        properties.push(Property::text("getlastmodified", date.to_string()));
+    }
+
+    properties
+}
+
+#[cfg(test)]
+mod test {
+    use header::Headers;
+    use super::{Depth, Overwrite, Destination, MultiStatus, Resource, Property};
+
+    #[test]
+    fn parse_headers() {
+        let mut headers = Headers::new();
+        headers.set_raw("Depth", vec![b"Infinity".to_vec()]);
+        headers.set_raw("Overwrite", vec![b"F".to_vec()]);
+        headers.set_raw("Destination", vec![b"/a/b?c".to_vec()]);
+
+        assert_eq!(headers.get::<Depth>(), Some(&Depth::Infinity));
+        assert_eq!(headers.get::<Overwrite>(), Some(&Overwrite(false)));
+        assert_eq!(headers.get::<Destination>().and_then(Destination::path), Some("/a/b".into()));
+
+        headers.set_raw("Depth", vec![b"2".to_vec()]);
+        assert_eq!(headers.get::<Depth>(), None);
+    }
+
+    #[test]
+    fn escape_text() {
+        let status = MultiStatus::new()
+            .resource(Resource::new("/a&b/<c>.txt").property(Property::text("displayname", "\"Tom's\" <notes>")));
+
+        let xml = status.to_string();
+        assert!(xml.contains("<D:href>/a&amp;b/&lt;c&gt;.txt</D:href>"), "unexpected XML: {}", xml);
+        assert!(xml.contains("<D:displayname>&quot;Tom&#39;s&quot; &lt;notes&gt;</D:displayname>"), "unexpected XML: {}", xml);
+    }
+}